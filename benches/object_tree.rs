@@ -4,7 +4,7 @@ use crate::criterion;
 
 pub fn add_node(c: &mut Criterion) {
     c.bench_function("ObjectTree::add_root", |b| b.iter_with_setup(
-        || Tree::new(),
+        Tree::new,
         |tree| {
             tree.add_root(black_box(5))
         }
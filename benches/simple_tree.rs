@@ -5,7 +5,7 @@ use crate::criterion;
 
 pub fn add_node(c: &mut Criterion) {
     c.bench_function("SimpleTree::add_root", |b| b.iter_with_setup(
-        || Tree::new(),
+        Tree::new,
         |mut tree| {
             tree.add_root(black_box(5))
         }
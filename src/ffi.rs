@@ -0,0 +1,371 @@
+//! A C interface to a [`simple::Tree`](crate::tree::simple::Tree) of opaque pointers, for
+//! embedding in other languages.
+//!
+//! Trees are behind an opaque [`ForestTree`] handle, and nodes are identified by `u64` keys, with
+//! [`FOREST_NULL_KEY`] meaning no node. Values are `void *`s owned by the caller. Functions that
+//! drop values take an optional callback, called once with each value being dropped.
+//!
+//! No function panics or unwinds, and every function accepts a null tree, treating it as empty.
+
+use core::ffi::c_void;
+use core::ptr;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// The key meaning no node, returned when a node can't be found or created
+pub const FOREST_NULL_KEY: u64 = 0;
+
+/// A callback for a value leaving a tree
+pub type ForestDropFn = Option<unsafe extern "C" fn(val: *mut c_void)>;
+
+/// A callback for each node in a traversal, given the node's key, value and depth, and the user
+/// pointer. Returning `false` stops the traversal.
+pub type ForestVisitFn = unsafe extern "C" fn(key: u64, val: *mut c_void, depth: usize, user: *mut c_void) -> bool;
+
+/// An opaque handle to a tree, created by [`forest_tree_new`] and destroyed by
+/// [`forest_tree_free`]
+pub struct ForestTree {
+    tree: Tree<*mut c_void>,
+}
+
+fn to_key(key: u64) -> Option<TreeKey> {
+    // `TreeKey::from_ffi` would turn zero into a key which may be valid
+    (key != FOREST_NULL_KEY).then(|| TreeKey::from_ffi(key))
+}
+
+fn from_key(key: Option<TreeKey>) -> u64 {
+    key.map_or(FOREST_NULL_KEY, TreeKey::to_ffi)
+}
+
+/// Collect the keys of the subtree rooted at a node, parents before children
+fn subtree_keys(tree: &Tree<*mut c_void>, key: TreeKey) -> Vec<TreeKey> {
+    let mut keys = Vec::new();
+    let mut stack = alloc::vec![key];
+    while let Some(key) = stack.pop() {
+        stack.extend(tree.child_keys(key).into_iter().flatten().rev());
+        keys.push(key);
+    }
+    keys
+}
+
+/// Create a new, empty tree. Must be destroyed with [`forest_tree_free`].
+#[no_mangle]
+pub extern "C" fn forest_tree_new() -> *mut ForestTree {
+    Box::into_raw(Box::new(ForestTree { tree: Tree::new() }))
+}
+
+/// Destroy a tree, calling `drop_val` with the value of each node if it isn't null
+///
+/// # Safety
+///
+/// `tree` must be null or a tree from [`forest_tree_new`] which hasn't been freed yet
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_free(tree: *mut ForestTree, drop_val: ForestDropFn) {
+    if tree.is_null() {
+        return;
+    }
+    // SAFETY: Caller guarantees the tree came from `forest_tree_new` and is still live
+    let tree = unsafe { Box::from_raw(tree) };
+    if let Some(drop_val) = drop_val {
+        for node in tree.tree.unordered_iter() {
+            // SAFETY: Caller guarantees the callback is safe to call with values they added
+            unsafe { drop_val(*node) };
+        }
+    }
+}
+
+/// Get the number of nodes in a tree
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_len(tree: *const ForestTree) -> usize {
+    // SAFETY: Caller guarantees the tree is null or live
+    unsafe { tree.as_ref() }.map_or(0, |tree| tree.tree.len())
+}
+
+/// Add a new root to a tree, after the existing roots. Returns the key of the new node, or
+/// [`FOREST_NULL_KEY`] if the tree is null.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_add_root(tree: *mut ForestTree, val: *mut c_void) -> u64 {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return FOREST_NULL_KEY;
+    };
+    tree.tree.add_root(val).to_ffi()
+}
+
+/// Add a new child to a node, after its existing children. Returns the key of the new node, or
+/// [`FOREST_NULL_KEY`] if the parent doesn't exist.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_add_child(tree: *mut ForestTree, parent: u64, val: *mut c_void) -> u64 {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return FOREST_NULL_KEY;
+    };
+    from_key(to_key(parent).and_then(|parent| tree.tree.add_child(val, parent)))
+}
+
+/// Check whether a node is in a tree
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_contains(tree: *const ForestTree, key: u64) -> bool {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return false;
+    };
+    to_key(key).is_some_and(|key| tree.tree.get(key).is_some())
+}
+
+/// Get the value of a node, or null if it doesn't exist
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_get(tree: *const ForestTree, key: u64) -> *mut c_void {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return ptr::null_mut();
+    };
+    to_key(key)
+        .and_then(|key| tree.tree.get(key))
+        .map_or(ptr::null_mut(), |node| *node)
+}
+
+/// Replace the value of a node, returning the old value, or null if the node doesn't exist
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_set(tree: *mut ForestTree, key: u64, val: *mut c_void) -> *mut c_void {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return ptr::null_mut();
+    };
+    to_key(key)
+        .and_then(|key| tree.tree.get_mut(key))
+        .map_or(ptr::null_mut(), |mut node| core::mem::replace(&mut *node, val))
+}
+
+/// Remove a node and all its descendants, calling `drop_val` with each of their values if it
+/// isn't null. Returns whether the node existed.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]. `drop_val` must not access the
+/// tree.
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_remove(tree: *mut ForestTree, key: u64, drop_val: ForestDropFn) -> bool {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return false;
+    };
+    let Some(key) = to_key(key).filter(|&key| tree.tree.get(key).is_some()) else {
+        return false;
+    };
+    let vals = subtree_keys(&tree.tree, key)
+        .into_iter()
+        .filter_map(|key| tree.tree.get(key).map(|node| *node))
+        .collect::<Vec<_>>();
+    if tree.tree.remove_recursive(key).is_none() {
+        return false;
+    }
+    if let Some(drop_val) = drop_val {
+        for val in vals {
+            // SAFETY: Caller guarantees the callback is safe to call with values they added
+            unsafe { drop_val(val) };
+        }
+    }
+    true
+}
+
+/// Get the key of a node's parent, or [`FOREST_NULL_KEY`] if it's a root or doesn't exist
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_parent(tree: *const ForestTree, key: u64) -> u64 {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return FOREST_NULL_KEY;
+    };
+    from_key(to_key(key).and_then(|key| tree.tree.parent_key_of(key)))
+}
+
+/// Get the number of roots of a tree
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_root_count(tree: *const ForestTree) -> usize {
+    // SAFETY: Caller guarantees the tree is null or live
+    unsafe { tree.as_ref() }.map_or(0, |tree| tree.tree.raw_roots().len())
+}
+
+/// Get the key of the root at an index, or [`FOREST_NULL_KEY`] if the index is out of bounds
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_root_at(tree: *const ForestTree, idx: usize) -> u64 {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return FOREST_NULL_KEY;
+    };
+    from_key(tree.tree.raw_roots().get(idx).copied())
+}
+
+/// Get the number of children of a node, or zero if it doesn't exist
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_child_count(tree: *const ForestTree, key: u64) -> usize {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return 0;
+    };
+    to_key(key)
+        .and_then(|key| tree.tree.child_keys(key))
+        .map_or(0, <[_]>::len)
+}
+
+/// Get the key of a node's child at an index, or [`FOREST_NULL_KEY`] if the node doesn't exist or
+/// the index is out of bounds
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_child_at(tree: *const ForestTree, key: u64, idx: usize) -> u64 {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return FOREST_NULL_KEY;
+    };
+    from_key(to_key(key).and_then(|key| tree.tree.child_keys(key)?.get(idx).copied()))
+}
+
+/// Visit every node of a tree in pre-order, calling `visit` with each node's key, value and depth
+/// below the roots, and `user`. Stops early if `visit` returns `false`, returning `false` as well.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree from [`forest_tree_new`]. `visit` must not modify the tree.
+#[no_mangle]
+pub unsafe extern "C" fn forest_tree_visit(tree: *const ForestTree, visit: ForestVisitFn, user: *mut c_void) -> bool {
+    // SAFETY: Caller guarantees the tree is null or live
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return true;
+    };
+    let mut stack = tree.tree.raw_roots().iter().rev().map(|&key| (key, 0)).collect::<Vec<_>>();
+    while let Some((key, depth)) = stack.pop() {
+        let Some(node) = tree.tree.get(key) else {
+            continue;
+        };
+        // SAFETY: Caller guarantees the callback is safe to call, and won't modify the tree
+        if !unsafe { visit(key.to_ffi(), *node, depth, user) } {
+            return false;
+        }
+        stack.extend(tree.tree.child_keys(key).into_iter().flatten().rev().map(|&child| (child, depth + 1)));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    fn val(n: usize) -> *mut c_void {
+        n as *mut c_void
+    }
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn count_drop(val: *mut c_void) {
+        DROPPED.fetch_add(val as usize, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn record(key: u64, val: *mut c_void, depth: usize, user: *mut c_void) -> bool {
+        // SAFETY: The tests pass a pointer to a live `Vec`
+        let out = unsafe { &mut *user.cast::<Vec<(u64, usize, usize)>>() };
+        out.push((key, val as usize, depth));
+        out.len() < 4
+    }
+
+    #[test]
+    fn test_ffi_tree() {
+        unsafe {
+            let tree = forest_tree_new();
+            let root = forest_tree_add_root(tree, val(1));
+            let a = forest_tree_add_child(tree, root, val(2));
+            let a1 = forest_tree_add_child(tree, a, val(4));
+            let b = forest_tree_add_child(tree, root, val(8));
+            let other = forest_tree_add_root(tree, val(16));
+            assert_eq!(forest_tree_add_child(tree, FOREST_NULL_KEY, val(0)), FOREST_NULL_KEY);
+
+            assert_eq!(forest_tree_len(tree), 5);
+            assert_eq!(forest_tree_get(tree, a1), val(4));
+            assert_eq!(forest_tree_parent(tree, a1), a);
+            assert_eq!(forest_tree_parent(tree, root), FOREST_NULL_KEY);
+            assert_eq!(forest_tree_root_count(tree), 2);
+            assert_eq!(forest_tree_root_at(tree, 1), other);
+            assert_eq!(forest_tree_root_at(tree, 2), FOREST_NULL_KEY);
+            assert_eq!(forest_tree_child_count(tree, root), 2);
+            assert_eq!(forest_tree_child_at(tree, root, 1), b);
+            assert_eq!(forest_tree_set(tree, b, val(32)), val(8));
+
+            let mut seen = Vec::<(u64, usize, usize)>::new();
+            let user = ptr::addr_of_mut!(seen).cast();
+            assert!(!forest_tree_visit(tree, record, user));
+            assert_eq!(seen, [(root, 1, 0), (a, 2, 1), (a1, 4, 2), (b, 32, 1)]);
+
+            assert!(forest_tree_remove(tree, a, Some(count_drop)));
+            assert!(!forest_tree_remove(tree, a, Some(count_drop)));
+            assert_eq!(DROPPED.load(Ordering::Relaxed), 6);
+            assert!(!forest_tree_contains(tree, a1));
+            assert!(forest_tree_get(tree, a1).is_null());
+            assert_eq!(forest_tree_child_count(tree, root), 1);
+
+            forest_tree_free(tree, Some(count_drop));
+            assert_eq!(DROPPED.load(Ordering::Relaxed), 6 + 1 + 32 + 16);
+        }
+    }
+
+    #[test]
+    fn test_ffi_null() {
+        unsafe {
+            let tree = ptr::null_mut();
+            assert_eq!(forest_tree_len(tree), 0);
+            assert_eq!(forest_tree_add_root(tree, val(1)), FOREST_NULL_KEY);
+            assert!(forest_tree_get(tree, 1).is_null());
+            assert!(!forest_tree_remove(tree, 1, None));
+            assert!(forest_tree_visit(tree, record, ptr::null_mut()));
+            forest_tree_free(tree, None);
+
+            let tree = forest_tree_new();
+            assert!(!forest_tree_contains(tree, FOREST_NULL_KEY));
+            assert!(!forest_tree_contains(tree, u64::MAX));
+            forest_tree_free(tree, None);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+//! Heap implementations
+
+pub mod dary;
+
+pub use dary::{DaryHeap, HeapKey};
@@ -0,0 +1,234 @@
+//! A d-ary min-heap with stable handles to its items, supporting decrease-key
+
+use core::fmt;
+use alloc::vec::Vec;
+use slotmap::{new_key_type, SlotMap};
+
+new_key_type! {
+    /// A handle to an item in a [`DaryHeap`], which stays valid while the item is in the heap
+    pub struct HeapKey;
+}
+
+struct Slot<T> {
+    val: T,
+    /// Position of this item in the heap order
+    pos: usize,
+}
+
+/// A min-heap where each node has `D` children, defaulting to 4. Pushing returns a [`HeapKey`],
+/// which can later be used to read, change or remove the item.
+///
+/// Larger `D` makes pushes and decreases cheaper at the cost of pops.
+pub struct DaryHeap<T, const D: usize = 4> {
+    slots: SlotMap<HeapKey, Slot<T>>,
+    order: Vec<HeapKey>,
+}
+
+impl<T: Ord, const D: usize> DaryHeap<T, D> {
+    const VALID: () = assert!(D >= 2, "DaryHeap must have at least two children per node");
+
+    /// Create a new, empty heap
+    pub fn new() -> DaryHeap<T, D> {
+        let () = Self::VALID;
+        DaryHeap { slots: SlotMap::with_key(), order: Vec::new() }
+    }
+
+    /// Get the number of items in this heap
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Check whether this heap has no items
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Check whether an item is still in this heap
+    pub fn contains(&self, key: HeapKey) -> bool {
+        self.slots.contains_key(key)
+    }
+
+    /// Get an item by its handle
+    pub fn get(&self, key: HeapKey) -> Option<&T> {
+        self.slots.get(key).map(|slot| &slot.val)
+    }
+
+    /// Add an item to this heap, returning its handle
+    pub fn push(&mut self, val: T) -> HeapKey {
+        let pos = self.order.len();
+        let key = self.slots.insert(Slot { val, pos });
+        self.order.push(key);
+        self.sift_up(pos);
+        key
+    }
+
+    /// Get the smallest item and its handle, without removing it
+    pub fn peek(&self) -> Option<(HeapKey, &T)> {
+        let key = *self.order.first()?;
+        Some((key, &self.slots.get(key)?.val))
+    }
+
+    /// Remove the smallest item, returning it and its now-invalid handle
+    pub fn pop(&mut self) -> Option<(HeapKey, T)> {
+        let key = *self.order.first()?;
+        self.remove(key).map(|val| (key, val))
+    }
+
+    /// Remove an item by its handle
+    pub fn remove(&mut self, key: HeapKey) -> Option<T> {
+        let Slot { val, pos } = self.slots.remove(key)?;
+        self.order.swap_remove(pos);
+        if pos < self.order.len() {
+            self.slots[self.order[pos]].pos = pos;
+            self.sift_up(pos);
+            self.sift_down(pos);
+        }
+        Some(val)
+    }
+
+    /// Replace the value of an item, moving it to its new place in the heap. Returns the old
+    /// value.
+    pub fn update(&mut self, key: HeapKey, val: T) -> Option<T> {
+        let slot = self.slots.get_mut(key)?;
+        let pos = slot.pos;
+        let old = core::mem::replace(&mut slot.val, val);
+        self.sift_up(pos);
+        self.sift_down(pos);
+        Some(old)
+    }
+
+    /// Lower the value of an item, returning the old value. Leaves the heap unchanged and returns
+    /// `None` if the item isn't in the heap or the new value is larger.
+    pub fn decrease_key(&mut self, key: HeapKey, val: T) -> Option<T> {
+        if *self.get(key)? < val {
+            return None;
+        }
+        let slot = self.slots.get_mut(key)?;
+        let pos = slot.pos;
+        let old = core::mem::replace(&mut slot.val, val);
+        self.sift_up(pos);
+        Some(old)
+    }
+
+    /// Remove every item, invalidating all handles
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.order.clear();
+    }
+
+    /// Iterate over every item and its handle, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (HeapKey, &T)> + '_ {
+        self.slots.iter().map(|(key, slot)| (key, &slot.val))
+    }
+
+    fn less(&self, a: usize, b: usize) -> bool {
+        self.slots[self.order[a]].val < self.slots[self.order[b]].val
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.order.swap(a, b);
+        self.slots[self.order[a]].pos = a;
+        self.slots[self.order[b]].pos = b;
+    }
+
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / D;
+            if !self.less(pos, parent) {
+                break;
+            }
+            self.swap(pos, parent);
+            pos = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let first = pos * D + 1;
+            let last = (first + D).min(self.order.len());
+            let Some(min) = (first..last).reduce(|min, child| if self.less(child, min) { child } else { min }) else {
+                break;
+            };
+            if !self.less(min, pos) {
+                break;
+            }
+            self.swap(pos, min);
+            pos = min;
+        }
+    }
+}
+
+impl<T: Ord, const D: usize> Default for DaryHeap<T, D> {
+    fn default() -> Self {
+        DaryHeap::new()
+    }
+}
+
+impl<T: Ord, const D: usize> Extend<T> for DaryHeap<T, D> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+
+impl<T: Ord, const D: usize> FromIterator<T> for DaryHeap<T, D> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut heap = DaryHeap::new();
+        heap.extend(iter);
+        heap
+    }
+}
+
+impl<T: fmt::Debug, const D: usize> fmt::Debug for DaryHeap<T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.order.iter().map(|&key| (key, &self.slots[key].val)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain<T: Ord, const D: usize>(heap: &mut DaryHeap<T, D>) -> Vec<T> {
+        core::iter::from_fn(|| heap.pop().map(|(_, val)| val)).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let mut heap = [5, 3, 8, 1, 9, 2, 7, 1].into_iter().collect::<DaryHeap<_>>();
+        assert_eq!(heap.len(), 8);
+        assert_eq!(heap.peek().map(|(_, &val)| val), Some(1));
+        assert_eq!(drain(&mut heap), [1, 1, 2, 3, 5, 7, 8, 9]);
+        assert!(heap.is_empty());
+
+        let mut binary = DaryHeap::<_, 2>::new();
+        binary.extend((0..50).rev());
+        assert_eq!(drain(&mut binary), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut heap = DaryHeap::<_, 3>::new();
+        let keys = (0..20).map(|val| heap.push(val * 10)).collect::<Vec<_>>();
+
+        assert_eq!(heap.decrease_key(keys[15], 5), Some(150));
+        assert_eq!(heap.decrease_key(keys[3], 100), None);
+        assert_eq!(heap.get(keys[3]), Some(&30));
+        assert_eq!(heap.update(keys[0], 500), Some(0));
+        assert_eq!(heap.remove(keys[7]), Some(70));
+        assert!(!heap.contains(keys[7]));
+        assert_eq!(heap.remove(keys[7]), None);
+
+        let (key, val) = heap.pop().unwrap();
+        assert_eq!((key, val), (keys[15], 5));
+        assert_eq!(heap.get(keys[15]), None);
+
+        let rest = drain(&mut heap);
+        assert_eq!(rest.len(), 18);
+        assert!(rest.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(rest.last(), Some(&500));
+    }
+}
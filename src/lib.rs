@@ -1,6 +1,6 @@
 //! Handy data structures, particularly trees and occasionally other things
 
-#![cfg_attr(feature = "unstable", feature(unsize, coerce_unsized))]
+#![cfg_attr(feature = "unstable", feature(unsize, coerce_unsized, allocator_api))]
 #![cfg_attr(not(feature = "std"), no_std)]
 
 #![deny(clippy::all)]
@@ -10,7 +10,6 @@
     explicit_outlives_requirements,
     missing_abi,
     noop_method_call,
-    pointer_structural_match,
     semicolon_in_expressions_from_macros,
     unused_import_braces,
     unused_lifetimes,
@@ -20,12 +19,26 @@
     clippy::ptr_as_ptr,
     clippy::cloned_instead_of_copied,
     clippy::unreadable_literal,
-    clippy::missing_panics_doc
 )]
 
 extern crate alloc;
 
 pub mod tree;
 pub mod list;
+pub mod heap;
+pub mod rope;
+pub mod merkle;
+pub mod spatial;
 pub mod stable;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
 pub(crate) mod util;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::collections::BTreeMap;
+}
@@ -1,2 +1,10 @@
+//! List implementations
 
 pub mod array_vec;
+pub mod linked;
+pub mod persistent;
+pub mod skip_list;
+
+pub use linked::{LinkedList, ListKey};
+pub use persistent::PersistentVec;
+pub use skip_list::{SkipKey, SkipList};
@@ -1,3 +1,5 @@
+//! A fixed-capacity, inline-allocated vector
+
 use core::fmt::Debug;
 use core::mem::MaybeUninit;
 use core::slice::SliceIndex;
@@ -5,12 +7,14 @@ use core::ptr;
 use core::ops::{Deref, DerefMut, Index, IndexMut};
 use crate::util::{MaybeUninitArray, MaybeUninitSlice};
 
+/// A vector backed by a fixed-size inline array, never allocating
 pub struct ArrayVec<T, const N: usize> {
     init: usize,
     data: [MaybeUninit<T>; N],
 }
 
 impl<T, const N: usize> ArrayVec<T, N> {
+    /// Create a new, empty `ArrayVec`
     pub const fn new() -> ArrayVec<T, N> {
         ArrayVec {
             init: 0,
@@ -18,26 +22,33 @@ impl<T, const N: usize> ArrayVec<T, N> {
         }
     }
 
+    /// Get the number of initialized items
     pub const fn len(&self) -> usize {
         self.init
     }
 
+    /// Get the maximum number of items this vector can hold
     pub const fn capacity(&self) -> usize {
         N
     }
 
+    /// Check whether this vector contains no items
     pub const fn is_empty(&self) -> bool {
         self.init == 0
     }
 
+    /// Get the initialized items as a slice
     pub fn as_slice(&self) -> &[T] {
         unsafe { MaybeUninitSlice::assume_init_ref(&self.data[..self.init]) }
     }
 
+    /// Get the initialized items as a mutable slice
     pub fn as_slice_mut(&mut self) -> &mut [T] {
         unsafe { MaybeUninitSlice::assume_init_mut(&mut self.data[..self.init]) }
     }
 
+    /// Push an item onto the end of this vector
+    ///
     /// # Panics
     ///
     /// If a push would overflow the capacity of the backing array
@@ -49,6 +60,7 @@ impl<T, const N: usize> ArrayVec<T, N> {
         self.init += 1;
     }
 
+    /// Remove the last item from this vector, returning `None` if it is empty
     pub fn pop(&mut self) -> Option<T> {
         if self.init == 0 {
             None
@@ -59,15 +71,23 @@ impl<T, const N: usize> ArrayVec<T, N> {
         }
     }
 
+    /// Get an item or subslice, returning `None` if out of bounds
     pub fn get<I: SliceIndex<[T]>>(&self, idx: I) -> Option<&I::Output> {
         self.as_slice().get(idx)
     }
 
+    /// Get an item or subslice mutably, returning `None` if out of bounds
     pub fn get_mut<I: SliceIndex<[T]>>(&mut self, idx: I) -> Option<&mut I::Output> {
         self.as_slice_mut().get_mut(idx)
     }
 }
 
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        ArrayVec::new()
+    }
+}
+
 impl<T, const N: usize> Deref for ArrayVec<T, N> {
     type Target = [T];
 
@@ -124,7 +144,7 @@ mod tests {
     fn test_push() {
         let mut v = ArrayVec::<_, 5>::new();
         assert_eq!(v.len(), 0);
-        assert_eq!(v.as_slice(), &[]);
+        assert_eq!(v.as_slice(), &[] as &[i32]);
         assert_eq!(v.get(0), None);
         v.push(0);
         assert_eq!(v.len(), 1);
@@ -159,7 +179,7 @@ mod tests {
         assert_eq!(v.pop(), Some(1));
         assert_eq!(v.as_slice(), &[0]);
         assert_eq!(v.pop(), Some(0));
-        assert_eq!(v.as_slice(), &[]);
+        assert_eq!(v.as_slice(), &[] as &[i32]);
         assert_eq!(v.pop(), None);
     }
 }
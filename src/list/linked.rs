@@ -0,0 +1,298 @@
+//! A doubly linked list with stable handles to its items
+
+use core::fmt;
+use alloc::vec::Vec;
+use slotmap::{new_key_type, SecondaryMap, SlotMap};
+
+new_key_type! {
+    /// A handle to an item in a [`LinkedList`], which stays valid while the item is in the list
+    pub struct ListKey;
+}
+
+struct Link<T> {
+    val: T,
+    prev: Option<ListKey>,
+    next: Option<ListKey>,
+}
+
+/// A doubly linked list, with items stored in a slotmap. Adding an item returns a [`ListKey`],
+/// which can later be used to read it, remove it, or add items next to it in `O(1)` time.
+pub struct LinkedList<T> {
+    nodes: SlotMap<ListKey, Link<T>>,
+    head: Option<ListKey>,
+    tail: Option<ListKey>,
+}
+
+impl<T> LinkedList<T> {
+    /// Create a new, empty list
+    pub fn new() -> LinkedList<T> {
+        LinkedList { nodes: SlotMap::with_key(), head: None, tail: None }
+    }
+
+    /// Get the number of items in this list
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this list has no items
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Check whether an item is still in this list
+    pub fn contains(&self, key: ListKey) -> bool {
+        self.nodes.contains_key(key)
+    }
+
+    /// Get an item by its handle
+    pub fn get(&self, key: ListKey) -> Option<&T> {
+        self.nodes.get(key).map(|link| &link.val)
+    }
+
+    /// Get an item mutably by its handle
+    pub fn get_mut(&mut self, key: ListKey) -> Option<&mut T> {
+        self.nodes.get_mut(key).map(|link| &mut link.val)
+    }
+
+    /// Get the handle of the first item
+    pub fn front(&self) -> Option<ListKey> {
+        self.head
+    }
+
+    /// Get the handle of the last item
+    pub fn back(&self) -> Option<ListKey> {
+        self.tail
+    }
+
+    /// Get the handle of the item after another
+    pub fn next(&self, key: ListKey) -> Option<ListKey> {
+        self.nodes.get(key)?.next
+    }
+
+    /// Get the handle of the item before another
+    pub fn prev(&self, key: ListKey) -> Option<ListKey> {
+        self.nodes.get(key)?.prev
+    }
+
+    /// Link a detached node in between two neighbors, either of which may be the end of the list
+    fn link(&mut self, key: ListKey, prev: Option<ListKey>, next: Option<ListKey>) {
+        if let Some(link) = self.nodes.get_mut(key) {
+            link.prev = prev;
+            link.next = next;
+        }
+        match prev.and_then(|prev| self.nodes.get_mut(prev)) {
+            Some(link) => link.next = Some(key),
+            None => self.head = Some(key),
+        }
+        match next.and_then(|next| self.nodes.get_mut(next)) {
+            Some(link) => link.prev = Some(key),
+            None => self.tail = Some(key),
+        }
+    }
+
+    /// Detach a node from its neighbors, leaving it in the slotmap
+    fn unlink(&mut self, key: ListKey) -> Option<()> {
+        let link = self.nodes.get(key)?;
+        let (prev, next) = (link.prev, link.next);
+        match prev.and_then(|prev| self.nodes.get_mut(prev)) {
+            Some(link) => link.next = next,
+            None => self.head = next,
+        }
+        match next.and_then(|next| self.nodes.get_mut(next)) {
+            Some(link) => link.prev = prev,
+            None => self.tail = prev,
+        }
+        Some(())
+    }
+
+    fn insert_between(&mut self, val: T, prev: Option<ListKey>, next: Option<ListKey>) -> ListKey {
+        let key = self.nodes.insert(Link { val, prev: None, next: None });
+        self.link(key, prev, next);
+        key
+    }
+
+    /// Add an item to the start of this list, returning its handle
+    pub fn push_front(&mut self, val: T) -> ListKey {
+        self.insert_between(val, None, self.head)
+    }
+
+    /// Add an item to the end of this list, returning its handle
+    pub fn push_back(&mut self, val: T) -> ListKey {
+        self.insert_between(val, self.tail, None)
+    }
+
+    /// Remove the first item of this list
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.remove(self.head?)
+    }
+
+    /// Remove the last item of this list
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.remove(self.tail?)
+    }
+
+    /// Add an item directly before another, returning its handle. Returns `None` if the other
+    /// item isn't in this list.
+    pub fn insert_before(&mut self, key: ListKey, val: T) -> Option<ListKey> {
+        let prev = self.nodes.get(key)?.prev;
+        Some(self.insert_between(val, prev, Some(key)))
+    }
+
+    /// Add an item directly after another, returning its handle. Returns `None` if the other
+    /// item isn't in this list.
+    pub fn insert_after(&mut self, key: ListKey, val: T) -> Option<ListKey> {
+        let next = self.nodes.get(key)?.next;
+        Some(self.insert_between(val, Some(key), next))
+    }
+
+    /// Remove an item by its handle
+    pub fn remove(&mut self, key: ListKey) -> Option<T> {
+        self.unlink(key)?;
+        self.nodes.remove(key).map(|link| link.val)
+    }
+
+    /// Move an item to directly before another, keeping its handle. Fails if either item isn't
+    /// in this list, or they're the same item.
+    pub fn move_before(&mut self, key: ListKey, target: ListKey) -> Option<()> {
+        if key == target || !self.contains(key) {
+            return None;
+        }
+        let prev = self.nodes.get(target)?.prev;
+        self.unlink(key)?;
+        let prev = if prev == Some(key) { self.prev(target) } else { prev };
+        self.link(key, prev, Some(target));
+        Some(())
+    }
+
+    /// Move an item to directly after another, keeping its handle. Fails if either item isn't
+    /// in this list, or they're the same item.
+    pub fn move_after(&mut self, key: ListKey, target: ListKey) -> Option<()> {
+        if key == target || !self.contains(key) {
+            return None;
+        }
+        let next = self.nodes.get(target)?.next;
+        self.unlink(key)?;
+        let next = if next == Some(key) { self.next(target) } else { next };
+        self.link(key, Some(target), next);
+        Some(())
+    }
+
+    /// Remove every item, invalidating all handles
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    /// Iterate over the handles of every item, front to back
+    pub fn keys(&self) -> impl Iterator<Item = ListKey> + '_ {
+        let mut cur = self.head;
+        core::iter::from_fn(move || {
+            let key = cur?;
+            cur = self.next(key);
+            Some(key)
+        })
+    }
+
+    /// Iterate over every item, front to back
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.keys().filter_map(|key| self.get(key))
+    }
+
+    /// Iterate over every item mutably, front to back
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        let mut pos = SecondaryMap::new();
+        for (idx, key) in self.keys().enumerate() {
+            pos.insert(key, idx);
+        }
+        let mut ordered = (0..self.len()).map(|_| None).collect::<Vec<_>>();
+        for (key, link) in self.nodes.iter_mut() {
+            if let Some(slot) = pos.get(key).and_then(|&idx| ordered.get_mut(idx)) {
+                *slot = Some(&mut link.val);
+            }
+        }
+        ordered.into_iter().flatten()
+    }
+}
+
+impl<T> Default for LinkedList<T> {
+    fn default() -> Self {
+        LinkedList::new()
+    }
+}
+
+impl<T> Extend<T> for LinkedList<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for val in iter {
+            self.push_back(val);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for LinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = LinkedList::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LinkedList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items<T: Copy>(list: &LinkedList<T>) -> Vec<T> {
+        list.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut list = LinkedList::new();
+        list.push_back(2);
+        list.push_front(1);
+        list.push_back(3);
+        assert_eq!(items(&list), [1, 2, 3]);
+        assert_eq!(list.pop_front(), Some(1));
+        assert_eq!(list.pop_back(), Some(3));
+        assert_eq!(list.pop_back(), Some(2));
+        assert_eq!(list.pop_back(), None);
+        assert_eq!((list.front(), list.back()), (None, None));
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut list = (0..5).collect::<LinkedList<_>>();
+        let keys = list.keys().collect::<Vec<_>>();
+
+        list.insert_after(keys[1], 10).unwrap();
+        list.insert_before(keys[0], 20).unwrap();
+        assert_eq!(items(&list), [20, 0, 1, 10, 2, 3, 4]);
+        assert_eq!(list.remove(keys[3]), Some(3));
+        assert_eq!(list.remove(keys[3]), None);
+        assert_eq!(list.insert_after(keys[3], 5), None);
+        assert_eq!(list.next(keys[2]), Some(keys[4]));
+
+        list.move_before(keys[4], keys[0]).unwrap();
+        list.move_after(keys[0], keys[2]).unwrap();
+        assert_eq!(items(&list), [20, 4, 1, 10, 2, 0]);
+        assert_eq!(list.move_after(keys[1], keys[1]), None);
+        list.move_after(keys[1], keys[0]).unwrap();
+        list.move_before(keys[2], keys[1]).unwrap();
+        assert_eq!(items(&list), [20, 4, 10, 0, 2, 1]);
+        assert_eq!(list.back(), Some(keys[1]));
+
+        for val in list.iter_mut() {
+            *val *= 2;
+        }
+        assert_eq!(items(&list), [40, 8, 20, 0, 4, 2]);
+        assert_eq!(list.get(keys[4]), Some(&8));
+        let back = core::iter::successors(list.back(), |&key| list.prev(key)).count();
+        assert_eq!(back, list.len());
+    }
+}
@@ -0,0 +1,315 @@
+//! A persistent vector, where clones are cheap snapshots sharing structure with the original
+
+use core::fmt;
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "atomic"))]
+type Shared<T> = alloc::rc::Rc<T>;
+#[cfg(feature = "atomic")]
+type Shared<T> = alloc::sync::Arc<T>;
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum PNode<T> {
+    Branch(Vec<Shared<PNode<T>>>),
+    Leaf(Vec<T>),
+}
+
+impl<T> PNode<T> {
+    /// Create an empty node, `shift` bits above the leaves
+    fn empty(shift: usize) -> PNode<T> {
+        if shift == 0 {
+            PNode::Leaf(Vec::with_capacity(WIDTH))
+        } else {
+            PNode::Branch(Vec::with_capacity(WIDTH))
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            PNode::Branch(children) => children.is_empty(),
+            PNode::Leaf(items) => items.is_empty(),
+        }
+    }
+}
+
+/// A vector stored as a trie with 32 items or children per node. Cloning it is `O(1)`, and
+/// clones share all their nodes until one is modified, at which point only the `O(log32 n)`
+/// nodes on the path to the change are copied.
+///
+/// Useful for keeping many versions of a large list, such as snapshots for undo.
+pub struct PersistentVec<T> {
+    len: usize,
+    /// Bits of an index consumed above the leaves
+    shift: usize,
+    root: Shared<PNode<T>>,
+}
+
+impl<T> PersistentVec<T> {
+    /// Create a new, empty vector
+    pub fn new() -> PersistentVec<T> {
+        PersistentVec { len: 0, shift: 0, root: Shared::new(PNode::empty(0)) }
+    }
+
+    /// Get the number of items in this vector
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether this vector has no items
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the leaf holding an index, as a slice starting at the first item of the leaf
+    fn leaf(&self, idx: usize) -> Option<&[T]> {
+        if idx >= self.len {
+            return None;
+        }
+        let mut node = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match node {
+                PNode::Branch(children) => {
+                    node = children.get((idx >> shift) & MASK)?;
+                    shift -= BITS;
+                }
+                PNode::Leaf(items) => return Some(items),
+            }
+        }
+    }
+
+    /// Get the item at an index
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.leaf(idx)?.get(idx & MASK)
+    }
+
+    /// Get the first item
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    /// Get the last item
+    pub fn last(&self) -> Option<&T> {
+        self.get(self.len.checked_sub(1)?)
+    }
+
+    /// Iterate over every item in order
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut idx = 0;
+        let mut leaf: &[T] = &[];
+        core::iter::from_fn(move || {
+            if leaf.is_empty() {
+                leaf = self.leaf(idx)?;
+            }
+            let (item, rest) = leaf.split_first()?;
+            leaf = rest;
+            idx += 1;
+            Some(item)
+        })
+    }
+
+    /// Check whether two vectors are the same version, sharing their whole structure
+    pub fn ptr_eq(&self, other: &PersistentVec<T>) -> bool {
+        Shared::ptr_eq(&self.root, &other.root)
+    }
+}
+
+impl<T: Clone> PersistentVec<T> {
+    /// Add an item to the end of this vector, copying only the nodes shared with other versions
+    pub fn push(&mut self, val: T) {
+        if self.len == 1 << (self.shift + BITS) {
+            let old = core::mem::replace(&mut self.root, Shared::new(PNode::Branch(Vec::with_capacity(WIDTH))));
+            if let PNode::Branch(children) = Shared::make_mut(&mut self.root) {
+                children.push(old);
+            }
+            self.shift += BITS;
+        }
+
+        let idx = self.len;
+        let mut node = Shared::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            match node {
+                PNode::Branch(children) => {
+                    let slot = (idx >> shift) & MASK;
+                    shift -= BITS;
+                    if slot == children.len() {
+                        children.push(Shared::new(PNode::empty(shift)));
+                    }
+                    match children.get_mut(slot) {
+                        Some(child) => node = Shared::make_mut(child),
+                        None => return,
+                    }
+                }
+                PNode::Leaf(items) => {
+                    items.push(val);
+                    self.len += 1;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Remove the last item of this vector
+    pub fn pop(&mut self) -> Option<T> {
+        let idx = self.len.checked_sub(1)?;
+        let val = Self::pop_from(Shared::make_mut(&mut self.root))?;
+        self.len = idx;
+
+        while self.shift > 0 {
+            let only = match &*self.root {
+                PNode::Branch(children) if children.len() == 1 => children[0].clone(),
+                _ => break,
+            };
+            self.root = only;
+            self.shift -= BITS;
+        }
+        Some(val)
+    }
+
+    /// Remove the last item below a node, dropping nodes left empty
+    fn pop_from(node: &mut PNode<T>) -> Option<T> {
+        match node {
+            PNode::Leaf(items) => items.pop(),
+            PNode::Branch(children) => {
+                let last = children.last_mut()?;
+                let val = Self::pop_from(Shared::make_mut(last));
+                if last.is_empty() {
+                    children.pop();
+                }
+                val
+            }
+        }
+    }
+
+    /// Get the item at an index mutably, copying only the nodes shared with other versions
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        if idx >= self.len {
+            return None;
+        }
+        let mut node = Shared::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            match node {
+                PNode::Branch(children) => {
+                    node = Shared::make_mut(children.get_mut((idx >> shift) & MASK)?);
+                    shift -= BITS;
+                }
+                PNode::Leaf(items) => return items.get_mut(idx & MASK),
+            }
+        }
+    }
+
+    /// Replace the item at an index, returning the old item
+    pub fn set(&mut self, idx: usize, val: T) -> Option<T> {
+        self.get_mut(idx).map(|item| core::mem::replace(item, val))
+    }
+
+    /// Create a new version with the item at an index replaced, leaving this one unchanged
+    pub fn update(&self, idx: usize, val: T) -> Option<PersistentVec<T>> {
+        let mut new = self.clone();
+        new.set(idx, val)?;
+        Some(new)
+    }
+}
+
+impl<T> Clone for PersistentVec<T> {
+    fn clone(&self) -> Self {
+        PersistentVec { len: self.len, shift: self.shift, root: Shared::clone(&self.root) }
+    }
+}
+
+impl<T> Default for PersistentVec<T> {
+    fn default() -> Self {
+        PersistentVec::new()
+    }
+}
+
+impl<T: Clone> Extend<T> for PersistentVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PersistentVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = PersistentVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: PartialEq> PartialEq for PersistentVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && (self.ptr_eq(other) || self.iter().eq(other.iter()))
+    }
+}
+
+impl<T: Eq> Eq for PersistentVec<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let mut vec = (0..5000).collect::<PersistentVec<_>>();
+        assert_eq!(vec.len(), 5000);
+        assert_eq!(vec.shift, 2 * BITS);
+        assert!(vec.iter().copied().eq(0..5000));
+        assert_eq!(vec.get(1234), Some(&1234));
+        assert_eq!(vec.get(5000), None);
+
+        for expected in (20..5000).rev() {
+            assert_eq!(vec.pop(), Some(expected));
+        }
+        assert_eq!(vec.shift, 0);
+        assert_eq!(vec.last(), Some(&19));
+        vec.extend(20..40);
+        assert!(vec.iter().copied().eq(0..40));
+        while vec.pop().is_some() {}
+        assert!(vec.is_empty());
+        assert_eq!(vec.first(), None);
+    }
+
+    #[test]
+    fn test_sharing() {
+        let old = (0..1000).collect::<PersistentVec<_>>();
+        let mut new = old.clone();
+        assert!(new.ptr_eq(&old));
+
+        new.set(900, 0);
+        *new.get_mut(3).unwrap() = 100;
+        new.push(1000);
+        assert!(!new.ptr_eq(&old));
+        assert_eq!(new.get(900), Some(&0));
+        assert_eq!(new.get(3), Some(&100));
+        assert_eq!(old.get(900), Some(&900));
+        assert!(old.iter().copied().eq(0..1000));
+
+        let (PNode::Branch(old_leaves), PNode::Branch(new_leaves)) = (&*old.root, &*new.root) else {
+            panic!("Expected branches at the root");
+        };
+        let shared = old_leaves.iter().zip(new_leaves).filter(|(a, b)| Shared::ptr_eq(a, b)).count();
+        assert_eq!(shared, old_leaves.len() - 3);
+
+        let updated = old.update(7, 0).unwrap();
+        assert_eq!(updated.get(7), Some(&0));
+        assert_eq!(old.get(7), Some(&7));
+        assert!(old.update(1000, 0).is_none());
+        assert_ne!(updated, old);
+        assert_eq!(updated.update(7, 7).unwrap(), old);
+    }
+}
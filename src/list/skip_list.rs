@@ -0,0 +1,405 @@
+//! An ordered map stored as a skip list, with stable handles to its entries
+
+use core::fmt;
+use core::ops::{Bound, RangeBounds};
+use alloc::vec;
+use alloc::vec::Vec;
+use slotmap::{new_key_type, SlotMap};
+
+new_key_type! {
+    /// A handle to an entry in a [`SkipList`], which stays valid while the entry is in the list
+    pub struct SkipKey;
+}
+
+/// Most levels a list may have
+const MAX_LEVEL: usize = 24;
+
+struct SkipNode<K, V> {
+    key: K,
+    val: V,
+    /// Next node on each level this node is part of
+    next: Vec<Option<SkipKey>>,
+    prev: Option<SkipKey>,
+}
+
+/// A map ordered by key, stored as a linked list with express lanes skipping over entries.
+/// Inserts, removals and lookups take `O(log n)` time on average.
+///
+/// Entries can be reached through [`SkipKey`] handles, which act as cursors. A handle stays valid
+/// as other entries are inserted or removed, and stepping to the next or previous entry from it
+/// takes `O(1)` time.
+pub struct SkipList<K, V> {
+    nodes: SlotMap<SkipKey, SkipNode<K, V>>,
+    /// First node on each level
+    head: Vec<Option<SkipKey>>,
+    tail: Option<SkipKey>,
+    /// State for picking the levels of new nodes
+    rng: u64,
+}
+
+impl<K: Ord, V> SkipList<K, V> {
+    /// Create a new, empty list
+    pub fn new() -> SkipList<K, V> {
+        SkipList {
+            nodes: SlotMap::with_key(),
+            head: Vec::new(),
+            tail: None,
+            rng: 0x2545_F491_4F6C_DD1D,
+        }
+    }
+
+    /// Get the number of entries in this list
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this list has no entries
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Pick the number of levels for a new node, each level being a quarter as likely as the last
+    fn random_height(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (1 + self.rng.trailing_zeros() as usize / 2).min(MAX_LEVEL)
+    }
+
+    /// Get the node after another on a level, where `None` is the head of the list
+    fn next_of(&self, cur: Option<SkipKey>, level: usize) -> Option<SkipKey> {
+        match cur {
+            None => self.head.get(level).copied().flatten(),
+            Some(key) => self.nodes.get(key).and_then(|node| node.next.get(level).copied().flatten()),
+        }
+    }
+
+    fn set_next(&mut self, cur: Option<SkipKey>, level: usize, next: Option<SkipKey>) {
+        let slot = match cur {
+            None => self.head.get_mut(level),
+            Some(key) => self.nodes.get_mut(key).and_then(|node| node.next.get_mut(level)),
+        };
+        if let Some(slot) = slot {
+            *slot = next;
+        }
+    }
+
+    /// Get the last node on each level whose key is `before` the target, where `None` is the
+    /// head of the list
+    fn preds(&self, before: impl Fn(&K) -> bool) -> Vec<Option<SkipKey>> {
+        let mut preds = vec![None; self.head.len()];
+        let mut cur = None;
+        for level in (0..self.head.len()).rev() {
+            while let Some(next) = self.next_of(cur, level) {
+                if !self.nodes.get(next).is_some_and(|node| before(&node.key)) {
+                    break;
+                }
+                cur = Some(next);
+            }
+            preds[level] = cur;
+        }
+        preds
+    }
+
+    /// Get the first node whose key isn't `before` the target
+    fn seek(&self, before: impl Fn(&K) -> bool) -> Option<SkipKey> {
+        let preds = self.preds(before);
+        self.next_of(preds.first().copied().flatten(), 0)
+    }
+
+    /// Get the handle of the entry with a key
+    pub fn find(&self, key: &K) -> Option<SkipKey> {
+        self.lower_bound(key).filter(|&handle| self.nodes.get(handle).is_some_and(|node| node.key == *key))
+    }
+
+    /// Get the handle of the first entry with a key at least as large as `key`
+    pub fn lower_bound(&self, key: &K) -> Option<SkipKey> {
+        self.seek(|other| other < key)
+    }
+
+    /// Check whether this list has an entry with a key
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Get the value for a key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.nodes.get(self.find(key)?).map(|node| &node.val)
+    }
+
+    /// Get the value for a key mutably
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let handle = self.find(key)?;
+        self.nodes.get_mut(handle).map(|node| &mut node.val)
+    }
+
+    /// Insert a value for a key, returning the handle of its entry. If the key was already
+    /// present, its entry keeps the same handle, and the old value is returned.
+    pub fn insert(&mut self, key: K, val: V) -> (SkipKey, Option<V>) {
+        let mut preds = self.preds(|other| *other < key);
+        let next = self.next_of(preds.first().copied().flatten(), 0);
+        if let Some(next) = next {
+            if let Some(node) = self.nodes.get_mut(next).filter(|node| node.key == key) {
+                return (next, Some(core::mem::replace(&mut node.val, val)));
+            }
+        }
+
+        let height = self.random_height();
+        while self.head.len() < height {
+            self.head.push(None);
+            preds.push(None);
+        }
+        let next_links = (0..height).map(|level| self.next_of(preds[level], level)).collect();
+        let handle = self.nodes.insert(SkipNode { key, val, next: next_links, prev: preds[0] });
+        for (level, &pred) in preds.iter().enumerate().take(height) {
+            self.set_next(pred, level, Some(handle));
+        }
+        match next.and_then(|next| self.nodes.get_mut(next)) {
+            Some(node) => node.prev = Some(handle),
+            None => self.tail = Some(handle),
+        }
+        (handle, None)
+    }
+
+    /// Unlink a node, given the last nodes before it on each level
+    fn unlink(&mut self, handle: SkipKey, preds: &[Option<SkipKey>]) -> Option<(K, V)> {
+        let node = self.nodes.remove(handle)?;
+        for (level, &next) in node.next.iter().enumerate() {
+            self.set_next(preds.get(level).copied().flatten(), level, next);
+        }
+        match node.next.first().copied().flatten().and_then(|next| self.nodes.get_mut(next)) {
+            Some(next) => next.prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        while self.head.last() == Some(&None) {
+            self.head.pop();
+        }
+        Some((node.key, node.val))
+    }
+
+    /// Remove the entry with a key, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let handle = self.find(key)?;
+        let preds = self.preds(|other| other < key);
+        self.unlink(handle, &preds).map(|(_, val)| val)
+    }
+
+    /// Remove the entry for a handle, returning its key and value
+    pub fn remove_at(&mut self, handle: SkipKey) -> Option<(K, V)> {
+        let key = &self.nodes.get(handle)?.key;
+        let preds = self.preds(|other| other < key);
+        self.unlink(handle, &preds)
+    }
+
+    /// Get the key and value of the entry for a handle
+    pub fn entry(&self, handle: SkipKey) -> Option<(&K, &V)> {
+        self.nodes.get(handle).map(|node| (&node.key, &node.val))
+    }
+
+    /// Get the value of the entry for a handle mutably
+    pub fn value_mut(&mut self, handle: SkipKey) -> Option<&mut V> {
+        self.nodes.get_mut(handle).map(|node| &mut node.val)
+    }
+
+    /// Get the handle of the entry with the smallest key
+    pub fn first(&self) -> Option<SkipKey> {
+        self.next_of(None, 0)
+    }
+
+    /// Get the handle of the entry with the largest key
+    pub fn last(&self) -> Option<SkipKey> {
+        self.tail
+    }
+
+    /// Get the handle of the entry after another
+    pub fn next(&self, handle: SkipKey) -> Option<SkipKey> {
+        self.next_of(Some(handle), 0)
+    }
+
+    /// Get the handle of the entry before another
+    pub fn prev(&self, handle: SkipKey) -> Option<SkipKey> {
+        self.nodes.get(handle)?.prev
+    }
+
+    /// Iterate over the entries from a handle onward
+    fn iter_from(&self, start: Option<SkipKey>) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let mut cur = start;
+        core::iter::from_fn(move || {
+            let node = self.nodes.get(cur?)?;
+            cur = node.next.first().copied().flatten();
+            Some((&node.key, &node.val))
+        })
+    }
+
+    /// Iterate over every entry, in order of their keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.iter_from(self.first())
+    }
+
+    /// Iterate over the entries whose keys are in a range, in order of their keys
+    pub fn range<'a, R: RangeBounds<K> + 'a>(&'a self, range: R) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.seek(|other| other < start),
+            Bound::Excluded(start) => self.seek(|other| other <= start),
+            Bound::Unbounded => self.first(),
+        };
+        self.iter_from(start).take_while(move |(key, _)| match range.end_bound() {
+            Bound::Included(end) => *key <= end,
+            Bound::Excluded(end) => *key < end,
+            Bound::Unbounded => true,
+        })
+    }
+
+    /// Split this list in two, moving every entry with a key at least as large as `key` into a
+    /// new list. Moved entries get new handles in the new list.
+    pub fn split_off(&mut self, key: &K) -> SkipList<K, V> {
+        let preds = self.preds(|other| other < key);
+        let mut moved = Vec::new();
+        let mut cur = self.next_of(preds.first().copied().flatten(), 0);
+        while let Some(handle) = cur {
+            moved.push(handle);
+            cur = self.next(handle);
+        }
+        for (level, &pred) in preds.iter().enumerate() {
+            self.set_next(pred, level, None);
+        }
+        self.tail = preds.first().copied().flatten();
+        while self.head.last() == Some(&None) {
+            self.head.pop();
+        }
+
+        let mut other = SkipList::new();
+        // Xorshift state must stay non-zero
+        other.rng = self.rng.rotate_left(32) | 1;
+        // Last node added to the new list on each level, where `None` is its head
+        let mut lasts = Vec::new();
+        for handle in moved {
+            let Some(node) = self.nodes.remove(handle) else {
+                continue;
+            };
+            let height = node.next.len();
+            let new = other.nodes.insert(SkipNode { key: node.key, val: node.val, next: vec![None; height], prev: other.tail });
+            while other.head.len() < height {
+                other.head.push(None);
+                lasts.push(None);
+            }
+            for (level, last) in lasts.iter_mut().enumerate().take(height) {
+                other.set_next(*last, level, Some(new));
+                *last = Some(new);
+            }
+            other.tail = Some(new);
+        }
+        other
+    }
+
+    /// Remove every entry, invalidating all handles
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.head.clear();
+        self.tail = None;
+    }
+}
+
+impl<K: Ord, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        SkipList::new()
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for SkipList<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SkipList<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut list = SkipList::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug> fmt::Debug for SkipList<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    #[test]
+    fn test_against_btree() {
+        let mut list = SkipList::new();
+        let mut expected = BTreeMap::new();
+        let mut state = 1_u32;
+        for _ in 0..2000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (state >> 16) % 300;
+            if state.is_multiple_of(3) {
+                assert_eq!(list.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(list.insert(key, state).1, expected.insert(key, state));
+            }
+        }
+
+        assert_eq!(list.len(), expected.len());
+        assert!(list.iter().eq(expected.iter()));
+        assert!(list.range(50..=120).eq(expected.range(50..=120)));
+        assert!(list.range((Bound::Excluded(10), Bound::Unbounded)).eq(expected.range(11..)));
+
+        let mut back = Vec::new();
+        let mut cur = list.last();
+        while let Some(handle) = cur {
+            back.push(list.entry(handle).map(|(&key, _)| key));
+            cur = list.prev(handle);
+        }
+        back.reverse();
+        assert!(back.into_iter().eq(expected.keys().map(|&key| Some(key))));
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut list = (0..100).map(|key| (key * 2, key)).collect::<SkipList<_, _>>();
+        let handle = list.find(&40).unwrap();
+        assert_eq!(list.lower_bound(&41).and_then(|h| list.entry(h)), Some((&42, &21)));
+        assert_eq!(list.find(&41), None);
+
+        list.insert(41, 0);
+        list.remove(&38);
+        assert_eq!(list.insert(40, 100), (handle, Some(20)));
+        assert_eq!(list.entry(handle), Some((&40, &100)));
+        assert_eq!(list.next(handle).and_then(|h| list.entry(h)), Some((&41, &0)));
+        assert_eq!(list.prev(handle).and_then(|h| list.entry(h)), Some((&36, &18)));
+
+        *list.value_mut(handle).unwrap() = 7;
+        assert_eq!(list.remove_at(handle), Some((40, 7)));
+        assert_eq!(list.entry(handle), None);
+        assert_eq!(list.get(&40), None);
+        assert_eq!(list.len(), 99);
+    }
+
+    #[test]
+    fn test_split_off() {
+        let mut list = (0..500).map(|key| (key, ())).collect::<SkipList<_, _>>();
+        let mut high = list.split_off(&200);
+        assert_eq!(list.len(), 200);
+        assert_eq!(high.len(), 300);
+        assert!(list.iter().map(|(&key, _)| key).eq(0..200));
+        assert!(high.iter().map(|(&key, _)| key).eq(200..500));
+        assert_eq!(list.last().and_then(|h| list.entry(h)), Some((&199, &())));
+
+        for key in (200..500).step_by(3) {
+            assert_eq!(high.remove(&key), Some(()));
+        }
+        high.insert(1000, ());
+        assert!(high.iter().map(|(&key, _)| key).eq((200..500).filter(|key| key % 3 != 2).chain([1000])));
+        list.insert(-1, ());
+        assert_eq!(list.first().and_then(|h| list.entry(h)), Some((&-1, &())));
+    }
+}
@@ -0,0 +1,268 @@
+//! Merkle trees, hashing a list of leaves into a single root hash, with proofs that a leaf is
+//! included under a root
+
+use alloc::vec::Vec;
+use crate::tree::simple::{Node, Tree, TreeKey};
+
+/// A hash function for building a [`MerkleTree`]
+///
+/// Any function `Fn(&[u8]) -> [u8; N]`, such as a SHA-256 wrapper, is a hasher. Leaves are
+/// hashed with a `0` byte before their data, and pairs with a `1` byte before their hashes, so a
+/// leaf can't be passed off as an internal node.
+pub trait Hasher {
+    /// The hashes produced
+    type Hash: Clone + Eq + AsRef<[u8]>;
+
+    /// Hash the data of a leaf
+    fn hash_leaf(&self, data: &[u8]) -> Self::Hash;
+
+    /// Hash two sibling nodes into their parent
+    fn hash_pair(&self, left: &Self::Hash, right: &Self::Hash) -> Self::Hash;
+}
+
+impl<F, const N: usize> Hasher for F
+where
+    F: Fn(&[u8]) -> [u8; N],
+{
+    type Hash = [u8; N];
+
+    fn hash_leaf(&self, data: &[u8]) -> [u8; N] {
+        let mut buf = Vec::with_capacity(data.len() + 1);
+        buf.push(0);
+        buf.extend_from_slice(data);
+        self(&buf)
+    }
+
+    fn hash_pair(&self, left: &[u8; N], right: &[u8; N]) -> [u8; N] {
+        let mut buf = Vec::with_capacity(2 * N + 1);
+        buf.push(1);
+        buf.extend_from_slice(left);
+        buf.extend_from_slice(right);
+        self(&buf)
+    }
+}
+
+/// Which side of a node its sibling is on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    /// The sibling is the left child of their parent
+    Left,
+    /// The sibling is the right child of their parent
+    Right,
+}
+
+/// A proof that a leaf is included in a [`MerkleTree`], made of the hashes of the siblings
+/// along the path from the leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof<Hs> {
+    index: usize,
+    siblings: Vec<(Side, Hs)>,
+}
+
+impl<Hs> Proof<Hs> {
+    /// Get the index of the leaf this proof is for
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the siblings of the nodes from the leaf up to the root, with the side each is on
+    pub fn siblings(&self) -> &[(Side, Hs)] {
+        &self.siblings
+    }
+
+    /// Check that a leaf with some data hashes up to a root hash through this proof
+    pub fn verify<H: Hasher<Hash = Hs>>(&self, hasher: &H, data: &[u8], root: &Hs) -> bool
+    where
+        Hs: PartialEq,
+    {
+        let hash = self.siblings.iter().fold(hasher.hash_leaf(data), |hash, (side, sibling)| {
+            match side {
+                Side::Left => hasher.hash_pair(sibling, &hash),
+                Side::Right => hasher.hash_pair(&hash, sibling),
+            }
+        });
+        hash == *root
+    }
+}
+
+/// A binary tree of hashes, with a leaf for each piece of data and each internal node hashing
+/// its two children. When a level has an odd number of nodes, the last is moved up to the next
+/// level as it is.
+pub struct MerkleTree<H: Hasher> {
+    hasher: H,
+    tree: Tree<H::Hash>,
+    leaves: Vec<TreeKey>,
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Build a tree over the data of some leaves, in order
+    pub fn new<D: AsRef<[u8]>>(hasher: H, leaves: impl IntoIterator<Item = D>) -> MerkleTree<H> {
+        // Hash every level bottom-up, then build the tree top-down so children stay in order
+        let mut levels = alloc::vec![leaves.into_iter()
+            .map(|data| hasher.hash_leaf(data.as_ref()))
+            .collect::<Vec<_>>()];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let next = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hasher.hash_pair(left, right),
+                    _ => pair[0].clone(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        let mut tree = Tree::new();
+        let mut leaves = alloc::vec![TreeKey::default(); levels[0].len()];
+        let mut stack = Vec::new();
+        if !leaves.is_empty() {
+            stack.push((levels.len() - 1, 0, None));
+        }
+        while let Some((depth, idx, parent)) = stack.pop() {
+            // A node moved up from the level below is the same node, so isn't added twice
+            if depth > 0 && 2 * idx + 1 >= levels[depth - 1].len() {
+                stack.push((depth - 1, 2 * idx, parent));
+                continue;
+            }
+            let hash = levels[depth][idx].clone();
+            let key = match parent {
+                Some(parent) => tree.add_child(hash, parent),
+                None => Some(tree.add_root(hash)),
+            };
+            let Some(key) = key else {
+                continue;
+            };
+            if depth == 0 {
+                leaves[idx] = key;
+            } else {
+                stack.push((depth - 1, 2 * idx + 1, Some(key)));
+                stack.push((depth - 1, 2 * idx, Some(key)));
+            }
+        }
+
+        MerkleTree { hasher, tree, leaves }
+    }
+
+    fn hash(&self, key: TreeKey) -> Option<&H::Hash> {
+        self.tree.raw_nodes().get(key).map(Node::val)
+    }
+
+    /// Get the hasher used by this tree
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the underlying tree of hashes
+    pub fn tree(&self) -> &Tree<H::Hash> {
+        &self.tree
+    }
+
+    /// Get the number of leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Check whether this tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Get the root hash, or `None` if there are no leaves
+    pub fn root(&self) -> Option<&H::Hash> {
+        self.hash(*self.tree.raw_roots().first()?)
+    }
+
+    /// Get the hash of the leaf at an index
+    pub fn leaf(&self, idx: usize) -> Option<&H::Hash> {
+        self.hash(*self.leaves.get(idx)?)
+    }
+
+    /// Replace the data of the leaf at an index, rehashing its ancestors. Returns `None` if the
+    /// index is out of bounds.
+    pub fn set_leaf(&mut self, idx: usize, data: &[u8]) -> Option<()> {
+        let mut key = *self.leaves.get(idx)?;
+        *self.tree.get_mut(key)? = self.hasher.hash_leaf(data);
+        while let Some(parent) = self.tree.parent_key_of(key) {
+            let hash = match *self.tree.child_keys(parent)? {
+                [left, right] => self.hasher.hash_pair(self.hash(left)?, self.hash(right)?),
+                _ => return None,
+            };
+            *self.tree.get_mut(parent)? = hash;
+            key = parent;
+        }
+        Some(())
+    }
+
+    /// Make a proof that the leaf at an index is included under the root. Returns `None` if the
+    /// index is out of bounds.
+    pub fn proof(&self, idx: usize) -> Option<Proof<H::Hash>> {
+        let mut key = *self.leaves.get(idx)?;
+        let mut siblings = Vec::new();
+        while let Some(parent) = self.tree.parent_key_of(key) {
+            let sibling = match *self.tree.child_keys(parent)? {
+                [left, right] if left == key => (Side::Right, right),
+                [left, _] => (Side::Left, left),
+                _ => return None,
+            };
+            siblings.push((sibling.0, self.hash(sibling.1)?.clone()));
+            key = parent;
+        }
+        Some(Proof { index: idx, siblings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::hash::Hasher as _;
+    use super::*;
+    use crate::util::Fnv;
+
+    /// A fast, insecure hash, standing in for a real one
+    fn fnv(data: &[u8]) -> [u8; 8] {
+        let mut hasher = Fnv::default();
+        hasher.write(data);
+        hasher.finish().to_le_bytes()
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let tree = MerkleTree::new(fnv, ["a", "b", "c"]);
+        assert_eq!(tree.len(), 3);
+        let ab = fnv.hash_pair(&fnv.hash_leaf(b"a"), &fnv.hash_leaf(b"b"));
+        let root = fnv.hash_pair(&ab, &fnv.hash_leaf(b"c"));
+        assert_eq!(tree.root(), Some(&root));
+        assert_eq!(tree.leaf(2), Some(&fnv.hash_leaf(b"c")));
+        // Three leaves and two internal nodes, with `c` moved up
+        assert_eq!(tree.tree().len(), 5);
+
+        let single = MerkleTree::new(fnv, ["a"]);
+        assert_eq!(single.root(), Some(&fnv.hash_leaf(b"a")));
+        let empty = MerkleTree::new(fnv, [] as [&str; 0]);
+        assert!(empty.is_empty());
+        assert_eq!(empty.root(), None);
+        assert!(empty.proof(0).is_none());
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let data = ["a", "b", "c", "d", "e", "f", "g"];
+        let mut tree = MerkleTree::new(fnv, data);
+        let root = *tree.root().unwrap();
+        for (idx, leaf) in data.iter().enumerate() {
+            let proof = tree.proof(idx).unwrap();
+            assert_eq!(proof.index(), idx);
+            assert!(proof.verify(&fnv, leaf.as_bytes(), &root));
+            assert!(!proof.verify(&fnv, b"x", &root));
+        }
+        assert_eq!(tree.proof(6).unwrap().siblings().len(), 2);
+        assert_eq!(tree.proof(0).unwrap().siblings().len(), 3);
+        assert!(tree.proof(7).is_none());
+
+        tree.set_leaf(3, b"x").unwrap();
+        let changed = *tree.root().unwrap();
+        assert_ne!(changed, root);
+        assert_eq!(Some(&changed), MerkleTree::new(fnv, ["a", "b", "c", "x", "e", "f", "g"]).root());
+        assert!(tree.proof(3).unwrap().verify(&fnv, b"x", &changed));
+        assert!(!tree.proof(3).unwrap().verify(&fnv, b"x", &root));
+        assert!(tree.set_leaf(7, b"x").is_none());
+    }
+}
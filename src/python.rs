@@ -0,0 +1,231 @@
+//! Python bindings to a [`simple::Tree`](crate::tree::simple::Tree) of Python objects, through
+//! pyo3.
+//!
+//! The tree is exported as the class `Tree`, and nodes as `NodeRef`. A `NodeRef` keeps its tree
+//! alive, and raises `KeyError` once its node has been removed.
+
+use alloc::vec::Vec;
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use crate::tree::simple::{Tree, TreeKey};
+
+fn missing() -> PyErr {
+    PyKeyError::new_err("Node is not in the tree")
+}
+
+/// A tree of Python objects, exported to Python as `Tree`
+#[pyclass(name = "Tree", module = "forest")]
+#[derive(Default)]
+pub struct PyTree {
+    tree: Tree<Py<PyAny>>,
+}
+
+impl PyTree {
+    fn node(slf: &Bound<'_, PyTree>, key: TreeKey) -> PyNodeRef {
+        PyNodeRef { tree: slf.clone().unbind(), key }
+    }
+
+    fn nodes(slf: &Bound<'_, PyTree>, keys: impl IntoIterator<Item = TreeKey>) -> Vec<PyNodeRef> {
+        keys.into_iter().map(|key| PyTree::node(slf, key)).collect()
+    }
+
+    /// Collect the keys of every node in pre-order
+    fn pre_order_keys(&self) -> Vec<TreeKey> {
+        let mut keys = Vec::with_capacity(self.tree.len());
+        let mut stack = self.tree.raw_roots().iter().rev().copied().collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            stack.extend(self.tree.child_keys(key).into_iter().flatten().rev());
+            keys.push(key);
+        }
+        keys
+    }
+}
+
+#[pymethods]
+impl PyTree {
+    /// Create a new, empty tree
+    #[new]
+    pub fn new() -> PyTree {
+        PyTree::default()
+    }
+
+    /// Get the number of nodes in this tree
+    pub fn __len__(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Check whether a node is in this tree
+    pub fn __contains__(slf: &Bound<'_, PyTree>, node: &PyNodeRef) -> PyResult<bool> {
+        Ok(node.tree.is(slf) && slf.try_borrow()?.tree.get(node.key).is_some())
+    }
+
+    /// Add a new root after the existing roots
+    pub fn add_root(slf: &Bound<'_, PyTree>, value: Py<PyAny>) -> PyResult<PyNodeRef> {
+        let key = slf.try_borrow_mut()?.tree.add_root(value);
+        Ok(PyTree::node(slf, key))
+    }
+
+    /// Add a new child after the existing children of a node
+    pub fn add_child(slf: &Bound<'_, PyTree>, parent: &PyNodeRef, value: Py<PyAny>) -> PyResult<PyNodeRef> {
+        if !parent.tree.is(slf) {
+            return Err(missing());
+        }
+        let key = slf.try_borrow_mut()?.tree.add_child(value, parent.key).ok_or_else(missing)?;
+        Ok(PyTree::node(slf, key))
+    }
+
+    /// Remove a node and all its descendants
+    pub fn remove(slf: &Bound<'_, PyTree>, node: &PyNodeRef) -> PyResult<()> {
+        if !node.tree.is(slf) {
+            return Err(missing());
+        }
+        slf.try_borrow_mut()?.tree.remove_recursive(node.key).ok_or_else(missing)
+    }
+
+    /// Get the roots of this tree, in order
+    pub fn roots(slf: &Bound<'_, PyTree>) -> PyResult<Vec<PyNodeRef>> {
+        let keys = slf.try_borrow()?.tree.raw_roots().to_vec();
+        Ok(PyTree::nodes(slf, keys))
+    }
+
+    /// Get every node of this tree in pre-order, parents before their children
+    pub fn pre_order(slf: &Bound<'_, PyTree>) -> PyResult<Vec<PyNodeRef>> {
+        let keys = slf.try_borrow()?.pre_order_keys();
+        Ok(PyTree::nodes(slf, keys))
+    }
+
+    /// Get every node of this tree in post-order, children before their parents
+    pub fn post_order(slf: &Bound<'_, PyTree>) -> PyResult<Vec<PyNodeRef>> {
+        let keys = slf.try_borrow()?.tree.post_order_keys().collect::<Vec<_>>();
+        Ok(PyTree::nodes(slf, keys))
+    }
+
+    /// Remove every node
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+}
+
+/// A reference to a node in a [`PyTree`], exported to Python as `NodeRef`
+#[pyclass(name = "NodeRef", module = "forest", frozen)]
+pub struct PyNodeRef {
+    tree: Py<PyTree>,
+    key: TreeKey,
+}
+
+#[pymethods]
+impl PyNodeRef {
+    /// Get the key of this node, which is unique within its tree
+    #[getter]
+    pub fn key(&self) -> u64 {
+        self.key.to_ffi()
+    }
+
+    /// Get the tree this node belongs to
+    #[getter]
+    pub fn tree(&self, py: Python<'_>) -> Py<PyTree> {
+        self.tree.clone_ref(py)
+    }
+
+    /// Get the value of this node
+    #[getter]
+    pub fn value(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
+        let tree = self.tree.bind(py).try_borrow()?;
+        let node = tree.tree.get(self.key).ok_or_else(missing)?;
+        Ok(node.clone_ref(py))
+    }
+
+    /// Replace the value of this node
+    #[setter]
+    pub fn set_value(&self, py: Python<'_>, value: Py<PyAny>) -> PyResult<()> {
+        let mut tree = self.tree.bind(py).try_borrow_mut()?;
+        let mut node = tree.tree.get_mut(self.key).ok_or_else(missing)?;
+        *node = value;
+        Ok(())
+    }
+
+    /// Get the parent of this node, or `None` if it's a root
+    #[getter]
+    pub fn parent(&self, py: Python<'_>) -> PyResult<Option<PyNodeRef>> {
+        let tree = self.tree.bind(py);
+        let borrow = tree.try_borrow()?;
+        borrow.tree.get(self.key).ok_or_else(missing)?;
+        Ok(borrow.tree.parent_key_of(self.key).map(|key| PyTree::node(tree, key)))
+    }
+
+    /// Get the children of this node, in order
+    #[getter]
+    pub fn children(&self, py: Python<'_>) -> PyResult<Vec<PyNodeRef>> {
+        let tree = self.tree.bind(py);
+        let keys = tree.try_borrow()?.tree.child_keys(self.key).ok_or_else(missing)?.to_vec();
+        Ok(PyTree::nodes(tree, keys))
+    }
+
+    /// Add a new child after the existing children of this node
+    pub fn add_child(&self, py: Python<'_>, value: Py<PyAny>) -> PyResult<PyNodeRef> {
+        PyTree::add_child(self.tree.bind(py), self, value)
+    }
+
+    /// Check whether two references are to the same node
+    pub fn __eq__(&self, other: &PyNodeRef) -> bool {
+        self.tree.is(&other.tree) && self.key == other.key
+    }
+
+    /// Hash this reference by its node's key
+    pub fn __hash__(&self) -> u64 {
+        self.key.to_ffi()
+    }
+}
+
+/// Add the tree classes to a Python module, for use in a `#[pymodule]` function
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyTree>()?;
+    module.add_class::<PyNodeRef>()
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::types::PyDict;
+    use super::*;
+
+    #[test]
+    fn test_python_tree() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "forest").unwrap();
+            register(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("forest", module).unwrap();
+            py.run(
+                cr#"
+tree = forest.Tree()
+root = tree.add_root("root")
+a = root.add_child("a")
+a.add_child("a1")
+b = tree.add_child(root, "b")
+tree.add_root("other")
+b.value = "B"
+
+assert len(tree) == 5
+assert [node.value for node in tree.pre_order()] == ["root", "a", "a1", "B", "other"]
+assert [node.value for node in tree.post_order()] == ["a1", "a", "B", "root", "other"]
+assert [node.value for node in root.children] == ["a", "B"]
+assert a.parent == root and root.parent is None
+assert {root, a} == {tree.roots()[0], root.children[0]}
+
+tree.remove(a)
+assert a not in tree and b in tree and len(tree) == 3
+for op in (lambda: a.value, lambda: a.children, lambda: a.add_child(1), lambda: tree.remove(a)):
+    try:
+        op()
+        assert False
+    except KeyError:
+        pass
+assert root not in forest.Tree()
+"#,
+                Some(&globals),
+                None,
+            ).unwrap();
+        });
+    }
+}
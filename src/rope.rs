@@ -0,0 +1,529 @@
+//! A rope, storing text as a balanced tree of string chunks for cheap edits in the middle of
+//! large texts
+
+use core::fmt;
+use core::ops::Range;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::tree::simple::{Node, Tree, TreeKey};
+
+/// Largest chunk of text stored in a single leaf, in bytes
+const MAX_CHUNK: usize = 1024;
+/// Most children an internal node may have
+const MAX_CHILDREN: usize = 8;
+
+/// Sizes of a piece of text
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Length in bytes
+    pub bytes: usize,
+    /// Length in chars
+    pub chars: usize,
+    /// Number of line breaks
+    pub newlines: usize,
+}
+
+impl Metrics {
+    fn of(text: &str) -> Metrics {
+        Metrics {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            newlines: text.bytes().filter(|&b| b == b'\n').count(),
+        }
+    }
+
+    fn add(self, other: Metrics) -> Metrics {
+        Metrics {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+
+    fn sub(self, other: Metrics) -> Metrics {
+        Metrics {
+            bytes: self.bytes - other.bytes,
+            chars: self.chars - other.chars,
+            newlines: self.newlines - other.newlines,
+        }
+    }
+}
+
+/// A node of the rope. Leaves hold text, while internal nodes only sum the metrics of their
+/// children.
+#[derive(Debug, Default)]
+struct Piece {
+    text: String,
+    metrics: Metrics,
+}
+
+impl Piece {
+    fn leaf(text: String) -> Piece {
+        Piece { metrics: Metrics::of(&text), text }
+    }
+
+    fn internal(metrics: Metrics) -> Piece {
+        Piece { text: String::new(), metrics }
+    }
+}
+
+/// Stand-in for missing nodes, which never occur in a consistent rope
+static EMPTY: Piece = Piece {
+    text: String::new(),
+    metrics: Metrics { bytes: 0, chars: 0, newlines: 0 },
+};
+
+/// Get the byte offset of a char in some text, or its length if the char is one past the end
+fn byte_of(text: &str, char_idx: usize) -> usize {
+    text.char_indices().nth(char_idx).map_or(text.len(), |(idx, _)| idx)
+}
+
+/// A string stored as a B-tree of chunks in a [`Tree`], making inserts, removals and lookups by
+/// char or line logarithmic in the length of the text. Every leaf is at the same depth.
+pub struct Rope {
+    tree: Tree<Piece>,
+    root: TreeKey,
+}
+
+impl Rope {
+    /// Create a new, empty rope
+    pub fn new() -> Rope {
+        let mut tree = Tree::new();
+        let root = tree.add_root(Piece::default());
+        Rope { tree, root }
+    }
+
+    fn children(&self, key: TreeKey) -> &[TreeKey] {
+        self.tree.child_keys(key).unwrap_or(&[])
+    }
+
+    fn piece(&self, key: TreeKey) -> &Piece {
+        self.tree.raw_nodes().get(key).map_or(&EMPTY, Node::val)
+    }
+
+    /// Get the sizes of the whole text
+    pub fn metrics(&self) -> Metrics {
+        self.piece(self.root).metrics
+    }
+
+    /// Get the length of the text in bytes
+    pub fn len_bytes(&self) -> usize {
+        self.metrics().bytes
+    }
+
+    /// Get the length of the text in chars
+    pub fn len_chars(&self) -> usize {
+        self.metrics().chars
+    }
+
+    /// Get the number of lines in the text, which is one more than the number of line breaks
+    pub fn len_lines(&self) -> usize {
+        self.metrics().newlines + 1
+    }
+
+    /// Check whether the text is empty
+    pub fn is_empty(&self) -> bool {
+        self.len_bytes() == 0
+    }
+
+    /// Find the leaf holding a char, and the offset of the char within it. An index at the end of
+    /// a leaf is found in that leaf only if `at_end` is set, so inserts can append to it.
+    fn locate(&self, mut char_idx: usize, at_end: bool) -> (TreeKey, usize) {
+        let mut cur = self.root;
+        loop {
+            let children = self.children(cur);
+            let Some(&last) = children.last() else {
+                return (cur, char_idx);
+            };
+            cur = last;
+            for &child in children {
+                let chars = self.piece(child).metrics.chars;
+                if char_idx < chars || (at_end && char_idx == chars) {
+                    cur = child;
+                    break;
+                }
+                if child != last {
+                    char_idx -= chars;
+                }
+            }
+        }
+    }
+
+    /// Apply a change in metrics to a node and all its ancestors
+    fn adjust(&mut self, key: TreeKey, f: impl Fn(Metrics) -> Metrics) {
+        let mut cur = Some(key);
+        while let Some(key) = cur {
+            if let Some(mut node) = self.tree.get_mut(key) {
+                node.metrics = f(node.metrics);
+            }
+            cur = self.tree.parent_key_of(key);
+        }
+    }
+
+    /// Get the char at an index
+    pub fn char(&self, char_idx: usize) -> Option<char> {
+        if char_idx >= self.len_chars() {
+            return None;
+        }
+        let (leaf, offset) = self.locate(char_idx, false);
+        self.piece(leaf).text.chars().nth(offset)
+    }
+
+    /// Insert text before the char at an index. Returns `None` if the index is past the end of
+    /// the text.
+    pub fn insert(&mut self, char_idx: usize, text: &str) -> Option<()> {
+        if char_idx > self.len_chars() {
+            return None;
+        }
+        if text.is_empty() {
+            return Some(());
+        }
+
+        let (leaf, offset) = self.locate(char_idx, true);
+        let added = Metrics::of(text);
+        {
+            let mut node = self.tree.get_mut(leaf)?;
+            let byte = byte_of(&node.text, offset);
+            node.text.insert_str(byte, text);
+        }
+        self.adjust(leaf, |metrics| metrics.add(added));
+
+        if self.piece(leaf).text.len() > MAX_CHUNK {
+            self.split_leaf(leaf);
+        }
+        Some(())
+    }
+
+    /// Split an oversized leaf into several leaves following it
+    fn split_leaf(&mut self, leaf: TreeKey) {
+        if leaf == self.root {
+            self.grow_root();
+        }
+        let text = match self.tree.get_mut(leaf) {
+            Some(mut node) => core::mem::take(&mut node.text),
+            None => return,
+        };
+
+        let pieces = text.len().div_ceil(MAX_CHUNK);
+        let target = text.len().div_ceil(pieces);
+        let mut chunks = Vec::new();
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            // Rounding down keeps every chunk within the limit, at the cost of a short last one
+            let mut cut = if rest.len() <= MAX_CHUNK { rest.len() } else { target };
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let (chunk, tail) = rest.split_at(cut);
+            chunks.push(chunk);
+            rest = tail;
+        }
+
+        let mut prev = leaf;
+        for (idx, chunk) in chunks.into_iter().enumerate() {
+            let piece = Piece::leaf(String::from(chunk));
+            if idx == 0 {
+                if let Some(mut node) = self.tree.get_mut(leaf) {
+                    *node = piece;
+                }
+            } else if let Some(key) = self.tree.add_sibling_after(prev, piece) {
+                prev = key;
+            }
+        }
+
+        if let Some(parent) = self.tree.parent_key_of(leaf) {
+            self.split_internal(parent);
+        }
+    }
+
+    /// Split internal nodes with too many children, from a node up to the root
+    fn split_internal(&mut self, mut key: TreeKey) {
+        while self.children(key).len() > MAX_CHILDREN {
+            if key == self.root {
+                self.grow_root();
+            }
+
+            let children = self.children(key).to_vec();
+            let groups = children.len().div_ceil(MAX_CHILDREN);
+            let size = children.len().div_ceil(groups);
+            let mut prev = key;
+            for group in children.chunks(size).skip(1) {
+                let metrics = group.iter()
+                    .fold(Metrics::default(), |acc, &child| acc.add(self.piece(child).metrics));
+                let Some(sibling) = self.tree.add_sibling_after(prev, Piece::internal(metrics)) else {
+                    return;
+                };
+                for &child in group {
+                    self.tree.set_child(sibling, child);
+                }
+                if let Some(mut node) = self.tree.get_mut(key) {
+                    node.metrics = node.metrics.sub(metrics);
+                }
+                prev = sibling;
+            }
+
+            match self.tree.parent_key_of(key) {
+                Some(parent) => key = parent,
+                None => return,
+            }
+        }
+    }
+
+    /// Add a new root above the current one
+    fn grow_root(&mut self) {
+        let metrics = self.metrics();
+        let root = self.tree.add_root(Piece::internal(metrics));
+        self.tree.set_child(root, self.root);
+        self.root = root;
+    }
+
+    /// Remove the chars in a range. Returns `None` if the range is reversed or extends past the
+    /// end of the text.
+    pub fn remove(&mut self, range: Range<usize>) -> Option<()> {
+        if range.start > range.end || range.end > self.len_chars() {
+            return None;
+        }
+
+        let mut remaining = range.end - range.start;
+        while remaining > 0 {
+            let (leaf, offset) = self.locate(range.start, false);
+            let removed = {
+                let mut node = self.tree.get_mut(leaf)?;
+                let start = byte_of(&node.text, offset);
+                let end = start + byte_of(&node.text[start..], remaining);
+                let removed = Metrics::of(&node.text[start..end]);
+                node.text.replace_range(start..end, "");
+                removed
+            };
+            self.adjust(leaf, |metrics| metrics.sub(removed));
+            remaining -= removed.chars;
+            if self.piece(leaf).text.is_empty() {
+                self.prune(leaf);
+            }
+        }
+        self.shrink_root();
+        Some(())
+    }
+
+    /// Remove an empty node, and any ancestors left without children
+    fn prune(&mut self, mut key: TreeKey) {
+        while key != self.root && self.children(key).is_empty() {
+            let parent = self.tree.parent_key_of(key);
+            self.tree.remove_recursive(key);
+            match parent {
+                Some(parent) => key = parent,
+                None => return,
+            }
+        }
+    }
+
+    /// Remove roots with only a single child
+    fn shrink_root(&mut self) {
+        while let &[child] = self.children(self.root) {
+            let old = self.root;
+            self.tree.remove_child(old, child);
+            self.tree.remove_recursive(old);
+            self.root = child;
+        }
+    }
+
+    /// Get the index of the line containing a char. An index one past the end of the text is on
+    /// the last line.
+    pub fn char_to_line(&self, mut char_idx: usize) -> Option<usize> {
+        if char_idx > self.len_chars() {
+            return None;
+        }
+        let mut line = 0;
+        let mut cur = self.root;
+        loop {
+            let children = self.children(cur);
+            let Some(&last) = children.last() else {
+                let text = &self.piece(cur).text;
+                return Some(line + text.chars().take(char_idx).filter(|&c| c == '\n').count());
+            };
+            cur = last;
+            for &child in children {
+                let metrics = self.piece(child).metrics;
+                if char_idx < metrics.chars {
+                    cur = child;
+                    break;
+                }
+                if child != last {
+                    char_idx -= metrics.chars;
+                    line += metrics.newlines;
+                }
+            }
+        }
+    }
+
+    /// Get the index of the first char of a line
+    pub fn line_to_char(&self, line: usize) -> Option<usize> {
+        if line >= self.len_lines() {
+            return None;
+        }
+        if line == 0 {
+            return Some(0);
+        }
+
+        // Find the line break ending the previous line
+        let mut remaining = line;
+        let mut chars = 0;
+        let mut cur = self.root;
+        loop {
+            let children = self.children(cur);
+            if children.is_empty() {
+                let (idx, _) = self.piece(cur).text
+                    .chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == '\n')
+                    .nth(remaining - 1)?;
+                return Some(chars + idx + 1);
+            }
+            let mut next = None;
+            for &child in children {
+                let metrics = self.piece(child).metrics;
+                if remaining <= metrics.newlines {
+                    next = Some(child);
+                    break;
+                }
+                remaining -= metrics.newlines;
+                chars += metrics.chars;
+            }
+            cur = next?;
+        }
+    }
+
+    /// Iterate over the chunks of the text, in order
+    pub fn chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        let mut stack = alloc::vec![self.root];
+        core::iter::from_fn(move || loop {
+            let key = stack.pop()?;
+            let children = self.children(key);
+            if children.is_empty() {
+                return Some(self.piece(key).text.as_str());
+            }
+            stack.extend(children.iter().rev());
+        })
+    }
+
+    /// Get the depth of the tree backing this rope, where a single leaf has depth 1
+    pub fn depth(&self) -> usize {
+        let mut depth = 1;
+        let mut cur = self.root;
+        while let Some(&child) = self.children(cur).first() {
+            depth += 1;
+            cur = child;
+        }
+        depth
+    }
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Rope::new()
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Self {
+        let mut rope = Rope::new();
+        let _ = rope.insert(0, text);
+        rope
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Rope")
+            .field(&alloc::string::ToString::to_string(self))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// A small deterministic generator, so failures are reproducible
+    fn lcg(state: &mut u64) -> usize {
+        *state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (*state >> 33) as usize
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut rope = Rope::from("hello world");
+        rope.insert(5, ",").unwrap();
+        rope.insert(12, "!").unwrap();
+        assert_eq!(rope.to_string(), "hello, world!");
+        rope.remove(0..7).unwrap();
+        assert_eq!(rope.to_string(), "world!");
+        assert_eq!(rope.char(5), Some('!'));
+        assert_eq!(rope.char(6), None);
+        assert!(rope.insert(7, "x").is_none());
+        assert!(rope.remove(3..7).is_none());
+        rope.remove(0..6).unwrap();
+        assert!(rope.is_empty());
+    }
+
+    #[test]
+    fn test_split_char_boundary() {
+        let text = String::from("a") + &"\u{e9}".repeat(1023) + "b";
+        let rope = Rope::from(text.as_str());
+        assert!(rope.chunks().all(|chunk| chunk.len() <= MAX_CHUNK));
+        assert_eq!(rope.to_string(), text);
+    }
+
+    #[test]
+    fn test_against_string() {
+        let mut rope = Rope::new();
+        let mut expected = String::new();
+        let mut state = 7;
+        let words = ["a", "line\n", "\u{e9}t\u{e9}", "\u{1F980}\n", "longer text here "];
+
+        for step in 0..1500 {
+            let len = expected.chars().count();
+            if step % 4 == 3 && len > 0 {
+                let start = lcg(&mut state) % len;
+                let end = (start + lcg(&mut state) % 40).min(len);
+                rope.remove(start..end).unwrap();
+                let (a, b) = (byte_of(&expected, start), byte_of(&expected, end));
+                expected.replace_range(a..b, "");
+            } else {
+                let idx = lcg(&mut state) % (len + 1);
+                let word = words[lcg(&mut state) % words.len()].repeat(1 + lcg(&mut state) % 30);
+                rope.insert(idx, &word).unwrap();
+                expected.insert_str(byte_of(&expected, idx), &word);
+            }
+        }
+
+        assert_eq!(rope.to_string(), expected);
+        assert_eq!(rope.metrics(), Metrics::of(&expected));
+        assert!(rope.chunks().all(|chunk| chunk.len() <= MAX_CHUNK));
+        assert!(rope.depth() > 2);
+        assert!(rope.tree.validate().is_ok());
+
+        let breaks = expected.char_indices().filter(|&(_, c)| c == '\n').count();
+        assert_eq!(rope.len_lines(), breaks + 1);
+        for line in [0, 1, breaks / 2, breaks] {
+            let start = rope.line_to_char(line).unwrap();
+            assert_eq!(rope.char_to_line(start), Some(line));
+            if line > 0 {
+                assert_eq!(rope.char(start - 1), Some('\n'));
+            }
+        }
+        assert_eq!(rope.line_to_char(breaks + 1), None);
+        assert_eq!(rope.char_to_line(rope.len_chars()), Some(breaks));
+
+        rope.remove(0..rope.len_chars()).unwrap();
+        assert!(rope.is_empty());
+        assert_eq!(rope.depth(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+//! Spatial indices, for finding items by where they are rather than by key. Region trees index
+//! the user's own [`slotmap`] keys, such as [`object::TreeKey`](crate::tree::object::TreeKey), so
+//! they can sit next to the tree or map holding the items themselves, and a [`Bvh`] can be built
+//! directly over an object tree.
+
+pub mod orthtree;
+pub mod kd;
+pub mod bvh;
+
+pub use bvh::Bvh;
+pub use kd::KdTree;
+pub use orthtree::{Octree, Orthtree, Quadtree};
+
+/// An axis-aligned bounding box in `N` dimensions. A point is a box with equal corners.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb<const N: usize> {
+    /// The lowest corner
+    pub min: [f64; N],
+    /// The highest corner
+    pub max: [f64; N],
+}
+
+impl<const N: usize> Aabb<N> {
+    /// Create a box from its lowest and highest corners
+    pub const fn new(min: [f64; N], max: [f64; N]) -> Aabb<N> {
+        Aabb { min, max }
+    }
+
+    /// Create a box covering a single point
+    pub const fn point(point: [f64; N]) -> Aabb<N> {
+        Aabb { min: point, max: point }
+    }
+
+    /// Get the center of this box
+    pub fn center(&self) -> [f64; N] {
+        core::array::from_fn(|axis| (self.min[axis] + self.max[axis]) / 2.0)
+    }
+
+    /// Check whether another box lies entirely within this one
+    pub fn contains(&self, other: &Aabb<N>) -> bool {
+        (0..N).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis])
+    }
+
+    /// Check whether a point lies within this box
+    pub fn contains_point(&self, point: [f64; N]) -> bool {
+        self.contains(&Aabb::point(point))
+    }
+
+    /// Check whether this box overlaps another, including only touching it
+    pub fn intersects(&self, other: &Aabb<N>) -> bool {
+        (0..N).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    /// Get the squared distance from a point to the closest point of this box, which is zero for
+    /// points inside it
+    pub fn distance_sq(&self, point: [f64; N]) -> f64 {
+        (0..N)
+            .map(|axis| {
+                let below = self.min[axis] - point[axis];
+                let above = point[axis] - self.max[axis];
+                let dist = below.max(above).max(0.0);
+                dist * dist
+            })
+            .sum()
+    }
+
+    /// Get how far along a ray it enters this box, in multiples of `dir`, or `None` if the ray
+    /// misses it. Rays starting inside the box enter it at zero.
+    pub fn ray_entry(&self, origin: [f64; N], dir: [f64; N]) -> Option<f64> {
+        let mut enter = 0.0_f64;
+        let mut exit = f64::INFINITY;
+        for axis in 0..N {
+            if dir[axis] == 0.0 {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[axis] - origin[axis]) / dir[axis];
+            let b = (self.max[axis] - origin[axis]) / dir[axis];
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+        (enter <= exit).then_some(enter)
+    }
+
+    /// Get the smallest box containing both this box and another
+    pub fn union(&self, other: &Aabb<N>) -> Aabb<N> {
+        Aabb {
+            min: core::array::from_fn(|axis| self.min[axis].min(other.min[axis])),
+            max: core::array::from_fn(|axis| self.max[axis].max(other.max[axis])),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Bounding volume hierarchies over the nodes of an object tree
+
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::object::{Error, Tree, TreeKey};
+use super::Aabb;
+
+#[derive(Debug, Copy, Clone)]
+enum Entry {
+    Leaf(TreeKey),
+    Branch(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+struct BvhNode<const N: usize> {
+    bounds: Aabb<N>,
+    parent: Option<usize>,
+    entry: Entry,
+}
+
+/// A binary tree of bounding boxes over the nodes of an object [`Tree`], for finding which
+/// nodes overlap an area or are hit by a ray without checking every one of them.
+///
+/// Boxes are read from the tree's values with a closure. When values change, the hierarchy can
+/// be refit to the new boxes without rebuilding it, which is cheaper but makes queries slower
+/// the further nodes have moved.
+#[derive(Debug, Clone)]
+pub struct Bvh<const N: usize> {
+    /// Parents come before their children, and the root is first
+    nodes: Vec<BvhNode<N>>,
+    leaves: SecondaryMap<TreeKey, usize>,
+}
+
+impl<const N: usize> Bvh<N> {
+    /// Create a new, empty hierarchy
+    pub fn new() -> Bvh<N> {
+        Bvh { nodes: Vec::new(), leaves: SecondaryMap::new() }
+    }
+
+    /// Build a hierarchy over every node of a tree, getting the box of each node from its value.
+    /// Nodes which are reserved but not yet filled are left out.
+    ///
+    /// Fails if any node can't be borrowed.
+    pub fn build<T: ?Sized>(tree: &Tree<T>, mut f: impl FnMut(&T) -> Aabb<N>) -> Result<Bvh<N>, Error> {
+        let mut items = Vec::new();
+        for key in tree.unordered_keys() {
+            match tree.try_get(key) {
+                Ok(node) => items.push((key, f(&node))),
+                Err(Error::Unfilled) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut bvh = Bvh::new();
+        bvh.build_from(&mut items, None);
+        Ok(bvh)
+    }
+
+    /// Build a subtree from some items, splitting them at the median of their longest axis
+    fn build_from(&mut self, items: &mut [(TreeKey, Aabb<N>)], parent: Option<usize>) -> Option<usize> {
+        let idx = self.nodes.len();
+        match items {
+            [] => return None,
+            [(key, bounds)] => {
+                self.nodes.push(BvhNode { bounds: *bounds, parent, entry: Entry::Leaf(*key) });
+                self.leaves.insert(*key, idx);
+                return Some(idx);
+            }
+            _ => (),
+        }
+
+        let centers = items.iter()
+            .map(|(_, bounds)| Aabb::point(bounds.center()))
+            .reduce(|a, b| a.union(&b))?;
+        let axis = (0..N)
+            .max_by(|&a, &b| {
+                let extent = |axis: usize| centers.max[axis] - centers.min[axis];
+                extent(a).total_cmp(&extent(b))
+            })
+            .unwrap_or(0);
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |(_, a), (_, b)| a.center()[axis].total_cmp(&b.center()[axis]));
+
+        let bounds = items.iter().map(|(_, bounds)| *bounds).reduce(|a, b| a.union(&b))?;
+        self.nodes.push(BvhNode { bounds, parent, entry: Entry::Branch(0, 0) });
+        let (low, high) = items.split_at_mut(mid);
+        let left = self.build_from(low, Some(idx))?;
+        let right = self.build_from(high, Some(idx))?;
+        self.nodes[idx].entry = Entry::Branch(left, right);
+        Some(idx)
+    }
+
+    /// Get the number of tree nodes in this hierarchy
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Check whether this hierarchy has no tree nodes
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Check whether a tree node is in this hierarchy
+    pub fn contains(&self, key: TreeKey) -> bool {
+        self.leaves.contains_key(key)
+    }
+
+    /// Get the box of a tree node, as of the last build or refit
+    pub fn get(&self, key: TreeKey) -> Option<&Aabb<N>> {
+        self.leaves.get(key).map(|&idx| &self.nodes[idx].bounds)
+    }
+
+    /// Get the box containing every tree node, or `None` if there are none
+    pub fn bounds(&self) -> Option<Aabb<N>> {
+        self.nodes.first().map(|node| node.bounds)
+    }
+
+    fn union_of_children(&self, idx: usize) -> Option<Aabb<N>> {
+        match self.nodes[idx].entry {
+            Entry::Leaf(_) => None,
+            Entry::Branch(left, right) => Some(self.nodes[left].bounds.union(&self.nodes[right].bounds)),
+        }
+    }
+
+    /// Refit every box in this hierarchy to the current values of the tree
+    ///
+    /// Fails if any node in the hierarchy no longer exists or can't be borrowed. Nodes added to
+    /// the tree since building aren't included until the next build.
+    pub fn refit<T: ?Sized>(&mut self, tree: &Tree<T>, mut f: impl FnMut(&T) -> Aabb<N>) -> Result<(), Error> {
+        for idx in (0..self.nodes.len()).rev() {
+            let bounds = match self.nodes[idx].entry {
+                Entry::Leaf(key) => f(&*tree.try_get(key)?),
+                Entry::Branch(..) => self.union_of_children(idx).ok_or(Error::Missing)?,
+            };
+            self.nodes[idx].bounds = bounds;
+        }
+        Ok(())
+    }
+
+    /// Refit the boxes of some tree nodes which moved, and the boxes containing them
+    ///
+    /// Fails with [`Error::Missing`] if a node isn't in the hierarchy or no longer exists, or if
+    /// it can't be borrowed. Nodes before the failing one are still refit.
+    pub fn refit_nodes<T: ?Sized>(
+        &mut self,
+        tree: &Tree<T>,
+        keys: impl IntoIterator<Item = TreeKey>,
+        mut f: impl FnMut(&T) -> Aabb<N>,
+    ) -> Result<(), Error> {
+        for key in keys {
+            let bounds = f(&*tree.try_get(key)?);
+            self.set_bounds(key, bounds).ok_or(Error::Missing)?;
+        }
+        Ok(())
+    }
+
+    /// Set the box of a tree node directly, refitting the boxes containing it. Returns the old
+    /// box, or `None` if the node isn't in this hierarchy.
+    pub fn set_bounds(&mut self, key: TreeKey, bounds: Aabb<N>) -> Option<Aabb<N>> {
+        let idx = *self.leaves.get(key)?;
+        let old = core::mem::replace(&mut self.nodes[idx].bounds, bounds);
+        let mut cur = self.nodes[idx].parent;
+        while let Some(idx) = cur {
+            self.nodes[idx].bounds = self.union_of_children(idx)?;
+            cur = self.nodes[idx].parent;
+        }
+        Some(old)
+    }
+
+    /// Visit the leaves whose boxes pass a test, skipping any branch whose box fails it
+    fn search(&self, mut hit: impl FnMut(&Aabb<N>) -> bool, mut f: impl FnMut(TreeKey, &Aabb<N>)) {
+        let mut stack = Vec::from_iter((!self.nodes.is_empty()).then_some(0));
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !hit(&node.bounds) {
+                continue;
+            }
+            match node.entry {
+                Entry::Leaf(key) => f(key, &node.bounds),
+                Entry::Branch(left, right) => stack.extend([right, left]),
+            }
+        }
+    }
+
+    /// Get every tree node whose box intersects an area
+    pub fn query(&self, area: &Aabb<N>) -> Vec<TreeKey> {
+        let mut found = Vec::new();
+        self.search(|bounds| bounds.intersects(area), |key, _| found.push(key));
+        found
+    }
+
+    /// Get every tree node whose box is hit by a ray, with the distance along the ray to where
+    /// it enters the box, nearest first. The distance is in multiples of `dir`.
+    pub fn raycast(&self, origin: [f64; N], dir: [f64; N]) -> Vec<(TreeKey, f64)> {
+        let mut found = Vec::new();
+        self.search(
+            |bounds| bounds.ray_entry(origin, dir).is_some(),
+            |key, bounds| found.extend(bounds.ray_entry(origin, dir).map(|dist| (key, dist))),
+        );
+        found.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        found
+    }
+}
+
+impl<const N: usize> Default for Bvh<N> {
+    fn default() -> Self {
+        Bvh::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(x: f64) -> Aabb<3> {
+        Aabb::new([x, 0.0, 0.0], [x + 1.0, 1.0, 1.0])
+    }
+
+    #[test]
+    fn test_build_query() {
+        let tree = Tree::new();
+        let root = tree.add_root(0.0);
+        let keys = (1..50)
+            .map(|x| tree.add_child(x as f64 * 2.0, root).unwrap())
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&tree, |&x| cube(x)).unwrap();
+        assert_eq!(bvh.len(), 50);
+        assert_eq!(bvh.bounds(), Some(Aabb::new([0.0; 3], [99.0, 1.0, 1.0])));
+
+        let mut found = bvh.query(&Aabb::new([9.5, 0.5, 0.5], [14.5, 0.5, 0.5]));
+        found.sort();
+        let mut expected = alloc::vec![keys[4], keys[5], keys[6]];
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let hits = bvh.raycast([-5.0, 0.5, 0.5], [1.0, 0.0, 0.0]);
+        assert_eq!(hits.len(), 50);
+        assert_eq!(hits[0], (root, 5.0));
+        assert_eq!(hits[1], (keys[0], 7.0));
+        assert!(bvh.raycast([-5.0, 2.0, 0.5], [1.0, 0.0, 0.0]).is_empty());
+        assert_eq!(bvh.raycast([50.5, 5.0, 0.5], [0.0, -1.0, 0.0]), [(keys[24], 4.0)]);
+    }
+
+    #[test]
+    fn test_refit() {
+        let tree = Tree::new();
+        let root = tree.add_root(0.0);
+        let child = tree.add_child(10.0, root).unwrap();
+        let mut bvh = Bvh::build(&tree, |&x| cube(x)).unwrap();
+
+        *tree.try_get_mut(child).unwrap() = 30.0;
+        assert_eq!(bvh.query(&cube(30.0)), []);
+        bvh.refit_nodes(&tree, [child], |&x| cube(x)).unwrap();
+        assert_eq!(bvh.query(&cube(30.0)), [child]);
+        assert_eq!(bvh.bounds(), Some(cube(0.0).union(&cube(30.0))));
+
+        *tree.try_get_mut(root).unwrap() = -10.0;
+        bvh.refit(&tree, |&x| cube(x)).unwrap();
+        assert_eq!(bvh.get(root), Some(&cube(-10.0)));
+        assert_eq!(bvh.bounds(), Some(cube(-10.0).union(&cube(30.0))));
+
+        let borrow = tree.try_get_mut(root).unwrap();
+        assert!(matches!(bvh.refit(&tree, |&x| cube(x)), Err(Error::CantBorrow)));
+        drop(borrow);
+        tree.remove_child(root, child);
+        tree.remove_recursive(child);
+        assert!(matches!(bvh.refit_nodes(&tree, [child], |&x| cube(x)), Err(Error::Missing)));
+    }
+}
@@ -0,0 +1,246 @@
+//! K-d trees, for nearest neighbor queries over points in any number of dimensions
+
+use alloc::vec::Vec;
+
+struct KdNode<const D: usize, T> {
+    point: [f64; D],
+    val: T,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// A tree of points in `D` dimensions, each with a value. Each level splits the points on the
+/// next axis in turn.
+///
+/// Points can be inserted after building, which leaves the tree less balanced. Once enough
+/// points have been inserted, the tree is rebuilt to keep queries fast.
+pub struct KdTree<const D: usize, T> {
+    nodes: Vec<KdNode<D, T>>,
+    root: Option<usize>,
+    /// Number of points inserted since the last rebuild
+    inserted: usize,
+}
+
+fn dist_sq<const D: usize>(a: &[f64; D], b: &[f64; D]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+impl<const D: usize, T> KdTree<D, T> {
+    const VALID: () = assert!(D >= 1, "KdTree must have at least one dimension");
+
+    /// Create a new, empty tree
+    pub fn new() -> KdTree<D, T> {
+        let () = Self::VALID;
+        KdTree { nodes: Vec::new(), root: None, inserted: 0 }
+    }
+
+    /// Build a balanced tree from a slice of points and their values
+    pub fn build(points: &[([f64; D], T)]) -> KdTree<D, T>
+    where
+        T: Clone,
+    {
+        points.iter().cloned().collect()
+    }
+
+    /// Get the number of points in this tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this tree has no points
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Iterate over every point and its value, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&[f64; D], &T)> + '_ {
+        self.nodes.iter().map(|node| (&node.point, &node.val))
+    }
+
+    /// Add a point to this tree, rebuilding it if many points were added since it was last
+    /// balanced
+    pub fn insert(&mut self, point: [f64; D], val: T) {
+        let new = self.nodes.len();
+        self.nodes.push(KdNode { point, val, left: None, right: None });
+        self.inserted += 1;
+        if self.inserted > new / 2 {
+            self.rebuild();
+            return;
+        }
+
+        let mut cur = self.root;
+        let mut depth = 0;
+        while let Some(idx) = cur {
+            let axis = depth % D;
+            let node = &mut self.nodes[idx];
+            let next = if point[axis] < node.point[axis] { &mut node.left } else { &mut node.right };
+            if next.is_none() {
+                *next = Some(new);
+                return;
+            }
+            cur = *next;
+            depth += 1;
+        }
+        self.root = Some(new);
+    }
+
+    /// Rebalance this tree, making queries as fast as after a fresh build
+    pub fn rebuild(&mut self) {
+        let mut order = (0..self.nodes.len()).collect::<Vec<_>>();
+        self.root = Self::link(&mut self.nodes, &mut order, 0);
+        self.inserted = 0;
+    }
+
+    /// Link some nodes into a balanced subtree, returning its root
+    fn link(nodes: &mut [KdNode<D, T>], order: &mut [usize], depth: usize) -> Option<usize> {
+        if order.is_empty() {
+            return None;
+        }
+        let axis = depth % D;
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| nodes[a].point[axis].total_cmp(&nodes[b].point[axis]));
+
+        let (below, above) = order.split_at_mut(mid);
+        let idx = above[0];
+        let left = Self::link(nodes, below, depth + 1);
+        let right = Self::link(nodes, &mut above[1..], depth + 1);
+        nodes[idx].left = left;
+        nodes[idx].right = right;
+        Some(idx)
+    }
+
+    /// Find the point closest to another, with its value
+    pub fn nearest(&self, point: [f64; D]) -> Option<(&[f64; D], &T)> {
+        self.k_nearest(point, 1).into_iter().next().map(|(point, val, _)| (point, val))
+    }
+
+    /// Find up to `k` points closest to another, closest first, with their values and squared
+    /// distances from the point
+    pub fn k_nearest(&self, point: [f64; D], k: usize) -> Vec<(&[f64; D], &T, f64)> {
+        let mut found = Vec::with_capacity(k.min(self.len()) + 1);
+        if k > 0 {
+            self.search_nearest(self.root, 0, &point, k, &mut found);
+        }
+        found.into_iter()
+            .map(|(dist, idx)| {
+                let node = &self.nodes[idx];
+                (&node.point, &node.val, dist)
+            })
+            .collect()
+    }
+
+    /// Search for the `k` nearest points, keeping `found` sorted by distance
+    fn search_nearest(&self, cur: Option<usize>, depth: usize, point: &[f64; D], k: usize, found: &mut Vec<(f64, usize)>) {
+        let Some(idx) = cur else {
+            return;
+        };
+        let node = &self.nodes[idx];
+        let dist = dist_sq(point, &node.point);
+        if found.len() < k || found.last().is_some_and(|&(worst, _)| dist < worst) {
+            let pos = found.partition_point(|&(other, _)| other <= dist);
+            found.insert(pos, (dist, idx));
+            found.truncate(k);
+        }
+
+        let axis = depth % D;
+        let diff = point[axis] - node.point[axis];
+        let (near, far) = if diff < 0.0 { (node.left, node.right) } else { (node.right, node.left) };
+        self.search_nearest(near, depth + 1, point, k, found);
+        if found.len() < k || found.last().is_some_and(|&(worst, _)| diff * diff <= worst) {
+            self.search_nearest(far, depth + 1, point, k, found);
+        }
+    }
+
+    /// Find every point within a distance of another, with their values, in no particular order
+    pub fn within_radius(&self, point: [f64; D], radius: f64) -> Vec<(&[f64; D], &T)> {
+        let mut found = Vec::new();
+        let mut stack = Vec::from_iter(self.root.map(|root| (root, 0)));
+        let limit = radius * radius;
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx];
+            if dist_sq(&point, &node.point) <= limit {
+                found.push((&node.point, &node.val));
+            }
+
+            let diff = point[depth % D] - node.point[depth % D];
+            if let Some(left) = node.left.filter(|_| diff <= radius) {
+                stack.push((left, depth + 1));
+            }
+            if let Some(right) = node.right.filter(|_| diff >= -radius) {
+                stack.push((right, depth + 1));
+            }
+        }
+        found
+    }
+}
+
+impl<const D: usize, T> Default for KdTree<D, T> {
+    fn default() -> Self {
+        KdTree::new()
+    }
+}
+
+impl<const D: usize, T> Extend<([f64; D], T)> for KdTree<D, T> {
+    fn extend<I: IntoIterator<Item = ([f64; D], T)>>(&mut self, iter: I) {
+        self.nodes.extend(iter.into_iter().map(|(point, val)| KdNode { point, val, left: None, right: None }));
+        self.rebuild();
+    }
+}
+
+impl<const D: usize, T> FromIterator<([f64; D], T)> for KdTree<D, T> {
+    fn from_iter<I: IntoIterator<Item = ([f64; D], T)>>(iter: I) -> Self {
+        let mut tree = KdTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Vec<([f64; 3], usize)> {
+        (0..1000)
+            .map(|idx| ([(idx % 10) as f64, (idx / 10 % 10) as f64, (idx / 100) as f64], idx))
+            .collect()
+    }
+
+    #[test]
+    fn test_queries() {
+        let points = grid();
+        let tree = KdTree::build(&points);
+        assert_eq!(tree.len(), 1000);
+
+        assert_eq!(tree.nearest([3.2, 4.4, 5.9]).map(|(_, &val)| val), Some(643));
+        let near = tree.k_nearest([0.0, 0.0, 0.0], 4);
+        assert_eq!(near[0].1, &0);
+        assert!(near[1..].iter().all(|&(_, _, dist)| dist == 1.0));
+        assert_eq!(tree.k_nearest([0.0; 3], 2000).len(), 1000);
+        assert!(tree.k_nearest([0.0; 3], 0).is_empty());
+
+        let mut within = tree.within_radius([5.0, 5.0, 5.0], 1.0)
+            .into_iter()
+            .map(|(_, &val)| val)
+            .collect::<Vec<_>>();
+        within.sort_unstable();
+        assert_eq!(within, [455, 545, 554, 555, 556, 565, 655]);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut tree = KdTree::new();
+        for (point, val) in grid().into_iter().rev() {
+            tree.insert(point, val);
+        }
+        assert_eq!(tree.len(), 1000);
+        for (point, val) in grid().into_iter().step_by(37) {
+            assert_eq!(tree.nearest(point), Some((&point, &val)));
+            assert_eq!(tree.within_radius(point, 0.5).len(), 1);
+        }
+
+        let mut empty = KdTree::<2, ()>::new();
+        assert_eq!(empty.nearest([0.0, 0.0]), None);
+        empty.insert([1.0, 1.0], ());
+        assert_eq!(empty.nearest([0.0, 0.0]), Some((&[1.0, 1.0], &())));
+    }
+}
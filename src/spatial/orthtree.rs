@@ -0,0 +1,381 @@
+//! Quadtrees and octrees, recursively splitting space into equal regions
+
+use alloc::vec::Vec;
+use slotmap::{Key, SecondaryMap};
+use crate::tree::simple::{Node, Tree, TreeKey};
+use super::Aabb;
+
+/// Most items a region holds before it's split
+const MAX_ITEMS: usize = 8;
+/// Deepest a region may be split, so many items in one spot can't split forever
+const MAX_DEPTH: usize = 16;
+
+/// A region of space, holding the items that fit in it but in none of its children
+struct Region<K, const N: usize> {
+    bounds: Aabb<N>,
+    items: Vec<K>,
+}
+
+/// A tree splitting space into `2^N` equal regions at each level, indexing items by their
+/// bounding boxes. Items are found by their user-provided keys.
+///
+/// Items which straddle a split are kept in the region above it, and items outside the bounds of
+/// the whole tree are kept at the root, so any box can be inserted.
+pub struct Orthtree<K: Key, const N: usize> {
+    tree: Tree<Region<K, N>>,
+    root: TreeKey,
+    items: SecondaryMap<K, (Aabb<N>, TreeKey)>,
+}
+
+/// A 2D [`Orthtree`]
+pub type Quadtree<K> = Orthtree<K, 2>;
+/// A 3D [`Orthtree`]
+pub type Octree<K> = Orthtree<K, 3>;
+
+impl<K: Key, const N: usize> Orthtree<K, N> {
+    /// Create a new, empty tree covering some bounds
+    pub fn new(bounds: Aabb<N>) -> Orthtree<K, N> {
+        let mut tree = Tree::new();
+        let root = tree.add_root(Region { bounds, items: Vec::new() });
+        Orthtree { tree, root, items: SecondaryMap::new() }
+    }
+
+    fn region(&self, key: TreeKey) -> Option<&Region<K, N>> {
+        self.tree.raw_nodes().get(key).map(Node::val)
+    }
+
+    fn children(&self, key: TreeKey) -> &[TreeKey] {
+        self.tree.child_keys(key).unwrap_or(&[])
+    }
+
+    /// Get the bounds covered by this tree
+    pub fn bounds(&self) -> Aabb<N> {
+        self.region(self.root).map_or(Aabb::new([0.0; N], [0.0; N]), |region| region.bounds)
+    }
+
+    /// Get the number of items in this tree
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Check whether this tree has no items
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Check whether an item is in this tree
+    pub fn contains(&self, key: K) -> bool {
+        self.items.contains_key(key)
+    }
+
+    /// Get the bounding box of an item
+    pub fn get(&self, key: K) -> Option<&Aabb<N>> {
+        self.items.get(key).map(|(bounds, _)| bounds)
+    }
+
+    /// Get the index of the child of a region which entirely contains a box, if any
+    fn child_for(region: &Aabb<N>, bounds: &Aabb<N>) -> Option<usize> {
+        let center = region.center();
+        let mut idx = 0;
+        for (axis, &mid) in center.iter().enumerate() {
+            if bounds.min[axis] >= mid {
+                idx |= 1 << axis;
+            } else if bounds.max[axis] > mid {
+                return None;
+            }
+        }
+        Some(idx)
+    }
+
+    /// Get the bounds of a child of a region
+    fn child_bounds(region: &Aabb<N>, idx: usize) -> Aabb<N> {
+        let center = region.center();
+        let mut bounds = *region;
+        for (axis, mid) in center.into_iter().enumerate() {
+            if idx & (1 << axis) != 0 {
+                bounds.min[axis] = mid;
+            } else {
+                bounds.max[axis] = mid;
+            }
+        }
+        bounds
+    }
+
+    /// Find the deepest existing region which should hold a box
+    fn find_region(&self, bounds: &Aabb<N>) -> (TreeKey, usize) {
+        let mut cur = self.root;
+        let mut depth = 0;
+        while let Some(region) = self.region(cur) {
+            let children = self.children(cur);
+            let next = if children.is_empty() || !region.bounds.contains(bounds) {
+                None
+            } else {
+                Self::child_for(&region.bounds, bounds).and_then(|idx| children.get(idx).copied())
+            };
+            match next {
+                Some(child) => {
+                    cur = child;
+                    depth += 1;
+                }
+                None => break,
+            }
+        }
+        (cur, depth)
+    }
+
+    /// Add an item to this tree, or move it if it's already present. Returns the item's old
+    /// bounding box, if it had one.
+    pub fn insert(&mut self, key: K, bounds: Aabb<N>) -> Option<Aabb<N>> {
+        let old = self.remove(key);
+        let (region, depth) = self.find_region(&bounds);
+        if let Some(mut node) = self.tree.get_mut(region) {
+            node.items.push(key);
+        }
+        self.items.insert(key, (bounds, region));
+        if self.region(region).is_some_and(|r| r.items.len() > MAX_ITEMS) && depth < MAX_DEPTH {
+            self.split(region);
+        }
+        old
+    }
+
+    /// Split a leaf region into children, moving down the items which fit in one
+    fn split(&mut self, key: TreeKey) {
+        let Some(region) = self.region(key) else {
+            return;
+        };
+        if !self.children(key).is_empty() {
+            return;
+        }
+        let bounds = region.bounds;
+
+        let children = (0..1 << N)
+            .filter_map(|idx| {
+                let region = Region { bounds: Self::child_bounds(&bounds, idx), items: Vec::new() };
+                self.tree.add_child(region, key)
+            })
+            .collect::<Vec<_>>();
+
+        let items = match self.tree.get_mut(key) {
+            Some(mut node) => core::mem::take(&mut node.items),
+            None => return,
+        };
+        let mut kept = Vec::new();
+        for item in items {
+            let target = self.items.get(item)
+                .filter(|(item_bounds, _)| bounds.contains(item_bounds))
+                .and_then(|(item_bounds, _)| Self::child_for(&bounds, item_bounds))
+                .and_then(|idx| children.get(idx).copied());
+            match target {
+                Some(child) => {
+                    if let Some(mut node) = self.tree.get_mut(child) {
+                        node.items.push(item);
+                    }
+                    if let Some((_, region)) = self.items.get_mut(item) {
+                        *region = child;
+                    }
+                }
+                None => kept.push(item),
+            }
+        }
+        if let Some(mut node) = self.tree.get_mut(key) {
+            node.items = kept;
+        }
+    }
+
+    /// Remove an item from this tree, returning its bounding box
+    pub fn remove(&mut self, key: K) -> Option<Aabb<N>> {
+        let (bounds, region) = self.items.remove(key)?;
+        if let Some(mut node) = self.tree.get_mut(region) {
+            node.items.retain(|&item| item != key);
+        }
+
+        let mut cur = if self.children(region).is_empty() {
+            self.tree.parent_key_of(region)
+        } else {
+            Some(region)
+        };
+        while let Some(key) = cur {
+            if !self.collapse(key) {
+                break;
+            }
+            cur = self.tree.parent_key_of(key);
+        }
+        Some(bounds)
+    }
+
+    /// Merge the children of a region back into it, if they're all leaves and few enough items
+    /// remain. Returns whether the region was merged.
+    fn collapse(&mut self, key: TreeKey) -> bool {
+        let children = self.children(key).to_vec();
+        let total = children.iter()
+            .chain([&key])
+            .filter_map(|&child| self.region(child))
+            .map(|region| region.items.len())
+            .sum::<usize>();
+        if children.is_empty() || total > MAX_ITEMS || children.iter().any(|&child| !self.children(child).is_empty()) {
+            return false;
+        }
+
+        for child in children {
+            let items = match self.tree.get_mut(child) {
+                Some(mut node) => core::mem::take(&mut node.items),
+                None => continue,
+            };
+            for &item in &items {
+                if let Some((_, region)) = self.items.get_mut(item) {
+                    *region = key;
+                }
+            }
+            if let Some(mut node) = self.tree.get_mut(key) {
+                node.items.extend(items);
+            }
+            let _ = self.tree.remove_recursive(child);
+        }
+        true
+    }
+
+    /// Remove every item from this tree
+    pub fn clear(&mut self) {
+        let bounds = self.bounds();
+        *self = Orthtree::new(bounds);
+    }
+
+    /// Iterate over every item whose bounding box intersects an area
+    pub fn query<'a>(&'a self, area: &'a Aabb<N>) -> impl Iterator<Item = (K, &'a Aabb<N>)> + 'a {
+        let mut stack = Vec::from([self.root]);
+        let mut items: &[K] = &[];
+        core::iter::from_fn(move || loop {
+            while let Some((&item, rest)) = items.split_first() {
+                items = rest;
+                match self.items.get(item) {
+                    Some((bounds, _)) if bounds.intersects(area) => return Some((item, bounds)),
+                    _ => (),
+                }
+            }
+
+            let key = stack.pop()?;
+            if let Some(region) = self.region(key) {
+                items = &region.items;
+                stack.extend(
+                    self.children(key)
+                        .iter()
+                        .filter(|&&child| self.region(child).is_some_and(|child| child.bounds.intersects(area))),
+                );
+            }
+        })
+    }
+
+    /// Find the item closest to a point, along with its squared distance from the point. Items
+    /// containing the point have a distance of zero.
+    pub fn nearest(&self, point: [f64; N]) -> Option<(K, f64)> {
+        let mut best: Option<(K, f64)> = None;
+        let mut stack = Vec::from([self.root]);
+        while let Some(key) = stack.pop() {
+            let Some(region) = self.region(key) else {
+                continue;
+            };
+            let limit = best.map_or(f64::INFINITY, |(_, dist)| dist);
+            if key != self.root && region.bounds.distance_sq(point) > limit {
+                continue;
+            }
+
+            for &item in &region.items {
+                let Some((bounds, _)) = self.items.get(item) else {
+                    continue;
+                };
+                let dist = bounds.distance_sq(point);
+                if best.is_none_or(|(_, best)| dist < best) {
+                    best = Some((item, dist));
+                }
+            }
+
+            // Visit the closest child first, so later ones are more likely to be pruned
+            let mut children = self.children(key).to_vec();
+            children.sort_by(|&a, &b| {
+                let dist = |key| self.region(key).map_or(f64::INFINITY, |r| r.bounds.distance_sq(point));
+                dist(b).total_cmp(&dist(a))
+            });
+            stack.extend(children);
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::{DefaultKey, SlotMap};
+
+    fn brute_nearest(items: &SlotMap<DefaultKey, Aabb<2>>, point: [f64; 2]) -> f64 {
+        items.values().map(|bounds| bounds.distance_sq(point)).fold(f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn test_quadtree() {
+        let mut items = SlotMap::new();
+        let mut tree = Quadtree::new(Aabb::new([0.0, 0.0], [100.0, 100.0]));
+        for x in 0..20 {
+            for y in 0..20 {
+                let bounds = Aabb::new([x as f64 * 5.0, y as f64 * 5.0], [x as f64 * 5.0 + 1.0, y as f64 * 5.0 + 1.0]);
+                let key = items.insert(bounds);
+                tree.insert(key, bounds);
+            }
+        }
+        let outside = items.insert(Aabb::point([150.0, 150.0]));
+        tree.insert(outside, items[outside]);
+        assert_eq!(tree.len(), 401);
+        assert!(tree.tree.len() > 1);
+
+        let area = Aabb::new([12.0, 12.0], [31.0, 21.0]);
+        let mut found = tree.query(&area).map(|(key, _)| key).collect::<Vec<_>>();
+        let mut expected = items.iter().filter(|(_, b)| b.intersects(&area)).map(|(k, _)| k).collect::<Vec<_>>();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(found.len(), 8);
+
+        for point in [[2.5, 2.5], [52.0, 48.0], [-10.0, 40.0], [140.0, 140.0]] {
+            let (key, dist) = tree.nearest(point).unwrap();
+            assert_eq!(dist, brute_nearest(&items, point));
+            assert_eq!(items[key].distance_sq(point), dist);
+        }
+        assert_eq!(tree.nearest([3.0, 3.0]).map(|(_, dist)| dist), Some(8.0));
+
+        let moved = items.keys().next().unwrap();
+        assert_eq!(tree.insert(moved, Aabb::point([99.0, 1.0])), Some(items[moved]));
+        assert_eq!(tree.len(), 401);
+        assert_eq!(tree.nearest([99.0, 0.0]), Some((moved, 1.0)));
+
+        for key in items.keys() {
+            assert!(tree.remove(key).is_some());
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.tree.len(), 1);
+        assert_eq!(tree.nearest([0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn test_octree() {
+        let mut items = SlotMap::<DefaultKey, ()>::new();
+        let mut tree = Octree::new(Aabb::new([-1.0; 3], [1.0; 3]));
+        let keys = (0..100)
+            .map(|idx| {
+                let key = items.insert(());
+                let pos = idx as f64 / 100.0;
+                tree.insert(key, Aabb::point([pos, -pos, pos / 2.0]));
+                key
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(tree.nearest([0.502, -0.502, 0.251]).map(|(key, _)| key), Some(keys[50]));
+        let area = Aabb::new([0.1, -0.2, 0.0], [0.2, 0.0, 1.0]);
+        assert_eq!(tree.query(&area).count(), 11);
+        assert_eq!(tree.remove(keys[15]), Some(Aabb::point([0.15, -0.15, 0.075])));
+        assert_eq!(tree.query(&area).count(), 10);
+        assert!(!tree.contains(keys[15]));
+
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.bounds(), Aabb::new([-1.0; 3], [1.0; 3]));
+    }
+}
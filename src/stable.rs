@@ -4,5 +4,13 @@
 //! This allows for mutable references to the contained data, unlike an `Rc`.
 
 mod util;
+mod sync;
+mod error;
+mod id;
+pub mod leak;
+pub mod allocator;
 pub mod cell;
 pub mod lock;
+
+pub use error::BorrowError;
+pub use id::CellId;
@@ -0,0 +1,65 @@
+//! The allocators stable cells and locks may be created in. With the `unstable` feature these are
+//! the standard [`Allocator`] and [`Global`], otherwise only the global allocator is available.
+
+use alloc::boxed::Box;
+use core::ptr::NonNull;
+
+#[cfg(feature = "unstable")]
+pub use alloc::alloc::{Allocator, Global};
+
+#[cfg(not(feature = "unstable"))]
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A stand-in for the unstable `Allocator` trait, only implemented by [`Global`]
+#[cfg(not(feature = "unstable"))]
+pub trait Allocator: sealed::Sealed {}
+
+/// The global memory allocator
+#[cfg(not(feature = "unstable"))]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Global;
+
+#[cfg(not(feature = "unstable"))]
+impl sealed::Sealed for Global {}
+
+#[cfg(not(feature = "unstable"))]
+impl Allocator for Global {}
+
+/// Move a value into a new allocation from `alloc`, returning the allocator alongside it so it
+/// can be stored for [`dealloc_in`]
+#[cfg(feature = "unstable")]
+pub(crate) fn alloc_in<T, A: Allocator>(val: T, alloc: A) -> (NonNull<T>, A) {
+    let (ptr, alloc) = Box::into_raw_with_allocator(Box::new_in(val, alloc));
+    // SAFETY: Box pointers are never null
+    (unsafe { NonNull::new_unchecked(ptr) }, alloc)
+}
+
+/// Move a value into a new allocation from `alloc`, returning the allocator alongside it so it
+/// can be stored for [`dealloc_in`]
+#[cfg(not(feature = "unstable"))]
+pub(crate) fn alloc_in<T, A: Allocator>(val: T, alloc: A) -> (NonNull<T>, A) {
+    (NonNull::from(Box::leak(Box::new(val))), alloc)
+}
+
+/// Drop the value behind a pointer from [`alloc_in`] and free its allocation
+///
+/// # Safety
+///
+/// The pointer must have been allocated by `alloc`, and not be used again
+#[cfg(feature = "unstable")]
+pub(crate) unsafe fn dealloc_in<T: ?Sized, A: Allocator>(ptr: NonNull<T>, alloc: A) {
+    drop(Box::from_raw_in(ptr.as_ptr(), alloc));
+}
+
+/// Drop the value behind a pointer from [`alloc_in`] and free its allocation
+///
+/// # Safety
+///
+/// The pointer must have been allocated by `alloc`, and not be used again
+#[cfg(not(feature = "unstable"))]
+pub(crate) unsafe fn dealloc_in<T: ?Sized, A: Allocator>(ptr: NonNull<T>, alloc: A) {
+    drop(alloc);
+    drop(Box::from_raw(ptr.as_ptr()));
+}
@@ -1,44 +1,60 @@
 //! A non-thread-safe stable cell
 
+#[cfg(feature = "unstable")]
 use alloc::boxed::Box;
 use core::cell::{Cell, UnsafeCell};
 use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 #[cfg(feature = "unstable")]
 use core::marker::Unsize;
 #[cfg(feature = "unstable")]
 use core::ops::CoerceUnsized;
+use crate::stable::allocator::{self, Allocator, Global};
 use crate::stable::util::BorrowState;
+use crate::util;
+use crate::stable::leak::{self, BorrowKind};
+use crate::stable::{BorrowError, CellId};
 
 #[derive(Debug)]
 #[repr(C)]
-struct CellState<T: ?Sized> {
+struct CellState<T: ?Sized, A: Allocator = Global> {
     borrow: Cell<BorrowState>,
+    /// The allocator this state was allocated in, written once it's on the heap
+    alloc: MaybeUninit<A>,
     value: UnsafeCell<T>,
 }
 
-impl<T: ?Sized> CellState<T> {
-    fn try_add_ref(&self) -> Option<()> {
+impl<T: ?Sized, A: Allocator> CellState<T, A> {
+    /// Record the allocator a state was allocated in, so it can later be freed
+    fn store_alloc(ptr: NonNull<Self>, alloc: A) -> NonNull<Self> {
+        unsafe { (*ptr.as_ptr()).alloc = MaybeUninit::new(alloc) };
+        ptr
+    }
+
+    /// Drop the value and free the allocation of a heap state
+    ///
+    /// # Safety
+    ///
+    /// The state must have been created by [`Self::store_alloc`], and not be used again
+    unsafe fn free(ptr: NonNull<Self>) {
+        let alloc = ptr.as_ref().alloc.as_ptr().read();
+        allocator::dealloc_in(ptr, alloc);
+    }
+
+    fn try_add_ref(&self) -> Result<(), BorrowError> {
         let cur = self.borrow.get();
-        match cur.incr_ref() {
-            Some(new) => {
-                self.borrow.set(new);
-                Some(())
-            }
-            None => None,
-        }
+        let new = cur.incr_ref()?;
+        self.borrow.set(new);
+        Ok(())
     }
 
-    fn try_add_mut(&self) -> Option<()> {
+    fn try_add_mut(&self) -> Result<(), BorrowError> {
         let cur = self.borrow.get();
-        match cur.incr_mut() {
-            Some(new) => {
-                self.borrow.set(new);
-                Some(())
-            }
-            _ => None,
-        }
+        let new = cur.incr_mut()?;
+        self.borrow.set(new);
+        Ok(())
     }
 
     /// Return a boolean indication whether this `CellState` should be dropped
@@ -57,6 +73,13 @@ impl<T: ?Sized> CellState<T> {
         drop
     }
 
+    /// Apply a fallible transition to the borrow state
+    fn try_transition(&self, f: impl FnOnce(BorrowState) -> Result<BorrowState, BorrowError>) -> Result<(), BorrowError> {
+        let new = f(self.borrow.get())?;
+        self.borrow.set(new);
+        Ok(())
+    }
+
     unsafe fn val_ref<'a>(&self) -> &'a T {
         &*self.value.get()
     }
@@ -66,49 +89,119 @@ impl<T: ?Sized> CellState<T> {
     }
 }
 
-impl<T> CellState<T> {
-    fn new(val: T) -> CellState<T> {
+impl<T, A: Allocator> CellState<T, A> {
+    const fn new(val: T) -> CellState<T, A> {
         CellState {
             borrow: Cell::new(BorrowState::new()),
+            alloc: MaybeUninit::uninit(),
             value: UnsafeCell::new(val),
         }
     }
 }
 
 #[cfg(feature = "unstable")]
-impl<T: CoerceUnsized<U>, U> CoerceUnsized<CellState<U>> for CellState<T> {}
+impl<T: CoerceUnsized<U>, U, A: Allocator> CoerceUnsized<CellState<U, A>> for CellState<T, A> {}
 
 /// A stable cell. Mixes traits of an `Rc` and a `RefCell` - Borrows may outlive the cell itself,
 /// and also may be mutable due to the single-ownership.
-pub struct StableCell<T: ?Sized>(NonNull<CellState<T>>);
+///
+/// The value is allocated in `A`, which with the `unstable` feature may be any [`Allocator`].
+pub struct StableCell<T: ?Sized, A: Allocator = Global>(NonNull<CellState<T, A>>);
 
 impl<T: ?Sized> StableCell<T> {
     /// Create a new `StableCell` from a type which unsizes to the cell type
     #[cfg(feature = "unstable")]
     pub fn new_from<U: Unsize<T>>(val: U) -> StableCell<T> {
-        let ptr = Box::leak(Box::new(CellState::new(val)) as Box<CellState<T>>);
-        StableCell(NonNull::from(ptr))
+        StableCell::new_from_in(val, Global)
+    }
+}
+
+impl<T: ?Sized, A: Allocator> StableCell<T, A> {
+    /// Create a new `StableCell` in the provided allocator from a type which unsizes to the cell
+    /// type
+    #[cfg(feature = "unstable")]
+    pub fn new_from_in<U: Unsize<T>>(val: U, alloc: A) -> StableCell<T, A> {
+        // Allocate sized and let the compiler unsize the box, so the layout and pointer metadata
+        // of the unsized state always come from the language rather than being computed by hand
+        let boxed = Box::new_in(CellState::new(val), alloc) as Box<CellState<T, A>, A>;
+        let (ptr, alloc) = Box::into_raw_with_allocator(boxed);
+        StableCell(CellState::store_alloc(unsafe { NonNull::new_unchecked(ptr) }, alloc))
     }
 
     /// Attempt to get a shared borrow to this cell. The borrow may live as long as `T`
-    pub fn try_borrow<'a>(&self) -> Option<StableRef<'a, T>> {
+    pub fn try_borrow<'a>(&self) -> Result<StableRef<'a, T, A>, BorrowError> {
         let state = unsafe { self.0.as_ref() };
         state.try_add_ref()
-            .map(|_| StableRef { state: self.0, _phantom: PhantomData })
+            .map(|_| StableRef { state: self.0, leak: leak::Token::new(self.id(), BorrowKind::Shared), _phantom: PhantomData })
     }
 
     /// Attempt to get a unique borrow to this cell. The borrow may live as long as `T`
-    pub fn try_borrow_mut<'a>(&self) -> Option<StableMut<'a, T>> {
+    pub fn try_borrow_mut<'a>(&self) -> Result<StableMut<'a, T, A>, BorrowError> {
         let state = unsafe { self.0.as_ref() };
         state.try_add_mut()
-            .map(|_| StableMut { state: self.0, _phantom: PhantomData })
+            .map(|_| StableMut { state: self.0, leak: leak::Token::new(self.id(), BorrowKind::Unique), _phantom: PhantomData })
+    }
+
+    /// Get the current borrow state of this cell, without borrowing it
+    #[cfg_attr(feature = "atomic", allow(unused))]
+    pub(crate) fn borrow_state(&self) -> BorrowState {
+        let state = unsafe { self.0.as_ref() };
+        state.borrow.get()
+    }
+
+    /// Attempt to get a shared borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_opt<'a>(&self) -> Option<StableRef<'a, T, A>> {
+        self.try_borrow().ok()
+    }
+
+    /// Attempt to get a unique borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_mut_opt<'a>(&self) -> Option<StableMut<'a, T, A>> {
+        self.try_borrow_mut().ok()
+    }
+
+    /// Get the identity of this cell, which is shared by no other live cell
+    pub fn id(&self) -> CellId {
+        CellId::from_ptr(self.0.as_ptr())
+    }
+
+    /// Check whether two cells are the same cell, like [`Rc::ptr_eq`](alloc::rc::Rc::ptr_eq)
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+
+    /// Get the outstanding borrows of this cell, oldest first
+    #[cfg(feature = "debug-leak-check")]
+    pub fn outstanding_borrows(&self) -> alloc::vec::Vec<leak::BorrowRecord> {
+        leak::outstanding(self.id())
+    }
+
+    /// Get the size in bytes of the heap allocation backing this cell, including its borrow state
+    pub fn alloc_size(&self) -> usize {
+        mem::size_of_val(unsafe { self.0.as_ref() })
     }
 }
 
 impl<T> StableCell<T> {
+    /// Create a new `StableCell` containing the provided value
     pub fn new(val: T) -> StableCell<T> {
-        let ptr = Box::leak(Box::new(CellState::new(val)));
-        StableCell(NonNull::from(ptr))
+        StableCell::new_in(val, Global)
+    }
+
+    /// Attempt to clone this cell, failing if its value can't be borrowed
+    pub fn try_clone(&self) -> Result<StableCell<T>, BorrowError>
+    where
+        T: Clone,
+    {
+        Ok(StableCell::new(T::clone(&*self.try_borrow()?)))
+    }
+}
+
+impl<T, A: Allocator> StableCell<T, A> {
+    /// Create a new `StableCell` containing the provided value, allocated in the provided
+    /// allocator
+    pub fn new_in(val: T, alloc: A) -> StableCell<T, A> {
+        let (ptr, alloc) = allocator::alloc_in(CellState::new(val), alloc);
+        StableCell(CellState::store_alloc(ptr, alloc))
     }
 }
 
@@ -117,29 +210,100 @@ where
     T: Clone,
 {
     fn clone(&self) -> Self {
-        StableCell::new(self.try_borrow().expect("Couldn't borrow value to clone").clone())
+        self.try_clone().expect("Couldn't borrow value to clone")
     }
 }
 
-impl<T: ?Sized> Drop for StableCell<T> {
+impl<T: ?Sized, A: Allocator> Drop for StableCell<T, A> {
     fn drop(&mut self) {
         let state = unsafe { self.0.as_ref() };
         let borrow = state.borrow.get();
         if borrow.is_none() {
-            unsafe { Box::from_raw(self.0.as_ptr()) };
+            unsafe { CellState::free(self.0) };
         } else {
+            leak::report_dropped(self.id());
             state.borrow.set(borrow.make_drop());
         }
     }
 }
 
+/// A [`StableCell`] which stores its value inline rather than on the heap, avoiding an allocation
+/// for zero-sized or small values. Borrows are the same types as a [`StableCell`]'s, but may not
+/// outlive the cell.
 #[derive(Debug)]
-pub struct StableRef<'a, T: ?Sized> {
-    state: NonNull<CellState<T>>,
+pub struct InlineStableCell<T: ?Sized>(CellState<T>);
+
+impl<T> InlineStableCell<T> {
+    /// Create a new `InlineStableCell` containing the provided value
+    pub const fn new(val: T) -> InlineStableCell<T> {
+        InlineStableCell(CellState::new(val))
+    }
+
+    /// Consume this cell, returning the contained value
+    pub fn into_inner(self) -> T {
+        self.0.value.into_inner()
+    }
+
+    /// Attempt to clone this cell, failing if its value can't be borrowed
+    pub fn try_clone(&self) -> Result<InlineStableCell<T>, BorrowError>
+    where
+        T: Clone,
+    {
+        Ok(InlineStableCell::new(T::clone(&*self.try_borrow()?)))
+    }
+}
+
+impl<T: ?Sized> InlineStableCell<T> {
+    /// Attempt to get a shared borrow to this cell
+    pub fn try_borrow(&self) -> Result<StableRef<'_, T>, BorrowError> {
+        self.0.try_add_ref()
+            .map(|_| StableRef { state: NonNull::from(&self.0), leak: leak::Token::new(CellId::from_ptr(&self.0), BorrowKind::Shared), _phantom: PhantomData })
+    }
+
+    /// Attempt to get a unique borrow to this cell
+    pub fn try_borrow_mut(&self) -> Result<StableMut<'_, T>, BorrowError> {
+        self.0.try_add_mut()
+            .map(|_| StableMut { state: NonNull::from(&self.0), leak: leak::Token::new(CellId::from_ptr(&self.0), BorrowKind::Unique), _phantom: PhantomData })
+    }
+
+    /// Attempt to get a shared borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_opt(&self) -> Option<StableRef<'_, T>> {
+        self.try_borrow().ok()
+    }
+
+    /// Attempt to get a unique borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_mut_opt(&self) -> Option<StableMut<'_, T>> {
+        self.try_borrow_mut().ok()
+    }
+
+    /// Get a mutable reference to the contained value. No borrows can be outstanding, as this
+    /// cell is uniquely borrowed.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for InlineStableCell<T> {
+    fn default() -> Self {
+        InlineStableCell::new(T::default())
+    }
+}
+
+impl<T: Clone> Clone for InlineStableCell<T> {
+    fn clone(&self) -> Self {
+        self.try_clone().expect("Couldn't borrow value to clone")
+    }
+}
+
+/// A shared borrow of a [`StableCell`], which may outlive the originating cell
+#[derive(Debug)]
+pub struct StableRef<'a, T: ?Sized, A: Allocator = Global> {
+    state: NonNull<CellState<T, A>>,
+    leak: leak::Token,
     _phantom: PhantomData<&'a T>,
 }
 
-impl<T: ?Sized> Deref for StableRef<'_, T> {
+impl<T: ?Sized, A: Allocator> Deref for StableRef<'_, T, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -147,34 +311,59 @@ impl<T: ?Sized> Deref for StableRef<'_, T> {
     }
 }
 
-impl<T: ?Sized + PartialEq> PartialEq for StableRef<'_, T> {
-    fn eq(&self, other: &Self) -> bool {
-        **self == **other
+impl<'a, T: ?Sized, A: Allocator> StableRef<'a, T, A> {
+    /// Turn this into a unique borrow, if no other shared borrows are outstanding. Otherwise this
+    /// borrow is handed back unchanged.
+    pub fn try_promote(mut self) -> Result<StableMut<'a, T, A>, StableRef<'a, T, A>> {
+        let state = unsafe { self.state.as_ref() };
+        match state.try_transition(BorrowState::promote) {
+            Ok(()) => {
+                let leak = mem::take(&mut self.leak);
+                leak.set_kind(BorrowKind::Unique);
+                let out = StableMut { state: self.state, leak, _phantom: PhantomData };
+                mem::forget(self);
+                Ok(out)
+            }
+            Err(_) => Err(self),
+        }
     }
 }
 
-impl<T: ?Sized> Drop for StableRef<'_, T> {
+util::forward_value_traits!(impl<T: ?Sized, A: Allocator> for StableRef<'_, T, A>);
+
+impl<T: ?Sized, A: Allocator> Drop for StableRef<'_, T, A> {
     fn drop(&mut self) {
         let state = unsafe { self.state.as_ref() };
         if state.try_de_ref() {
-            unsafe { Box::from_raw(self.state.as_ptr()) };
+            unsafe { CellState::free(self.state) };
         }
     }
 }
 
+/// A unique borrow of a [`StableCell`], which may outlive the originating cell
 #[derive(Debug)]
-pub struct StableMut<'a, T: ?Sized> {
-    state: NonNull<CellState<T>>,
+pub struct StableMut<'a, T: ?Sized, A: Allocator = Global> {
+    state: NonNull<CellState<T, A>>,
+    leak: leak::Token,
     _phantom: PhantomData<&'a mut T>,
 }
 
-impl<T: ?Sized + PartialEq> PartialEq for StableMut<'_, T> {
-    fn eq(&self, other: &Self) -> bool {
-        **self == **other
+impl<'a, T: ?Sized, A: Allocator> StableMut<'a, T, A> {
+    /// Turn this into a shared borrow, without allowing a unique borrow to intervene
+    pub fn demote(mut self) -> StableRef<'a, T, A> {
+        let state = unsafe { self.state.as_ref() };
+        state.borrow.set(state.borrow.get().demote());
+        let leak = mem::take(&mut self.leak);
+        leak.set_kind(BorrowKind::Shared);
+        let out = StableRef { state: self.state, leak, _phantom: PhantomData };
+        mem::forget(self);
+        out
     }
 }
 
-impl<T: ?Sized> Deref for StableMut<'_, T> {
+util::forward_value_traits!(impl<T: ?Sized, A: Allocator> for StableMut<'_, T, A>);
+
+impl<T: ?Sized, A: Allocator> Deref for StableMut<'_, T, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -182,17 +371,17 @@ impl<T: ?Sized> Deref for StableMut<'_, T> {
     }
 }
 
-impl<T: ?Sized> DerefMut for StableMut<'_, T> {
+impl<T: ?Sized, A: Allocator> DerefMut for StableMut<'_, T, A> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { self.state.as_ref().val_mut() }
     }
 }
 
-impl<T: ?Sized> Drop for StableMut<'_, T> {
+impl<T: ?Sized, A: Allocator> Drop for StableMut<'_, T, A> {
     fn drop(&mut self) {
         let state = unsafe { self.state.as_ref() };
         if state.try_de_mut() {
-            unsafe { Box::from_raw(self.state.as_ptr()) };
+            unsafe { CellState::free(self.state) };
         }
     }
 }
@@ -212,6 +401,26 @@ mod tests {
         drop(bm);
     }
 
+    #[test]
+    fn test_try_clone() {
+        let cell = StableCell::new(5);
+        let clone = cell.try_clone().unwrap();
+        assert!(!cell.ptr_eq(&clone));
+        *clone.try_borrow_mut().unwrap() = 6;
+        assert_eq!(*cell.try_borrow().unwrap(), 5);
+
+        let held = cell.try_borrow_mut().unwrap();
+        assert!(matches!(cell.try_clone(), Err(BorrowError::AlreadyMut)));
+        drop(held);
+        assert!(cell.try_clone().is_ok());
+
+        let inline = InlineStableCell::new(5);
+        let held = inline.try_borrow_mut().unwrap();
+        assert!(matches!(inline.try_clone(), Err(BorrowError::AlreadyMut)));
+        drop(held);
+        assert_eq!(inline.try_clone().unwrap().into_inner(), 5);
+    }
+
     #[test]
     #[cfg(feature = "unstable")]
     fn test_unsized() {
@@ -220,19 +429,50 @@ mod tests {
         assert_eq!(&*b, &[1, 2, 3]);
     }
 
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_unsized_outlives_cell() {
+        let cell = StableCell::<[i32]>::new_from([1, 2, 3]);
+        let mut b = cell.try_borrow_mut().unwrap();
+        drop(cell);
+        b[1] = 5;
+        assert_eq!(&*b, &[1, 5, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_unsized_drop() {
+        use alloc::rc::Rc;
+        use core::any::Any;
+
+        let marker = Rc::new(());
+        let cell = StableCell::<dyn Any>::new_from(Rc::clone(&marker));
+        let b = cell.try_borrow().unwrap();
+        assert!(b.is::<Rc<()>>());
+        drop(cell);
+        assert_eq!(Rc::strong_count(&marker), 2);
+        drop(b);
+        assert_eq!(Rc::strong_count(&marker), 1);
+
+        let cell = StableCell::<dyn Any>::new_from(Rc::clone(&marker));
+        drop(cell);
+        assert_eq!(Rc::strong_count(&marker), 1);
+    }
+
     #[test]
     fn test_borrow() {
         let cell = StableCell::new(5);
         assert_eq!(
             cell.try_borrow().as_deref(),
-            Some(&5),
+            Ok(&5),
         );
 
         let b1 = cell.try_borrow().unwrap();
         let b2 = cell.try_borrow().unwrap();
         assert_eq!(b1, b2);
 
-        assert_eq!(cell.try_borrow_mut(), None);
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+        assert!(cell.try_borrow_mut_opt().is_none());
 
         drop(b1);
         drop(b2);
@@ -244,19 +484,128 @@ mod tests {
 
         assert_eq!(
             cell.try_borrow_mut().as_deref_mut(),
-            Some(&mut 5),
+            Ok(&mut 5),
         );
 
         let b1 = cell.try_borrow_mut().unwrap();
 
         assert_eq!(
             cell.try_borrow_mut(),
-            None,
+            Err(BorrowError::AlreadyMut),
+        );
+        assert_eq!(
+            cell.try_borrow(),
+            Err(BorrowError::AlreadyMut),
         );
 
         drop(b1);
     }
 
+    #[test]
+    fn test_forwarded_traits() {
+        use alloc::collections::BTreeSet;
+        use alloc::string::ToString;
+
+        let a = StableCell::new(2);
+        let b = StableCell::new(1);
+        let (ra, rb) = (a.try_borrow().unwrap(), b.try_borrow().unwrap());
+        assert!(rb < ra);
+        assert_eq!(ra.to_string(), "2");
+
+        let set = [ra, rb].into_iter().collect::<BTreeSet<_>>();
+        assert_eq!(set.iter().map(|r| **r).collect::<alloc::vec::Vec<_>>(), [1, 2]);
+
+        let (c, d) = (StableCell::new(2), StableCell::new(2));
+        assert_eq!(c.try_borrow_mut().unwrap().cmp(&d.try_borrow_mut().unwrap()), core::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn test_promote_demote() {
+        let cell = StableCell::new(5);
+        let b = cell.try_borrow().unwrap();
+        let other = cell.try_borrow().unwrap();
+        let b = b.try_promote().unwrap_err();
+        drop(other);
+
+        let mut b = b.try_promote().unwrap();
+        *b += 1;
+        assert_eq!(cell.try_borrow(), Err(BorrowError::AlreadyMut));
+        let b = b.demote();
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&6));
+        drop(cell);
+        assert_eq!(*b.try_promote().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(mem::size_of::<InlineStableCell<()>>(), mem::size_of::<usize>());
+
+        const fn empty() -> InlineStableCell<()> {
+            InlineStableCell::new(())
+        }
+        let cell = empty();
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&()));
+
+        let mut cell = InlineStableCell::new(5);
+        let b = cell.try_borrow().unwrap();
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+        let mut b = b.try_promote().unwrap();
+        *b += 1;
+        assert_eq!(cell.try_borrow(), Err(BorrowError::AlreadyMut));
+        drop(b);
+
+        *cell.get_mut() += 1;
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&7));
+        assert_eq!(cell.into_inner(), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_alloc_in() {
+        use core::alloc::{AllocError, Layout};
+        use core::cell::Cell;
+
+        struct Counting<'a>(&'a Cell<usize>);
+
+        unsafe impl Allocator for Counting<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let live = Cell::new(0);
+        let cell = StableCell::new_in(5, Counting(&live));
+        assert_eq!(live.get(), 1);
+        let b = cell.try_borrow().unwrap();
+        drop(cell);
+        assert_eq!(live.get(), 1);
+        assert_eq!(*b, 5);
+        drop(b);
+        assert_eq!(live.get(), 0);
+
+        let cell = StableCell::<[i32], _>::new_from_in([1, 2, 3], Counting(&live));
+        assert_eq!(&*cell.try_borrow().unwrap(), &[1, 2, 3]);
+        drop(cell);
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    fn test_ptr_eq() {
+        let a = StableCell::new(1);
+        let b = StableCell::new(1);
+        assert!(a.ptr_eq(&a));
+        assert!(!a.ptr_eq(&b));
+        assert_eq!(a.id(), a.id());
+        assert_ne!(a.id(), b.id());
+    }
+
     #[test]
     fn test_drop_borrow() {
         let cell = StableCell::new(-1);
@@ -0,0 +1,33 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
+/// Possible failures when borrowing a stable cell or lock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorrowError {
+    /// The value is already mutably borrowed
+    AlreadyMut,
+    /// The value is already immutably borrowed, so can't be mutably borrowed
+    AlreadyShared,
+    /// The owning cell has been dropped, and the value is waiting on outstanding borrows
+    Dropping,
+    /// Too many shared borrows are outstanding to track another
+    Overflow,
+    /// A unique borrow was dropped during a panic, so the value may be inconsistent
+    Poisoned,
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorrowError::AlreadyMut => write!(f, "Value is already mutably borrowed"),
+            BorrowError::AlreadyShared => write!(f, "Value is already immutably borrowed"),
+            BorrowError::Dropping => write!(f, "Value is being dropped"),
+            BorrowError::Overflow => write!(f, "Too many outstanding borrows of value"),
+            BorrowError::Poisoned => write!(f, "Value was poisoned by a panic while mutably borrowed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdError for BorrowError {}
@@ -0,0 +1,12 @@
+/// The identity of a stable cell or lock, based on the address of its shared state.
+///
+/// Ids are unique among values which are alive at the same time. Once a value and all its
+/// borrows are dropped, its id may be reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CellId(usize);
+
+impl CellId {
+    pub(crate) fn from_ptr<T: ?Sized>(ptr: *const T) -> CellId {
+        CellId(ptr.cast::<u8>() as usize)
+    }
+}
@@ -0,0 +1,131 @@
+//! Tracking of outstanding borrows, to find which code holds a borrow for too long. Borrows are
+//! only recorded with the `debug-leak-check` feature, otherwise tracking compiles away.
+
+use crate::stable::CellId;
+
+#[cfg(feature = "debug-leak-check")]
+pub use imp::{log_dropped_while_borrowed, BorrowRecord};
+
+/// The kind of an outstanding borrow
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorrowKind {
+    /// A shared borrow
+    Shared,
+    /// A shared borrow which may be upgraded to a unique borrow
+    Upgradable,
+    /// A unique borrow
+    Unique,
+}
+
+/// Registers a borrow while it's alive. A moved-out token is empty, and registers nothing.
+#[derive(Debug, Default)]
+pub(crate) struct Token(#[cfg(feature = "debug-leak-check")] Option<(CellId, u64)>);
+
+#[cfg(not(feature = "debug-leak-check"))]
+impl Token {
+    pub(crate) fn new(_: CellId, _: BorrowKind) -> Token {
+        Token()
+    }
+
+    pub(crate) fn set_kind(&self, _: BorrowKind) {}
+}
+
+#[cfg(not(feature = "debug-leak-check"))]
+pub(crate) fn report_dropped(_: CellId) {}
+
+#[cfg(feature = "debug-leak-check")]
+pub(crate) use imp::{outstanding, report_dropped};
+
+#[cfg(feature = "debug-leak-check")]
+mod imp {
+    use std::backtrace::Backtrace;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::vec::Vec;
+    use super::{BorrowKind, CellId, Token};
+
+    /// An outstanding borrow of a cell or lock
+    #[derive(Debug, Clone)]
+    pub struct BorrowRecord {
+        /// The kind of the borrow, which may change if it's promoted or demoted
+        pub kind: BorrowKind,
+        /// The order this borrow was created in, counting every borrow of every cell
+        pub serial: u64,
+        /// Where the borrow was created. Only captured if enabled by `RUST_BACKTRACE` or
+        /// `RUST_LIB_BACKTRACE`.
+        pub backtrace: Arc<Backtrace>,
+    }
+
+    static REGISTRY: Mutex<BTreeMap<CellId, BTreeMap<u64, BorrowRecord>>> = Mutex::new(BTreeMap::new());
+    static SERIAL: AtomicU64 = AtomicU64::new(0);
+    static LOG_DROPPED: AtomicBool = AtomicBool::new(false);
+
+    fn with_registry<R>(f: impl FnOnce(&mut BTreeMap<CellId, BTreeMap<u64, BorrowRecord>>) -> R) -> R {
+        f(&mut REGISTRY.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Set whether dropping a cell or lock while it's still borrowed logs its outstanding borrows
+    /// to stderr
+    pub fn log_dropped_while_borrowed(enabled: bool) {
+        LOG_DROPPED.store(enabled, Ordering::Relaxed);
+    }
+
+    impl Token {
+        pub(crate) fn new(id: CellId, kind: BorrowKind) -> Token {
+            let serial = SERIAL.fetch_add(1, Ordering::Relaxed);
+            let record = BorrowRecord { kind, serial, backtrace: Arc::new(Backtrace::capture()) };
+            with_registry(|registry| registry.entry(id).or_default().insert(serial, record));
+            Token(Some((id, serial)))
+        }
+
+        pub(crate) fn set_kind(&self, kind: BorrowKind) {
+            if let Some((id, serial)) = self.0 {
+                with_registry(|registry| {
+                    if let Some(record) = registry.get_mut(&id).and_then(|cell| cell.get_mut(&serial)) {
+                        record.kind = kind;
+                    }
+                });
+            }
+        }
+    }
+
+    impl Drop for Token {
+        fn drop(&mut self) {
+            if let Some((id, serial)) = self.0 {
+                with_registry(|registry| {
+                    if let Some(cell) = registry.get_mut(&id) {
+                        cell.remove(&serial);
+                        if cell.is_empty() {
+                            registry.remove(&id);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    /// Get the outstanding borrows of a cell, oldest first
+    pub(crate) fn outstanding(id: CellId) -> Vec<BorrowRecord> {
+        with_registry(|registry| {
+            registry.get(&id)
+                .map(|cell| cell.values().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Called when a cell is dropped while still borrowed
+    pub(crate) fn report_dropped(id: CellId) {
+        if !LOG_DROPPED.load(Ordering::Relaxed) {
+            return;
+        }
+        for record in outstanding(id) {
+            std::eprintln!(
+                "Cell {id:?} dropped while borrowed: {:?} borrow #{} created at:\n{}",
+                record.kind,
+                record.serial,
+                record.backtrace,
+            );
+        }
+    }
+}
@@ -3,41 +3,83 @@
 use core::cell::UnsafeCell;
 use core::ptr::NonNull;
 use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
 #[cfg(feature = "unstable")]
 use core::marker::Unsize;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "unstable")]
 use alloc::boxed::Box;
+use crate::stable::allocator::{self, Allocator, Global};
+use crate::stable::sync::{AtomicUsize, Ordering};
 use crate::stable::util::BorrowState;
+use crate::util;
+use crate::stable::leak::{self, BorrowKind};
+use crate::stable::{BorrowError, CellId};
+
+/// Check whether the current thread is panicking. Panics can't be detected without `std`.
+fn panicking() -> bool {
+    #[cfg(feature = "std")]
+    return std::thread::panicking();
+    #[cfg(not(feature = "std"))]
+    return false;
+}
 
 #[derive(Debug)]
 #[repr(C)]
-struct LockState<T: ?Sized> {
+struct LockState<T: ?Sized, A: Allocator> {
     borrow: AtomicUsize,
+    /// The allocator this state was allocated in, written once it's on the heap
+    alloc: MaybeUninit<A>,
     value: UnsafeCell<T>,
 }
 
-impl<T: ?Sized> LockState<T> {
-    fn try_add_ref(&self) -> Option<()> {
+impl<T: ?Sized, A: Allocator> LockState<T, A> {
+    /// Record the allocator a state was allocated in, so it can later be freed
+    fn store_alloc(ptr: NonNull<Self>, alloc: A) -> NonNull<Self> {
+        unsafe { (*ptr.as_ptr()).alloc = MaybeUninit::new(alloc) };
+        ptr
+    }
+
+    /// Drop the value and free the allocation of a state
+    ///
+    /// # Safety
+    ///
+    /// The state must have been created by [`Self::store_alloc`], and not be used again
+    unsafe fn free(ptr: NonNull<Self>) {
+        let alloc = ptr.as_ref().alloc.as_ptr().read();
+        allocator::dealloc_in(ptr, alloc);
+    }
+
+    fn try_add_ref(&self) -> Result<(), BorrowError> {
+        let mut err = BorrowError::Dropping;
         self.borrow.fetch_update(
             Ordering::AcqRel,
             Ordering::Acquire,
             |cur| {
-                BorrowState::from_val(cur).incr_ref().map(BorrowState::to_val)
+                BorrowState::from_val(cur)
+                    .incr_ref()
+                    .map_err(|e| err = e)
+                    .map(BorrowState::to_val)
+                    .ok()
             })
             .map(|_| ())
-            .ok()
+            .map_err(|_| err)
     }
 
-    fn try_add_mut(&self) -> Option<()> {
+    fn try_add_mut(&self) -> Result<(), BorrowError> {
+        let mut err = BorrowError::Dropping;
         self.borrow.fetch_update(
             Ordering::AcqRel,
             Ordering::Acquire,
             |cur| {
-                BorrowState::from_val(cur).incr_mut().map(BorrowState::to_val)
+                BorrowState::from_val(cur)
+                    .incr_mut()
+                    .map_err(|e| err = e)
+                    .map(BorrowState::to_val)
+                    .ok()
             })
             .map(|_| ())
-            .ok()
+            .map_err(|_| err)
     }
 
     /// Return a boolean indication whether this `LockState` should be dropped
@@ -54,14 +96,51 @@ impl<T: ?Sized> LockState<T> {
         drop_flag
     }
 
+    /// Return a boolean indication whether this `LockState` should be dropped. The value is
+    /// poisoned if the borrow is released by a panic which started after it was taken.
+    fn try_de_mut(&self, was_panicking: bool) -> bool {
+        let poison = !was_panicking && panicking();
+
+        let mut drop_flag = false;
+        let _ = self.borrow.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |cur| {
+                let mut cur = BorrowState::from_val(cur);
+                if poison {
+                    cur = cur.make_poisoned();
+                }
+                let (out, drop) = cur.decr_mut();
+                drop_flag = drop;
+                Some(out.to_val())
+            });
+        drop_flag
+    }
+
+    /// Apply a fallible transition to the borrow state
+    fn try_transition(&self, f: impl Fn(BorrowState) -> Result<BorrowState, BorrowError>) -> Result<(), BorrowError> {
+        let mut err = BorrowError::Dropping;
+        self.borrow.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |cur| {
+                f(BorrowState::from_val(cur))
+                    .map_err(|e| err = e)
+                    .map(BorrowState::to_val)
+                    .ok()
+            })
+            .map(|_| ())
+            .map_err(|_| err)
+    }
+
     /// Return a boolean indication whether this `LockState` should be dropped
-    fn try_de_mut(&self) -> bool {
+    fn try_de_upgradable(&self) -> bool {
         let mut drop_flag = false;
         let _ = self.borrow.fetch_update(
             Ordering::AcqRel,
             Ordering::Acquire,
             |cur| {
-                let (out, drop) = BorrowState::from_val(cur).decr_mut();
+                let (out, drop) = BorrowState::from_val(cur).decr_upgradable();
                 drop_flag = drop;
                 Some(out.to_val())
             });
@@ -77,51 +156,149 @@ impl<T: ?Sized> LockState<T> {
     }
 }
 
-impl<T> LockState<T> {
-    fn new(val: T) -> LockState<T> {
+impl<T, A: Allocator> LockState<T, A> {
+    fn new(val: T) -> LockState<T, A> {
         LockState {
             borrow: AtomicUsize::new(BorrowState::new().to_val()),
+            alloc: MaybeUninit::uninit(),
             value: UnsafeCell::new(val),
         }
     }
 }
 
-pub struct StableLock<T: ?Sized>(NonNull<LockState<T>>);
+/// A stable lock. The thread-safe equivalent of a [`StableCell`](crate::stable::cell::StableCell),
+/// borrows may outlive the lock itself and are tracked atomically.
+///
+/// The value is allocated in `A`, which with the `unstable` feature may be any [`Allocator`].
+pub struct StableLock<T: ?Sized, A: Allocator = Global>(NonNull<LockState<T, A>>);
 
 impl<T: ?Sized> StableLock<T> {
     /// Create a new `StableLock` from a type which unsizes to the cell type
     #[cfg(feature = "unstable")]
     pub fn new_from<U: Unsize<T>>(val: U) -> StableLock<T> {
-        let ptr = Box::leak(Box::new(LockState::new(val)) as Box<LockState<T>>);
-        StableLock(NonNull::from(ptr))
+        StableLock::new_from_in(val, Global)
+    }
+}
+
+impl<T: ?Sized, A: Allocator> StableLock<T, A> {
+    /// Create a new `StableLock` in the provided allocator from a type which unsizes to the cell
+    /// type
+    #[cfg(feature = "unstable")]
+    pub fn new_from_in<U: Unsize<T>>(val: U, alloc: A) -> StableLock<T, A> {
+        // Allocate sized and let the compiler unsize the box, so the layout and pointer metadata
+        // of the unsized state always come from the language rather than being computed by hand
+        let boxed = Box::new_in(LockState::new(val), alloc) as Box<LockState<T, A>, A>;
+        let (ptr, alloc) = Box::into_raw_with_allocator(boxed);
+        StableLock(LockState::store_alloc(unsafe { NonNull::new_unchecked(ptr) }, alloc))
     }
 
     /// Attempt to get a shared borrow to this cell. The borrow may live as long as `T`
-    pub fn try_borrow<'a>(&self) -> Option<StableRef<'a, T>> {
+    pub fn try_borrow<'a>(&self) -> Result<StableRef<'a, T, A>, BorrowError> {
         let state = unsafe { self.0.as_ref() };
         state.try_add_ref()
-            .map(|_| StableRef { state: self.0, _phantom: PhantomData })
+            .map(|_| StableRef { state: self.0, leak: leak::Token::new(self.id(), BorrowKind::Shared), _phantom: PhantomData })
     }
 
     /// Attempt to get a unique borrow to this cell. The borrow may live as long as `T`
-    pub fn try_borrow_mut<'a>(&self) -> Option<StableMut<'a, T>> {
+    pub fn try_borrow_mut<'a>(&self) -> Result<StableMut<'a, T, A>, BorrowError> {
         let state = unsafe { self.0.as_ref() };
         state.try_add_mut()
-            .map(|_| StableMut { state: self.0, _phantom: PhantomData })
+            .map(|_| StableMut { state: self.0, leak: leak::Token::new(self.id(), BorrowKind::Unique), panicking: panicking(), _phantom: PhantomData })
+    }
+
+    /// Attempt to get a shared borrow to this cell which can later be upgraded to a unique borrow
+    /// with [`StableUpgradable::upgrade`]. Other shared borrows may coexist with it, but only one
+    /// upgradable borrow may be held at a time, failing with [`BorrowError::AlreadyShared`].
+    pub fn try_borrow_upgradable<'a>(&self) -> Result<StableUpgradable<'a, T, A>, BorrowError> {
+        let state = unsafe { self.0.as_ref() };
+        state.try_transition(BorrowState::incr_upgradable)
+            .map(|_| StableUpgradable { state: self.0, leak: leak::Token::new(self.id(), BorrowKind::Upgradable), _phantom: PhantomData })
+    }
+
+    /// Check whether a unique borrow of this lock was released by a panic, which may have left
+    /// the value inconsistent. Borrowing a poisoned lock fails with [`BorrowError::Poisoned`].
+    ///
+    /// Poisoning requires the `std` feature, as panics can't be detected without it.
+    pub fn is_poisoned(&self) -> bool {
+        let state = unsafe { self.0.as_ref() };
+        BorrowState::from_val(state.borrow.load(Ordering::Acquire)).is_poisoned()
+    }
+
+    /// Clear the poisoned state of this lock, once the value is known to be consistent
+    pub fn clear_poison(&self) {
+        let state = unsafe { self.0.as_ref() };
+        let _ = state.try_transition(|cur| Ok(cur.clear_poison()));
+    }
+
+    /// Get the current borrow state of this lock, without borrowing it
+    #[cfg_attr(not(feature = "atomic"), allow(unused))]
+    pub(crate) fn borrow_state(&self) -> BorrowState {
+        let state = unsafe { self.0.as_ref() };
+        BorrowState::from_val(state.borrow.load(Ordering::Acquire))
+    }
+
+    /// Attempt to get a shared borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_opt<'a>(&self) -> Option<StableRef<'a, T, A>> {
+        self.try_borrow().ok()
+    }
+
+    /// Attempt to get a unique borrow to this cell, discarding the reason for failure
+    pub fn try_borrow_mut_opt<'a>(&self) -> Option<StableMut<'a, T, A>> {
+        self.try_borrow_mut().ok()
+    }
+
+    /// Get the identity of this lock, which is shared by no other live lock
+    pub fn id(&self) -> CellId {
+        CellId::from_ptr(self.0.as_ptr())
+    }
+
+    /// Check whether two locks are the same lock, like [`Rc::ptr_eq`](alloc::rc::Rc::ptr_eq)
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+
+    /// Get the outstanding borrows of this lock, oldest first
+    #[cfg(feature = "debug-leak-check")]
+    pub fn outstanding_borrows(&self) -> alloc::vec::Vec<leak::BorrowRecord> {
+        leak::outstanding(self.id())
+    }
+
+    /// Get the size in bytes of the heap allocation backing this lock, including its borrow state
+    pub fn alloc_size(&self) -> usize {
+        mem::size_of_val(unsafe { self.0.as_ref() })
     }
 }
 
 impl<T> StableLock<T> {
+    /// Create a new `StableLock` containing the provided value
     pub fn new(val: T) -> StableLock<T> {
-        let ptr = Box::leak(Box::new(LockState::new(val)));
-        StableLock(NonNull::from(ptr))
+        StableLock::new_in(val, Global)
+    }
+
+    /// Attempt to clone this lock, failing if its value can't be borrowed
+    pub fn try_clone(&self) -> Result<StableLock<T>, BorrowError>
+    where
+        T: Clone,
+    {
+        Ok(StableLock::new(T::clone(&*self.try_borrow()?)))
     }
 }
 
-unsafe impl<T: ?Sized + Send> Send for StableLock<T> {}
-unsafe impl<T: ?Sized + Send> Sync for StableLock<T> {}
+impl<T, A: Allocator> StableLock<T, A> {
+    /// Create a new `StableLock` containing the provided value, allocated in the provided
+    /// allocator
+    pub fn new_in(val: T, alloc: A) -> StableLock<T, A> {
+        let (ptr, alloc) = allocator::alloc_in(LockState::new(val), alloc);
+        StableLock(LockState::store_alloc(ptr, alloc))
+    }
+}
+
+// Whichever thread releases the value last frees it, so the allocator must be `Send`
+unsafe impl<T: ?Sized + Send, A: Allocator + Send> Send for StableLock<T, A> {}
+// Shared borrows from several threads hand out `&T` concurrently, so `T` must also be `Sync`
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Sync for StableLock<T, A> {}
 
-impl<T: ?Sized> Drop for StableLock<T> {
+impl<T: ?Sized, A: Allocator> Drop for StableLock<T, A> {
     fn drop(&mut self) {
         let mut drop_flag = false;
         let state = unsafe { self.0.as_ref() };
@@ -138,18 +315,26 @@ impl<T: ?Sized> Drop for StableLock<T> {
                 }
             });
         if drop_flag {
-            unsafe { Box::from_raw(self.0.as_ptr()) };
+            unsafe { LockState::free(self.0) };
+        } else {
+            leak::report_dropped(self.id());
         }
     }
 }
 
+/// A shared borrow of a [`StableLock`], which may outlive the originating cell
 #[derive(Debug)]
-pub struct StableRef<'a, T: ?Sized> {
-    state: NonNull<LockState<T>>,
+pub struct StableRef<'a, T: ?Sized, A: Allocator = Global> {
+    state: NonNull<LockState<T, A>>,
+    leak: leak::Token,
     _phantom: PhantomData<&'a T>,
 }
 
-impl<T: ?Sized> Deref for StableRef<'_, T> {
+// A shared borrow is an `&T` which may also free the value, so it has the bounds of an `Arc<T>`
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Send for StableRef<'_, T, A> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Sync for StableRef<'_, T, A> {}
+
+impl<T: ?Sized, A: Allocator> Deref for StableRef<'_, T, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -157,34 +342,114 @@ impl<T: ?Sized> Deref for StableRef<'_, T> {
     }
 }
 
-impl<T: ?Sized + PartialEq> PartialEq for StableRef<'_, T> {
-    fn eq(&self, other: &Self) -> bool {
-        **self == **other
+impl<'a, T: ?Sized, A: Allocator> StableRef<'a, T, A> {
+    /// Turn this into a unique borrow, if no other shared borrows are outstanding. Otherwise this
+    /// borrow is handed back unchanged.
+    pub fn try_promote(mut self) -> Result<StableMut<'a, T, A>, StableRef<'a, T, A>> {
+        let state = unsafe { self.state.as_ref() };
+        match state.try_transition(BorrowState::promote) {
+            Ok(()) => {
+                let leak = mem::take(&mut self.leak);
+                leak.set_kind(BorrowKind::Unique);
+                let out = StableMut { state: self.state, leak, panicking: panicking(), _phantom: PhantomData };
+                mem::forget(self);
+                Ok(out)
+            }
+            Err(_) => Err(self),
+        }
     }
 }
 
-impl<T: ?Sized> Drop for StableRef<'_, T> {
+util::forward_value_traits!(impl<T: ?Sized, A: Allocator> for StableRef<'_, T, A>);
+
+impl<T: ?Sized, A: Allocator> Drop for StableRef<'_, T, A> {
     fn drop(&mut self) {
         let state = unsafe { self.state.as_ref() };
         if state.try_de_ref() {
-            unsafe { Box::from_raw(self.state.as_ptr()) };
+            unsafe { LockState::free(self.state) };
+        }
+    }
+}
+
+/// A shared borrow of a [`StableLock`] which can be upgraded to a unique borrow without
+/// releasing it, and may outlive the originating cell
+#[derive(Debug)]
+pub struct StableUpgradable<'a, T: ?Sized, A: Allocator = Global> {
+    state: NonNull<LockState<T, A>>,
+    leak: leak::Token,
+    _phantom: PhantomData<&'a T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Send for StableUpgradable<'_, T, A> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Sync for StableUpgradable<'_, T, A> {}
+
+impl<'a, T: ?Sized, A: Allocator> StableUpgradable<'a, T, A> {
+    /// Turn this into a unique borrow, if no other shared borrows are outstanding. Otherwise this
+    /// borrow is handed back unchanged. No writer can intervene between reading through this
+    /// borrow and upgrading it.
+    pub fn upgrade(mut self) -> Result<StableMut<'a, T, A>, StableUpgradable<'a, T, A>> {
+        let state = unsafe { self.state.as_ref() };
+        match state.try_transition(BorrowState::upgrade) {
+            Ok(()) => {
+                let leak = mem::take(&mut self.leak);
+                leak.set_kind(BorrowKind::Unique);
+                let out = StableMut { state: self.state, leak, panicking: panicking(), _phantom: PhantomData };
+                mem::forget(self);
+                Ok(out)
+            }
+            Err(_) => Err(self),
         }
     }
 }
 
+impl<T: ?Sized, A: Allocator> Deref for StableUpgradable<'_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.state.as_ref().val_ref() }
+    }
+}
+
+util::forward_value_traits!(impl<T: ?Sized, A: Allocator> for StableUpgradable<'_, T, A>);
+
+impl<T: ?Sized, A: Allocator> Drop for StableUpgradable<'_, T, A> {
+    fn drop(&mut self) {
+        let state = unsafe { self.state.as_ref() };
+        if state.try_de_upgradable() {
+            unsafe { LockState::free(self.state) };
+        }
+    }
+}
+
+/// A unique borrow of a [`StableLock`], which may outlive the originating cell
 #[derive(Debug)]
-pub struct StableMut<'a, T: ?Sized> {
-    state: NonNull<LockState<T>>,
+pub struct StableMut<'a, T: ?Sized, A: Allocator = Global> {
+    state: NonNull<LockState<T, A>>,
+    leak: leak::Token,
+    /// Whether the thread was already panicking when this borrow was taken
+    panicking: bool,
     _phantom: PhantomData<&'a mut T>,
 }
 
-impl<T: ?Sized + PartialEq> PartialEq for StableMut<'_, T> {
-    fn eq(&self, other: &Self) -> bool {
-        **self == **other
+unsafe impl<T: ?Sized + Send, A: Allocator + Send> Send for StableMut<'_, T, A> {}
+unsafe impl<T: ?Sized + Sync, A: Allocator + Send> Sync for StableMut<'_, T, A> {}
+
+impl<'a, T: ?Sized, A: Allocator> StableMut<'a, T, A> {
+    /// Turn this into a shared borrow, without allowing a unique borrow to intervene
+    pub fn demote(mut self) -> StableRef<'a, T, A> {
+        let state = unsafe { self.state.as_ref() };
+        let _ = state.try_transition(|cur| Ok(cur.demote()));
+        let leak = mem::take(&mut self.leak);
+        leak.set_kind(BorrowKind::Shared);
+        let out = StableRef { state: self.state, leak, _phantom: PhantomData };
+        mem::forget(self);
+        out
     }
 }
 
-impl<T: ?Sized> Deref for StableMut<'_, T> {
+util::forward_value_traits!(impl<T: ?Sized, A: Allocator> for StableMut<'_, T, A>);
+
+impl<T: ?Sized, A: Allocator> Deref for StableMut<'_, T, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -192,17 +457,17 @@ impl<T: ?Sized> Deref for StableMut<'_, T> {
     }
 }
 
-impl<T: ?Sized> DerefMut for StableMut<'_, T> {
+impl<T: ?Sized, A: Allocator> DerefMut for StableMut<'_, T, A> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { self.state.as_ref().val_mut() }
     }
 }
 
-impl<T: ?Sized> Drop for StableMut<'_, T> {
+impl<T: ?Sized, A: Allocator> Drop for StableMut<'_, T, A> {
     fn drop(&mut self) {
         let state = unsafe { self.state.as_ref() };
-        if state.try_de_mut() {
-            unsafe { Box::from_raw(self.state.as_ptr()) };
+        if state.try_de_mut(self.panicking) {
+            unsafe { LockState::free(self.state) };
         }
     }
 }
@@ -222,6 +487,20 @@ mod tests {
         drop(bm);
     }
 
+    #[test]
+    fn test_try_clone() {
+        let cell = StableLock::new(5);
+        let clone = cell.try_clone().unwrap();
+        assert!(!cell.ptr_eq(&clone));
+        *clone.try_borrow_mut().unwrap() = 6;
+        assert_eq!(*cell.try_borrow().unwrap(), 5);
+
+        let held = cell.try_borrow_mut().unwrap();
+        assert!(matches!(cell.try_clone(), Err(BorrowError::AlreadyMut)));
+        drop(held);
+        assert!(cell.try_clone().is_ok());
+    }
+
     #[test]
     #[cfg(feature = "unstable")]
     fn test_unsized() {
@@ -230,19 +509,50 @@ mod tests {
         assert_eq!(&*b, &[1, 2, 3]);
     }
 
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_unsized_outlives_cell() {
+        let cell = StableLock::<[i32]>::new_from([1, 2, 3]);
+        let mut b = cell.try_borrow_mut().unwrap();
+        drop(cell);
+        b[1] = 5;
+        assert_eq!(&*b, &[1, 5, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_unsized_drop() {
+        use alloc::rc::Rc;
+        use core::any::Any;
+
+        let marker = Rc::new(());
+        let cell = StableLock::<dyn Any>::new_from(Rc::clone(&marker));
+        let b = cell.try_borrow().unwrap();
+        assert!(b.is::<Rc<()>>());
+        drop(cell);
+        assert_eq!(Rc::strong_count(&marker), 2);
+        drop(b);
+        assert_eq!(Rc::strong_count(&marker), 1);
+
+        let cell = StableLock::<dyn Any>::new_from(Rc::clone(&marker));
+        drop(cell);
+        assert_eq!(Rc::strong_count(&marker), 1);
+    }
+
     #[test]
     fn test_borrow() {
         let cell = StableLock::new(5);
         assert_eq!(
             cell.try_borrow().as_deref(),
-            Some(&5),
+            Ok(&5),
         );
 
         let b1 = cell.try_borrow().unwrap();
         let b2 = cell.try_borrow().unwrap();
         assert_eq!(b1, b2);
 
-        assert_eq!(cell.try_borrow_mut(), None);
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+        assert!(cell.try_borrow_mut_opt().is_none());
 
         drop(b1);
         drop(b2);
@@ -254,19 +564,157 @@ mod tests {
 
         assert_eq!(
             cell.try_borrow_mut().as_deref_mut(),
-            Some(&mut 5),
+            Ok(&mut 5),
         );
 
         let b1 = cell.try_borrow_mut().unwrap();
 
         assert_eq!(
             cell.try_borrow_mut(),
-            None,
+            Err(BorrowError::AlreadyMut),
+        );
+        assert_eq!(
+            cell.try_borrow(),
+            Err(BorrowError::AlreadyMut),
         );
 
         drop(b1);
     }
 
+    #[test]
+    fn test_borrow_upgradable() {
+        let cell = StableLock::new(5);
+        let up = cell.try_borrow_upgradable().unwrap();
+        assert_eq!(*up, 5);
+        assert_eq!(cell.try_borrow_upgradable().unwrap_err(), BorrowError::AlreadyShared);
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+
+        let other = cell.try_borrow().unwrap();
+        let up = up.upgrade().unwrap_err();
+        drop(other);
+
+        let mut b = up.upgrade().unwrap();
+        *b += 1;
+        assert_eq!(cell.try_borrow(), Err(BorrowError::AlreadyMut));
+        drop(b);
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&6));
+    }
+
+    #[test]
+    fn test_upgradable_outlives_cell() {
+        let cell = StableLock::new(alloc::string::String::from("value"));
+        let up = cell.try_borrow_upgradable().unwrap();
+        drop(cell);
+        let mut b = up.upgrade().unwrap();
+        b.push('!');
+        assert_eq!(*b, "value!");
+    }
+
+    #[test]
+    fn test_promote_demote() {
+        let cell = StableLock::new(5);
+        let b = cell.try_borrow().unwrap();
+        let other = cell.try_borrow().unwrap();
+        let b = b.try_promote().unwrap_err();
+        drop(other);
+
+        let mut b = b.try_promote().unwrap();
+        *b += 1;
+        assert_eq!(cell.try_borrow(), Err(BorrowError::AlreadyMut));
+        let b = b.demote();
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::AlreadyShared));
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&6));
+        drop(cell);
+        assert_eq!(*b.try_promote().unwrap(), 6);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_poison() {
+        let cell = StableLock::new(5);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut b = cell.try_borrow_mut().unwrap();
+            *b = 6;
+            panic!("Poison the lock");
+        }));
+        assert!(res.is_err());
+        assert!(cell.is_poisoned());
+        assert_eq!(cell.try_borrow(), Err(BorrowError::Poisoned));
+        assert_eq!(cell.try_borrow_mut(), Err(BorrowError::Poisoned));
+        assert_eq!(cell.try_borrow_upgradable().unwrap_err(), BorrowError::Poisoned);
+
+        cell.clear_poison();
+        assert!(!cell.is_poisoned());
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&6));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_poison_during_unwind() {
+        struct Guard<'a>(&'a StableLock<i32>);
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                *self.0.try_borrow_mut().unwrap() += 1;
+            }
+        }
+
+        let cell = StableLock::new(5);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = Guard(&cell);
+            panic!("Unrelated panic");
+        }));
+        assert!(res.is_err());
+        assert!(!cell.is_poisoned());
+        assert_eq!(cell.try_borrow().as_deref(), Ok(&6));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_alloc_in() {
+        use core::alloc::{AllocError, Layout};
+        use core::cell::Cell;
+
+        struct Counting<'a>(&'a Cell<usize>);
+
+        unsafe impl Allocator for Counting<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let live = Cell::new(0);
+        let cell = StableLock::new_in(5, Counting(&live));
+        assert_eq!(live.get(), 1);
+        let b = cell.try_borrow().unwrap();
+        drop(cell);
+        assert_eq!(live.get(), 1);
+        assert_eq!(*b, 5);
+        drop(b);
+        assert_eq!(live.get(), 0);
+
+        let cell = StableLock::<[i32], _>::new_from_in([1, 2, 3], Counting(&live));
+        assert_eq!(&*cell.try_borrow().unwrap(), &[1, 2, 3]);
+        drop(cell);
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    fn test_ptr_eq() {
+        let a = StableLock::new(1);
+        let b = StableLock::new(1);
+        assert!(a.ptr_eq(&a));
+        assert!(!a.ptr_eq(&b));
+        assert_eq!(a.id(), a.id());
+        assert_ne!(a.id(), b.id());
+    }
+
     #[test]
     fn test_drop_borrow() {
         let cell = StableLock::new(-1);
@@ -284,3 +732,133 @@ mod tests {
         assert_eq!(*b, -1);
     }
 }
+
+#[cfg(all(test, feature = "loom"))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::sync::atomic::AtomicBool;
+    use loom::thread;
+
+    #[test]
+    fn loom_shared_borrows() {
+        loom::model(|| {
+            let lock = Arc::new(StableLock::new(1));
+            let other = Arc::clone(&lock);
+
+            let handle = thread::spawn(move || {
+                let b = other.try_borrow().unwrap();
+                assert_eq!(*b, 1);
+            });
+            let b = lock.try_borrow().unwrap();
+            assert_eq!(*b, 1);
+            drop(b);
+            handle.join().unwrap();
+
+            assert!(lock.try_borrow_mut().is_ok());
+        });
+    }
+
+    #[test]
+    fn loom_unique_borrows() {
+        loom::model(|| {
+            let lock = Arc::new(StableLock::new(0));
+            let held = Arc::new(AtomicBool::new(false));
+
+            let handles = (0..2)
+                .map(|_| {
+                    let lock = Arc::clone(&lock);
+                    let held = Arc::clone(&held);
+                    thread::spawn(move || {
+                        if let Ok(mut b) = lock.try_borrow_mut() {
+                            assert!(!held.swap(true, Ordering::SeqCst));
+                            *b += 1;
+                            held.store(false, Ordering::SeqCst);
+                        }
+                    })
+                })
+                .collect::<alloc::vec::Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            let b = lock.try_borrow().unwrap();
+            assert!((1..=2).contains(&*b));
+        });
+    }
+
+    #[test]
+    fn loom_upgrade_excludes_writers() {
+        loom::model(|| {
+            let lock = Arc::new(StableLock::new(0));
+            let other = Arc::clone(&lock);
+
+            let handle = thread::spawn(move || {
+                if let Ok(mut b) = other.try_borrow_mut() {
+                    *b += 10;
+                }
+            });
+            if let Ok(up) = lock.try_borrow_upgradable() {
+                let seen = *up;
+                if let Ok(mut b) = up.upgrade() {
+                    assert_eq!(*b, seen);
+                    *b += 1;
+                }
+            }
+            handle.join().unwrap();
+
+            let b = lock.try_borrow().unwrap();
+            assert!([0, 1, 10, 11].contains(&*b));
+        });
+    }
+
+    #[test]
+    fn loom_drop_with_shared_borrow() {
+        loom::model(|| {
+            let lock = StableLock::new(alloc::string::String::from("value"));
+            let b = lock.try_borrow().unwrap();
+
+            let handle = thread::spawn(move || {
+                assert_eq!(*b, "value");
+                drop(b);
+            });
+            drop(lock);
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn loom_drop_with_unique_borrow() {
+        loom::model(|| {
+            let lock = StableLock::new(alloc::string::String::from("value"));
+            let mut b = lock.try_borrow_mut().unwrap();
+
+            let handle = thread::spawn(move || {
+                b.push('!');
+                assert_eq!(*b, "value!");
+            });
+            drop(lock);
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn loom_drop_with_racing_borrow() {
+        loom::model(|| {
+            let lock = StableLock::new(5);
+            let b = lock.try_borrow().unwrap();
+
+            let handle = thread::spawn(move || {
+                let lock = lock;
+                let b2 = lock.try_borrow();
+                drop(lock);
+                if let Ok(b2) = b2 {
+                    assert_eq!(*b2, 5);
+                }
+            });
+            assert_eq!(*b, 5);
+            drop(b);
+            handle.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,8 @@
+//! Atomics used by [`StableLock`](crate::stable::lock::StableLock), swapped for loom's
+//! instrumented versions when model checking
+
+#[cfg(not(feature = "loom"))]
+pub(crate) use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "loom")]
+pub(crate) use loom::sync::atomic::{AtomicUsize, Ordering};
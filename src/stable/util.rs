@@ -1,7 +1,25 @@
 use core::mem;
+use crate::stable::BorrowError;
+
+/// Number of low bits used for flags, above which shared borrows are counted
+const FLAG_BITS: u32 = 4;
+/// A single shared borrow in the packed count
+const REF: usize = 1 << FLAG_BITS;
+/// Flags which are kept when the last borrow is released
+const KEPT: usize = 0b1010;
+
+/// Maximum number of shared borrows a single [`BorrowState`] can track
+pub const MAX_REFS: usize = usize::MAX >> FLAG_BITS;
 
 // TODO: Once we get custom niches, make this an enum again
-#[derive(Debug, Copy, Clone)]
+/// Packed borrow state. Bit 0 marks a unique borrow, bit 1 marks that the owner was dropped, bit 2
+/// marks that one of the shared borrows is upgradable, bit 3 marks that the value was poisoned, and
+/// the remaining bits count shared borrows.
+///
+/// Counters never wrap or saturate: once [`MAX_REFS`] shared borrows are outstanding, further
+/// shared borrows fail with [`BorrowError::Overflow`] and the state is left unchanged. Releasing a
+/// borrow that isn't held is a no-op rather than an underflow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct BorrowState(usize);
 
@@ -10,7 +28,7 @@ impl BorrowState {
         BorrowState(val)
     }
 
-    pub fn new() -> BorrowState {
+    pub const fn new() -> BorrowState {
         BorrowState(0)
     }
 
@@ -18,9 +36,10 @@ impl BorrowState {
         self.0
     }
 
+    /// Check whether there are no borrows, and the owner hasn't been dropped
     #[inline]
     pub fn is_none(self) -> bool {
-        self.0 == 0
+        self.clear_poison().0 == 0
     }
 
     #[inline]
@@ -35,12 +54,37 @@ impl BorrowState {
 
     #[inline]
     pub fn is_ref(self) -> bool {
-        self.0 >> 2 > 0
+        self.ref_count() > 0
+    }
+
+    #[inline]
+    pub fn is_upgradable(self) -> bool {
+        self.0 & 0b100 != 0
+    }
+
+    #[inline]
+    pub fn is_poisoned(self) -> bool {
+        self.0 & 0b1000 != 0
+    }
+
+    #[inline]
+    pub fn make_poisoned(self) -> BorrowState {
+        BorrowState(self.0 | 0b1000)
+    }
+
+    #[inline]
+    pub fn clear_poison(self) -> BorrowState {
+        BorrowState(self.0 & !0b1000)
+    }
+
+    #[inline]
+    pub fn ref_count(self) -> usize {
+        self.0 >> FLAG_BITS
     }
 
     #[inline]
     pub fn is_mut(self) -> bool {
-        self.is_borrow() && (self.0 >> 2 == 0)
+        self.is_borrow() && !self.is_ref()
     }
 
     #[inline]
@@ -49,42 +93,103 @@ impl BorrowState {
     }
 
     #[inline]
-    pub fn incr_ref(self) -> Option<BorrowState> {
-        if self.is_none() {
-            Some(BorrowState(self.0 | 0b100))
+    pub fn incr_ref(self) -> Result<BorrowState, BorrowError> {
+        if self.is_drop() {
+            Err(BorrowError::Dropping)
+        } else if self.is_poisoned() {
+            Err(BorrowError::Poisoned)
+        } else if self.is_none() {
+            Ok(BorrowState(self.0 | REF))
+        } else if self.ref_count() == MAX_REFS {
+            Err(BorrowError::Overflow)
         } else if self.is_ref() {
-            Some(BorrowState(self.0 + 0b100))
+            Ok(BorrowState(self.0 + REF))
         } else {
-            None
+            Err(BorrowError::AlreadyMut)
         }
     }
 
     #[inline]
     pub fn decr_ref(self) -> (BorrowState, bool) {
         if self.is_ref() {
-            if (self.0 >> 2) - 1 == 0 {
-                (BorrowState(self.0 & 0b10), self.is_drop())
+            if self.ref_count() == 1 {
+                (BorrowState(self.0 & KEPT), self.is_drop())
             } else {
-                (BorrowState(self.0 - 0b100), false)
+                (BorrowState(self.0 - REF), false)
             }
         } else {
             (self, false)
         }
     }
 
+    /// Turn the only shared borrow into a unique borrow. Fails if other shared borrows are
+    /// outstanding, including an upgradable one.
+    #[inline]
+    pub fn promote(self) -> Result<BorrowState, BorrowError> {
+        if self.is_upgradable() || self.ref_count() != 1 {
+            Err(BorrowError::AlreadyShared)
+        } else {
+            Ok(BorrowState((self.0 & KEPT) | 0b1))
+        }
+    }
+
+    /// Turn a unique borrow into a shared borrow
+    #[inline]
+    pub fn demote(self) -> BorrowState {
+        if self.is_mut() {
+            BorrowState((self.0 & KEPT) | REF)
+        } else {
+            self
+        }
+    }
+
+    /// Add a shared borrow which may later be upgraded. Only one may be held at a time.
+    #[inline]
+    pub fn incr_upgradable(self) -> Result<BorrowState, BorrowError> {
+        if self.is_upgradable() {
+            return Err(BorrowError::AlreadyShared);
+        }
+        self.incr_ref().map(|state| BorrowState(state.0 | 0b100))
+    }
+
+    #[inline]
+    pub fn decr_upgradable(self) -> (BorrowState, bool) {
+        if self.is_upgradable() {
+            BorrowState(self.0 & !0b100).decr_ref()
+        } else {
+            (self, false)
+        }
+    }
+
+    /// Turn the upgradable borrow into a unique borrow, if it is the only shared borrow
     #[inline]
-    pub fn incr_mut(self) -> Option<BorrowState> {
-        if self.is_none() {
-            Some(BorrowState(self.0 | 0b1))
+    pub fn upgrade(self) -> Result<BorrowState, BorrowError> {
+        if !self.is_upgradable() || self.ref_count() != 1 {
+            Err(BorrowError::AlreadyShared)
         } else {
-            None
+            Ok(BorrowState((self.0 & KEPT) | 0b1))
+        }
+    }
+
+    #[inline]
+    pub fn incr_mut(self) -> Result<BorrowState, BorrowError> {
+        if self.is_drop() {
+            Err(BorrowError::Dropping)
+        } else if self.is_poisoned() {
+            Err(BorrowError::Poisoned)
+        } else if self.is_none() {
+            Ok(BorrowState(self.0 | 0b1))
+        } else if self.is_ref() {
+            Err(BorrowError::AlreadyShared)
+        } else {
+            Err(BorrowError::AlreadyMut)
         }
     }
 
     #[inline]
     pub fn decr_mut(self) -> (BorrowState, bool) {
         if self.is_mut() {
-            (BorrowState(self.0 & 0b10), self.is_drop())
+            (BorrowState(self.0 & KEPT), self.is_drop())
         } else {
             (self, false)
         }
@@ -92,3 +197,108 @@ impl BorrowState {
 }
 
 const _: () = assert!(mem::size_of::<BorrowState>() == mem::size_of::<usize>());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ref_count() {
+        let state = BorrowState::new()
+            .incr_ref()
+            .and_then(BorrowState::incr_ref)
+            .unwrap();
+        assert_eq!(state.ref_count(), 2);
+        assert_eq!(state.incr_mut(), Err(BorrowError::AlreadyShared));
+
+        let (state, drop) = state.decr_ref();
+        assert!(!drop);
+        let (state, drop) = state.decr_ref();
+        assert!(!drop);
+        assert!(state.is_none());
+    }
+
+    #[test]
+    fn test_max_refs() {
+        let state = BorrowState::from_val((MAX_REFS - 1) << FLAG_BITS);
+        let state = state.incr_ref().unwrap();
+        assert_eq!(state.ref_count(), MAX_REFS);
+        assert_eq!(state.incr_ref(), Err(BorrowError::Overflow));
+        assert!(!state.is_borrow());
+        assert!(!state.is_drop());
+
+        let (state, drop) = state.decr_ref();
+        assert!(!drop);
+        assert_eq!(state.ref_count(), MAX_REFS - 1);
+        assert!(state.incr_ref().is_ok());
+    }
+
+    #[test]
+    fn test_max_refs_drop() {
+        let state = BorrowState::from_val(MAX_REFS << FLAG_BITS).make_drop();
+        assert_eq!(state.ref_count(), MAX_REFS);
+        assert!(state.is_drop());
+
+        let (state, drop) = state.decr_ref();
+        assert!(!drop);
+        assert_eq!(state.ref_count(), MAX_REFS - 1);
+        assert!(state.is_drop());
+    }
+
+    #[test]
+    fn test_upgradable() {
+        let state = BorrowState::new().incr_upgradable().unwrap();
+        assert!(state.is_upgradable());
+        assert_eq!(state.incr_upgradable(), Err(BorrowError::AlreadyShared));
+        assert_eq!(state.incr_mut(), Err(BorrowError::AlreadyShared));
+
+        let shared = state.incr_ref().unwrap();
+        assert_eq!(shared.upgrade(), Err(BorrowError::AlreadyShared));
+        assert_eq!(shared.decr_ref().0, state);
+
+        let unique = state.upgrade().unwrap();
+        assert!(unique.is_mut());
+        assert_eq!(unique.decr_mut(), (BorrowState::new(), false));
+
+        assert_eq!(state.make_drop().decr_upgradable(), (BorrowState::new().make_drop(), true));
+        assert_eq!(BorrowState::new().upgrade(), Err(BorrowError::AlreadyShared));
+    }
+
+    #[test]
+    fn test_promote_demote() {
+        let state = BorrowState::new().incr_ref().unwrap();
+        let unique = state.promote().unwrap();
+        assert!(unique.is_mut());
+        assert_eq!(unique.demote(), state);
+        assert_eq!(unique.make_drop().demote(), state.make_drop());
+
+        let shared = state.incr_ref().unwrap();
+        assert_eq!(shared.promote(), Err(BorrowError::AlreadyShared));
+        let upgradable = BorrowState::new().incr_upgradable().unwrap();
+        assert_eq!(upgradable.promote(), Err(BorrowError::AlreadyShared));
+        assert_eq!(state.demote(), state);
+    }
+
+    #[test]
+    fn test_poisoned() {
+        let state = BorrowState::new().incr_mut().unwrap();
+        let (state, drop) = state.make_poisoned().decr_mut();
+        assert!(!drop);
+        assert!(state.is_poisoned());
+        assert!(state.is_none());
+        assert_eq!(state.incr_ref(), Err(BorrowError::Poisoned));
+        assert_eq!(state.incr_mut(), Err(BorrowError::Poisoned));
+        assert_eq!(state.clear_poison(), BorrowState::new());
+
+        let state = BorrowState::new().incr_mut().unwrap().make_poisoned().make_drop();
+        assert_eq!(state.incr_mut(), Err(BorrowError::Dropping));
+        assert!(state.decr_mut().1);
+    }
+
+    #[test]
+    fn test_decr_unheld() {
+        let state = BorrowState::new();
+        assert_eq!(state.decr_ref(), (state, false));
+        assert_eq!(state.decr_mut(), (state, false));
+    }
+}
@@ -2,3 +2,66 @@
 
 pub mod simple;
 pub mod object;
+pub mod order_stat;
+mod error;
+mod render;
+mod stats;
+mod validate;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+
+pub use error::Error;
+pub use stats::TreeStats;
+pub use validate::InvariantViolation;
+#[cfg(feature = "arbitrary")]
+pub use fuzz::ArbitraryShape;
+
+/// How operations that remove nodes treat the children of those nodes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Remove children along with their parent
+    Remove,
+    /// Move children into the place of their removed parent, under its parent or as roots
+    Reparent,
+}
+
+/// How traversals treat nodes which are already borrowed incompatibly
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorrowPolicy {
+    /// Pass over the node, but still visit its children
+    Skip,
+    /// Stop the traversal with an error
+    Fail,
+}
+
+/// Decision made for each node visited by a filtered traversal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Yield the node and continue into its children
+    Accept,
+    /// Skip the node, but continue into its children
+    Skip,
+    /// Skip the node and all its descendants
+    Prune,
+}
+
+/// Which way to go from each node visited by a decision walk, such as
+/// [`simple::Tree::evaluate`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChildSelect {
+    /// Continue into the child at this index
+    Child(usize),
+    /// End the walk at the current node
+    Stop,
+}
+
+/// How [`simple::Tree::rebalance`] limits the number of children of each node
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rebalance {
+    /// Group children into runs of this many, in order, with any remainder in the last group.
+    /// Every group but the last is full, as in a bulk-loaded B-tree.
+    Packed(usize),
+    /// Group children into as few groups as possible with at most this many children each,
+    /// with the sizes of the groups differing by at most one
+    Even(usize),
+}
@@ -0,0 +1,59 @@
+
+use core::fmt;
+use core::cell::{BorrowError, BorrowMutError};
+use crate::stable::BorrowError as StableBorrowError;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
+/// Possible failures for tree operations. Fallible operations on both kinds of tree report this
+/// rather than panicking, and more variants may be added as needed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Node doesn't exist
+    Missing,
+    /// Node can't be borrowed as requested
+    CantBorrow,
+    /// Key is malformed or already in use
+    InvalidKey,
+    /// Node relationships would form a cycle
+    Cycle,
+    /// Node key was reserved, but hasn't been given a value yet
+    Unfilled,
+    /// Node was given more than one parent
+    MultipleParents,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Missing => write!(f, "Tree missing expected node"),
+            Error::CantBorrow => write!(f, "Tree node is already borrowed incompatibly"),
+            Error::InvalidKey => write!(f, "Tree key is malformed or already in use"),
+            Error::Cycle => write!(f, "Tree nodes would form a cycle"),
+            Error::Unfilled => write!(f, "Tree node was reserved but not yet filled"),
+            Error::MultipleParents => write!(f, "Tree node was given more than one parent"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdError for Error {}
+
+impl From<BorrowError> for Error {
+    fn from(_: BorrowError) -> Self {
+        Error::CantBorrow
+    }
+}
+
+impl From<BorrowMutError> for Error {
+    fn from(_: BorrowMutError) -> Self {
+        Error::CantBorrow
+    }
+}
+
+impl From<StableBorrowError> for Error {
+    fn from(_: StableBorrowError) -> Self {
+        Error::CantBorrow
+    }
+}
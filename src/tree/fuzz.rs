@@ -0,0 +1,112 @@
+//! Random tree generation for fuzzing and property tests, with [`arbitrary`]
+
+use alloc::vec::Vec;
+use arbitrary::{Arbitrary, Result, Unstructured};
+use crate::tree::{object, simple};
+
+/// Limits on the shape of randomly generated trees
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArbitraryShape {
+    /// Maximum number of roots
+    pub max_roots: usize,
+    /// Maximum depth of any node, where roots have a depth of zero
+    pub max_depth: usize,
+    /// Maximum number of children of any node
+    pub max_children: usize,
+}
+
+impl Default for ArbitraryShape {
+    fn default() -> Self {
+        ArbitraryShape {
+            max_roots: 4,
+            max_depth: 8,
+            max_children: 8,
+        }
+    }
+}
+
+impl ArbitraryShape {
+    /// Generate the nodes of a tree in pre-order, as each node's value alongside the index of its
+    /// parent
+    fn nodes<'a, T: Arbitrary<'a>>(&self, u: &mut Unstructured<'a>) -> Result<Vec<(T, Option<usize>)>> {
+        let mut out = Vec::new();
+        // (parent index, depth), in reverse order of generation
+        let roots = u.int_in_range(0..=self.max_roots)?;
+        let mut stack = (0..roots).map(|_| (None, 0)).collect::<Vec<_>>();
+
+        while let Some((parent, depth)) = stack.pop() {
+            let idx = out.len();
+            out.push((T::arbitrary(u)?, parent));
+            if depth < self.max_depth {
+                let children = u.int_in_range(0..=self.max_children)?;
+                stack.extend((0..children).map(|_| (Some(idx), depth + 1)));
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Generate a simple tree within these limits
+    pub fn simple<'a, T: Arbitrary<'a>>(&self, u: &mut Unstructured<'a>) -> Result<simple::Tree<T>> {
+        Ok(self.nodes(u)?.into_iter().collect())
+    }
+
+    /// Generate an object tree within these limits
+    pub fn object<'a, T: Arbitrary<'a>>(&self, u: &mut Unstructured<'a>) -> Result<object::Tree<T>> {
+        let tree = object::Tree::new();
+        let mut keys = Vec::new();
+        for (val, parent) in self.nodes(u)? {
+            let key = match parent.and_then(|parent| keys.get(parent)) {
+                Some(&parent) => tree.add_child(val, parent)
+                    .map_err(|_| arbitrary::Error::IncorrectFormat)?,
+                None => tree.add_root(val),
+            };
+            keys.push(key);
+        }
+        Ok(tree)
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for simple::Tree<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        ArbitraryShape::default().simple(u)
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for object::Tree<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        ArbitraryShape::default().object(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_shape() {
+        let data = (0..=255).cycle().take(4096).collect::<Vec<u8>>();
+        let shape = ArbitraryShape { max_roots: 3, max_depth: 2, max_children: 3 };
+
+        let tree = shape.simple::<u8>(&mut Unstructured::new(&data)).unwrap();
+        assert!(tree.validate().is_ok());
+        let stats = tree.stats();
+        assert!(stats.roots <= 3);
+        assert!(stats.max_depth <= 2);
+        assert!(tree.unordered_keys().all(|key| tree.child_keys(key).unwrap().len() <= 3));
+
+        let tree = shape.object::<u8>(&mut Unstructured::new(&data)).unwrap();
+        assert!(tree.validate().is_ok());
+        assert!(tree.stats().max_depth <= 2);
+    }
+
+    #[test]
+    fn test_arbitrary_exhausted() {
+        let tree = simple::Tree::<u32>::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert!(tree.is_empty());
+
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let tree = object::Tree::<u32>::arbitrary(&mut Unstructured::new(&data)).unwrap();
+        assert!(tree.validate().is_ok());
+    }
+}
@@ -9,6 +9,20 @@
 //!
 //! Implementation of a nicely traversable tree that supports mutable references to multiple
 //! nodes concurrently
+//!
+//! Children are kept in the order they were attached to their parent. New children are appended,
+//! and detaching a child preserves the order of the remaining children.
+//!
+//! ## Reentrancy
+//!
+//! The tree never holds internal borrows while running user code or while a returned value is
+//! alive. Callbacks, iterators and node references may all be used to read or modify the tree
+//! they came from. Structural reads such as [`Tree::child_keys`] take a snapshot up front, and
+//! lazy iterators read each node's children when that node is reached. Snapshots share storage
+//! with the tree, which only copies a key list if it is modified while a snapshot of it is alive.
+//! Node values are the only thing borrowed for longer, and accessing a value which is
+//! incompatibly borrowed fails with [`Error::CantBorrow`] rather than panicking. Removed values are
+//! dropped after the tree releases its internal borrows, so their destructors may also use the tree.
 
 #[cfg(not(feature = "atomic"))]
 mod __stable {
@@ -17,6 +31,7 @@ mod __stable {
     pub type StableMut<'a, T> = crate::stable::cell::StableMut<'a, T>;
 
     pub type Cell<T> = core::cell::RefCell<T>;
+    pub type Shared<T> = alloc::rc::Rc<T>;
 }
 
 #[cfg(feature = "atomic")]
@@ -25,6 +40,8 @@ mod __stable {
     pub type StableRef<'a, T> = crate::stable::lock::StableRef<'a, T>;
     pub type StableMut<'a, T> = crate::stable::lock::StableMut<'a, T>;
 
+    pub type Shared<T> = std::sync::Arc<T>;
+
     pub struct Cell<T>(std::sync::Mutex<T>);
 
     impl<T> Cell<T> {
@@ -32,12 +49,14 @@ mod __stable {
             Cell(std::sync::Mutex::new(val))
         }
 
+        // Guards are never held while running user code, so a poisoned lock can't have been left
+        // half-updated and is safe to recover
         pub fn borrow(&self) -> std::sync::MutexGuard<'_, T> {
-            self.0.lock().unwrap()
+            self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
         }
 
         pub fn borrow_mut(&self) -> std::sync::MutexGuard<'_, T> {
-            self.0.lock().unwrap()
+            self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
         }
     }
 }
@@ -45,17 +64,28 @@ mod __stable {
 pub(crate) use __stable::*;
 
 mod error;
+#[cfg(feature = "expr")]
+pub mod expr;
+mod iter;
 mod node_ref;
+mod query;
+mod statechart;
+mod transaction;
 mod tree;
 
 pub use error::Error;
+pub use iter::{BfsKeys, Descendants, DfsKeys, PostOrderKeys};
 pub use node_ref::{NodeRef, NodeRefMut};
-pub use tree::{Tree, TreeKey};
+pub use query::Query;
+pub use statechart::{State, StateMachine, Transition};
+pub use transaction::Transaction;
+pub use tree::{BorrowKind, ChildKeys, RelationsRef, Removal, Tree, TreeKey};
 
 #[cfg(test)]
 mod tests {
     use alloc::vec::Vec;
     use super::*;
+    use crate::tree::{BorrowPolicy, ChildSelect, Filter, OrphanPolicy};
     use super::error::Result;
 
     #[test]
@@ -82,8 +112,8 @@ mod tests {
 
         {
             let mut root = tree.roots_mut().next().unwrap().unwrap();
-            root.new_child(true);
-            root.new_child(false);
+            root.new_child(true).unwrap();
+            root.new_child(false).unwrap();
         }
 
         assert_eq!(tree.len(), 3);
@@ -91,7 +121,7 @@ mod tests {
         let roots = tree.roots().collect::<Result<Vec<_>>>().unwrap();
 
         assert_eq!(roots.len(), 1);
-        assert_eq!(*roots[0], true);
+        assert!(*roots[0]);
 
         let children = roots[0].children().collect::<Result<Vec<_>>>().unwrap();
 
@@ -110,7 +140,7 @@ mod tests {
             let mut root = root.try_promote()
                 .expect("Could promote unique reference");
 
-            root.new_child(false);
+            root.new_child(false).unwrap();
         }
 
         {
@@ -133,10 +163,1079 @@ mod tests {
             let mut root = tree.try_get_mut(id)
                 .unwrap();
 
-            root.new_child(false);
+            root.new_child(false).unwrap();
 
             let root = root.demote();
-            assert_eq!(*root, true);
+            assert!(*root);
+        }
+    }
+
+    #[test]
+    fn test_new_child_key() {
+        let tree = Tree::new();
+        let id = tree.add_root(0);
+
+        let child = tree.try_get_mut(id)
+            .unwrap()
+            .new_child(1)
+            .unwrap();
+
+        assert_eq!(tree.parent_key_of(child), Some(id));
+        assert_eq!(*tree.try_get(child).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_add_child_missing() {
+        let tree = Tree::new();
+        let id = tree.add_root(0);
+        tree.remove_recursive(id);
+
+        assert!(matches!(tree.add_child(1, id), Err(Error::Missing)));
+        assert_eq!(tree.len(), 0);
+    }
+
+    #[test]
+    fn test_add_with() {
+        let tree = Tree::new();
+        let root = tree.add_root_with(|key| (key, 0));
+        let child = tree.add_child_with(root, |key| (key, 1)).unwrap();
+        assert_eq!(tree.try_get(child).unwrap().0, child);
+
+        let res = tree.try_add_child_with(root, |_| Err(Error::InvalidKey));
+        assert!(matches!(res, Err(Error::InvalidKey)));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(&*tree.child_keys(root), [child]);
+
+        tree.remove_recursive(child);
+        let res = tree.try_add_child_with(child, |key| Ok::<_, Error>((key, 2)));
+        assert!(matches!(res, Err(Error::Missing)));
+        assert!(tree.try_add_root_with(|_| Err::<(TreeKey, i32), _>(())).is_err());
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_reserve_fill() {
+        let tree = Tree::new();
+        let root = tree.add_root((None, 0));
+        let a = tree.reserve_key();
+        let b = tree.add_child((Some(a), 2), root).unwrap();
+        tree.set_child(root, a).unwrap();
+
+        assert!(matches!(tree.try_get(a), Err(Error::Unfilled)));
+        assert!(matches!(tree.try_get_mut(a), Err(Error::Unfilled)));
+        assert_eq!(tree.render_ascii(|out, val| write!(out, "{}", val.1)), "0\n|-- 2\n`-- (Unfilled)\n");
+        tree.retain(OrphanPolicy::Remove, |_, _| true).unwrap();
+
+        tree.fill(a, (Some(b), 1)).unwrap();
+        assert!(matches!(tree.fill(a, (None, 3)), Err(Error::InvalidKey)));
+        let node = tree.try_get(a).unwrap();
+        assert_eq!(node.0, Some(b));
+        assert_eq!(tree.try_get(node.0.unwrap()).unwrap().0, Some(a));
+
+        tree.remove_recursive(a);
+        assert!(matches!(tree.fill(a, (None, 3)), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_reentrant_add_with() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let mut inner = None;
+        let child = tree.add_child_with(root, |_| {
+            inner = Some(tree.add_child(1, root).unwrap());
+            2
+        }).unwrap();
+        assert_eq!(&*tree.child_keys(root), [inner.unwrap(), child]);
+
+        let res = tree.add_child_with(root, |_| {
+            tree.remove_recursive(root);
+            3
+        });
+        assert!(matches!(res, Err(Error::Missing)));
+        assert!(tree.is_empty());
+
+        let key = tree.add_root_with(|key| {
+            assert!(matches!(tree.try_get(key), Err(Error::Unfilled)));
+            4
+        });
+        assert_eq!(*tree.try_get(key).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_reentrant_iteration() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        tree.add_child(1, root).unwrap();
+        tree.add_child(2, root).unwrap();
+
+        for child in tree.child_keys(root).iter() {
+            tree.add_child(3, *child).unwrap();
         }
+        assert_eq!(tree.len(), 5);
+
+        let node = tree.try_get(root).unwrap();
+        let vals = node.descendants()
+            .map(|node| {
+                let node = node.unwrap();
+                if *node == 1 {
+                    tree.add_child(4, node.key()).unwrap();
+                }
+                *node
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 3, 2, 3]);
+        assert_eq!(tree.len(), 6);
+
+        tree.retain(OrphanPolicy::Remove, |key, &val| {
+            if val == 2 {
+                tree.add_child(5, key).unwrap();
+            }
+            val != 3
+        }).unwrap();
+        assert_eq!(tree.len(), 5);
+        assert!(matches!(tree.try_get_mut(root), Err(Error::CantBorrow)));
+    }
+
+    #[test]
+    fn test_try_set_child() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, a).unwrap();
+        let other = tree.add_root(3);
+
+        assert!(matches!(tree.try_set_child(b, root), Err(Error::Cycle)));
+        assert!(matches!(tree.try_set_child(a, a), Err(Error::Cycle)));
+        assert!(tree.set_child(b, a).is_none());
+
+        tree.try_set_child(other, b).unwrap();
+        assert_eq!(tree.parent_key_of(b), Some(other));
+        assert!(tree.child_keys(a).is_empty());
+
+        tree.remove_recursive(a);
+        assert!(matches!(tree.try_set_child(a, b), Err(Error::Missing)));
+        assert!(matches!(tree.try_set_child(other, a), Err(Error::Missing)));
+        assert_eq!(tree.parent_key_of(b), Some(other));
+    }
+
+    #[test]
+    fn test_try_remove_child() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_root(2);
+
+        assert!(matches!(tree.try_remove_child(root, b), Err(Error::Missing)));
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [root, b]);
+
+        tree.try_remove_child(root, a).unwrap();
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [root, b, a]);
+        assert!(matches!(tree.try_remove_child(root, a), Err(Error::Missing)));
+
+        tree.remove_recursive(root);
+        tree.remove_child(root, a);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [b, a]);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let removed = tree.add_child(1, root).unwrap();
+        let child = tree.add_child(2, root).unwrap();
+        tree.remove_recursive(removed);
+        let reused = tree.add_child(3, child).unwrap();
+
+        let parts = [root, child, reused]
+            .into_iter()
+            .map(|key| (key, tree.parent_key_of(key), *tree.try_get(key).unwrap()))
+            .collect::<Vec<_>>();
+
+        let new_tree = Tree::from_parts(parts).unwrap();
+        assert_eq!(new_tree.len(), 3);
+        for key in [root, child, reused] {
+            assert_eq!(*new_tree.try_get(key).unwrap(), *tree.try_get(key).unwrap());
+            assert_eq!(new_tree.parent_key_of(key), tree.parent_key_of(key));
+        }
+        assert_eq!(new_tree.child_keys_of(child).collect::<Vec<_>>(), [reused]);
+        assert!(matches!(new_tree.try_get(removed), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_from_parts_invalid() {
+        let tree = Tree::new();
+        let a = tree.add_root(0);
+        let b = tree.add_child(1, a).unwrap();
+        let c = tree.add_root(2);
+        tree.remove_recursive(c);
+
+        assert!(matches!(Tree::from_parts([(a, None, 0), (a, None, 1)]), Err(Error::InvalidKey)));
+        assert!(matches!(Tree::from_parts([(a, None, 0), (b, Some(c), 1)]), Err(Error::Missing)));
+        assert!(matches!(Tree::from_parts([(a, Some(b), 0), (b, Some(a), 1)]), Err(Error::Cycle)));
+
+        let high_version = TreeKey::from_ffi((0x7fff_ffff << 32) | 1);
+        assert!(matches!(Tree::from_parts([(high_version, None, 0)]), Err(Error::InvalidKey)));
+        let high_index = TreeKey::from_ffi((1 << 32) | 0xffff_fff0);
+        assert!(matches!(Tree::from_parts([(high_index, None, 0)]), Err(Error::InvalidKey)));
+    }
+
+    #[test]
+    fn test_key_ffi() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+
+        let ffi = root.to_ffi();
+        assert_eq!(TreeKey::from_ffi(ffi), root);
+        assert_eq!(*tree.try_get(TreeKey::from_ffi(ffi)).unwrap(), 0);
+        assert!(tree.try_get(TreeKey::from_ffi(0)).is_err());
+    }
+
+    #[test]
+    fn test_remove_recursive() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let child = tree.add_child(1, root).unwrap();
+        tree.add_child(2, child).unwrap();
+        let other = tree.add_root(3);
+
+        tree.remove_recursive(child);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.child_keys_of(root).count(), 0);
+
+        tree.remove_recursive(root);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [other]);
+    }
+
+    #[test]
+    fn test_retain() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        tree.add_child(4, b).unwrap();
+
+        tree.retain(OrphanPolicy::Reparent, |_, &val| val != 1).unwrap();
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.child_keys_of(root).collect::<Vec<_>>(), [c, b]);
+        assert_eq!(tree.parent_key_of(c), Some(root));
+
+        tree.retain(OrphanPolicy::Remove, |_, &val| val != 2).unwrap();
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.child_keys_of(root).collect::<Vec<_>>(), [c]);
+
+        let _borrow = tree.try_get_mut(c).unwrap();
+        assert!(matches!(tree.retain(OrphanPolicy::Remove, |_, _| false), Err(Error::CantBorrow)));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_clear() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let child = tree.add_child(1, root).unwrap();
+
+        let borrow = tree.try_get(child).unwrap();
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_keys().count(), 0);
+        assert_eq!(*borrow, 1);
+        drop(borrow);
+
+        tree.add_root(2);
+        assert!(matches!(tree.try_get(root), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let children = (1..100)
+            .map(|i| tree.add_child(i, root).unwrap())
+            .collect::<Vec<_>>();
+        for child in &children[1..] {
+            tree.remove_recursive(*child);
+        }
+
+        tree.shrink_to_fit();
+        assert_eq!(tree.child_keys_of(root).collect::<Vec<_>>(), &children[..1]);
+        assert_eq!(tree.parent_key_of(children[0]), Some(root));
+        assert_eq!(tree.parent_key_of(children[1]), None);
+    }
+
+    #[test]
+    fn test_child_keys() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, root).unwrap();
+
+        tree.remove_child(root, b);
+        assert_eq!(&*tree.child_keys(root), &[a, c]);
+
+        tree.set_child(root, b);
+        let node = tree.try_get(root).unwrap();
+        assert_eq!(&*node.child_keys(), &[a, c, b]);
+        assert!(tree.child_keys(a).is_empty());
+    }
+
+    #[test]
+    fn test_descendants() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        let b = tree.add_child(3, a).unwrap();
+        tree.add_child(4, root).unwrap();
+
+        let root = tree.try_get(root).unwrap();
+        let vals = root.descendants()
+            .map(|node| *node.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 2, 3, 4]);
+
+        let _borrow = tree.try_get_mut(b).unwrap();
+        let results = root.descendants()
+            .map(|node| node.map(|node| *node))
+            .collect::<Vec<_>>();
+        assert!(matches!(results[..], [Ok(1), Ok(2), Err(Error::CantBorrow), Ok(4)]));
+    }
+
+    #[test]
+    fn test_descendants_matching() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        let b = tree.add_child(3, root).unwrap();
+        tree.add_child(4, b).unwrap();
+
+        let root = tree.try_get(root).unwrap();
+        let vals = root.descendants_matching(|&val| if val % 2 == 1 { Filter::Skip } else { Filter::Accept })
+            .map(|node| *node.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [2, 4]);
+
+        let vals = root.descendants_matching(|&val| if val == 1 { Filter::Prune } else { Filter::Accept })
+            .map(|node| *node.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [3, 4]);
+    }
+
+    #[test]
+    fn test_descendants_limits() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, a).unwrap();
+        let b = tree.add_child(4, root).unwrap();
+        tree.add_child(5, b).unwrap();
+
+        let root = tree.try_get(root).unwrap();
+        let vals = root.descendants()
+            .take_depth(1)
+            .map(|node| *node.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 4]);
+
+        let vals = root.descendants()
+            .take_breadth(1)
+            .map(|node| *node.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 2]);
+
+        let vals = root.descendants()
+            .take_depth(0)
+            .count();
+        assert_eq!(vals, 0);
+    }
+
+    #[test]
+    fn test_post_order() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, a).unwrap();
+        let c = tree.add_child(3, root).unwrap();
+
+        let keys = tree.post_order_keys().collect::<Vec<_>>();
+        assert_eq!(keys, [b, a, c, root]);
+
+        let _borrow = tree.try_get_mut(a).unwrap();
+        let results = tree.iter_post_order()
+            .map(|node| node.map(|node| *node))
+            .collect::<Vec<_>>();
+        assert!(matches!(results[..], [Ok(2), Err(Error::CantBorrow), Ok(3), Ok(0)]));
+    }
+
+    #[test]
+    fn test_forwarded_traits() {
+        use alloc::string::ToString;
+
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        tree.add_child(2, root).unwrap();
+        tree.add_child(1, root).unwrap();
+        tree.add_child(2, root).unwrap();
+
+        let root = tree.try_get(root).unwrap();
+        let mut vals = root.children()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        vals.sort();
+        vals.dedup();
+        assert_eq!(vals.iter().map(ToString::to_string).collect::<Vec<_>>(), ["1", "2"]);
+    }
+
+    #[test]
+    fn test_query() {
+        let tree = Tree::new();
+        let root = tree.add_root("div");
+        let a = tree.add_child("p", root).unwrap();
+        tree.add_child("span", a).unwrap();
+        let b = tree.add_child("div", root).unwrap();
+        let c = tree.add_child("span", b).unwrap();
+        let d = tree.add_child("div", c).unwrap();
+        let e = tree.add_child("span", d).unwrap();
+        tree.add_root("p");
+
+        let keys = Query::root()
+            .filter(|&val| val == "div")
+            .descendant(|&val| val == "div")
+            .child(|&val| val == "span")
+            .run_keys(&tree);
+        assert_eq!(keys, [c, e]);
+
+        let keys = Query::root()
+            .descendant(|_| true)
+            .descendant(|&val| val == "span")
+            .run_keys(&tree);
+        assert_eq!(keys.len(), 3);
+
+        let _borrow = tree.try_get_mut(c).unwrap();
+        let found = Query::root()
+            .descendant(|&val| val == "span")
+            .run(&tree)
+            .map(|node| node.unwrap().key())
+            .collect::<Vec<_>>();
+        assert_eq!(found.len(), 2);
+        assert!(!found.contains(&c));
+    }
+
+    #[test]
+    fn test_render() {
+        let tree = Tree::new();
+        let root = tree.add_root("root");
+        let a = tree.add_child("a", root).unwrap();
+        let b = tree.add_child("b", a).unwrap();
+        tree.add_child("c", b).unwrap();
+        tree.add_child("d", root).unwrap();
+
+        assert_eq!(
+            tree.render_unicode(|out, val| out.write_str(val)),
+            "root\n├── a\n│   └── b\n│       └── c\n└── d\n",
+        );
+
+        let _borrow = tree.try_get_mut(b).unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| out.write_str(val)),
+            "root\n|-- a\n|   `-- (Borrowed)\n|       `-- c\n`-- d\n",
+        );
+    }
+
+    #[test]
+    fn test_memory_footprint() {
+        let tree = Tree::<[u8; 64]>::new();
+        let empty = tree.memory_footprint();
+
+        let root = tree.add_root([0; 64]);
+        tree.add_child([0; 64], root).unwrap();
+        assert!(tree.memory_footprint() >= empty + 128);
+    }
+
+    #[test]
+    fn test_stats() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, a).unwrap();
+        tree.add_child(4, a).unwrap();
+
+        let stats = tree.stats();
+        assert_eq!(stats.roots, 1);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.nodes_per_depth, [1, 1, 3]);
+        assert_eq!(stats.avg_branching, 2.0);
+    }
+
+    #[test]
+    fn test_any_all() {
+        let tree = Tree::new();
+        let root = tree.add_root(1);
+        let a = tree.add_child(2, root).unwrap();
+        tree.add_child(3, a).unwrap();
+        tree.reserve_key();
+
+        assert!(tree.any(BorrowPolicy::Fail, |_, &val| val == 3).unwrap());
+        assert!(tree.all(BorrowPolicy::Fail, |_, &val| val > 0).unwrap());
+
+        let _borrow = tree.try_get_mut(a).unwrap();
+        assert!(matches!(tree.any(BorrowPolicy::Fail, |_, &val| val == 3), Err(Error::CantBorrow)));
+        assert!(tree.any(BorrowPolicy::Skip, |_, &val| val == 3).unwrap());
+        assert!(!tree.any(BorrowPolicy::Skip, |_, &val| val == 2).unwrap());
+        assert!(tree.all(BorrowPolicy::Skip, |_, &val| val != 2).unwrap());
+    }
+
+    #[test]
+    fn test_any_reentrant() {
+        let tree = Tree::new();
+        let root = tree.add_root(1);
+        let child = tree.add_child(2, root).unwrap();
+
+        let found = tree.any(BorrowPolicy::Fail, |key, _| {
+            if key == root {
+                tree.remove_recursive(child);
+            }
+            key == child
+        });
+        assert!(!found.unwrap());
+    }
+
+    #[test]
+    fn test_with_nodes() {
+        let tree = Tree::new();
+        let a = tree.add_root(1);
+        let b = tree.add_root(2);
+        let c = tree.add_child(3, b).unwrap();
+
+        let sum = tree.with_nodes([a, b, c], |[mut a, b, mut c]| {
+            *a += 10;
+            *c += 10;
+            *a + *b + *c
+        });
+        assert_eq!(sum.unwrap(), 26);
+        assert_eq!(*tree.try_get(a).unwrap(), 11);
+        assert!(tree.try_get_mut(c).is_ok());
+
+        assert!(matches!(tree.with_nodes([a, a], |_| ()), Err(Error::CantBorrow)));
+        assert!(tree.try_get_mut(a).is_ok());
+
+        let _borrow = tree.try_get(b).unwrap();
+        assert!(matches!(tree.with_nodes([a, b], |_| ()), Err(Error::CantBorrow)));
+    }
+
+    #[test]
+    fn test_sort_children_recursive() {
+        let tree = Tree::new();
+        let b = tree.add_root((2, 'b'));
+        let a = tree.add_root((1, 'a'));
+        let b2 = tree.add_child((2, 'x'), b).unwrap();
+        let b1 = tree.add_child((1, 'y'), b).unwrap();
+        let b2_again = tree.add_child((2, 'z'), b).unwrap();
+        let leaf = tree.add_child((0, 'l'), b2).unwrap();
+
+        let order = tree.sort_children_recursive(BorrowPolicy::Fail, |x, y| x.0.cmp(&y.0)).unwrap();
+        assert_eq!(order, [a, b, b1, b2, leaf, b2_again]);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [a, b]);
+        assert_eq!(&*tree.child_keys(b), [b1, b2, b2_again]);
+
+        // Failing leaves every list as it was
+        let held = tree.try_get_mut(b2_again).unwrap();
+        let reverse = |x: &(i32, char), y: &(i32, char)| y.0.cmp(&x.0);
+        assert!(matches!(tree.sort_children_recursive(BorrowPolicy::Fail, reverse), Err(Error::CantBorrow)));
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [a, b]);
+
+        let order = tree.sort_children_recursive(BorrowPolicy::Skip, reverse).unwrap();
+        assert_eq!(order, [b, b1, b2, leaf, b2_again, a]);
+        drop(held);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_remove_recursive_tracked() {
+        use alloc::rc::Rc;
+
+        let marker = Rc::new(());
+        let tree = Tree::new();
+        let root = tree.add_root(Rc::clone(&marker));
+        let a = tree.add_child(Rc::clone(&marker), root).unwrap();
+        let b = tree.add_child(Rc::clone(&marker), a).unwrap();
+        let c = tree.add_child(Rc::clone(&marker), a).unwrap();
+        let other = tree.add_root(Rc::clone(&marker));
+
+        let held = tree.try_get(b).unwrap();
+        let removal = tree.remove_recursive_tracked(a);
+        assert_eq!(removal, Removal { removed: alloc::vec![a, b, c], deferred: alloc::vec![b] });
+        assert_eq!(tree.len(), 2);
+        assert!(matches!(tree.try_get(b), Err(Error::Missing)));
+        // The held value lives on, while the others are freed
+        assert_eq!(Rc::strong_count(&marker), 4);
+        assert_eq!(tree.deferred_keys(), [b]);
+
+        let mut freed = Vec::new();
+        tree.reclaim_deferred(|key| freed.push(key));
+        assert!(freed.is_empty());
+        drop(held);
+        assert_eq!(Rc::strong_count(&marker), 4);
+        tree.reclaim_deferred(|key| freed.push(key));
+        assert_eq!(freed, [b]);
+        assert_eq!(Rc::strong_count(&marker), 3);
+        assert!(tree.deferred_keys().is_empty());
+
+        assert_eq!(tree.remove_recursive_tracked(a), Removal::default());
+        let held = tree.try_get_mut(other).unwrap();
+        assert_eq!(tree.remove_recursive_tracked(other).deferred, [other]);
+        drop(held);
+        drop(tree);
+        assert_eq!(Rc::strong_count(&marker), 1);
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let removed = tree.add_child(3, a).unwrap();
+        tree.remove_recursive(removed);
+        let reserved = tree.reserve_key();
+
+        let clone = tree.try_clone().unwrap();
+        assert_eq!(&*clone.child_keys(root), [a, b]);
+        assert_eq!(*clone.try_get(b).unwrap(), 2);
+        assert!(matches!(clone.try_get(reserved), Err(Error::Unfilled)));
+        assert!(matches!(clone.try_get(removed), Err(Error::Missing)));
+        assert_eq!(clone.insertion_index(b), tree.insertion_index(b));
+
+        // The trees are independent after cloning
+        *clone.try_get_mut(a).unwrap() = 10;
+        clone.remove_recursive(b);
+        let new = clone.add_root(4);
+        assert_ne!(new, removed);
+        assert_eq!(*tree.try_get(a).unwrap(), 1);
+        assert_eq!(&*tree.child_keys(root), [a, b]);
+
+        let held = tree.try_get_mut(a).unwrap();
+        let also_held = tree.try_get_mut(b).unwrap();
+        let mut borrowed = tree.try_clone().err().unwrap();
+        borrowed.sort();
+        let mut expected = alloc::vec![a, b];
+        expected.sort();
+        assert_eq!(borrowed, expected);
+        drop((held, also_held));
+        assert!(tree.try_clone().is_ok());
+    }
+
+    #[test]
+    fn test_replace_swap_update() {
+        let tree = Tree::new();
+        let a = tree.add_root(1);
+        let b = tree.add_child(2, a).unwrap();
+
+        assert_eq!(tree.replace(a, 10).unwrap(), 1);
+        tree.swap(a, b).unwrap();
+        assert_eq!(*tree.try_get(a).unwrap(), 2);
+        assert_eq!(*tree.try_get(b).unwrap(), 10);
+        assert_eq!(tree.parent_key_of(b), Some(a));
+        tree.swap(a, a).unwrap();
+        assert_eq!(tree.update(b, |val| {
+            *val += 1;
+            *val * 2
+        }).unwrap(), 22);
+        assert_eq!(tree.try_get_mut(b).unwrap().replace_value(0), 11);
+        assert_eq!(*tree.try_get(b).unwrap(), 0);
+
+        let held = tree.try_get(b).unwrap();
+        assert!(matches!(tree.swap(a, b), Err(Error::CantBorrow)));
+        assert!(matches!(tree.swap(b, b), Err(Error::CantBorrow)));
+        assert!(matches!(tree.replace(b, 5), Err(Error::CantBorrow)));
+        assert!(matches!(tree.update(b, |_| ()), Err(Error::CantBorrow)));
+        drop(held);
+        assert_eq!(*tree.try_get(a).unwrap(), 2);
+
+        let reserved = tree.reserve_key();
+        assert!(matches!(tree.replace(reserved, 1), Err(Error::Unfilled)));
+        tree.remove_recursive(a);
+        assert!(matches!(tree.swap(a, reserved), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_shared_snapshots() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+
+        let first = tree.child_keys(root);
+        let second = tree.child_keys(root);
+        assert_eq!(first.as_ptr(), second.as_ptr());
+
+        tree.remove_child(root, a);
+        assert_eq!(*first, [a, b]);
+        assert_eq!(*tree.child_keys(root), [b]);
+
+        let roots = tree.root_keys();
+        tree.remove_recursive(a);
+        assert_eq!(roots.collect::<Vec<_>>(), [root, a]);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [root]);
+    }
+
+    #[test]
+    fn test_levels() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        let other = tree.add_root(4);
+
+        let mut levels = tree.levels();
+        assert_eq!(levels.next().unwrap(), [root, other]);
+        let d = tree.add_child(5, b).unwrap();
+        assert_eq!(levels.next().unwrap(), [a, b]);
+        assert_eq!(levels.next().unwrap(), [c, d]);
+        assert!(levels.next().is_none());
+    }
+
+    fn render(tree: &Tree<i32>) -> alloc::string::String {
+        tree.render_ascii(|out, val| write!(out, "{}", val))
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        let before = render(&tree);
+
+        let res = tree.transaction(|txn| {
+            txn.set_child(b, a)?;
+            txn.remove_recursive(c)?;
+            txn.add_child(4, root)?;
+            assert!(!txn.contains(c));
+            assert!(matches!(txn.set_child(c, b), Err(Error::Missing)));
+            txn.set_child(a, root)
+        });
+        assert!(matches!(res, Err(Error::Cycle)));
+        assert_eq!(render(&tree), before);
+        assert_eq!(tree.len(), 4);
+        assert!(tree.try_get(c).is_ok());
+        assert!(tree.validate().is_ok());
+
+        let d = tree.transaction(|txn| {
+            txn.set_child(b, a)?;
+            txn.remove_recursive(c)?;
+            txn.add_child(4, root)
+        }).unwrap();
+        assert_eq!(render(&tree), "0\n|-- 2\n|   `-- 1\n`-- 4\n");
+        assert_eq!(tree.len(), 4);
+        assert!(tree.try_get(c).is_err());
+        assert!(tree.try_get(d).is_ok());
+        assert!(tree.validate().is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_transaction_panic() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, root).unwrap();
+        let before = render(&tree);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tree.transaction(|txn| {
+                txn.remove_recursive(a)?;
+                txn.add_root(3);
+                txn.remove_child(root, a)?;
+                Ok::<_, Error>(())
+            }).unwrap();
+        }));
+        assert!(res.is_err());
+        assert_eq!(render(&tree), before);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_relations() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+
+        let relations = tree.relations();
+        assert_eq!(relations.roots(), [root]);
+        assert_eq!(relations.children_of(root), [a, b]);
+        assert_eq!(relations.parent_of(c), Some(a));
+        assert_eq!(relations.parent_of(root), None);
+        assert_eq!(relations.parents().count(), 3);
+
+        tree.set_child(b, c).unwrap();
+        tree.add_root(4);
+        assert_eq!(relations.parent_of(c), Some(a));
+        assert_eq!(relations.roots(), [root]);
+        assert!(relations.children_of(b).is_empty());
+        assert_eq!(tree.relations().parent_of(c), Some(b));
+    }
+
+    #[test]
+    fn test_sorted_keys() {
+        let tree = Tree::new();
+        let a = tree.add_root(0);
+        let b = tree.add_child(1, a).unwrap();
+        let c = tree.reserve_key();
+        tree.remove_recursive(b);
+        let d = tree.add_child(2, c).unwrap();
+        let e = tree.add_root(3);
+        assert_eq!(tree.sorted_keys(), [a, c, d, e]);
+
+        tree.remove_recursive(c);
+        assert_eq!(tree.sorted_keys(), [a, e]);
+    }
+
+    #[test]
+    fn test_fold_accumulate() {
+        let tree = Tree::new();
+        let root = tree.add_root(1);
+        let a = tree.add_child(2, root).unwrap();
+        let b = tree.add_child(3, a).unwrap();
+        tree.add_child(4, root).unwrap();
+
+        assert_eq!(tree.fold_subtree(root, |_, &val| val, |acc, child| acc + child).unwrap(), 10);
+        assert_eq!(tree.fold_subtree(a, |_, _| 1, |acc, child| acc + child).unwrap(), 2);
+
+        let depths = tree.accumulate(root, 0, |depth, _, _| depth + 1).unwrap();
+        assert_eq!(depths.len(), 4);
+        assert_eq!((depths[root], depths[a], depths[b]), (1, 2, 3));
+
+        let _borrow = tree.try_get_mut(b).unwrap();
+        assert!(matches!(tree.fold_subtree(root, |_, _| (), |_, _| ()), Err(Error::CantBorrow)));
+        assert!(matches!(tree.accumulate(a, (), |_, _, _| ()), Err(Error::CantBorrow)));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let tree = Tree::new();
+        let root = tree.add_root(10);
+        let low = tree.add_child(5, root).unwrap();
+        let tiny = tree.add_child(0, low).unwrap();
+        tree.add_child(0, low).unwrap();
+        let high = tree.add_child(0, root).unwrap();
+
+        let chooser = |&threshold: &i32, &val: &i32| ChildSelect::Child(usize::from(val >= threshold));
+        assert_eq!(tree.evaluate(root, &3, chooser).unwrap(), [root, low, tiny]);
+        assert_eq!(tree.evaluate(root, &20, chooser).unwrap(), [root, high]);
+        assert_eq!(tree.evaluate(root, &3, |_, _| ChildSelect::Stop).unwrap(), [root]);
+        assert_eq!(tree.evaluate(root, &3, |_, _| ChildSelect::Child(5)).unwrap(), [root]);
+
+        let _borrow = tree.try_get_mut(low).unwrap();
+        assert!(matches!(tree.evaluate(root, &3, chooser), Err(Error::CantBorrow)));
+        assert!(matches!(tree.evaluate(TreeKey::default(), &3, chooser), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_two_phase() {
+        struct Widget {
+            height: u32,
+            y: u32,
+        }
+
+        let tree = Tree::new();
+        let root = tree.add_root(Widget { height: 1, y: 0 });
+        let a = tree.add_child(Widget { height: 2, y: 0 }, root).unwrap();
+        let b = tree.add_child(Widget { height: 3, y: 0 }, a).unwrap();
+        let c = tree.add_child(Widget { height: 4, y: 0 }, root).unwrap();
+
+        // Stack every widget below its parent and its earlier siblings
+        let stack = |tree: &Tree<Widget>| tree.two_phase(
+            root,
+            |_, widget, children| widget.height + children.iter().sum::<u32>(),
+            10,
+            |_, widget, _, y, children| {
+                widget.y = y;
+                children.iter()
+                    .scan(y + widget.height, |next, &height| {
+                        let y = *next;
+                        *next += height;
+                        Some(y)
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+        assert_eq!(stack(&tree).unwrap(), 10);
+        let ys = [root, a, b, c].map(|key| tree.try_get(key).unwrap().y);
+        assert_eq!(ys, [10, 11, 13, 16]);
+
+        // Children without an input aren't arranged
+        let arranged = tree.two_phase(root, |_, _, _| (), 0, |_, widget, _, _, _| {
+            widget.y = 0;
+            None
+        });
+        assert!(arranged.is_ok());
+        assert_eq!(tree.try_get(a).unwrap().y, 11);
+        assert_eq!(tree.try_get(root).unwrap().y, 0);
+
+        let _borrow = tree.try_get(c).unwrap();
+        assert!(matches!(stack(&tree), Err(Error::CantBorrow)));
+        assert!(matches!(tree.two_phase(TreeKey::default(), |_, _, _| (), (), |_, _, _, _, _| None), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_key_traversals() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let a1 = tree.add_child(2, a).unwrap();
+        let b = tree.add_child(3, root).unwrap();
+        let other = tree.add_root(4);
+
+        let _borrows = [root, a, a1, b, other].map(|key| tree.try_get_mut(key).unwrap());
+        assert_eq!(tree.keys_dfs().collect::<Vec<_>>(), [root, a, a1, b, other]);
+        assert_eq!(tree.keys_bfs().collect::<Vec<_>>(), [root, other, a, b, a1]);
+        assert_eq!(tree.descendant_keys(root).collect::<Vec<_>>(), [a, a1, b]);
+        assert_eq!(tree.descendant_keys(a1).count(), 0);
+
+        let mut keys = tree.keys_dfs();
+        keys.next();
+        tree.remove_recursive(b);
+        assert_eq!(keys.collect::<Vec<_>>(), [a, a1, b, other]);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-leak-check")]
+    fn test_outstanding_borrows() {
+        use crate::stable::leak::BorrowKind;
+
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let child = tree.add_child(1, root).unwrap();
+        assert!(tree.outstanding_borrows().is_empty());
+
+        let a = tree.try_get(root).unwrap();
+        let b = tree.try_get_mut(child).unwrap();
+        let borrows = tree.outstanding_borrows();
+        assert_eq!(borrows.len(), 2);
+        let (key, record) = borrows.iter().find(|(key, _)| *key == child).unwrap();
+        assert_eq!((*key, record.kind), (child, BorrowKind::Unique));
+
+        let b = b.demote();
+        let kinds = tree.outstanding_borrows().into_iter().map(|(_, record)| record.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [BorrowKind::Shared, BorrowKind::Shared]);
+        drop((a, b));
+        assert!(tree.outstanding_borrows().is_empty());
+    }
+
+    #[test]
+    fn test_edges_roundtrip() {
+        let tree = Tree::from_edges([(1, 2), (3, 4), (1, 3)]).unwrap();
+        let edges = tree.to_edge_list()
+            .into_iter()
+            .map(|(parent, child)| (*tree.try_get(parent).unwrap(), *tree.try_get(child).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, [(1, 2), (1, 3), (3, 4)]);
+
+        assert!(matches!(Tree::from_edges([(1, 2), (3, 2)]), Err(Error::MultipleParents)));
+        assert!(matches!(Tree::from_edges([(1, 2), (2, 1)]), Err(Error::Cycle)));
+    }
+
+    #[test]
+    fn test_from_parent_list() {
+        let (tree, keys) = Tree::from_parent_list([(0, None), (1, Some(2)), (2, Some(0))]).unwrap();
+        assert_eq!(tree.to_edge_list(), [(keys[0], keys[2]), (keys[2], keys[1])]);
+        assert_eq!(*tree.try_get(keys[1]).unwrap(), 1);
+
+        assert_eq!(tree.to_parent_list().unwrap(), [(0, None), (2, Some(0)), (1, Some(1))]);
+        let _borrow = tree.try_get_mut(keys[1]).unwrap();
+        assert!(matches!(tree.to_parent_list(), Err(Error::CantBorrow)));
+
+        assert!(matches!(Tree::from_parent_list([(0, Some(1))]), Err(Error::Missing)));
+        assert!(matches!(Tree::from_parent_list([(0, Some(1)), (1, Some(0))]), Err(Error::Cycle)));
+    }
+
+    #[test]
+    fn test_insertion_index() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_root(2);
+        tree.set_child(root, b).unwrap();
+        assert_eq!(tree.insertion_index(a), Some(1));
+        assert_eq!(tree.insertion_index(b), Some(2));
+        assert_eq!(tree.newest_child(root), Some(b));
+        tree.remove_recursive(b);
+        assert_eq!(tree.insertion_index(b), None);
+        assert_eq!(tree.newest_child(root), Some(a));
+        assert_eq!(tree.newest_child(a), None);
+    }
+
+    #[test]
+    fn test_reentrant_drop() {
+        use alloc::rc::{Rc, Weak};
+        use core::cell::Cell;
+
+        struct Probe(Weak<Tree<Probe>>, Rc<Cell<usize>>);
+
+        impl Drop for Probe {
+            fn drop(&mut self) {
+                if let Some(tree) = self.0.upgrade() {
+                    let _ = (tree.len(), tree.root_keys().count(), tree.sorted_keys());
+                    self.1.set(self.1.get() + 1);
+                }
+            }
+        }
+
+        let tree = Rc::new(Tree::new());
+        let dropped = Rc::new(Cell::new(0));
+        let probe = || Probe(Rc::downgrade(&tree), dropped.clone());
+        let root = tree.add_root(probe());
+        let a = tree.add_child(probe(), root).unwrap();
+        tree.add_child(probe(), a).unwrap();
+        let b = tree.add_child(probe(), root).unwrap();
+
+        tree.retain(OrphanPolicy::Reparent, |key, _| key != b).unwrap();
+        assert_eq!(dropped.get(), 1);
+        tree.remove_recursive(a);
+        assert_eq!(dropped.get(), 3);
+
+        let res = tree.try_add_child_with(root, |_| {
+            tree.remove_recursive(root);
+            Ok::<_, Error>(probe())
+        });
+        assert!(matches!(res, Err(Error::Missing)));
+        assert_eq!(dropped.get(), 5);
+
+        let root = tree.add_root(probe());
+        tree.add_child(probe(), root).unwrap();
+        tree.add_root(probe());
+        tree.clear();
+        assert_eq!(dropped.get(), 8);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_borrow_state() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let reserved = tree.reserve_key();
+        assert_eq!(tree.borrow_state(root), Some(BorrowKind::Free));
+        assert_eq!(tree.borrow_state(reserved), None);
+
+        let a = tree.try_get(root).unwrap();
+        let b = tree.try_get(root).unwrap();
+        assert_eq!(tree.borrow_state(root), Some(BorrowKind::Shared(2)));
+        drop(a);
+        let b = b.try_promote().unwrap();
+        assert_eq!(tree.borrow_state(root), Some(BorrowKind::Mut));
+        drop(b);
+        assert_eq!(tree.borrow_state(root), Some(BorrowKind::Free));
+
+        tree.remove_recursive(root);
+        assert_eq!(tree.borrow_state(root), None);
     }
 }
@@ -0,0 +1,449 @@
+//! Arithmetic expression trees stored in a [`Tree`], with parsing, evaluation, simplification
+//! and printing
+//!
+//! Each node is an [`Expr`]. Operators are applied to their children in order, so a
+//! [`Op::Sub`] node with children `a` and `b` is `a - b`.
+
+use core::fmt::{self, Write};
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::error::Error;
+use super::{PostOrderKeys, Tree, TreeKey};
+
+/// An operator applied to the children of its node
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Sum of two operands
+    Add,
+    /// Difference of two operands
+    Sub,
+    /// Product of two operands
+    Mul,
+    /// Quotient of two operands
+    Div,
+    /// Negation of one operand
+    Neg,
+}
+
+impl Op {
+    /// Get the number of operands this operator takes
+    pub fn arity(self) -> usize {
+        match self {
+            Op::Neg => 1,
+            _ => 2,
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+            Op::Neg => 3,
+        }
+    }
+
+    fn apply(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Op::Add, [a, b]) => a + b,
+            (Op::Sub, [a, b]) => a - b,
+            (Op::Mul, [a, b]) => a * b,
+            (Op::Div, [a, b]) => a / b,
+            (Op::Neg, [a]) => -a,
+            _ => f64::NAN,
+        }
+    }
+}
+
+/// A node of an expression tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A constant
+    Num(f64),
+    /// A named variable
+    Var(String),
+    /// An operator, applied to the node's children
+    Op(Op),
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Op(op) => op.precedence(),
+            _ => 4,
+        }
+    }
+}
+
+/// Reasons an expression can't be parsed, evaluated or simplified
+#[derive(Debug)]
+pub enum ExprError {
+    /// A node of the expression couldn't be accessed
+    Tree(Error),
+    /// A variable has no value
+    UnknownVar(String),
+    /// The operator node with this key doesn't have the right number of children
+    Arity(TreeKey),
+    /// An unexpected character, or the end of the text, was found at a byte offset
+    Parse(usize),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::Tree(err) => write!(f, "{}", err),
+            ExprError::UnknownVar(name) => write!(f, "Unknown variable {:?}", name),
+            ExprError::Arity(key) => write!(f, "Operator {:?} has the wrong number of operands", key),
+            ExprError::Parse(pos) => write!(f, "Unexpected input at offset {}", pos),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ExprError {}
+
+impl From<Error> for ExprError {
+    fn from(err: Error) -> Self {
+        ExprError::Tree(err)
+    }
+}
+
+type Result<T> = core::result::Result<T, ExprError>;
+
+/// Deepest nesting of parentheses and unary `-` that [`parse`] accepts, so deeply nested input
+/// fails rather than overflowing the stack
+pub const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    tree: &'a Tree<Expr>,
+    text: &'a str,
+    pos: usize,
+    /// Nesting of the operand being parsed
+    depth: usize,
+    /// Every node added so far, to be removed if parsing fails
+    added: Vec<TreeKey>,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn add(&mut self, expr: Expr, children: &[TreeKey]) -> Result<TreeKey> {
+        let key = self.tree.add_root(expr);
+        self.added.push(key);
+        for &child in children {
+            self.tree.try_set_child(key, child)?;
+        }
+        Ok(key)
+    }
+
+    /// Parse operands separated by operators of the same precedence, from the loosest binding
+    fn binary(&mut self, precedence: u8) -> Result<TreeKey> {
+        if precedence > Op::Mul.precedence() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(precedence + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Sub,
+                Some('*') => Op::Mul,
+                Some('/') => Op::Div,
+                _ => return Ok(lhs),
+            };
+            if op.precedence() != precedence {
+                return Ok(lhs);
+            }
+            self.pos += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = self.add(Expr::Op(op), &[lhs, rhs])?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<TreeKey> {
+        let start = self.pos;
+        match self.peek() {
+            Some('-' | '(') if self.depth >= MAX_DEPTH => Err(ExprError::Parse(self.pos)),
+            Some('-') => {
+                self.pos += 1;
+                self.depth += 1;
+                let operand = self.unary()?;
+                self.depth -= 1;
+                self.add(Expr::Op(Op::Neg), &[operand])
+            }
+            Some('(') => {
+                self.pos += 1;
+                self.depth += 1;
+                let inner = self.binary(Op::Add.precedence())?;
+                self.depth -= 1;
+                if self.peek() != Some(')') {
+                    return Err(ExprError::Parse(self.pos));
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let rest = &self.text[self.pos..];
+                let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+                let val = rest[..len].parse().map_err(|_| ExprError::Parse(self.pos))?;
+                self.pos += len;
+                self.add(Expr::Num(val), &[])
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let rest = &self.text[self.pos..];
+                let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                self.pos += len;
+                self.add(Expr::Var(String::from(&rest[..len])), &[])
+            }
+            _ => Err(ExprError::Parse(start.max(self.pos))),
+        }
+    }
+}
+
+/// Parse infix arithmetic, such as `2 * (x + 1)`, into a new root of a tree, returning its key.
+/// Numbers, variables, parentheses, unary `-` and the binary operators `+`, `-`, `*` and `/` are
+/// supported. Nothing is added to the tree if parsing fails, including if the input nests deeper
+/// than [`MAX_DEPTH`].
+pub fn parse(tree: &Tree<Expr>, text: &str) -> Result<TreeKey> {
+    let mut parser = Parser { tree, text, pos: 0, depth: 0, added: Vec::new() };
+    let parsed = parser.binary(Op::Add.precedence()).and_then(|key| match parser.peek() {
+        None => Ok(key),
+        Some(_) => Err(ExprError::Parse(parser.pos)),
+    });
+    if parsed.is_err() {
+        for &key in &parser.added {
+            if tree.parent_key_of(key).is_none() {
+                tree.remove_recursive(key);
+            }
+        }
+    }
+    parsed
+}
+
+/// Get the keys of the children of an operator, checking it has the right number
+fn operands(tree: &Tree<Expr>, key: TreeKey, op: Op) -> Result<Vec<TreeKey>> {
+    let children = tree.child_keys(key);
+    if children.len() != op.arity() {
+        return Err(ExprError::Arity(key));
+    }
+    Ok(children.to_vec())
+}
+
+/// Evaluate the expression rooted at a node, looking up the values of variables with `vars`
+pub fn eval(tree: &Tree<Expr>, key: TreeKey, vars: impl Fn(&str) -> Option<f64>) -> Result<f64> {
+    // Children are evaluated before their parents, leaving their results on the stack
+    let mut results = Vec::new();
+    for cur in PostOrderKeys::new(tree, &[key]) {
+        let node = tree.try_get(cur)?;
+        let val = match &*node {
+            Expr::Num(val) => *val,
+            Expr::Var(name) => vars(name).ok_or_else(|| ExprError::UnknownVar(name.clone()))?,
+            Expr::Op(op) => {
+                let arity = operands(tree, cur, *op)?.len();
+                let args = results.split_off(results.len() - arity);
+                op.apply(&args)
+            }
+        };
+        results.push(val);
+    }
+    results.pop().ok_or(ExprError::Tree(Error::Missing))
+}
+
+/// Replace a node with one of its children, keeping the node's place among its siblings
+fn replace_with_child(tree: &Tree<Expr>, key: TreeKey, child: TreeKey) -> Result<()> {
+    let (parent, idx) = tree.position_of(key).ok_or(Error::Missing)?;
+    tree.relink(child, parent, idx);
+    tree.remove_recursive(key);
+    Ok(())
+}
+
+/// Simplify the expression rooted at a node in place. Operators whose operands are all constants
+/// are folded into a constant, and adding zero, multiplying by one, dividing by one and double
+/// negation are removed. Returns the key of the simplified expression, which differs from `key`
+/// if the root itself was removed.
+pub fn simplify(tree: &Tree<Expr>, key: TreeKey) -> Result<TreeKey> {
+    let num = |key: TreeKey| -> Result<Option<f64>> {
+        match &*tree.try_get(key)? {
+            Expr::Num(val) => Ok(Some(*val)),
+            _ => Ok(None),
+        }
+    };
+
+    let mut root = key;
+    // Children are simplified first, and only ever replace themselves, so the remaining keys
+    // stay valid
+    let order = PostOrderKeys::new(tree, &[key]).collect::<Vec<_>>();
+    for cur in order {
+        let node = tree.try_get(cur)?;
+        let Expr::Op(op) = *node else {
+            continue;
+        };
+        let args = operands(tree, cur, op)?;
+        let vals = args.iter().map(|&arg| num(arg)).collect::<Result<Vec<_>>>()?;
+
+        if let Some(vals) = vals.iter().copied().collect::<Option<Vec<_>>>() {
+            let mut node = node.try_promote()?;
+            *node = Expr::Num(op.apply(&vals));
+            drop(node.demote());
+            for arg in args {
+                tree.remove_recursive(arg);
+            }
+            continue;
+        }
+        drop(node);
+
+        let keep = match (op, vals.as_slice()) {
+            (Op::Add, [Some(zero), _]) if *zero == 0.0 => Some(args[1]),
+            (Op::Add | Op::Sub, [_, Some(zero)]) if *zero == 0.0 => Some(args[0]),
+            (Op::Mul, [Some(one), _]) if *one == 1.0 => Some(args[1]),
+            (Op::Mul | Op::Div, [_, Some(one)]) if *one == 1.0 => Some(args[0]),
+            (Op::Neg, [None]) => match *tree.try_get(args[0])? {
+                Expr::Op(Op::Neg) => operands(tree, args[0], Op::Neg)?.first().copied(),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(keep) = keep {
+            replace_with_child(tree, cur, keep)?;
+            if cur == root {
+                root = keep;
+            }
+        }
+    }
+    Ok(root)
+}
+
+fn write_infix(tree: &Tree<Expr>, key: TreeKey, out: &mut String) -> Result<()> {
+    let node = tree.try_get(key)?;
+    let op = match &*node {
+        // Writing to a string can't fail
+        Expr::Num(val) => {
+            let _ = write!(out, "{}", val);
+            return Ok(());
+        }
+        Expr::Var(name) => {
+            out.push_str(name);
+            return Ok(());
+        }
+        Expr::Op(op) => *op,
+    };
+    drop(node);
+
+    let args = operands(tree, key, op)?;
+    let operand = |out: &mut String, arg: TreeKey, right: bool| -> Result<()> {
+        let prec = tree.try_get(arg)?.precedence();
+        // Subtraction and division don't associate, so equal operators on the right need parens
+        let parens = prec < op.precedence() || (right && prec == op.precedence() && matches!(op, Op::Sub | Op::Div));
+        if parens {
+            out.push('(');
+        }
+        write_infix(tree, arg, out)?;
+        if parens {
+            out.push(')');
+        }
+        Ok(())
+    };
+
+    match (op, args.as_slice()) {
+        (Op::Neg, &[arg]) => {
+            out.push('-');
+            operand(out, arg, false)
+        }
+        (_, &[lhs, rhs]) => {
+            operand(out, lhs, false)?;
+            out.push_str(match op {
+                Op::Add => " + ",
+                Op::Sub => " - ",
+                Op::Mul => " * ",
+                _ => " / ",
+            });
+            operand(out, rhs, true)
+        }
+        _ => Err(ExprError::Arity(key)),
+    }
+}
+
+/// Print the expression rooted at a node as infix arithmetic, with only the parentheses needed
+/// to keep its meaning
+pub fn to_infix(tree: &Tree<Expr>, key: TreeKey) -> Result<String> {
+    let mut out = String::new();
+    write_infix(tree, key, &mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> Option<f64> {
+        match name {
+            "x" => Some(3.0),
+            "y" => Some(0.5),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expr_parse_print() {
+        let tree = Tree::new();
+        let key = parse(&tree, "2 * (x + 1) - -y / (4 - (1 - z))").unwrap();
+        assert_eq!(to_infix(&tree, key).unwrap(), "2 * (x + 1) - -y / (4 - (1 - z))");
+        let key = parse(&tree, "((a - b) - c) * 1.5").unwrap();
+        assert_eq!(to_infix(&tree, key).unwrap(), "(a - b - c) * 1.5");
+
+        let len = tree.len();
+        assert!(matches!(parse(&tree, "1 + (2 * x"), Err(ExprError::Parse(10))));
+        assert!(matches!(parse(&tree, "1 + * 2"), Err(ExprError::Parse(4))));
+        assert!(matches!(parse(&tree, "1 2"), Err(ExprError::Parse(2))));
+        assert_eq!(tree.len(), len);
+
+        let nested = "(".repeat(MAX_DEPTH) + "x" + &")".repeat(MAX_DEPTH);
+        let key = parse(&tree, &nested).unwrap();
+        assert_eq!(to_infix(&tree, key).unwrap(), "x");
+        let len = tree.len();
+        assert!(matches!(parse(&tree, &"(".repeat(100_000)), Err(ExprError::Parse(MAX_DEPTH))));
+        let negated = "-".repeat(MAX_DEPTH + 1) + "x";
+        assert!(matches!(parse(&tree, &negated), Err(ExprError::Parse(MAX_DEPTH))));
+        assert_eq!(tree.len(), len);
+    }
+
+    #[test]
+    fn test_expr_eval() {
+        let tree = Tree::new();
+        let key = parse(&tree, "2 * (x + 1) - -y / 4").unwrap();
+        assert_eq!(eval(&tree, key, vars).unwrap(), 8.125);
+
+        let key = parse(&tree, "x + z").unwrap();
+        assert!(matches!(eval(&tree, key, vars), Err(ExprError::UnknownVar(name)) if name == "z"));
+
+        let bad = tree.add_root(Expr::Op(Op::Add));
+        tree.add_child(Expr::Num(1.0), bad).unwrap();
+        assert!(matches!(eval(&tree, bad, vars), Err(ExprError::Arity(key)) if key == bad));
+    }
+
+    #[test]
+    fn test_expr_simplify() {
+        let tree = Tree::new();
+        let key = parse(&tree, "(2 * 3 + x) * (4 - 3) + 0").unwrap();
+        let key = simplify(&tree, key).unwrap();
+        assert_eq!(to_infix(&tree, key).unwrap(), "6 + x");
+        assert_eq!(tree.len(), 3);
+
+        let key = parse(&tree, "--(y / 1) - 0 * 5").unwrap();
+        let simplified = simplify(&tree, key).unwrap();
+        assert_eq!(to_infix(&tree, simplified).unwrap(), "y");
+        assert_eq!(eval(&tree, simplified, vars).unwrap(), 0.5);
+
+        // Simplifying part of an expression keeps it in place
+        let key = parse(&tree, "a * (1 * (b + 0))").unwrap();
+        let rhs = tree.child_keys(key)[1];
+        assert_ne!(simplify(&tree, rhs).unwrap(), rhs);
+        assert_eq!(to_infix(&tree, key).unwrap(), "a * b");
+
+        let _borrow = tree.try_get_mut(key).unwrap();
+        assert!(matches!(simplify(&tree, key), Err(ExprError::Tree(Error::CantBorrow))));
+    }
+}
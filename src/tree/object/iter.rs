@@ -0,0 +1,185 @@
+use super::{NodeRef, Tree, TreeKey};
+use super::error::Result;
+
+use core::marker::PhantomData;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use crate::tree::Filter;
+
+pub(super) fn accept<T: ?Sized>(_: &T) -> Filter {
+    Filter::Accept
+}
+
+/// A lazy pre-order iterator over the descendants of a node, optionally filtered.
+///
+/// Nodes which can't be borrowed are yielded as errors, and their children are still visited.
+pub struct Descendants<'a, 'b, T: ?Sized, F = fn(&T) -> Filter> {
+    tree: &'a Tree<T>,
+    /// Pending nodes, alongside their depth below the starting node
+    stack: Vec<(TreeKey, usize)>,
+    max_depth: usize,
+    max_breadth: usize,
+    filter: F,
+    _phantom: PhantomData<&'b T>,
+}
+
+impl<'a, 'b, T: ?Sized, F> Descendants<'a, 'b, T, F> {
+    pub(super) fn new(tree: &'a Tree<T>, key: TreeKey, filter: F) -> Descendants<'a, 'b, T, F> {
+        let mut out = Descendants {
+            tree,
+            stack: Vec::new(),
+            max_depth: usize::MAX,
+            max_breadth: usize::MAX,
+            filter,
+            _phantom: PhantomData,
+        };
+        out.stack.push((key, 0));
+        out
+    }
+
+    /// Only visit nodes at most `depth` levels below the starting node, so a depth of `1` yields
+    /// just its children
+    #[must_use]
+    pub fn take_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Only visit the first `breadth` children of each node
+    #[must_use]
+    pub fn take_breadth(mut self, breadth: usize) -> Self {
+        self.max_breadth = breadth;
+        self
+    }
+
+    fn push_children(&mut self, key: TreeKey, depth: usize) {
+        if depth >= self.max_depth {
+            return;
+        }
+        let children = self.tree.child_keys(key);
+        let len = children.len().min(self.max_breadth);
+        self.stack.extend(children[..len].iter().rev().map(|&child| (child, depth + 1)));
+    }
+}
+
+impl<'a, 'b, T, F> Iterator for Descendants<'a, 'b, T, F>
+where
+    T: ?Sized,
+    F: FnMut(&T) -> Filter,
+{
+    type Item = Result<NodeRef<'a, 'b, T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, depth) = self.stack.pop()?;
+            // The starting node is only pushed so limits set after construction apply to its
+            // children
+            if depth == 0 {
+                self.push_children(key, depth);
+                continue;
+            }
+
+            let node = match self.tree.try_get(key) {
+                Ok(node) => node,
+                Err(err) => {
+                    self.push_children(key, depth);
+                    return Some(Err(err));
+                }
+            };
+
+            match (self.filter)(&node) {
+                Filter::Accept => {
+                    self.push_children(key, depth);
+                    return Some(Ok(node));
+                }
+                Filter::Skip => self.push_children(key, depth),
+                Filter::Prune => (),
+            }
+        }
+    }
+}
+
+/// A lazy pre-order iterator over the keys of a tree, yielding parents before their children.
+///
+/// Only the structure of the tree is read, so borrowed nodes are still yielded.
+pub struct DfsKeys<'a, T: ?Sized> {
+    tree: &'a Tree<T>,
+    stack: Vec<TreeKey>,
+}
+
+impl<'a, T: ?Sized> DfsKeys<'a, T> {
+    pub(super) fn new(tree: &'a Tree<T>, start: &[TreeKey]) -> DfsKeys<'a, T> {
+        DfsKeys {
+            tree,
+            stack: start.iter().rev().copied().collect(),
+        }
+    }
+}
+
+impl<T: ?Sized> Iterator for DfsKeys<'_, T> {
+    type Item = TreeKey;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.stack.pop()?;
+        self.stack.extend(self.tree.child_keys(key).iter().rev());
+        Some(key)
+    }
+}
+
+/// A lazy breadth-first iterator over the keys of a tree, yielding each level in order.
+///
+/// Only the structure of the tree is read, so borrowed nodes are still yielded.
+pub struct BfsKeys<'a, T: ?Sized> {
+    tree: &'a Tree<T>,
+    queue: VecDeque<TreeKey>,
+}
+
+impl<'a, T: ?Sized> BfsKeys<'a, T> {
+    pub(super) fn new(tree: &'a Tree<T>, start: &[TreeKey]) -> BfsKeys<'a, T> {
+        BfsKeys {
+            tree,
+            queue: start.iter().copied().collect(),
+        }
+    }
+}
+
+impl<T: ?Sized> Iterator for BfsKeys<'_, T> {
+    type Item = TreeKey;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.queue.pop_front()?;
+        self.queue.extend(self.tree.child_keys(key).iter());
+        Some(key)
+    }
+}
+
+/// A lazy post-order iterator over the keys of a tree, yielding children before their parents
+pub struct PostOrderKeys<'a, T: ?Sized> {
+    tree: &'a Tree<T>,
+    /// Pending nodes, alongside whether their children have already been pushed
+    stack: Vec<(TreeKey, bool)>,
+}
+
+impl<'a, T: ?Sized> PostOrderKeys<'a, T> {
+    pub(super) fn new(tree: &'a Tree<T>, roots: &[TreeKey]) -> PostOrderKeys<'a, T> {
+        PostOrderKeys {
+            tree,
+            stack: roots.iter().rev().map(|&key| (key, false)).collect(),
+        }
+    }
+}
+
+impl<T: ?Sized> Iterator for PostOrderKeys<'_, T> {
+    type Item = TreeKey;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, expanded) = self.stack.pop()?;
+            if expanded {
+                return Some(key);
+            }
+            self.stack.push((key, true));
+            self.stack.extend(self.tree.child_keys(key).iter().rev().map(|&child| (child, false)));
+        }
+    }
+}
@@ -1,6 +1,7 @@
 
-use super::{Tree, TreeKey};
-use super::error::Result;
+use super::{ChildKeys, Descendants, Tree, TreeKey};
+use super::error::{Error, Result};
+use super::iter::accept;
 
 use core::fmt;
 use core::ops::{Deref, DerefMut};
@@ -8,7 +9,9 @@ use core::borrow::{Borrow, BorrowMut};
 #[cfg(feature = "unstable")]
 use core::marker::Unsize;
 use alloc::vec::Vec;
-use crate::tree::object::{Error, Stable, StableRef, StableMut};
+use crate::tree::object::{Stable, StableRef, StableMut};
+use crate::tree::Filter;
+use crate::util;
 
 macro_rules! ref_common {
     ($ty:ty) => {
@@ -35,6 +38,11 @@ macro_rules! ref_common {
                     .transpose()
             }
 
+            /// Get a snapshot of the keys of this node's children, in the order they were attached
+            pub fn child_keys(&self) -> ChildKeys<'a> {
+                self.tree.child_keys(self.key())
+            }
+
             /// Attempt to get references to the children of this node
             pub fn children(&self) -> impl Iterator<Item = Result<NodeRef<'a, 'b, T>>> {
                 self.tree
@@ -44,6 +52,21 @@ macro_rules! ref_common {
                     .into_iter()
             }
 
+            /// Lazily iterate over the descendants of this node in pre-order, not including this
+            /// node itself
+            pub fn descendants(&self) -> Descendants<'a, 'b, T> {
+                Descendants::new(self.tree, self.key(), accept)
+            }
+
+            /// Lazily iterate over the descendants of this node in pre-order, using the filter to
+            /// decide which nodes to yield and which branches to skip entirely
+            pub fn descendants_matching<F>(&self, filter: F) -> Descendants<'a, 'b, T, F>
+            where
+                F: FnMut(&T) -> Filter,
+            {
+                Descendants::new(self.tree, self.key(), filter)
+            }
+
             /// Attempt to get mutable references to the children of this node
             pub fn children_mut(&self) -> impl Iterator<Item = Result<NodeRefMut<'a, 'b, T>>> {
                 self.tree
@@ -94,24 +117,20 @@ impl<'a, 'b, T: ?Sized> NodeRef<'a, 'b, T> {
         Ok(NodeRef {
             tree,
             mykey: key,
-            node: cell.try_borrow().ok_or(Error::CantBorrow)?,
+            node: cell.try_borrow()?,
         })
     }
 
-    /// Attempt to promote this immutable ref into a mutable ref
+    /// Attempt to promote this immutable ref into a mutable ref. Fails with
+    /// [`Error::CantBorrow`] if the node is borrowed by any other reference.
     pub fn try_promote(self) -> Result<NodeRefMut<'a, 'b, T>> {
-        drop(self.node);
-        self.tree.try_get_mut(self.mykey)
-    }
-
-    /// Promote this immutable ref into a mutable ref, panicking on failure
-    pub fn promote(self) -> NodeRefMut<'a, 'b, T> {
-        drop(self.node);
-        self.tree.try_get_mut(self.mykey)
-            .expect("Could not promote immutable ref")
+        let node = self.node.try_promote().map_err(|_| Error::CantBorrow)?;
+        Ok(NodeRefMut { tree: self.tree, mykey: self.mykey, node })
     }
 }
 
+util::forward_value_traits!(impl<T: ?Sized> for NodeRef<'_, '_, T>);
+
 impl<T: ?Sized + fmt::Debug> fmt::Debug for NodeRef<'_, '_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("NodeRef")
@@ -140,21 +159,20 @@ impl<'a, 'b, T: ?Sized> NodeRefMut<'a, 'b, T> {
         Ok(NodeRefMut {
             tree,
             mykey: key,
-            node: cell.try_borrow_mut().ok_or(Error::CantBorrow)?,
+            node: cell.try_borrow_mut()?,
         })
     }
 
     /// Demote this mutable ref to an immutable ref
     pub fn demote(self) -> NodeRef<'a, 'b, T> {
-        core::mem::drop(self.node);
-        self.tree.try_get(self.mykey)
-            .expect("This should always work, as we have unique access")
+        NodeRef { tree: self.tree, mykey: self.mykey, node: self.node.demote() }
     }
 
-    /// Create a new child of this node from a type that unsizes into the type of the tree
+    /// Create a new child of this node from a type that unsizes into the type of the tree,
+    /// returning the key of the new node
     #[cfg(feature = "unstable")]
-    pub fn new_child_from<U: Unsize<T>>(&mut self, child: U) {
-        self.tree.add_child_from(child, self.key());
+    pub fn new_child_from<U: Unsize<T>>(&mut self, child: U) -> Result<TreeKey> {
+        self.tree.add_child_from(child, self.key())
     }
 
     /// Set the parent of this node, unsetting the current one as necessary
@@ -174,12 +192,19 @@ impl<'a, 'b, T: ?Sized> NodeRefMut<'a, 'b, T> {
 }
 
 impl<T> NodeRefMut<'_, '_, T> {
-    /// Create a new child of this node from the provided value
-    pub fn new_child(&mut self, child: T) {
-        let _ = self.tree.add_child(child, self.key());
+    /// Create a new child of this node from the provided value, returning the key of the new node
+    pub fn new_child(&mut self, child: T) -> Result<TreeKey> {
+        self.tree.add_child(child, self.key())
+    }
+
+    /// Replace the value of this node, returning the old value
+    pub fn replace_value(&mut self, val: T) -> T {
+        core::mem::replace(&mut **self, val)
     }
 }
 
+util::forward_value_traits!(impl<T: ?Sized> for NodeRefMut<'_, '_, T>);
+
 impl<T: ?Sized + fmt::Debug> fmt::Debug for NodeRefMut<'_, '_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("NodeRefMut")
@@ -0,0 +1,117 @@
+use super::{NodeRef, Tree, TreeKey};
+use super::error::Result;
+
+use core::fmt;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+
+enum Axis {
+    Filter,
+    Child,
+    Descendant,
+}
+
+struct Step<'q, T: ?Sized> {
+    axis: Axis,
+    pred: Box<dyn Fn(&T) -> bool + 'q>,
+}
+
+/// A composable query over a [`Tree`], similar to a CSS selector. Each step selects nodes
+/// relative to the nodes matched by the previous step, using a matcher closure.
+///
+/// Nodes which can't be borrowed when a matcher needs to check them are treated as not matching.
+pub struct Query<'q, T: ?Sized> {
+    steps: Vec<Step<'q, T>>,
+}
+
+impl<'q, T: ?Sized> Query<'q, T> {
+    /// Create a new query, starting from the roots of the tree
+    #[must_use]
+    pub fn root() -> Query<'q, T> {
+        Query { steps: Vec::new() }
+    }
+
+    /// Keep only the currently matched nodes which satisfy the matcher
+    #[must_use]
+    pub fn filter(mut self, pred: impl Fn(&T) -> bool + 'q) -> Query<'q, T> {
+        self.steps.push(Step { axis: Axis::Filter, pred: Box::new(pred) });
+        self
+    }
+
+    /// Select the children of the currently matched nodes which satisfy the matcher
+    #[must_use]
+    pub fn child(mut self, pred: impl Fn(&T) -> bool + 'q) -> Query<'q, T> {
+        self.steps.push(Step { axis: Axis::Child, pred: Box::new(pred) });
+        self
+    }
+
+    /// Select the descendants at any depth of the currently matched nodes which satisfy the
+    /// matcher
+    #[must_use]
+    pub fn descendant(mut self, pred: impl Fn(&T) -> bool + 'q) -> Query<'q, T> {
+        self.steps.push(Step { axis: Axis::Descendant, pred: Box::new(pred) });
+        self
+    }
+
+    /// Find the keys of all nodes in the tree matched by this query. Keys are unique, and in
+    /// the order they were discovered.
+    pub fn run_keys(&self, tree: &Tree<T>) -> Vec<TreeKey> {
+        let push_children = |stack: &mut Vec<TreeKey>, key| {
+            let start = stack.len();
+            stack.extend(tree.child_keys_of(key));
+            stack[start..].reverse();
+        };
+
+        let mut current = tree.root_keys().collect::<Vec<_>>();
+
+        for step in &self.steps {
+            let matches = |key| tree.try_get(key).is_ok_and(|node| (step.pred)(&node));
+            let mut seen = SecondaryMap::new();
+            let mut next = Vec::new();
+
+            match step.axis {
+                Axis::Filter => next = current.into_iter().filter(|&key| matches(key)).collect(),
+                Axis::Child => {
+                    for key in current {
+                        next.extend(tree.child_keys_of(key).filter(|&child| matches(child)));
+                    }
+                }
+                Axis::Descendant => {
+                    let mut stack = Vec::new();
+                    for key in current {
+                        push_children(&mut stack, key);
+                        while let Some(key) = stack.pop() {
+                            if seen.insert(key, ()).is_some() {
+                                continue;
+                            }
+                            if matches(key) {
+                                next.push(key);
+                            }
+                            push_children(&mut stack, key);
+                        }
+                    }
+                }
+            }
+
+            current = next;
+        }
+
+        current
+    }
+
+    /// Find all nodes in the tree matched by this query
+    pub fn run<'a>(&self, tree: &'a Tree<T>) -> impl Iterator<Item = Result<NodeRef<'a, 'a, T>>> + 'a {
+        self.run_keys(tree)
+            .into_iter()
+            .map(move |key| tree.try_get(key))
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Query<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Query")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
@@ -0,0 +1,269 @@
+//! Hierarchical state machines, with states stored as the nodes of a [`Tree`]
+
+use alloc::vec::Vec;
+use super::error::{Error, Result};
+use super::{NodeRefMut, Tree, TreeKey};
+
+/// A state of a hierarchical state machine, notified when it becomes active or stops being
+/// active. Every hook is given the same context `C`.
+pub trait State<C: ?Sized = ()> {
+    /// Called when this state becomes active, after its parent
+    fn on_enter(&mut self, _ctx: &mut C) {}
+
+    /// Called when this state stops being active, before its parent
+    fn on_exit(&mut self, _ctx: &mut C) {}
+}
+
+/// The states left and entered by a transition between two states
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    /// States which are exited, innermost first
+    pub exit: Vec<TreeKey>,
+    /// States which are entered, outermost first
+    pub enter: Vec<TreeKey>,
+}
+
+/// Get the keys from a node's root down to the node
+fn ancestry<T: ?Sized>(tree: &Tree<T>, key: TreeKey) -> Result<Vec<TreeKey>> {
+    if tree.position_of(key).is_none() {
+        return Err(Error::Missing);
+    }
+    let mut path = core::iter::successors(Some(key), |&key| tree.parent_key_of(key)).collect::<Vec<_>>();
+    path.reverse();
+    Ok(path)
+}
+
+impl<T: ?Sized> Tree<T> {
+    /// Find the states exited and entered when moving from one state to another. States below
+    /// the nearest common ancestor of the two are exited and entered, and states above it are
+    /// left active.
+    ///
+    /// A transition always enters its target, so a transition to the same state or one of its
+    /// ancestors exits and re-enters the target. A transition to a descendant leaves the starting
+    /// state active. States in different roots share no ancestors, so every state is exited and
+    /// entered.
+    pub fn transition_path(&self, from: TreeKey, to: TreeKey) -> Result<Transition> {
+        let from = ancestry(self, from)?;
+        let to = ancestry(self, to)?;
+        let mut shared = from.iter()
+            .zip(&to)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if shared == to.len() {
+            shared -= 1;
+        }
+        Ok(Transition {
+            exit: from[shared..].iter().rev().copied().collect(),
+            enter: to[shared..].to_vec(),
+        })
+    }
+
+    /// Move from one state to another, calling [`State::on_exit`] on every state exited and then
+    /// [`State::on_enter`] on every state entered, as found by
+    /// [`transition_path`](Tree::transition_path).
+    ///
+    /// Every state involved is borrowed before any hook is called, so if one can't be borrowed,
+    /// this fails without calling any hooks.
+    pub fn transition<C: ?Sized>(&self, from: TreeKey, to: TreeKey, ctx: &mut C) -> Result<Transition>
+    where
+        T: State<C>,
+    {
+        let transition = self.transition_path(from, to)?;
+        self.run_transition(&transition, ctx)?;
+        Ok(transition)
+    }
+
+    fn run_transition<C: ?Sized>(&self, transition: &Transition, ctx: &mut C) -> Result<()>
+    where
+        T: State<C>,
+    {
+        let mut nodes = Vec::<NodeRefMut<'_, '_, T>>::new();
+        for &key in transition.exit.iter().chain(&transition.enter) {
+            if !nodes.iter().any(|node| node.key() == key) {
+                nodes.push(self.try_get_mut(key)?);
+            }
+        }
+
+        let mut hook = |key: TreeKey, enter: bool| {
+            if let Some(node) = nodes.iter_mut().find(|node| node.key() == key) {
+                if enter {
+                    node.on_enter(ctx);
+                } else {
+                    node.on_exit(ctx);
+                }
+            }
+        };
+        for &key in &transition.exit {
+            hook(key, false);
+        }
+        for &key in &transition.enter {
+            hook(key, true);
+        }
+        Ok(())
+    }
+}
+
+/// A hierarchical state machine, tracking which state of a [`Tree`] of states is current. The
+/// current state and all its ancestors are active.
+pub struct StateMachine<T: ?Sized> {
+    tree: Tree<T>,
+    current: Option<TreeKey>,
+}
+
+impl<T: ?Sized> StateMachine<T> {
+    /// Create a machine over a tree of states, which hasn't entered any state yet
+    pub fn new(tree: Tree<T>) -> StateMachine<T> {
+        StateMachine { tree, current: None }
+    }
+
+    /// Get the tree of states
+    pub fn tree(&self) -> &Tree<T> {
+        &self.tree
+    }
+
+    /// Take back the tree of states, without exiting the active states
+    pub fn into_tree(self) -> Tree<T> {
+        self.tree
+    }
+
+    /// Get the current state, or `None` if the machine hasn't been started or has been stopped
+    pub fn current(&self) -> Option<TreeKey> {
+        self.current
+    }
+
+    /// Get the active states, innermost first
+    pub fn active_states(&self) -> Vec<TreeKey> {
+        core::iter::successors(self.current, |&key| self.tree.parent_key_of(key)).collect()
+    }
+
+    /// Check whether a state is active, meaning it's the current state or one of its ancestors
+    pub fn is_in(&self, state: TreeKey) -> bool {
+        core::iter::successors(self.current, |&key| self.tree.parent_key_of(key)).any(|key| key == state)
+    }
+
+    /// Move to a new state, calling the hooks of the states exited and entered as
+    /// [`Tree::transition`] does. If the machine hasn't been started, every state from the new
+    /// state's root down to it is entered. Fails if the current state has been removed from the
+    /// tree.
+    pub fn transition_to<C: ?Sized>(&mut self, to: TreeKey, ctx: &mut C) -> Result<Transition>
+    where
+        T: State<C>,
+    {
+        let transition = match self.current {
+            Some(from) => self.tree.transition_path(from, to)?,
+            None => Transition { exit: Vec::new(), enter: ancestry(&self.tree, to)? },
+        };
+        self.tree.run_transition(&transition, ctx)?;
+        self.current = Some(to);
+        Ok(transition)
+    }
+
+    /// Exit every active state, leaving the machine without a current state
+    pub fn stop<C: ?Sized>(&mut self, ctx: &mut C) -> Result<Transition>
+    where
+        T: State<C>,
+    {
+        let transition = Transition { exit: self.active_states(), enter: Vec::new() };
+        self.tree.run_transition(&transition, ctx)?;
+        self.current = None;
+        Ok(transition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+    use super::*;
+
+    struct Named(&'static str);
+
+    impl State<Vec<String>> for Named {
+        fn on_enter(&mut self, log: &mut Vec<String>) {
+            log.push(alloc::format!("enter {}", self.0));
+        }
+
+        fn on_exit(&mut self, log: &mut Vec<String>) {
+            log.push(alloc::format!("exit {}", self.0));
+        }
+    }
+
+    /// Build the states `root => [menu => [main, options], game => [playing, paused]]`
+    fn states() -> (Tree<Named>, [TreeKey; 7]) {
+        let tree = Tree::new();
+        let root = tree.add_root(Named("root"));
+        let menu = tree.add_child(Named("menu"), root).unwrap();
+        let main = tree.add_child(Named("main"), menu).unwrap();
+        let options = tree.add_child(Named("options"), menu).unwrap();
+        let game = tree.add_child(Named("game"), root).unwrap();
+        let playing = tree.add_child(Named("playing"), game).unwrap();
+        let paused = tree.add_child(Named("paused"), game).unwrap();
+        (tree, [root, menu, main, options, game, playing, paused])
+    }
+
+    #[test]
+    fn test_transition_path() {
+        let (tree, [root, menu, main, options, game, playing, _]) = states();
+        let path = tree.transition_path(main, playing).unwrap();
+        assert_eq!(path, Transition { exit: vec![main, menu], enter: vec![game, playing] });
+        let path = tree.transition_path(main, options).unwrap();
+        assert_eq!(path, Transition { exit: vec![main], enter: vec![options] });
+        let path = tree.transition_path(main, main).unwrap();
+        assert_eq!(path, Transition { exit: vec![main], enter: vec![main] });
+        let path = tree.transition_path(main, menu).unwrap();
+        assert_eq!(path, Transition { exit: vec![main, menu], enter: vec![menu] });
+        let path = tree.transition_path(root, main).unwrap();
+        assert_eq!(path, Transition { exit: vec![], enter: vec![menu, main] });
+
+        let other = tree.add_root(Named("other"));
+        let path = tree.transition_path(playing, other).unwrap();
+        assert_eq!(path, Transition { exit: vec![playing, game, root], enter: vec![other] });
+
+        tree.remove_recursive(other);
+        assert!(matches!(tree.transition_path(main, other), Err(Error::Missing)));
+    }
+
+    #[test]
+    fn test_transition_hooks() {
+        let (tree, [_, _, main, _, _, playing, paused]) = states();
+        let mut log = Vec::new();
+        tree.transition(main, playing, &mut log).unwrap();
+        assert_eq!(log, ["exit main", "exit menu", "enter game", "enter playing"]);
+
+        log.clear();
+        tree.transition(paused, paused, &mut log).unwrap();
+        assert_eq!(log, ["exit paused", "enter paused"]);
+
+        log.clear();
+        let held = tree.try_get(playing).unwrap();
+        assert!(matches!(tree.transition(playing, paused, &mut log), Err(Error::CantBorrow)));
+        assert!(log.is_empty());
+        drop(held);
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let (tree, [root, menu, main, _, game, playing, paused]) = states();
+        let mut machine = StateMachine::new(tree);
+        let mut log = Vec::new();
+        assert_eq!(machine.current(), None);
+
+        machine.transition_to(main, &mut log).unwrap();
+        assert_eq!(log, ["enter root", "enter menu", "enter main"]);
+        assert_eq!(machine.active_states(), [main, menu, root]);
+        assert!(machine.is_in(menu));
+        assert!(!machine.is_in(game));
+
+        log.clear();
+        machine.transition_to(playing, &mut log).unwrap();
+        machine.transition_to(paused, &mut log).unwrap();
+        assert_eq!(log, ["exit main", "exit menu", "enter game", "enter playing", "exit playing", "enter paused"]);
+        assert_eq!(machine.current(), Some(paused));
+
+        log.clear();
+        machine.stop(&mut log).unwrap();
+        assert_eq!(log, ["exit paused", "exit game", "exit root"]);
+        assert_eq!(machine.current(), None);
+        assert!(machine.active_states().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use super::error::{Error, Result};
+use super::{ChildKeys, Stable, Tree, TreeKey};
+
+/// A change made by a transaction, and what's needed to undo it
+enum Undo<T: ?Sized> {
+    Added(TreeKey),
+    Moved {
+        key: TreeKey,
+        parent: Option<TreeKey>,
+        index: usize,
+    },
+    Removed {
+        key: TreeKey,
+        parent: Option<TreeKey>,
+        index: usize,
+        values: Vec<(TreeKey, Option<Stable<T>>)>,
+    },
+}
+
+/// A set of structural changes to a [`Tree`] which are kept only if they all succeed. Created by
+/// [`Tree::transaction`].
+///
+/// Nodes removed by the transaction stay reserved until it commits, and can't be used by later
+/// changes within it.
+pub struct Transaction<'a, T: ?Sized> {
+    tree: &'a Tree<T>,
+    log: Vec<Undo<T>>,
+    removed: SecondaryMap<TreeKey, ()>,
+    committed: bool,
+}
+
+impl<'a, T: ?Sized> Transaction<'a, T> {
+    pub(super) fn new(tree: &'a Tree<T>) -> Transaction<'a, T> {
+        Transaction {
+            tree,
+            log: Vec::new(),
+            removed: SecondaryMap::new(),
+            committed: false,
+        }
+    }
+
+    /// Keep every change, finally dropping the values of removed nodes
+    pub(super) fn commit(mut self) {
+        self.committed = true;
+        for undo in &self.log {
+            if let Undo::Removed { key, .. } = undo {
+                self.tree.purge(*key);
+            }
+        }
+    }
+
+    /// Fail with [`Error::Missing`] if a node was removed earlier in this transaction
+    fn check(&self, key: TreeKey) -> Result<()> {
+        if self.removed.contains_key(key) {
+            Err(Error::Missing)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check whether a node exists, and hasn't been removed by this transaction
+    pub fn contains(&self, key: TreeKey) -> bool {
+        !self.removed.contains_key(key) && self.tree.position_of(key).is_some()
+    }
+
+    /// Get the key of a node's parent, if it has one
+    pub fn parent_key_of(&self, child: TreeKey) -> Option<TreeKey> {
+        self.check(child).ok()?;
+        self.tree.parent_key_of(child)
+    }
+
+    /// Get a snapshot of the child keys of a node, in order
+    pub fn child_keys(&self, parent: TreeKey) -> ChildKeys<'a> {
+        self.tree.child_keys(parent)
+    }
+
+    /// Set the first node as the parent of the second node, as by [`Tree::try_set_child`]
+    pub fn set_child(&mut self, parent: TreeKey, child: TreeKey) -> Result<()> {
+        self.check(parent)?;
+        self.check(child)?;
+        let (old_parent, index) = self.tree.position_of(child).ok_or(Error::Missing)?;
+        self.tree.try_set_child(parent, child)?;
+        self.log.push(Undo::Moved { key: child, parent: old_parent, index });
+        Ok(())
+    }
+
+    /// Remove the second node as a child of the first node, turning it into a root, as by
+    /// [`Tree::try_remove_child`]
+    pub fn remove_child(&mut self, parent: TreeKey, child: TreeKey) -> Result<()> {
+        self.check(parent)?;
+        self.check(child)?;
+        let (old_parent, index) = self.tree.position_of(child).ok_or(Error::Missing)?;
+        self.tree.try_remove_child(parent, child)?;
+        self.log.push(Undo::Moved { key: child, parent: old_parent, index });
+        Ok(())
+    }
+
+    /// Remove a node and all its children from the tree. Fails with [`Error::Missing`] if the
+    /// node doesn't exist.
+    pub fn remove_recursive(&mut self, key: TreeKey) -> Result<()> {
+        self.check(key)?;
+        let (parent, index) = self.tree.position_of(key).ok_or(Error::Missing)?;
+        self.tree.detach(key);
+        let values = self.tree.take_values(key);
+        for &(key, _) in &values {
+            self.removed.insert(key, ());
+        }
+        self.log.push(Undo::Removed { key, parent, index, values });
+        Ok(())
+    }
+
+    /// Undo every change, most recent first
+    fn rollback(&mut self) {
+        while let Some(undo) = self.log.pop() {
+            match undo {
+                Undo::Added(key) => {
+                    self.tree.detach(key);
+                    self.tree.purge(key);
+                }
+                Undo::Moved { key, parent, index } => self.tree.relink(key, parent, index),
+                Undo::Removed { key, parent, index, values } => {
+                    self.tree.restore_values(values);
+                    self.tree.relink(key, parent, index);
+                }
+            }
+        }
+        self.removed.clear();
+    }
+}
+
+impl<T> Transaction<'_, T> {
+    /// Add a new root to the tree initialized with the provided value
+    pub fn add_root(&mut self, item: T) -> TreeKey {
+        let key = self.tree.add_root(item);
+        self.log.push(Undo::Added(key));
+        key
+    }
+
+    /// Create a new child of a node from the provided value. Fails with [`Error::Missing`] if
+    /// the parent doesn't exist.
+    pub fn add_child(&mut self, item: T, parent: TreeKey) -> Result<TreeKey> {
+        self.check(parent)?;
+        let key = self.tree.add_child(item, parent)?;
+        self.log.push(Undo::Added(key));
+        Ok(key)
+    }
+}
+
+impl<T: ?Sized> Drop for Transaction<'_, T> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.rollback();
+        }
+    }
+}
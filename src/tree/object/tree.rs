@@ -1,17 +1,37 @@
 
 use super::error::{Error, Result};
-use super::{NodeRef, NodeRefMut};
+use super::{BfsKeys, DfsKeys, NodeRef, NodeRefMut, PostOrderKeys, Transaction};
 
-use core::fmt;
+use core::cmp::Ordering;
+use core::convert::Infallible;
+use core::fmt::{self, Write};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
 #[cfg(feature = "unstable")]
 use core::marker::Unsize;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::vec::Vec;
-use slotmap::{new_key_type, SlotMap, SecondaryMap};
-use crate::tree::object::{Stable, Cell};
+use slotmap::{new_key_type, KeyData, SlotMap, SecondaryMap};
+use crate::tree::object::{Stable, Cell, Shared};
+#[cfg(feature = "debug-leak-check")]
+use crate::stable::leak::BorrowRecord;
+use crate::tree::{render, validate, BorrowPolicy, ChildSelect, InvariantViolation, OrphanPolicy, TreeStats};
+use crate::stable::BorrowError;
+use crate::util;
 
+/// A list of keys shared with any snapshots taken of it, and copied only when modified while a
+/// snapshot is alive
+type KeyList = Shared<Vec<TreeKey>>;
+
+#[derive(Clone)]
 struct Relations {
     parents: SecondaryMap<TreeKey, TreeKey>,
-    children: SecondaryMap<TreeKey, Vec<TreeKey>>,
+    children: SecondaryMap<TreeKey, KeyList>,
+    /// Sequence number of each node, counting every node ever added
+    order: SecondaryMap<TreeKey, u64>,
+    next_order: u64,
 }
 
 impl Relations {
@@ -19,8 +39,103 @@ impl Relations {
         Relations {
             parents: SecondaryMap::new(),
             children: SecondaryMap::new(),
+            order: SecondaryMap::new(),
+            next_order: 0,
         }
     }
+
+    /// Give a newly added node the next sequence number
+    fn record_insertion(&mut self, key: TreeKey) {
+        self.order.insert(key, self.next_order);
+        self.next_order += 1;
+    }
+}
+
+/// A snapshot of the child keys of a node, in the order they were attached.
+///
+/// Taking a snapshot doesn't copy the keys, and the tree can be freely modified while it is
+/// alive. Later changes to the node's children aren't reflected.
+pub struct ChildKeys<'a> {
+    keys: Option<KeyList>,
+    _phantom: PhantomData<&'a ()>,
+}
+
+impl ChildKeys<'_> {
+    fn new(keys: Option<KeyList>) -> Self {
+        ChildKeys { keys, _phantom: PhantomData }
+    }
+}
+
+/// Iterate over a shared key list by index, so the list isn't copied
+fn iter_keys(keys: Option<KeyList>) -> impl Iterator<Item = TreeKey> {
+    let len = keys.as_ref().map_or(0, |keys| keys.len());
+    (0..len).filter_map(move |idx| keys.as_ref()?.get(idx).copied())
+}
+
+impl Deref for ChildKeys<'_> {
+    type Target = [TreeKey];
+
+    fn deref(&self) -> &Self::Target {
+        self.keys.as_deref().map_or(&[], Vec::as_slice)
+    }
+}
+
+impl AsRef<[TreeKey]> for ChildKeys<'_> {
+    fn as_ref(&self) -> &[TreeKey] {
+        self
+    }
+}
+
+impl fmt::Debug for ChildKeys<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.iter())
+            .finish()
+    }
+}
+
+/// A snapshot of the relationships between every node in a tree, for algorithms which need many
+/// lookups without borrowing the tree for each one.
+///
+/// Taking a snapshot doesn't copy anything, and the tree can be freely modified while it is
+/// alive. The first structural change made while it is alive copies the tree's relationships,
+/// and later changes aren't reflected.
+pub struct RelationsRef<'a> {
+    relations: Shared<Relations>,
+    roots: KeyList,
+    _phantom: PhantomData<&'a ()>,
+}
+
+impl RelationsRef<'_> {
+    /// Get the key of a node's parent, if it has one
+    pub fn parent_of(&self, key: TreeKey) -> Option<TreeKey> {
+        self.relations.parents.get(key).copied()
+    }
+
+    /// Get the child keys of a node, in the order they were attached. Nodes without children, or
+    /// which don't exist, produce an empty slice.
+    pub fn children_of(&self, key: TreeKey) -> &[TreeKey] {
+        self.relations.children.get(key).map_or(&[], |children| children.as_slice())
+    }
+
+    /// Get the keys of the roots, in order
+    pub fn roots(&self) -> &[TreeKey] {
+        &self.roots
+    }
+
+    /// Iterate over every node with a parent, as `(child, parent)` pairs in no particular order
+    pub fn parents(&self) -> impl Iterator<Item = (TreeKey, TreeKey)> + '_ {
+        self.relations.parents.iter().map(|(child, &parent)| (child, parent))
+    }
+}
+
+impl fmt::Debug for RelationsRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelationsRef")
+            .field("roots", &self.roots())
+            .field("parents", &self.relations.parents)
+            .finish()
+    }
 }
 
 new_key_type! {
@@ -29,13 +144,57 @@ new_key_type! {
     pub struct TreeKey;
 }
 
+impl TreeKey {
+    /// Convert this key into an opaque integer, which can cross an FFI boundary or be stored
+    /// externally
+    #[must_use]
+    pub fn to_ffi(self) -> u64 {
+        self.0.as_ffi()
+    }
+
+    /// Recreate a key from an integer produced by [`TreeKey::to_ffi`]. Invalid values produce a
+    /// key that doesn't refer to any node.
+    #[must_use]
+    pub fn from_ffi(val: u64) -> TreeKey {
+        TreeKey(KeyData::from_ffi(val))
+    }
+}
+
 /// An implementation of a tree data structure, with the ability to get mutable references to
 /// multiple nodes at once. Supports access via slot keys, or by traversing immutable or mutable
 /// node references.
+///
+/// Internal borrows are never held while user code runs, so callbacks and iterators may freely
+/// access and modify the tree they came from.
 pub struct Tree<T: ?Sized> {
-    nodes: Cell<SlotMap<TreeKey, Stable<T>>>,
-    relations: Cell<Relations>,
-    roots: Cell<Vec<TreeKey>>,
+    // When several of these are borrowed at once, they are borrowed in declaration order
+    relations: Cell<Shared<Relations>>,
+    roots: Cell<KeyList>,
+    /// Node values, or `None` for keys which are reserved but not yet filled
+    nodes: Cell<SlotMap<TreeKey, Option<Stable<T>>>>,
+    /// Values of nodes removed by [`remove_recursive_tracked`](Tree::remove_recursive_tracked)
+    /// while borrowed, kept until reclaimed
+    deferred: Cell<Vec<(TreeKey, Stable<T>)>>,
+}
+
+/// The nodes removed by [`Tree::remove_recursive_tracked`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Removal {
+    /// Every node removed, in pre-order
+    pub removed: Vec<TreeKey>,
+    /// Removed nodes which were borrowed, so their values couldn't be freed yet
+    pub deferred: Vec<TreeKey>,
+}
+
+/// How a node's value is currently borrowed, as reported by [`Tree::borrow_state`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorrowKind {
+    /// The value isn't borrowed
+    Free,
+    /// The value has this many shared borrows
+    Shared(usize),
+    /// The value is borrowed mutably
+    Mut,
 }
 
 impl<T: ?Sized> Tree<T> {
@@ -55,97 +214,692 @@ impl<T: ?Sized> Tree<T> {
         self.nodes.borrow().is_empty()
     }
 
+    /// Get the approximate number of bytes of heap memory used by this tree, including spare
+    /// capacity and the separate allocation of every node. Heap memory owned by the node values
+    /// themselves isn't counted.
+    pub fn memory_footprint(&self) -> usize {
+        let relations = self.relations.borrow();
+        let children = relations.children.values()
+            .map(|children| children.capacity() * mem::size_of::<TreeKey>())
+            .sum::<usize>();
+        let relations = util::secondary_heap_size(&relations.parents)
+            + util::secondary_heap_size(&relations.children)
+            + util::secondary_heap_size(&relations.order)
+            + children;
+        let roots = self.roots.borrow().capacity() * mem::size_of::<TreeKey>();
+        let nodes = self.nodes.borrow();
+        let values = nodes.values()
+            .flatten()
+            .map(Stable::alloc_size)
+            .sum::<usize>();
+        relations + roots + util::slotmap_heap_size(&nodes) + values
+    }
+
+    /// Remove all nodes from this tree. Values with outstanding borrows are freed once the last
+    /// borrow is released. Keys to removed nodes remain invalid, and will not refer to any nodes
+    /// added later.
+    pub fn clear(&self) {
+        let removed = {
+            let mut relations = self.relations.borrow_mut();
+            let relations = Shared::make_mut(&mut relations);
+            relations.parents.clear();
+            relations.children.clear();
+            relations.order.clear();
+            *self.roots.borrow_mut() = KeyList::default();
+            self.nodes.borrow_mut().drain().collect::<Vec<_>>()
+        };
+        // Dropped after the borrows are released, in case dropping a value accesses this tree
+        drop(removed);
+    }
+
+    /// Release excess capacity held for relations and roots after nodes are removed.
+    ///
+    /// Slot storage for nodes is retained, as it tracks which keys have been used so that stale
+    /// keys can never refer to new nodes. Freed slots will be reused by later insertions.
+    pub fn shrink_to_fit(&self) {
+        let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+        for children in relations.children.values_mut() {
+            Shared::make_mut(children).shrink_to_fit();
+        }
+        relations.parents = relations.parents.drain().collect();
+        relations.children = relations.children.drain().collect();
+        relations.order = relations.order.drain().collect();
+        Shared::make_mut(&mut self.roots.borrow_mut()).shrink_to_fit();
+    }
+
     /// Add a new root from a type that unsizes into the type of the tree
     #[cfg(feature = "unstable")]
     pub fn add_root_from<U: Unsize<T>>(&self, item: U) -> TreeKey {
-        let mut nodes = self.nodes.borrow_mut();
         let cell = Stable::new_from(item);
-        let new_key = nodes.insert(cell);
-        self.roots.borrow_mut().push(new_key);
+        let new_key = self.nodes.borrow_mut().insert(Some(cell));
+        self.record_insertion(new_key);
+        Shared::make_mut(&mut self.roots.borrow_mut()).push(new_key);
         new_key
     }
 
     /// Create a new child of a node from a type that unsizes into the type of the tree
     #[cfg(feature = "unstable")]
-    pub fn add_child_from<U: Unsize<T>>(&self, item: U, parent: TreeKey) -> Option<TreeKey> {
+    pub fn add_child_from<U: Unsize<T>>(&self, item: U, parent: TreeKey) -> Result<TreeKey> {
+        if !self.nodes.borrow().contains_key(parent) {
+            return Err(Error::Missing);
+        }
+
         let cell = Stable::new_from(item);
 
         let new_key = self.nodes
             .borrow_mut()
-            .insert(cell);
+            .insert(Some(cell));
 
         let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+        relations.record_insertion(new_key);
 
-        relations
+        let siblings = relations
             .children
-            .entry(parent)?
-            .or_default()
-            .push(new_key);
+            .entry(parent)
+            .ok_or(Error::Missing)?
+            .or_default();
+
+        Shared::make_mut(siblings).push(new_key);
 
         relations
             .parents
             .insert(new_key, parent);
 
-        Some(new_key)
+        Ok(new_key)
     }
 
     /// Set the first node as the parent of the second node,
-    /// unsetting the current parent if there is one
+    /// unsetting the current parent if there is one. Returns `None` if the change can't be made,
+    /// see [`try_set_child`](Self::try_set_child).
     pub fn set_child(&self, parent: TreeKey, child: TreeKey) -> Option<()> {
+        self.try_set_child(parent, child).ok()
+    }
+
+    /// Set the first node as the parent of the second node, unsetting the current parent if
+    /// there is one.
+    ///
+    /// Fails with [`Error::Missing`] if either node doesn't exist, or [`Error::Cycle`] if the
+    /// parent is the child itself or one of its descendants.
+    pub fn try_set_child(&self, parent: TreeKey, child: TreeKey) -> Result<()> {
         let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+
+        {
+            let nodes = self.nodes.borrow();
+            if !nodes.contains_key(parent) || !nodes.contains_key(child) {
+                return Err(Error::Missing);
+            }
+        }
 
-        let old_parent = relations.parents.get(child);
+        let mut ancestor = Some(parent);
+        while let Some(key) = ancestor {
+            if key == child {
+                return Err(Error::Cycle);
+            }
+            ancestor = relations.parents.get(key).copied();
+        }
 
         // Remove child's existing parent (remove it as a root, if it had no parent)
-        match old_parent {
-            Some(&old_parent) => relations.children[old_parent].retain(|&k| k != child),
-            None => self.roots.borrow_mut().retain(|&k| k != child),
+        match relations.parents.get(child) {
+            Some(&old_parent) => {
+                if let Some(siblings) = relations.children.get_mut(old_parent) {
+                    Shared::make_mut(siblings).retain(|&k| k != child);
+                }
+            }
+            None => Shared::make_mut(&mut self.roots.borrow_mut()).retain(|&k| k != child),
         }
 
         relations
             .parents
             .insert(child, parent);
-        relations
+        let siblings = relations
             .children
-            .entry(parent)?
-            .or_default()
-            .push(child);
-        Some(())
+            .entry(parent)
+            .ok_or(Error::Missing)?
+            .or_default();
+        Shared::make_mut(siblings).push(child);
+        Ok(())
     }
 
-    /// Remove the second node as a child of the first node
+    /// Remove the second node as a child of the first node, turning it into a root. Does nothing
+    /// if the change can't be made, see [`try_remove_child`](Self::try_remove_child).
     pub fn remove_child(&self, parent: TreeKey, child: TreeKey) {
+        let _ = self.try_remove_child(parent, child);
+    }
+
+    /// Remove the second node as a child of the first node, turning it into a root.
+    ///
+    /// Fails with [`Error::Missing`] if either node doesn't exist, or the second node isn't a
+    /// child of the first.
+    pub fn try_remove_child(&self, parent: TreeKey, child: TreeKey) -> Result<()> {
         let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
 
-        relations.children[parent].retain(|&k| k != child);
+        if relations.parents.get(child) != Some(&parent) {
+            return Err(Error::Missing);
+        }
+        if let Some(siblings) = relations.children.get_mut(parent) {
+            Shared::make_mut(siblings).retain(|&k| k != child);
+        }
         relations.parents.remove(child);
-        self.roots.borrow_mut().push(child);
+        Shared::make_mut(&mut self.roots.borrow_mut()).push(child);
+        Ok(())
     }
 
     /// Remove a node from the tree, removing all children as well. Fails if the node or any
     /// of its children are currently borrowed.
     pub fn remove_recursive(&self, node: TreeKey) {
+        // Declared first so removed values are dropped after the borrows below are released, in
+        // case dropping them accesses this tree
+        let mut removed = Vec::new();
         let mut relations = self.relations.borrow_mut();
-        let relations = &mut *relations;
+        let relations = Shared::make_mut(&mut relations);
+
+        match relations.parents.get(node) {
+            Some(&parent) => {
+                if let Some(siblings) = relations.children.get_mut(parent) {
+                    Shared::make_mut(siblings).retain(|&k| k != node);
+                }
+            }
+            None => Shared::make_mut(&mut self.roots.borrow_mut()).retain(|&k| k != node),
+        }
 
         let mut nodes = self.nodes
             .borrow_mut();
 
-        recurse_remove(node, &mut nodes, &mut relations.parents, &mut relations.children)
+        recurse_remove(node, &mut nodes, relations, &mut removed)
+    }
+
+    /// Remove a node from the tree along with all its children, as by
+    /// [`remove_recursive`](Self::remove_recursive), reporting which nodes were removed.
+    ///
+    /// The values of removed nodes which are still borrowed can't be freed yet. Rather than
+    /// being freed when their last borrow ends, they are kept by the tree until
+    /// [`reclaim_deferred`](Self::reclaim_deferred) finds them unborrowed, or the tree is
+    /// dropped.
+    pub fn remove_recursive_tracked(&self, node: TreeKey) -> Removal {
+        // Take the values out first, so borrowed ones can be kept rather than dropped
+        let values = self.take_values(node);
+        self.remove_recursive(node);
+
+        let mut removal = Removal::default();
+        let mut freed = Vec::new();
+        let mut deferred = self.deferred.borrow_mut();
+        for (key, val) in values {
+            removal.removed.push(key);
+            match val {
+                Some(val) if is_borrowed(&val) => {
+                    removal.deferred.push(key);
+                    deferred.push((key, val));
+                }
+                val => freed.push(val),
+            }
+        }
+        drop(deferred);
+        drop(freed);
+        removal
+    }
+
+    /// Free the values of nodes deferred by
+    /// [`remove_recursive_tracked`](Self::remove_recursive_tracked) which are no longer
+    /// borrowed, calling `on_free` with the key of each after its value is dropped
+    pub fn reclaim_deferred(&self, mut on_free: impl FnMut(TreeKey)) {
+        let (freed, kept) = mem::take(&mut *self.deferred.borrow_mut())
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, val)| !is_borrowed(val));
+        self.deferred.borrow_mut().extend(kept);
+        for (key, val) in freed {
+            drop(val);
+            on_free(key);
+        }
+    }
+
+    /// Get the keys of removed nodes whose values are waiting to be reclaimed by
+    /// [`reclaim_deferred`](Self::reclaim_deferred)
+    pub fn deferred_keys(&self) -> Vec<TreeKey> {
+        self.deferred.borrow().iter().map(|(key, _)| *key).collect()
+    }
+
+    /// Remove a node from the tree, moving its children into its place among its siblings
+    fn remove_reparenting(&self, node: TreeKey) {
+        let removed;
+        let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+
+        removed = self.nodes.borrow_mut().remove(node);
+        if removed.is_none() {
+            return;
+        }
+
+        let parent = relations.parents.remove(node);
+        let children = relations.children.remove(node).unwrap_or_default();
+        relations.order.remove(node);
+
+        for &child in children.iter() {
+            match parent {
+                Some(parent) => relations.parents.insert(child, parent),
+                None => relations.parents.remove(child),
+            };
+        }
+
+        let mut roots = self.roots.borrow_mut();
+        let siblings = match parent {
+            Some(parent) => relations.children.get_mut(parent),
+            None => Some(&mut *roots),
+        };
+        if let Some(siblings) = siblings {
+            if let Some(pos) = siblings.iter().position(|&k| k == node) {
+                Shared::make_mut(siblings).splice(pos..=pos, children.iter().copied());
+            }
+        }
+    }
+
+    /// Run `f` with a [`Transaction`] making structural changes to this tree. If `f` fails or
+    /// panics, every change made through the transaction is undone, restoring the tree's
+    /// structure and any removed nodes.
+    pub fn transaction<R, E>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction<'_, T>) -> core::result::Result<R, E>,
+    ) -> core::result::Result<R, E> {
+        let mut txn = Transaction::new(self);
+        let out = f(&mut txn)?;
+        txn.commit();
+        Ok(out)
+    }
+
+    /// Get the parent of a node and its index among its siblings, or among the roots
+    pub(super) fn position_of(&self, key: TreeKey) -> Option<(Option<TreeKey>, usize)> {
+        let relations = self.relations.borrow();
+        let parent = relations.parents.get(key).copied();
+        let index = match parent {
+            Some(parent) => relations.children.get(parent)?.iter().position(|&k| k == key)?,
+            None => self.roots.borrow().iter().position(|&k| k == key)?,
+        };
+        Some((parent, index))
+    }
+
+    /// Unlink a node from its parent or the roots, leaving it attached to nothing
+    pub(super) fn detach(&self, key: TreeKey) {
+        let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+        match relations.parents.remove(key) {
+            Some(parent) => {
+                if let Some(siblings) = relations.children.get_mut(parent) {
+                    Shared::make_mut(siblings).retain(|&k| k != key);
+                }
+            }
+            None => Shared::make_mut(&mut self.roots.borrow_mut()).retain(|&k| k != key),
+        }
+    }
+
+    /// Move a node to an index among the children of `parent`, or among the roots
+    pub(super) fn relink(&self, key: TreeKey, parent: Option<TreeKey>, index: usize) {
+        self.detach(key);
+        let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+        let mut roots = self.roots.borrow_mut();
+        let siblings = match parent {
+            Some(parent) => {
+                relations.parents.insert(key, parent);
+                match relations.children.entry(parent) {
+                    Some(entry) => Shared::make_mut(entry.or_default()),
+                    None => return,
+                }
+            }
+            None => Shared::make_mut(&mut roots),
+        };
+        siblings.insert(index.min(siblings.len()), key);
+    }
+
+    /// Take the values out of every node in a subtree in pre-order, leaving them unfilled
+    pub(super) fn take_values(&self, key: TreeKey) -> Vec<(TreeKey, Option<Stable<T>>)> {
+        let relations = self.relations.borrow();
+        let mut nodes = self.nodes.borrow_mut();
+        let mut values = Vec::new();
+        let mut stack = alloc::vec![key];
+        while let Some(key) = stack.pop() {
+            if let Some(slot) = nodes.get_mut(key) {
+                values.push((key, slot.take()));
+            }
+            if let Some(children) = relations.children.get(key) {
+                stack.extend(children.iter().rev().copied());
+            }
+        }
+        values
+    }
+
+    /// Put values taken by [`take_values`](Self::take_values) back
+    pub(super) fn restore_values(&self, values: Vec<(TreeKey, Option<Stable<T>>)>) {
+        let mut nodes = self.nodes.borrow_mut();
+        for (key, val) in values {
+            if let Some(slot) = nodes.get_mut(key) {
+                *slot = val;
+            }
+        }
+    }
+
+    /// Remove a detached subtree from the tree
+    pub(super) fn purge(&self, key: TreeKey) {
+        let mut removed = Vec::new();
+        let mut relations = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut relations);
+        recurse_remove(key, &mut self.nodes.borrow_mut(), relations, &mut removed);
+    }
+
+    /// Remove all nodes for which the predicate returns `false`. The children of removed nodes
+    /// are handled according to the provided policy.
+    ///
+    /// Reserved nodes which haven't been filled are always kept. Fails without removing anything
+    /// if any node can't be borrowed to check the predicate.
+    pub fn retain(&self, policy: OrphanPolicy, mut f: impl FnMut(TreeKey, &T) -> bool) -> Result<()> {
+        let mut removed = Vec::new();
+        for key in self.unordered_keys() {
+            let node = match self.try_get(key) {
+                Ok(node) => node,
+                Err(Error::Unfilled) => continue,
+                Err(err) => return Err(err),
+            };
+            if !f(key, &node) {
+                removed.push(key);
+            }
+        }
+
+        for key in removed {
+            match policy {
+                OrphanPolicy::Remove => self.remove_recursive(key),
+                OrphanPolicy::Reparent => self.remove_reparenting(key),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the predicate returns `true` for any node, visiting nodes in pre-order and
+    /// stopping at the first match.
+    ///
+    /// Reserved nodes which haven't been filled, or nodes removed during the traversal, are
+    /// skipped. Nodes which can't be borrowed are handled according to the provided policy.
+    pub fn any(&self, policy: BorrowPolicy, mut f: impl FnMut(TreeKey, &T) -> bool) -> Result<bool> {
+        let mut stack = self.root_keys().collect::<Vec<_>>();
+        stack.reverse();
+        while let Some(key) = stack.pop() {
+            match self.try_get(key) {
+                Ok(node) if f(key, &node) => return Ok(true),
+                Ok(_) | Err(Error::Unfilled) => (),
+                Err(Error::Missing) => continue,
+                Err(Error::CantBorrow) if policy == BorrowPolicy::Skip => (),
+                Err(err) => return Err(err),
+            }
+            stack.extend(self.child_keys(key).iter().rev());
+        }
+        Ok(false)
+    }
+
+    /// Check whether the predicate returns `true` for every node, visiting nodes in pre-order and
+    /// stopping at the first failure. Skipped nodes are handled as in [`any`](Self::any).
+    pub fn all(&self, policy: BorrowPolicy, mut f: impl FnMut(TreeKey, &T) -> bool) -> Result<bool> {
+        self.any(policy, |key, val| !f(key, val))
+            .map(|found| !found)
+    }
+
+    /// Fold the subtree of a node bottom-up. Each node's result starts as `init` of its value,
+    /// then the results of its children are combined into it in order with `f`.
+    ///
+    /// The subtree is walked once, reading each node's children when it's first reached, so
+    /// callbacks for earlier siblings' subtrees have already run by then. A node is only borrowed
+    /// while `init` and `f` run for it. Fails if any node in the subtree can't be borrowed when
+    /// its result is made, including nodes removed by earlier callbacks.
+    pub fn fold_subtree<R>(
+        &self,
+        key: TreeKey,
+        mut init: impl FnMut(TreeKey, &T) -> R,
+        mut f: impl FnMut(R, R) -> R,
+    ) -> Result<R> {
+        // Nodes are visited twice, first to queue their children and then to combine them
+        let mut stack = alloc::vec![(key, None)];
+        let mut results = Vec::new();
+        while let Some((cur, expanded)) = stack.pop() {
+            match expanded {
+                Some(len) => {
+                    let node = self.try_get(cur)?;
+                    let children = results.split_off(results.len() - len);
+                    let acc = children.into_iter().fold(init(cur, &node), &mut f);
+                    results.push(acc);
+                }
+                None => {
+                    let children = self.child_keys(cur);
+                    stack.push((cur, Some(children.len())));
+                    stack.extend(children.iter().rev().map(|&child| (child, None)));
+                }
+            }
+        }
+        results.pop().ok_or(Error::Missing)
+    }
+
+    /// Thread a context down the subtree of a node in pre-order. Each node's context is made by
+    /// `f` from its parent's, with the node itself starting from `ctx`. Returns the context of
+    /// every node in the subtree.
+    ///
+    /// Fails if any node in the subtree can't be borrowed.
+    pub fn accumulate<C>(
+        &self,
+        key: TreeKey,
+        ctx: C,
+        mut f: impl FnMut(&C, TreeKey, &T) -> C,
+    ) -> Result<SecondaryMap<TreeKey, C>> {
+        let mut out = SecondaryMap::new();
+        let mut stack = alloc::vec![(key, None)];
+        while let Some((cur, parent)) = stack.pop() {
+            let node = self.try_get(cur)?;
+            let parent_ctx = parent.and_then(|parent| out.get(parent)).unwrap_or(&ctx);
+            let cur_ctx = f(parent_ctx, cur, &node);
+            drop(node);
+            out.insert(cur, cur_ctx);
+            stack.extend(self.child_keys(cur).iter().rev().map(|&child| (child, Some(cur))));
+        }
+        Ok(out)
+    }
+
+    /// Walk down from a node as a decision tree, letting `chooser` pick which child to continue
+    /// into from each node's value and a context. The walk ends when `chooser` stops it, picks a
+    /// child which doesn't exist, or reaches a leaf, which isn't passed to `chooser`. Returns the
+    /// keys of the nodes visited, starting with the node and ending where the walk stopped.
+    ///
+    /// Each node is borrowed only while `chooser` runs on it. Fails if the node doesn't exist, or
+    /// any visited node can't be borrowed.
+    pub fn evaluate<C: ?Sized>(
+        &self,
+        key: TreeKey,
+        ctx: &C,
+        mut chooser: impl FnMut(&T, &C) -> ChildSelect,
+    ) -> Result<Vec<TreeKey>> {
+        let mut cur = key;
+        let mut path = alloc::vec![key];
+        loop {
+            let node = self.try_get(cur)?;
+            let children = self.child_keys(cur);
+            if children.is_empty() {
+                break;
+            }
+            let ChildSelect::Child(idx) = chooser(&node, ctx) else {
+                break;
+            };
+            let Some(&child) = children.get(idx) else {
+                break;
+            };
+            path.push(child);
+            cur = child;
+        }
+        Ok(path)
+    }
+
+    /// Run a two-phase pass over the subtree of a node, the shape of a layout engine. First
+    /// `measure` runs bottom-up, making a result for each node from its value and the results of
+    /// its children, in order. Then `arrange` runs top-down, given each node's value mutably, its
+    /// measured result, its input and the measured results of its children, and returns the
+    /// inputs of those children. The node itself is given `input`. Children without an input
+    /// aren't arranged. Returns the measured result of the node.
+    ///
+    /// The shape of the subtree is read once, before either pass runs, and only the node passed
+    /// to a callback is borrowed while it runs. Fails if any node in the subtree can't be borrowed when
+    /// it's reached.
+    pub fn two_phase<M, A, I>(
+        &self,
+        key: TreeKey,
+        mut measure: impl FnMut(TreeKey, &T, &[M]) -> M,
+        input: A,
+        mut arrange: impl FnMut(TreeKey, &mut T, &M, A, &[M]) -> I,
+    ) -> Result<M>
+    where
+        I: IntoIterator<Item = A>,
+    {
+        // Nodes in pre-order, with their parent within the subtree, index and children
+        let mut order = Vec::new();
+        let mut stack = alloc::vec![(key, None, 0)];
+        while let Some((cur, parent, idx)) = stack.pop() {
+            let children = self.child_keys(cur).to_vec();
+            stack.extend(children.iter().enumerate().rev().map(|(idx, &child)| (child, Some(cur), idx)));
+            order.push((cur, parent, idx, children));
+        }
+
+        // The measured results of each node's children, kept together to be passed as a slice
+        let mut measured = SecondaryMap::<TreeKey, Vec<M>>::new();
+        let mut results = Vec::new();
+        for (cur, _, _, children) in order.iter().rev() {
+            let mut child_results = results.split_off(results.len() - children.len());
+            child_results.reverse();
+            let node = self.try_get(*cur)?;
+            let result = measure(*cur, &node, &child_results);
+            drop(node);
+            measured.insert(*cur, child_results);
+            results.push(result);
+        }
+        let root = results.pop().ok_or(Error::Missing)?;
+
+        let mut inputs = SecondaryMap::<TreeKey, A>::new();
+        let mut root_input = Some(input);
+        for (cur, parent, idx, children) in &order {
+            let (own, input) = match parent {
+                Some(parent) => (measured.get(*parent).and_then(|results| results.get(*idx)), inputs.remove(*cur)),
+                None => (Some(&root), root_input.take()),
+            };
+            let (Some(own), Some(input)) = (own, input) else {
+                continue;
+            };
+            let child_results = measured.get(*cur).map_or(&[][..], Vec::as_slice);
+            let mut node = self.try_get_mut(*cur)?;
+            let child_inputs = arrange(*cur, &mut node, own, input, child_results);
+            drop(node);
+            for (&child, child_input) in children.iter().zip(child_inputs) {
+                inputs.insert(child, child_input);
+            }
+        }
+        Ok(root)
+    }
+
+    /// Stably sort the roots and the children of every node by comparing their values. Returns
+    /// the key of every node in pre-order of the sorted tree.
+    ///
+    /// Every list is sorted before the tree is changed, so a failure leaves it as it was. A list
+    /// holding a node which can't be borrowed, or a reserved node which hasn't been filled, is
+    /// handled according to the provided policy, either left in its current order or failing
+    /// the sort.
+    pub fn sort_children_recursive(
+        &self,
+        policy: BorrowPolicy,
+        mut cmp: impl FnMut(&T, &T) -> Ordering,
+    ) -> Result<Vec<TreeKey>> {
+        let lists = core::iter::once(None)
+            .chain(self.keys_dfs().map(Some))
+            .map(|parent| {
+                let keys = match parent {
+                    Some(parent) => self.child_keys(parent).to_vec(),
+                    None => self.root_keys().collect(),
+                };
+                (parent, keys)
+            })
+            .filter(|(_, keys)| keys.len() > 1);
+
+        let mut sorted = Vec::new();
+        for (parent, keys) in lists {
+            let mut nodes = match keys.iter().map(|&key| self.try_get(key)).collect::<Result<Vec<_>>>() {
+                Ok(nodes) => nodes,
+                Err(Error::CantBorrow | Error::Unfilled) if policy == BorrowPolicy::Skip => continue,
+                Err(err) => return Err(err),
+            };
+            nodes.sort_by(|a, b| cmp(a, b));
+            sorted.push((parent, nodes.iter().map(NodeRef::key).collect::<Vec<_>>()));
+        }
+
+        {
+            let mut relations = self.relations.borrow_mut();
+            let relations = Shared::make_mut(&mut relations);
+            let mut roots = self.roots.borrow_mut();
+            for (parent, keys) in sorted {
+                let list = match parent {
+                    Some(parent) => relations.children.get_mut(parent),
+                    None => Some(&mut *roots),
+                };
+                if let Some(list) = list {
+                    *Shared::make_mut(list) = keys;
+                }
+            }
+        }
+        Ok(self.keys_dfs().collect())
     }
 
     /// Try to get an immutable reference to a node identified by the provided key
     pub fn try_get<'b>(&self, key: TreeKey) -> Result<NodeRef<'_, 'b, T>> {
         let nodes = self.nodes.borrow();
         let rc = nodes.get(key).ok_or(Error::Missing)?;
-        NodeRef::try_borrow(self, key, rc)
+        NodeRef::try_borrow(self, key, rc.as_ref().ok_or(Error::Unfilled)?)
     }
 
     /// Try to get a mutable reference to a node identified by the provided key
     pub fn try_get_mut<'b>(&self, key: TreeKey) -> Result<NodeRefMut<'_, 'b, T>> {
         let nodes = self.nodes.borrow();
         let rc = nodes.get(key).ok_or(Error::Missing)?;
-        NodeRefMut::try_borrow(self, key, rc)
+        NodeRefMut::try_borrow(self, key, rc.as_ref().ok_or(Error::Unfilled)?)
+    }
+
+    /// Get how a node's value is currently borrowed, without borrowing it, or `None` if the node
+    /// doesn't exist or hasn't been filled. With the `atomic` feature, the state may change as soon
+    /// as it's read.
+    pub fn borrow_state(&self, key: TreeKey) -> Option<BorrowKind> {
+        let nodes = self.nodes.borrow();
+        let state = nodes.get(key)?.as_ref()?.borrow_state();
+        Some(if state.is_borrow() {
+            BorrowKind::Mut
+        } else if state.is_ref() {
+            BorrowKind::Shared(state.ref_count())
+        } else {
+            BorrowKind::Free
+        })
+    }
+
+    /// Mutably borrow several nodes at once for the duration of a closure. Every borrow is
+    /// released when the closure returns, and the references can't escape it.
+    ///
+    /// Fails without calling the closure if any node can't be borrowed, including when the same
+    /// key is passed more than once.
+    pub fn with_nodes<const N: usize, R>(
+        &self,
+        keys: [TreeKey; N],
+        f: impl for<'b> FnOnce([NodeRefMut<'_, 'b, T>; N]) -> R,
+    ) -> Result<R> {
+        let nodes = keys.iter()
+            .map(|&key| self.try_get_mut(key))
+            .collect::<Result<Vec<_>>>()?;
+        let nodes = <[_; N]>::try_from(nodes).map_err(|_| Error::Missing)?;
+        Ok(f(nodes))
+    }
+
+    /// Mutably borrow a node for the duration of a closure, returning the closure's result
+    pub fn update<R>(&self, key: TreeKey, f: impl FnOnce(&mut T) -> R) -> Result<R> {
+        let mut node = self.try_get_mut(key)?;
+        Ok(f(&mut node))
     }
 
     /// Iterate over all nodes in this tree, in no particular order
@@ -154,7 +908,7 @@ impl<T: ?Sized> Tree<T> {
             .borrow()
             .iter()
             .map(|(key, item)| {
-                NodeRef::try_borrow(self, key, item)
+                NodeRef::try_borrow(self, key, item.as_ref().ok_or(Error::Unfilled)?)
             })
             .collect::<Vec<_>>()
             .into_iter()
@@ -165,7 +919,7 @@ impl<T: ?Sized> Tree<T> {
         self.nodes
             .borrow()
             .iter()
-            .map(|(key, item)| NodeRefMut::try_borrow(self, key, item))
+            .map(|(key, item)| NodeRefMut::try_borrow(self, key, item.as_ref().ok_or(Error::Unfilled)?))
             .collect::<Vec<_>>()
             .into_iter()
     }
@@ -179,6 +933,41 @@ impl<T: ?Sized> Tree<T> {
             .into_iter()
     }
 
+    /// Get the keys of all nodes in this tree, in the order they were added. Unlike
+    /// [`unordered_keys`](Tree::unordered_keys), this doesn't depend on how keys are allocated,
+    /// so is reproducible after unrelated insertions and removals.
+    pub fn sorted_keys(&self) -> Vec<TreeKey> {
+        let relations = self.relations.borrow();
+        let mut keys = self.nodes
+            .borrow()
+            .keys()
+            .filter_map(|key| Some((*relations.order.get(key)?, key)))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.into_iter().map(|(_, key)| key).collect()
+    }
+
+    /// Get the sequence number of a node, counting every node ever added to this tree. Nodes
+    /// added later always have a greater index, and moving a node doesn't change it.
+    pub fn insertion_index(&self, key: TreeKey) -> Option<u64> {
+        self.relations.borrow().order.get(key).copied()
+    }
+
+    /// Get the child of a node which was most recently added to the tree
+    pub fn newest_child(&self, parent: TreeKey) -> Option<TreeKey> {
+        let relations = self.relations.borrow();
+        relations.children
+            .get(parent)?
+            .iter()
+            .copied()
+            .max_by_key(|&child| relations.order.get(child).copied())
+    }
+
+    /// Give a newly added node the next sequence number
+    fn record_insertion(&self, key: TreeKey) {
+        Shared::make_mut(&mut self.relations.borrow_mut()).record_insertion(key);
+    }
+
     /// Iterate over the roots of this tree.
     ///
     /// A root is any node that has no parent
@@ -190,7 +979,7 @@ impl<T: ?Sized> Tree<T> {
             .iter()
             .map(|key| {
                 let node = nodes.get(*key).ok_or(Error::Missing)?;
-                NodeRef::try_borrow(self, *key, node)
+                NodeRef::try_borrow(self, *key, node.as_ref().ok_or(Error::Unfilled)?)
             })
             .collect::<Vec<_>>()
             .into_iter()
@@ -207,7 +996,7 @@ impl<T: ?Sized> Tree<T> {
             .iter()
             .map(|key| {
                 let node = nodes.get(*key).ok_or(Error::Missing)?;
-                NodeRefMut::try_borrow(self, *key, node)
+                NodeRefMut::try_borrow(self, *key, node.as_ref().ok_or(Error::Unfilled)?)
             })
             .collect::<Vec<_>>()
             .into_iter()
@@ -217,7 +1006,7 @@ impl<T: ?Sized> Tree<T> {
     ///
     /// A root is any node that has no parent
     pub fn root_keys(&self) -> impl Iterator<Item = TreeKey> {
-        self.roots.borrow().clone().into_iter()
+        iter_keys(Some(self.roots.borrow().clone()))
     }
 
     /// Get the parent key of a node identified by the provided key
@@ -225,42 +1014,430 @@ impl<T: ?Sized> Tree<T> {
         self.relations.borrow().parents.get(child).copied()
     }
 
+    /// Get a snapshot of the child keys of a node identified by the provided key, in the order
+    /// they were attached. Nodes without children, or which don't exist, produce an empty slice.
+    pub fn child_keys(&self, parent: TreeKey) -> ChildKeys<'_> {
+        ChildKeys::new(self.relations.borrow().children.get(parent).cloned())
+    }
+
+    /// Get a snapshot of the relationships between every node, see [`RelationsRef`]
+    pub fn relations(&self) -> RelationsRef<'_> {
+        let relations = Shared::clone(&self.relations.borrow());
+        let roots = Shared::clone(&self.roots.borrow());
+        RelationsRef { relations, roots, _phantom: PhantomData }
+    }
+
     /// Get the child keys of a node identified by the provided key
     pub fn child_keys_of(&self, parent: TreeKey) -> impl Iterator<Item = TreeKey> + '_ {
-        self.relations
-            .borrow()
-            .children
-            .get(parent)
-            .cloned()
-            .unwrap_or_default()
-            .into_iter()
+        iter_keys(self.relations.borrow().children.get(parent).cloned())
+    }
+
+    /// Lazily iterate over the levels of this tree, yielding the keys of the nodes at each depth
+    /// starting with the roots. Each level is in pre-order, and is read as the level before it is
+    /// yielded.
+    pub fn levels(&self) -> impl Iterator<Item = Vec<TreeKey>> + '_ {
+        let first = Some(self.root_keys().collect::<Vec<_>>()).filter(|roots| !roots.is_empty());
+        core::iter::successors(first, |level| {
+            let next = level.iter()
+                .flat_map(|&key| self.child_keys_of(key))
+                .collect::<Vec<_>>();
+            Some(next).filter(|next| !next.is_empty())
+        })
+    }
+
+    /// Lazily iterate over the keys of every node in pre-order, so parents are always yielded
+    /// before their children. Roots are visited in order. Node values are never borrowed.
+    pub fn keys_dfs(&self) -> DfsKeys<'_, T> {
+        DfsKeys::new(self, &self.roots.borrow())
+    }
+
+    /// Lazily iterate over the keys of every node breadth-first, a level at a time starting with
+    /// the roots. Node values are never borrowed.
+    pub fn keys_bfs(&self) -> BfsKeys<'_, T> {
+        BfsKeys::new(self, &self.roots.borrow())
+    }
+
+    /// Lazily iterate over the keys of the descendants of a node in pre-order, not including the
+    /// node itself. Node values are never borrowed.
+    pub fn descendant_keys(&self, key: TreeKey) -> DfsKeys<'_, T> {
+        DfsKeys::new(self, &self.child_keys(key))
+    }
+
+    /// Flatten this tree into `(value, parent)` rows in pre-order, where each parent is the
+    /// index of an earlier row. The inverse of [`from_parent_list`](Tree::from_parent_list).
+    ///
+    /// Fails if any node can't be borrowed.
+    pub fn to_parent_list(&self) -> Result<Vec<(T, Option<usize>)>>
+    where
+        T: Clone,
+    {
+        let mut rows = Vec::new();
+        let mut positions = SecondaryMap::new();
+        for key in self.keys_dfs() {
+            let parent = self.parent_key_of(key).and_then(|parent| positions.get(parent).copied());
+            positions.insert(key, rows.len());
+            rows.push((T::clone(&*self.try_get(key)?), parent));
+        }
+        Ok(rows)
+    }
+
+    /// Get every parent-child link in this tree as `(parent, child)` pairs, with children in
+    /// pre-order
+    pub fn to_edge_list(&self) -> Vec<(TreeKey, TreeKey)> {
+        self.keys_dfs()
+            .filter_map(|key| Some((self.parent_key_of(key)?, key)))
+            .collect()
+    }
+
+    /// Lazily iterate over the keys of every node in post-order, so children are always yielded
+    /// before their parents. Roots are visited in order.
+    pub fn post_order_keys(&self) -> PostOrderKeys<'_, T> {
+        PostOrderKeys::new(self, &self.roots.borrow())
+    }
+
+    /// Lazily iterate over every node in post-order, so children are always yielded before their
+    /// parents. Roots are visited in order.
+    pub fn iter_post_order(&self) -> impl Iterator<Item = Result<NodeRef<'_, '_, T>>> + '_ {
+        self.post_order_keys()
+            .map(|key| self.try_get(key))
+    }
+
+    /// Check the internal consistency of this tree, returning every broken invariant found
+    pub fn validate(&self) -> core::result::Result<(), Vec<InvariantViolation<TreeKey>>> {
+        let relations = self.relations.borrow();
+        let roots = self.roots.borrow();
+        let nodes = self.nodes.borrow();
+
+        let stale = relations.parents.keys()
+            .chain(relations.children.keys())
+            .filter(|&key| !nodes.contains_key(key))
+            .map(InvariantViolation::Dangling)
+            .collect();
+        let keys = nodes.keys().collect::<Vec<_>>();
+        validate::validate(
+            stale,
+            &keys,
+            &roots,
+            |key| nodes.contains_key(key),
+            |key| relations.parents.get(key).copied(),
+            |key| relations.children.get(key).map_or(&[], |children| children.as_slice()),
+        )
+    }
+
+    /// Get every outstanding borrow of a node value in this tree, with the borrows of each node
+    /// oldest first. Borrows which outlived their node aren't included.
+    #[cfg(feature = "debug-leak-check")]
+    pub fn outstanding_borrows(&self) -> Vec<(TreeKey, BorrowRecord)> {
+        self.nodes.borrow()
+            .iter()
+            .filter_map(|(key, val)| Some((key, val.as_ref()?)))
+            .flat_map(|(key, val)| val.outstanding_borrows().into_iter().map(move |record| (key, record)))
+            .collect()
+    }
+
+    /// Gather statistics about the shape of this tree in a single traversal
+    pub fn stats(&self) -> TreeStats {
+        let roots = self.roots.borrow().clone();
+        TreeStats::collect(&roots, |key| self.child_keys(key))
+    }
+
+    fn render(&self, style: &render::Style, mut f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        let roots = self.roots.borrow().clone();
+        render::render(
+            style,
+            &roots,
+            |key| self.child_keys(key).to_vec(),
+            |out, key| match self.try_get(key) {
+                Ok(node) => f(out, &node),
+                Err(Error::Unfilled) => out.write_str("(Unfilled)"),
+                Err(_) => out.write_str("(Borrowed)"),
+            },
+        )
+    }
+
+    /// Render this tree as text, one node per line, with branches drawn using ASCII characters.
+    /// `f` writes the label for each node's value. Nodes that are mutably borrowed are shown
+    /// as `(Borrowed)`, and reserved nodes which haven't been filled as `(Unfilled)`.
+    pub fn render_ascii(&self, f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        self.render(&render::ASCII, f)
+    }
+
+    /// Render this tree as text, one node per line, with branches drawn using box-drawing
+    /// characters. `f` writes the label for each node's value. Nodes that are mutably borrowed
+    /// are shown as `(Borrowed)`, and reserved nodes which haven't been filled as `(Unfilled)`.
+    pub fn render_unicode(&self, f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        self.render(&render::UNICODE, f)
+    }
+}
+
+impl<K: Ord + Clone> Tree<K> {
+    /// Build a tree from `(parent, child)` edges between identifiers, with each distinct
+    /// identifier becoming a node holding it. Children are ordered as their edges appear, and
+    /// roots as they first appear.
+    ///
+    /// Fails with [`Error::MultipleParents`] if any identifier has more than one parent, or
+    /// [`Error::Cycle`] if the edges form a cycle.
+    pub fn from_edges(edges: impl IntoIterator<Item = (K, K)>) -> Result<Tree<K>> {
+        let tree = Tree::new();
+        let mut keys = BTreeMap::new();
+        for (parent, child) in edges {
+            let parent = *keys.entry(parent.clone()).or_insert_with(|| tree.add_root(parent));
+            let child = *keys.entry(child.clone()).or_insert_with(|| tree.add_root(child));
+            if tree.parent_key_of(child).is_some() {
+                return Err(Error::MultipleParents);
+            }
+            tree.try_set_child(parent, child)?;
+        }
+        Ok(tree)
     }
 }
 
 impl<T> Tree<T> {
+    /// Rebuild a tree from nodes with previously exported keys, provided as
+    /// `(key, parent, value)`. Children and roots are ordered as they appear.
+    ///
+    /// Fails with [`Error::InvalidKey`] if any key is malformed, repeated, or has a slot index or
+    /// version too large to restore quickly, [`Error::Missing`] if a parent isn't one of the
+    /// provided keys, or [`Error::Cycle`] if the parent links form a cycle.
+    pub fn from_parts(nodes: impl IntoIterator<Item = (TreeKey, Option<TreeKey>, T)>) -> Result<Tree<T>> {
+        let mut links = Vec::new();
+        let nodes = nodes.into_iter()
+            .map(|(key, parent, val)| {
+                links.push((key, parent));
+                (key, Some(Stable::new(val)))
+            })
+            .collect();
+        let nodes = util::slotmap_from_keys(nodes, util::MAX_RESTORE_WORK).ok_or(Error::InvalidKey)?;
+
+        let mut relations = Relations::new();
+        let mut roots = Vec::new();
+        for (key, parent) in links {
+            relations.record_insertion(key);
+            match parent {
+                Some(parent) => {
+                    if !nodes.contains_key(parent) {
+                        return Err(Error::Missing);
+                    }
+                    relations.parents.insert(key, parent);
+                    let children = relations.children
+                        .entry(parent)
+                        .ok_or(Error::Missing)?
+                        .or_default();
+                    Shared::make_mut(children).push(key);
+                }
+                None => roots.push(key),
+            }
+        }
+        if util::has_cycle(&relations.parents) {
+            return Err(Error::Cycle);
+        }
+
+        Ok(Tree {
+            nodes: Cell::new(nodes),
+            relations: Cell::new(Shared::new(relations)),
+            roots: Cell::new(Shared::new(roots)),
+            deferred: Cell::new(Vec::new()),
+        })
+    }
+
+    /// Clone this tree, keeping the same keys. Fails with the keys of every node which can't be
+    /// borrowed to clone its value. Reserved nodes which haven't been filled stay unfilled.
+    ///
+    /// Like [`from_parts`](Self::from_parts), this takes longer for trees which have had many
+    /// nodes added and removed.
+    pub fn try_clone(&self) -> core::result::Result<Tree<T>, Vec<TreeKey>>
+    where
+        T: Clone,
+    {
+        let relations = Shared::clone(&self.relations.borrow());
+        let roots = Shared::clone(&self.roots.borrow());
+        let mut values = Vec::new();
+        let mut borrowed = Vec::new();
+        for (key, slot) in self.nodes.borrow().iter() {
+            match slot.as_ref().map(Stable::try_clone).transpose() {
+                Ok(val) => values.push((key, val)),
+                Err(_) => borrowed.push(key),
+            }
+        }
+        if !borrowed.is_empty() {
+            return Err(borrowed);
+        }
+
+        // Keys taken from a live map are always valid, and took at least as much work to create
+        // as restoring them does
+        let nodes = util::slotmap_from_keys(values, u64::MAX).ok_or_else(Vec::new)?;
+        Ok(Tree {
+            nodes: Cell::new(nodes),
+            relations: Cell::new(relations),
+            roots: Cell::new(roots),
+            deferred: Cell::new(Vec::new()),
+        })
+    }
+
+    /// Build a tree from `(value, parent)` rows, where each parent is the index of another row.
+    /// Parents may come before or after their children. Children and roots are ordered as they
+    /// appear. Also returns the key of the node made from each row.
+    ///
+    /// Fails with [`Error::Missing`] if a parent index is out of range, or [`Error::Cycle`] if
+    /// the parent links form a cycle.
+    pub fn from_parent_list(items: impl IntoIterator<Item = (T, Option<usize>)>) -> Result<(Tree<T>, Vec<TreeKey>)> {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let mut minted = SlotMap::<TreeKey, ()>::with_capacity_and_key(items.len());
+        let keys = items.iter().map(|_| minted.insert(())).collect::<Vec<_>>();
+        let parts = items.into_iter()
+            .zip(&keys)
+            .map(|((val, parent), &key)| {
+                let parent = match parent {
+                    Some(idx) => Some(*keys.get(idx).ok_or(Error::Missing)?),
+                    None => None,
+                };
+                Ok((key, parent, val))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Tree::from_parts(parts).map(|tree| (tree, keys))
+    }
+
     /// Add a new root to the tree initialized with the provided value
     pub fn add_root(&self, item: T) -> TreeKey {
-        let mut nodes = self.nodes.borrow_mut();
-        let cell = Stable::new(item);
-        let new_key = nodes.insert(cell);
-        self.roots.borrow_mut().push(new_key);
+        self.add_root_with(|_| item)
+    }
+
+    /// Add a new root to the tree, initialized with the value returned by `f` given the new key.
+    ///
+    /// The key is reserved while `f` runs, so the tree may be accessed from it. If `f` removes the
+    /// new node, its value is dropped once `f` returns.
+    pub fn add_root_with(&self, f: impl FnOnce(TreeKey) -> T) -> TreeKey {
+        match self.try_add_root_with(|key| Ok::<_, Infallible>(f(key))) {
+            Ok(key) => key,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Add a new root to the tree, initialized with the value returned by `f` given the new key.
+    /// If `f` fails, nothing is inserted and its error is returned.
+    ///
+    /// The key is reserved while `f` runs, so the tree may be accessed from it. If `f` removes the
+    /// new node, its value is dropped once `f` returns.
+    pub fn try_add_root_with<E>(&self, f: impl FnOnce(TreeKey) -> core::result::Result<T, E>) -> core::result::Result<TreeKey, E> {
+        let new_key = self.insert_with(f)?;
+        if self.nodes.borrow().contains_key(new_key) {
+            Shared::make_mut(&mut self.roots.borrow_mut()).push(new_key);
+        }
+        Ok(new_key)
+    }
+
+    /// Insert a detached node with a value from `f`, without holding any borrows while it runs.
+    /// The node is removed again if `f` fails.
+    fn insert_with<E>(&self, f: impl FnOnce(TreeKey) -> core::result::Result<T, E>) -> core::result::Result<TreeKey, E> {
+        let new_key = self.nodes.borrow_mut().insert(None);
+        self.record_insertion(new_key);
+        match f(new_key) {
+            Ok(item) => {
+                if let Some(slot) = self.nodes.borrow_mut().get_mut(new_key) {
+                    *slot = Some(Stable::new(item));
+                }
+                Ok(new_key)
+            }
+            Err(err) => {
+                Shared::make_mut(&mut self.relations.borrow_mut()).order.remove(new_key);
+                self.nodes.borrow_mut().remove(new_key);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reserve a key for a new root without providing its value yet, so that values which refer
+    /// to each other by key can be constructed. The node can be attached to others as usual, but
+    /// accessing its value fails with [`Error::Unfilled`] until it is given one with
+    /// [`fill`](Self::fill).
+    pub fn reserve_key(&self) -> TreeKey {
+        let new_key = self.nodes.borrow_mut().insert(None);
+        self.record_insertion(new_key);
+        Shared::make_mut(&mut self.roots.borrow_mut()).push(new_key);
         new_key
     }
 
+    /// Provide the value for a key previously returned by [`reserve_key`](Self::reserve_key).
+    ///
+    /// Fails with [`Error::Missing`] if the node doesn't exist, or [`Error::InvalidKey`] if it
+    /// already has a value.
+    pub fn fill(&self, key: TreeKey, item: T) -> Result<()> {
+        let mut nodes = self.nodes.borrow_mut();
+        let slot = nodes.get_mut(key).ok_or(Error::Missing)?;
+        if slot.is_some() {
+            return Err(Error::InvalidKey);
+        }
+        *slot = Some(Stable::new(item));
+        Ok(())
+    }
+
+    /// Replace the value of a node, returning the old value
+    pub fn replace(&self, key: TreeKey, item: T) -> Result<T> {
+        self.update(key, |val| mem::replace(val, item))
+    }
+
+    /// Swap the values of two nodes, leaving their places in the tree unchanged. Swapping a node
+    /// with itself does nothing, as long as it can be borrowed.
+    pub fn swap(&self, a: TreeKey, b: TreeKey) -> Result<()> {
+        if a == b {
+            return self.try_get_mut(a).map(drop);
+        }
+        self.with_nodes([a, b], |[mut a, mut b]| mem::swap(&mut *a, &mut *b))
+    }
+
     /// Create a new child of a node from the provided value
     pub fn add_child(&self, item: T, parent: TreeKey) -> Result<TreeKey> {
-        let cell = Stable::new(item);
+        self.add_child_with(parent, |_| item)
+    }
 
-        let new_key = self.nodes.borrow_mut().insert(cell);
+    /// Create a new child of a node, initialized with the value returned by `f` given the new
+    /// key. Fails with [`Error::Missing`] without calling `f` if the parent doesn't exist.
+    ///
+    /// The key is reserved while `f` runs, so the tree may be accessed from it. Fails with
+    /// [`Error::Missing`] if `f` removes the parent or the new node.
+    pub fn add_child_with(&self, parent: TreeKey, f: impl FnOnce(TreeKey) -> T) -> Result<TreeKey> {
+        self.try_add_child_with(parent, |key| Ok(f(key)))
+    }
 
-        let mut relations = self.relations.borrow_mut();
+    /// Create a new child of a node, initialized with the value returned by `f` given the new
+    /// key. If `f` fails, nothing is inserted and its error is returned. Fails with
+    /// [`Error::Missing`] without calling `f` if the parent doesn't exist.
+    ///
+    /// The key is reserved while `f` runs, so the tree may be accessed from it. Fails with
+    /// [`Error::Missing`] if `f` removes the parent or the new node.
+    pub fn try_add_child_with<E: From<Error>>(
+        &self,
+        parent: TreeKey,
+        f: impl FnOnce(TreeKey) -> core::result::Result<T, E>,
+    ) -> core::result::Result<TreeKey, E> {
+        if !self.nodes.borrow().contains_key(parent) {
+            return Err(Error::Missing.into());
+        }
 
-        relations
+        let new_key = self.insert_with(f)?;
+
+        let mut guard = self.relations.borrow_mut();
+        let relations = Shared::make_mut(&mut guard);
+        let mut nodes = self.nodes.borrow_mut();
+        if !nodes.contains_key(new_key) {
+            return Err(Error::Missing.into());
+        }
+        if !nodes.contains_key(parent) {
+            let removed = nodes.remove(new_key);
+            // Release the borrows first, in case dropping the value accesses this tree
+            drop(nodes);
+            drop(guard);
+            drop(removed);
+            return Err(Error::Missing.into());
+        }
+
+        let siblings = relations
             .children
             .entry(parent)
             .ok_or(Error::Missing)?
-            .or_default()
-            .push(new_key);
+            .or_default();
+
+        Shared::make_mut(siblings).push(new_key);
 
         relations
             .parents
@@ -283,8 +1460,9 @@ impl<T: ?Sized> Default for Tree<T> {
     fn default() -> Self {
         Tree {
             nodes: Cell::new(SlotMap::with_key()),
-            relations: Cell::new(Relations::new()),
-            roots: Cell::new(Vec::new()),
+            relations: Cell::new(Shared::new(Relations::new())),
+            roots: Cell::new(KeyList::default()),
+            deferred: Cell::new(Vec::new()),
         }
     }
 }
@@ -301,22 +1479,55 @@ fn recurse_tree<T: ?Sized + fmt::Debug>(
                 recurse_tree(f, indent + 4, child)?;
             }
         }
+        Err(Error::Unfilled) => writeln!(f, "{}Node {{ (Unfilled) }}", " ".repeat(indent))?,
         Err(_) => writeln!(f, "{}Node {{ (Borrowed) }}", " ".repeat(indent))?,
     }
     Ok(())
 }
 
+/// Check whether a value has outstanding borrows, which would keep it alive if its cell was dropped
+fn is_borrowed<T: ?Sized>(val: &Stable<T>) -> bool {
+    matches!(val.try_borrow_mut(), Err(BorrowError::AlreadyMut | BorrowError::AlreadyShared))
+}
+
 fn recurse_remove<T: ?Sized>(
     node: TreeKey,
-    nodes: &mut SlotMap<TreeKey, Stable<T>>,
-    parents: &mut SecondaryMap<TreeKey, TreeKey>,
-    children: &mut SecondaryMap<TreeKey, Vec<TreeKey>>,
+    nodes: &mut SlotMap<TreeKey, Option<Stable<T>>>,
+    relations: &mut Relations,
+    removed: &mut Vec<Option<Stable<T>>>,
 ) {
-    nodes.remove(node);
-    parents.remove(node);
-    if let Some(node_children) = children.remove(node) {
-        for child in node_children {
-            recurse_remove(child, nodes, parents, children)
+    removed.extend(nodes.remove(node));
+    relations.parents.remove(node);
+    relations.order.remove(node);
+    if let Some(node_children) = relations.children.remove(node) {
+        for &child in node_children.iter() {
+            recurse_remove(child, nodes, relations, removed)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+
+    #[test]
+    fn tree_validate() {
+        let tree = Tree::new();
+        let root = tree.add_root(0);
+        let child = tree.add_child(1, root).unwrap();
+        tree.reserve_key();
+        assert!(tree.validate().is_ok());
+
+        Shared::make_mut(&mut tree.relations.borrow_mut()).parents.remove(child);
+        Shared::make_mut(&mut tree.roots.borrow_mut()).push(child);
+        assert_eq!(tree.validate(), Err(vec![
+            InvariantViolation::ParentMismatch { parent: root, child },
+            InvariantViolation::Duplicated(child),
+        ]));
+
+        Shared::make_mut(&mut tree.relations.borrow_mut()).children.remove(root);
+        tree.nodes.borrow_mut().remove(root);
+        assert_eq!(tree.validate(), Err(vec![InvariantViolation::Dangling(root)]));
+    }
+}
@@ -0,0 +1,478 @@
+//! An ordered map stored as a balanced binary search tree, augmented with subtree sizes to find
+//! entries by their position in order
+
+use core::cmp::Ordering;
+use core::fmt;
+use alloc::vec::Vec;
+use slotmap::{new_key_type, SlotMap};
+
+new_key_type! {
+    /// A handle to an entry in an [`OrderStatTree`], which stays valid while the entry is in the
+    /// tree
+    pub struct OrderKey;
+}
+
+struct OstNode<K, V> {
+    key: K,
+    val: V,
+    /// Random priority, which is never lower than the priority of either child
+    priority: u64,
+    /// Number of entries in the subtree rooted at this node
+    size: usize,
+    parent: Option<OrderKey>,
+    left: Option<OrderKey>,
+    right: Option<OrderKey>,
+}
+
+/// A map ordered by key, stored as a treap, a binary search tree kept balanced by random
+/// priorities. Each node knows the size of its subtree, so entries can be found by their position
+/// in order with [`select`](OrderStatTree::select), and positions found with
+/// [`rank`](OrderStatTree::rank). Inserts, removals, lookups, selection and ranking take
+/// `O(log n)` time on average.
+///
+/// Entries can be reached through [`OrderKey`] handles, which stay valid as other entries are
+/// inserted or removed.
+pub struct OrderStatTree<K, V> {
+    nodes: SlotMap<OrderKey, OstNode<K, V>>,
+    root: Option<OrderKey>,
+    /// State for picking the priorities of new nodes
+    rng: u64,
+}
+
+impl<K: Ord, V> OrderStatTree<K, V> {
+    /// Create a new, empty tree
+    pub fn new() -> OrderStatTree<K, V> {
+        OrderStatTree {
+            nodes: SlotMap::with_key(),
+            root: None,
+            rng: 0x2545_F491_4F6C_DD1D,
+        }
+    }
+
+    /// Get the number of entries in this tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this tree has no entries
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn random_priority(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn size_of(&self, node: Option<OrderKey>) -> usize {
+        node.and_then(|node| self.nodes.get(node)).map_or(0, |node| node.size)
+    }
+
+    fn update_size(&mut self, key: OrderKey) {
+        let Some(node) = self.nodes.get(key) else {
+            return;
+        };
+        let size = 1 + self.size_of(node.left) + self.size_of(node.right);
+        if let Some(node) = self.nodes.get_mut(key) {
+            node.size = size;
+        }
+    }
+
+    /// Point whatever links to `old`, its parent or the root, at `new` instead
+    fn replace_link(&mut self, parent: Option<OrderKey>, old: OrderKey, new: Option<OrderKey>) {
+        match parent.and_then(|parent| self.nodes.get_mut(parent)) {
+            Some(parent) if parent.left == Some(old) => parent.left = new,
+            Some(parent) => parent.right = new,
+            None => self.root = new,
+        }
+        if let Some(new) = new.and_then(|new| self.nodes.get_mut(new)) {
+            new.parent = parent;
+        }
+    }
+
+    /// Rotate a node above its parent, keeping the order of entries
+    fn rotate_up(&mut self, key: OrderKey) {
+        let Some(parent) = self.nodes.get(key).and_then(|node| node.parent) else {
+            return;
+        };
+        let Some(grandparent) = self.nodes.get(parent).map(|node| node.parent) else {
+            return;
+        };
+        let is_left = self.nodes[parent].left == Some(key);
+
+        // The inner child of the node moves across to the parent
+        let inner = if is_left { self.nodes[key].right } else { self.nodes[key].left };
+        if is_left {
+            self.nodes[parent].left = inner;
+            self.nodes[key].right = Some(parent);
+        } else {
+            self.nodes[parent].right = inner;
+            self.nodes[key].left = Some(parent);
+        }
+        if let Some(inner) = inner {
+            self.nodes[inner].parent = Some(parent);
+        }
+        self.nodes[parent].parent = Some(key);
+        self.replace_link(grandparent, parent, Some(key));
+
+        self.update_size(parent);
+        self.update_size(key);
+    }
+
+    /// Get the handle of the entry with a key
+    pub fn find(&self, key: &K) -> Option<OrderKey> {
+        let mut cur = self.root;
+        while let Some(handle) = cur {
+            let node = self.nodes.get(handle)?;
+            cur = match key.cmp(&node.key) {
+                Ordering::Less => node.left,
+                Ordering::Greater => node.right,
+                Ordering::Equal => return Some(handle),
+            };
+        }
+        None
+    }
+
+    /// Check whether this tree has an entry with a key
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Get the value for a key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.nodes.get(self.find(key)?).map(|node| &node.val)
+    }
+
+    /// Get the value for a key mutably
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let handle = self.find(key)?;
+        self.nodes.get_mut(handle).map(|node| &mut node.val)
+    }
+
+    /// Insert a value for a key, returning the handle of its entry. If the key was already
+    /// present, its entry keeps the same handle, and the old value is returned.
+    pub fn insert(&mut self, key: K, val: V) -> (OrderKey, Option<V>) {
+        let mut parent = None;
+        let mut go_left = false;
+        let mut cur = self.root;
+        while let Some(handle) = cur {
+            let node = &mut self.nodes[handle];
+            go_left = match key.cmp(&node.key) {
+                Ordering::Less => true,
+                Ordering::Greater => false,
+                Ordering::Equal => return (handle, Some(core::mem::replace(&mut node.val, val))),
+            };
+            cur = if go_left { node.left } else { node.right };
+            parent = Some(handle);
+        }
+
+        let priority = self.random_priority();
+        let handle = self.nodes.insert(OstNode { key, val, priority, size: 1, parent, left: None, right: None });
+        match parent {
+            Some(parent) if go_left => self.nodes[parent].left = Some(handle),
+            Some(parent) => self.nodes[parent].right = Some(handle),
+            None => self.root = Some(handle),
+        }
+
+        let mut ancestor = parent;
+        while let Some(key) = ancestor {
+            let node = &mut self.nodes[key];
+            node.size += 1;
+            ancestor = node.parent;
+        }
+        while let Some(parent) = self.nodes[handle].parent {
+            if self.nodes[parent].priority >= priority {
+                break;
+            }
+            self.rotate_up(handle);
+        }
+        (handle, None)
+    }
+
+    /// Remove the entry for a key, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let handle = self.find(key)?;
+        self.remove_at(handle).map(|(_, val)| val)
+    }
+
+    /// Remove the entry for a handle, returning its key and value
+    pub fn remove_at(&mut self, handle: OrderKey) -> Option<(K, V)> {
+        // Rotate the node down until it has at most one child, then splice it out
+        loop {
+            let node = self.nodes.get(handle)?;
+            let (Some(left), Some(right)) = (node.left, node.right) else {
+                break;
+            };
+            if self.nodes[left].priority > self.nodes[right].priority {
+                self.rotate_up(left);
+            } else {
+                self.rotate_up(right);
+            }
+        }
+
+        let node = self.nodes.remove(handle)?;
+        self.replace_link(node.parent, handle, node.left.or(node.right));
+        let mut ancestor = node.parent;
+        while let Some(key) = ancestor {
+            let node = &mut self.nodes[key];
+            node.size -= 1;
+            ancestor = node.parent;
+        }
+        Some((node.key, node.val))
+    }
+
+    /// Get the key and value of the entry for a handle
+    pub fn entry(&self, handle: OrderKey) -> Option<(&K, &V)> {
+        self.nodes.get(handle).map(|node| (&node.key, &node.val))
+    }
+
+    /// Get the value of the entry for a handle mutably
+    pub fn value_mut(&mut self, handle: OrderKey) -> Option<&mut V> {
+        self.nodes.get_mut(handle).map(|node| &mut node.val)
+    }
+
+    /// Get the handle of the entry at a position in order, so `select(0)` is the entry with the
+    /// smallest key
+    pub fn select(&self, mut idx: usize) -> Option<OrderKey> {
+        let mut cur = self.root;
+        while let Some(handle) = cur {
+            let node = self.nodes.get(handle)?;
+            let left = self.size_of(node.left);
+            cur = match idx.cmp(&left) {
+                Ordering::Less => node.left,
+                Ordering::Equal => return Some(handle),
+                Ordering::Greater => {
+                    idx -= left + 1;
+                    node.right
+                }
+            };
+        }
+        None
+    }
+
+    /// Get the number of entries with keys smaller than `key`, which is the position `key` has
+    /// or would have in order
+    pub fn rank(&self, key: &K) -> usize {
+        let mut rank = 0;
+        let mut cur = self.root;
+        while let Some(node) = cur.and_then(|handle| self.nodes.get(handle)) {
+            if *key <= node.key {
+                cur = node.left;
+            } else {
+                rank += self.size_of(node.left) + 1;
+                cur = node.right;
+            }
+        }
+        rank
+    }
+
+    /// Get the position of the entry for a handle in order
+    pub fn rank_of(&self, handle: OrderKey) -> Option<usize> {
+        let node = self.nodes.get(handle)?;
+        let mut rank = self.size_of(node.left);
+        let mut cur = handle;
+        let mut parent = node.parent;
+        while let Some(key) = parent {
+            let node = self.nodes.get(key)?;
+            if node.right == Some(cur) {
+                rank += self.size_of(node.left) + 1;
+            }
+            cur = key;
+            parent = node.parent;
+        }
+        Some(rank)
+    }
+
+    fn leftmost(&self, mut cur: OrderKey) -> OrderKey {
+        while let Some(left) = self.nodes.get(cur).and_then(|node| node.left) {
+            cur = left;
+        }
+        cur
+    }
+
+    fn rightmost(&self, mut cur: OrderKey) -> OrderKey {
+        while let Some(right) = self.nodes.get(cur).and_then(|node| node.right) {
+            cur = right;
+        }
+        cur
+    }
+
+    /// Get the handle of the entry with the smallest key
+    pub fn first(&self) -> Option<OrderKey> {
+        self.root.map(|root| self.leftmost(root))
+    }
+
+    /// Get the handle of the entry with the largest key
+    pub fn last(&self) -> Option<OrderKey> {
+        self.root.map(|root| self.rightmost(root))
+    }
+
+    /// Get the handle of the entry after another
+    pub fn next(&self, handle: OrderKey) -> Option<OrderKey> {
+        let node = self.nodes.get(handle)?;
+        if let Some(right) = node.right {
+            return Some(self.leftmost(right));
+        }
+        let mut cur = handle;
+        let mut parent = node.parent;
+        while let Some(key) = parent {
+            let node = self.nodes.get(key)?;
+            if node.left == Some(cur) {
+                return Some(key);
+            }
+            cur = key;
+            parent = node.parent;
+        }
+        None
+    }
+
+    /// Get the handle of the entry before another
+    pub fn prev(&self, handle: OrderKey) -> Option<OrderKey> {
+        let node = self.nodes.get(handle)?;
+        if let Some(left) = node.left {
+            return Some(self.rightmost(left));
+        }
+        let mut cur = handle;
+        let mut parent = node.parent;
+        while let Some(key) = parent {
+            let node = self.nodes.get(key)?;
+            if node.right == Some(cur) {
+                return Some(key);
+            }
+            cur = key;
+            parent = node.parent;
+        }
+        None
+    }
+
+    /// Iterate over every entry, in order of their keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let mut cur = self.first();
+        core::iter::from_fn(move || {
+            let handle = cur?;
+            cur = self.next(handle);
+            self.entry(handle)
+        })
+    }
+
+    /// Get the height of the tree, where an empty tree has height zero
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = Vec::from_iter(self.root.map(|root| (root, 1)));
+        while let Some((key, level)) = stack.pop() {
+            depth = depth.max(level);
+            if let Some(node) = self.nodes.get(key) {
+                stack.extend(node.left.iter().chain(&node.right).map(|&child| (child, level + 1)));
+            }
+        }
+        depth
+    }
+
+    /// Remove every entry from this tree
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.root = None;
+    }
+}
+
+impl<K: Ord, V> Default for OrderStatTree<K, V> {
+    fn default() -> Self {
+        OrderStatTree::new()
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for OrderStatTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for OrderStatTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = OrderStatTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug> fmt::Debug for OrderStatTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    #[test]
+    fn test_against_btree() {
+        let mut tree = OrderStatTree::new();
+        let mut expected = BTreeMap::new();
+        let mut state = 1_u32;
+        for _ in 0..2000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (state >> 16) % 300;
+            if state.is_multiple_of(3) {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key, state).1, expected.insert(key, state));
+            }
+        }
+
+        assert_eq!(tree.len(), expected.len());
+        assert!(tree.iter().eq(expected.iter()));
+        for (idx, (key, _)) in expected.iter().enumerate() {
+            let handle = tree.select(idx).unwrap();
+            assert_eq!(tree.entry(handle).map(|(key, _)| key), Some(key));
+            assert_eq!(tree.rank_of(handle), Some(idx));
+            assert_eq!(tree.rank(key), idx);
+        }
+        assert_eq!(tree.select(expected.len()), None);
+        assert_eq!(tree.rank(&1000), expected.len());
+        assert!(tree.depth() < 40);
+
+        let mut back = Vec::new();
+        let mut cur = tree.last();
+        while let Some(handle) = cur {
+            back.push(tree.entry(handle).map(|(&key, _)| key));
+            cur = tree.prev(handle);
+        }
+        back.reverse();
+        assert!(back.into_iter().eq(expected.keys().map(|&key| Some(key))));
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut tree = (0..100).map(|key| (key * 2, key)).collect::<OrderStatTree<_, _>>();
+        let handle = tree.find(&40).unwrap();
+        assert_eq!(tree.rank(&41), 21);
+        assert_eq!(tree.find(&41), None);
+
+        tree.insert(41, 0);
+        tree.remove(&38);
+        assert_eq!(tree.insert(40, 100), (handle, Some(20)));
+        assert_eq!(tree.entry(handle), Some((&40, &100)));
+        assert_eq!(tree.rank_of(handle), Some(19));
+        assert_eq!(tree.next(handle).and_then(|h| tree.entry(h)), Some((&41, &0)));
+        assert_eq!(tree.prev(handle).and_then(|h| tree.entry(h)), Some((&36, &18)));
+
+        *tree.value_mut(handle).unwrap() = 7;
+        assert_eq!(tree.remove_at(handle), Some((40, 7)));
+        assert_eq!(tree.entry(handle), None);
+        assert_eq!(tree.rank_of(handle), None);
+        assert_eq!(tree.get(&40), None);
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.select(19).and_then(|h| tree.entry(h)), Some((&41, &0)));
+
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.first(), None);
+        assert_eq!(tree.depth(), 0);
+    }
+}
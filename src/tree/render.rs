@@ -0,0 +1,72 @@
+//! Shared text rendering for the tree types
+
+use core::fmt;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The glyphs used to draw the branches of a rendered tree
+pub(crate) struct Style {
+    branch: &'static str,
+    last: &'static str,
+    pipe: &'static str,
+    blank: &'static str,
+}
+
+pub(crate) const ASCII: Style = Style {
+    branch: "|-- ",
+    last: "`-- ",
+    pipe: "|   ",
+    blank: "    ",
+};
+
+pub(crate) const UNICODE: Style = Style {
+    branch: "├── ",
+    last: "└── ",
+    pipe: "│   ",
+    blank: "    ",
+};
+
+/// Render the trees under `roots` one node per line, with each root starting a new unindented
+/// tree. `label` writes the text for a single node.
+pub(crate) fn render<K: Copy>(
+    style: &Style,
+    roots: &[K],
+    mut children: impl FnMut(K) -> Vec<K>,
+    mut label: impl FnMut(&mut String, K) -> fmt::Result,
+) -> String {
+    let mut out = String::new();
+    // (key, prefix of the parent, whether this is the last child, or None for roots)
+    let mut stack: Vec<(K, String, Option<bool>)> = roots.iter()
+        .rev()
+        .map(|&key| (key, String::new(), None))
+        .collect();
+
+    while let Some((key, prefix, last)) = stack.pop() {
+        out.push_str(&prefix);
+        let child_prefix = match last {
+            Some(true) => {
+                out.push_str(style.last);
+                prefix + style.blank
+            }
+            Some(false) => {
+                out.push_str(style.branch);
+                prefix + style.pipe
+            }
+            None => prefix,
+        };
+        // Writing to a string can only fail if the user's formatter does
+        let _ = label(&mut out, key);
+        out.push('\n');
+
+        let children = children(key);
+        let len = children.len();
+        stack.extend(
+            children.into_iter()
+                .enumerate()
+                .rev()
+                .map(|(idx, child)| (child, child_prefix.clone(), Some(idx + 1 == len)))
+        );
+    }
+
+    out
+}
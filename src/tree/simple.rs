@@ -1,15 +1,61 @@
 //! A simple one-to-many tree. Every node can have child data.
+//!
+//! Children are kept in the order they were attached to their parent. New children are appended,
+//! and detaching a child preserves the order of the remaining children.
 
 mod tree;
 mod node;
+mod edits;
+mod handle;
 mod node_ref;
+mod iter;
+mod key_map;
+mod zipper;
+mod frozen;
+mod frozen_bytes;
+mod cache;
+#[cfg(feature = "std")]
+mod fs;
+mod lcrs;
+mod newick;
+mod sexpr;
+mod succinct;
+mod dag;
+mod subtree;
+mod secondary;
+mod macros;
+#[cfg(feature = "interop")]
+mod interop;
+#[cfg(feature = "interop")]
+mod graphml;
+#[cfg(feature = "interop")]
+mod json;
 
+pub use cache::TreeCache;
+pub use dag::Dag;
+pub use edits::Edits;
+pub use handle::NodeHandle;
+pub use frozen::{FrozenTree, FrozenChildren};
+pub use frozen_bytes::{FormatError, FrozenTreeView};
+#[cfg(feature = "std")]
+pub use fs::{DirEntryInfo, EntryKind, ScanOptions, SymlinkPolicy};
+pub use iter::{Descendants, PostOrderKeys};
+pub use key_map::{KeyMap, KeyRemapListener, RemapListenerId};
+pub use lcrs::{LcrsKey, LcrsTree};
+pub use newick::NewickError;
 pub use node::Node;
+pub use sexpr::SexprError;
+pub use succinct::FrozenSuccinctTree;
+pub use secondary::{SecondaryId, TreeSecondaryMap};
+pub use subtree::SubtreeMut;
 pub use node_ref::{NodeRef, NodeMut, NodeMutLimited};
 pub use tree::{Tree, TreeKey};
+pub use zipper::Zipper;
+pub use crate::tree::Error;
 
 #[cfg(test)]
 mod tests {
+    use alloc::vec::Vec;
     use super::*;
 
     #[test]
@@ -57,4 +103,151 @@ mod tests {
 
         assert_eq!(*r1, 2);
     }
+
+    #[test]
+    fn test_traverse_children_mut() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        tree.add_child(3, a).unwrap();
+        let before = tree.subtree_hash(root);
+
+        let mut r = tree.get_mut(root).unwrap();
+        for mut child in r.traverse_children_mut() {
+            *child *= 10;
+        }
+        assert_eq!(*r, 0);
+        *r.traverse_child_mut(b).unwrap() += 1;
+
+        let values = tree.get(root).unwrap()
+            .traverse_children()
+            .map(|child| *child)
+            .collect::<Vec<_>>();
+        assert_eq!(values, [10, 21]);
+        assert_eq!(*tree.get(a).unwrap().traverse_children().next().unwrap(), 3);
+        assert_ne!(tree.subtree_hash(root), before);
+    }
+
+    #[test]
+    fn test_zipper() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+
+        let z = tree.zipper(root).unwrap();
+        assert_eq!(*z.value(), 0);
+        assert!(z.up().is_none());
+        assert!(z.left().is_none());
+
+        let za = z.down(0).unwrap();
+        assert_eq!(za.key(), a);
+        assert!(za.left().is_none());
+        let zb = za.right().unwrap();
+        assert_eq!(zb.key(), b);
+        assert!(zb.right().is_none());
+        assert_eq!(zb.left().unwrap().key(), a);
+        assert_eq!(za.down(0).unwrap().root().key(), root);
+
+        let edited = za.down(0).unwrap().set(30);
+        assert_eq!(*edited.value(), 30);
+        let edited = edited.up().unwrap().map(|val| val + 10);
+        assert_eq!(*edited.value(), 11);
+        assert_eq!(*za.value(), 1);
+
+        let edited = edited.down(0).unwrap().map(|val| val + 1);
+        let mut edits = edited.into_edits();
+        edits.sort_by_key(|&(_, val)| val);
+        assert_eq!(edits, [(a, 11), (c, 31)]);
+        assert_eq!(*tree.get(c).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_zipper_edges() {
+        let mut tree = Tree::<i32, &str>::default();
+        let root = tree.add_root(0);
+        let a = tree.add_child_with_edge(1, root, "left").unwrap();
+        tree.add_child_with_edge(2, root, "right").unwrap();
+
+        let z = tree.zipper(root).unwrap().down(1).unwrap().left().unwrap();
+        assert_eq!(z.key(), a);
+        let z = z.set(10);
+        assert_eq!(alloc::format!("{:?}", z), alloc::format!("Zipper {{ focus: {:?}, value: 10 }}", a));
+        assert_eq!(z.into_edits(), [(a, 10)]);
+        assert_eq!(tree.edge(root, a), Some(&"left"));
+    }
+
+    #[test]
+    fn test_descendants() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, a).unwrap();
+        let b = tree.add_child(4, root).unwrap();
+        tree.add_child(5, b).unwrap();
+
+        let root = tree.get(root).unwrap();
+        let vals = root.descendants()
+            .map(|node| *node)
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 2, 3, 4, 5]);
+
+        let vals = root.descendants()
+            .take_depth(1)
+            .map(|node| *node)
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 4]);
+
+        let vals = root.descendants()
+            .take_depth(2)
+            .take_breadth(1)
+            .map(|node| *node)
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [1, 2]);
+    }
+
+    #[test]
+    fn test_forwarded_traits() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(3, root).unwrap();
+        let b = tree.add_child(1, root).unwrap();
+
+        let root = tree.get(root).unwrap();
+        let mut children = root.traverse_children().collect::<Vec<_>>();
+        children.sort();
+        assert!(children == [tree.get(b).unwrap(), tree.get(a).unwrap()]);
+        assert_eq!(alloc::format!("{}", children[1]), "3");
+    }
+
+    #[test]
+    fn test_tree_macro() {
+        let (tree, keys) = crate::tree! {
+            root @ "root" => [
+                "a" => ["x", y @ "y"],
+                b @ "b",
+            ],
+            "other",
+        };
+        assert_eq!(tree.len(), 6);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(*tree.get(keys["y"]).unwrap(), "y");
+        assert_eq!(tree.parent_key_of(keys["b"]), Some(keys["root"]));
+        assert_eq!(tree.render_ascii(|out, val| out.write_str(val)), "root\n|-- a\n|   |-- x\n|   `-- y\n`-- b\nother\n");
+
+        let (tree, keys) = crate::tree! { 1 => [2 + 1] };
+        assert!(keys.is_empty());
+        assert_eq!(tree.iter_post_order().map(|node| *node).collect::<Vec<_>>(), [3, 1]);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let tree = [(0, None), (1, Some(0)), (2, Some(1)), (3, Some(0)), (4, None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+        assert_eq!(tree.render_ascii(|out, val| write!(out, "{}", val)), "0\n|-- 1\n|   `-- 2\n`-- 3\n4\n");
+    }
 }
@@ -0,0 +1,344 @@
+//! A least-recently-used cache keyed by hierarchical paths, stored in a [`Tree`]
+
+use core::borrow::Borrow;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// A node of the cache tree, for one path segment
+struct Slot<S, V> {
+    /// The last segment of the node's path, or `None` for the root's empty path
+    segment: Option<S>,
+    /// The node's value, with the tick it was last used at
+    entry: Option<(V, u64)>,
+}
+
+/// A least-recently-used cache whose keys are paths of segments, such as the parts of a URL.
+/// Entries are stored in a tree following their paths, so invalidating a path evicts the entries
+/// of every path it's a prefix of.
+///
+/// Once the cache holds more entries than its capacity, the least recently inserted or fetched
+/// entries are evicted. Nodes which no longer lead to an entry are removed along with them.
+pub struct TreeCache<S, V> {
+    tree: Tree<Slot<S, V>>,
+    root: TreeKey,
+    /// Nodes holding entries, by the tick they were last used at
+    recency: BTreeMap<u64, TreeKey>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl<S, V> TreeCache<S, V> {
+    /// Create an empty cache holding at most `capacity` entries
+    pub fn new(capacity: usize) -> TreeCache<S, V> {
+        let mut tree = Tree::new();
+        let root = tree.add_root(Slot { segment: None, entry: None });
+        TreeCache { tree, root, recency: BTreeMap::new(), capacity, tick: 0 }
+    }
+
+    /// Get the number of entries in the cache
+    pub fn len(&self) -> usize {
+        self.recency.len()
+    }
+
+    /// Check whether the cache has no entries
+    pub fn is_empty(&self) -> bool {
+        self.recency.is_empty()
+    }
+
+    /// Get the maximum number of entries the cache holds
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the maximum number of entries the cache holds, evicting the least recently used
+    /// entries if it holds more than that
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_over_capacity();
+    }
+
+    /// Remove every entry from the cache
+    pub fn clear(&mut self) {
+        self.tree.clear();
+        self.root = self.tree.add_root(Slot { segment: None, entry: None });
+        self.recency.clear();
+    }
+
+    fn slot(&self, key: TreeKey) -> Option<&Slot<S, V>> {
+        self.tree.raw_nodes().get(key).map(|node| node.val())
+    }
+
+    fn slot_mut(&mut self, key: TreeKey) -> Option<&mut Slot<S, V>> {
+        self.tree.raw_nodes_mut().get_mut(key).map(|node| node.val_mut())
+    }
+
+    fn child<Q>(&self, parent: TreeKey, segment: &Q) -> Option<TreeKey>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq,
+    {
+        self.tree.child_keys(parent)?
+            .iter()
+            .copied()
+            .find(|&child| {
+                self.slot(child)
+                    .and_then(|slot| slot.segment.as_ref())
+                    .is_some_and(|own| own.borrow() == segment)
+            })
+    }
+
+    fn find<'q, Q>(&self, path: impl IntoIterator<Item = &'q Q>) -> Option<TreeKey>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        path.into_iter().try_fold(self.root, |key, segment| self.child(key, segment))
+    }
+
+    /// Move an entry to the back of the eviction order
+    fn touch(&mut self, key: TreeKey) {
+        self.tick += 1;
+        let tick = self.tick;
+        let Some((_, used)) = self.slot_mut(key).and_then(|slot| slot.entry.as_mut()) else {
+            return;
+        };
+        let old = core::mem::replace(used, tick);
+        self.recency.remove(&old);
+        self.recency.insert(tick, key);
+    }
+
+    /// Remove a node and its ancestors for as long as they lead to no entries
+    fn prune(&mut self, mut key: TreeKey) {
+        while key != self.root {
+            let Some(node) = self.tree.raw_nodes().get(key) else {
+                return;
+            };
+            if node.val().entry.is_some() || !node.children().is_empty() {
+                return;
+            }
+            let parent = node.parent();
+            let _ = self.tree.remove_recursive(key);
+            match parent {
+                Some(parent) => key = parent,
+                None => return,
+            }
+        }
+    }
+
+    fn evict_over_capacity(&mut self) {
+        while self.recency.len() > self.capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                return;
+            };
+            if let Some(slot) = self.slot_mut(key) {
+                slot.entry = None;
+            }
+            self.prune(key);
+        }
+    }
+
+    /// Insert an entry at a path, returning the entry previously there. The empty path is a
+    /// valid key, and a prefix of every other path.
+    pub fn insert<'q, Q>(&mut self, path: impl IntoIterator<Item = &'q Q>, val: V) -> Option<V>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + ToOwned<Owned = S> + 'q,
+    {
+        let mut key = self.root;
+        for segment in path {
+            key = match self.child(key, segment) {
+                Some(child) => child,
+                None => {
+                    let slot = Slot { segment: Some(segment.to_owned()), entry: None };
+                    let Some(child) = self.tree.add_child(slot, key) else {
+                        return Some(val);
+                    };
+                    child
+                }
+            };
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        let old = self.slot_mut(key)?.entry.replace((val, tick));
+        self.recency.insert(tick, key);
+        let old = old.map(|(old, used)| {
+            self.recency.remove(&used);
+            old
+        });
+        self.evict_over_capacity();
+        old
+    }
+
+    /// Get the entry at a path, marking it as the most recently used
+    pub fn get<'q, Q>(&mut self, path: impl IntoIterator<Item = &'q Q>) -> Option<&V>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        let key = self.find(path)?;
+        self.touch(key);
+        self.slot(key)?.entry.as_ref().map(|(val, _)| val)
+    }
+
+    /// Get mutable access to the entry at a path, marking it as the most recently used
+    pub fn get_mut<'q, Q>(&mut self, path: impl IntoIterator<Item = &'q Q>) -> Option<&mut V>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        let key = self.find(path)?;
+        self.touch(key);
+        self.slot_mut(key)?.entry.as_mut().map(|(val, _)| val)
+    }
+
+    /// Get the entry at a path, without changing when it will be evicted
+    pub fn peek<'q, Q>(&self, path: impl IntoIterator<Item = &'q Q>) -> Option<&V>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        self.slot(self.find(path)?)?.entry.as_ref().map(|(val, _)| val)
+    }
+
+    /// Check whether there is an entry at a path
+    pub fn contains<'q, Q>(&self, path: impl IntoIterator<Item = &'q Q>) -> bool
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        self.peek(path).is_some()
+    }
+
+    /// Remove the entry at a path, leaving entries below it in place
+    pub fn remove<'q, Q>(&mut self, path: impl IntoIterator<Item = &'q Q>) -> Option<V>
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        let key = self.find(path)?;
+        let (val, used) = self.slot_mut(key)?.entry.take()?;
+        self.recency.remove(&used);
+        self.prune(key);
+        Some(val)
+    }
+
+    /// Evict the entry at a path along with the entries at every path below it, returning how
+    /// many entries were evicted
+    pub fn invalidate<'q, Q>(&mut self, path: impl IntoIterator<Item = &'q Q>) -> usize
+    where
+        S: Borrow<Q>,
+        Q: ?Sized + PartialEq + 'q,
+    {
+        let Some(key) = self.find(path) else {
+            return 0;
+        };
+
+        let mut evicted = 0;
+        let mut stack = alloc::vec![key];
+        while let Some(next) = stack.pop() {
+            let Some(node) = self.tree.raw_nodes().get(next) else {
+                continue;
+            };
+            if let Some((_, used)) = node.val().entry {
+                self.recency.remove(&used);
+                evicted += 1;
+            }
+            stack.extend(node.children());
+        }
+
+        if key == self.root {
+            let children = self.tree.child_keys(key).map(<[_]>::to_vec).unwrap_or_default();
+            for child in children {
+                let _ = self.tree.remove_recursive(child);
+            }
+            if let Some(slot) = self.slot_mut(key) {
+                slot.entry = None;
+            }
+        } else {
+            let parent = self.tree.parent_key_of(key);
+            let _ = self.tree.remove_recursive(key);
+            if let Some(parent) = parent {
+                self.prune(parent);
+            }
+        }
+        evicted
+    }
+
+    /// Iterate over the paths and values of every entry, from least to most recently used
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<&S>, &V)> + '_ {
+        self.recency.values().filter_map(|&key| {
+            let (val, _) = self.slot(key)?.entry.as_ref()?;
+            let mut path = core::iter::successors(Some(key), |&key| self.tree.parent_key_of(key))
+                .filter_map(|key| self.slot(key)?.segment.as_ref())
+                .collect::<Vec<_>>();
+            path.reverse();
+            Some((path, val))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use super::*;
+
+    #[test]
+    fn test_cache_lru() {
+        let mut cache = TreeCache::<String, i32>::new(2);
+        assert_eq!(cache.insert(["a", "b"], 1), None);
+        assert_eq!(cache.insert(["a", "c"], 2), None);
+        assert_eq!(cache.get(["a", "b"]), Some(&1));
+        assert_eq!(cache.insert(["d"], 3), None);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(["a", "c"]));
+        assert_eq!(cache.peek(["a", "b"]), Some(&1));
+
+        // Peeking doesn't count as a use
+        cache.insert(["e"], 4);
+        assert!(!cache.contains(["a", "b"]));
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [
+            (alloc::vec![&String::from("d")], &3),
+            (alloc::vec![&String::from("e")], &4),
+        ]);
+        // Evicted paths are pruned from the tree, leaving the root and two leaves
+        assert_eq!(cache.tree.len(), 3);
+
+        assert_eq!(cache.insert(["e"], 5), Some(4));
+        *cache.get_mut(["d"]).unwrap() += 10;
+        cache.set_capacity(1);
+        assert_eq!(cache.iter().map(|(_, val)| *val).collect::<Vec<_>>(), [13]);
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_cache_invalidate() {
+        let mut cache = TreeCache::<String, i32>::new(10);
+        cache.insert(["api", "users"], 1);
+        cache.insert(["api", "users", "1"], 2);
+        cache.insert(["api", "users", "2"], 3);
+        cache.insert(["api", "posts"], 4);
+        cache.insert::<str>([], 5);
+
+        assert_eq!(cache.invalidate(["api", "users"]), 3);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(["api", "users", "1"]));
+        assert_eq!(cache.peek(["api", "posts"]), Some(&4));
+        assert_eq!(cache.invalidate(["api", "missing"]), 0);
+
+        assert_eq!(cache.remove(["api", "posts"]), Some(4));
+        assert_eq!(cache.remove(["api", "posts"]), None);
+        assert_eq!(cache.tree.len(), 1);
+
+        cache.insert(["x"], 6);
+        assert_eq!(cache.invalidate::<str>([]), 2);
+        assert!(cache.is_empty());
+        cache.insert(["y"], 7);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.tree.len(), 1);
+    }
+}
@@ -0,0 +1,136 @@
+use core::hash::{Hash, Hasher};
+use core::ops::Index;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+use crate::util;
+
+/// A directed acyclic graph built from a [`Tree`] by sharing identical subtrees, with nodes
+/// addressed by index. Every node is stored after all of its children.
+///
+/// Created by [`Tree::intern_shared_subtrees`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dag<T> {
+    vals: Vec<T>,
+    children: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+}
+
+impl<T: Hash + Eq> Dag<T> {
+    pub(super) fn from_tree<E>(mut tree: Tree<T, E>) -> (Dag<T>, SecondaryMap<TreeKey, usize>) {
+        let order = tree.post_order_keys().collect::<Vec<_>>();
+        let roots = tree.raw_roots().to_vec();
+
+        let mut dag = Dag { vals: Vec::new(), children: Vec::new(), roots: Vec::new() };
+        let mut mapping = SecondaryMap::<TreeKey, usize>::new();
+        // Nodes by the hash of their value and children, which are already interned
+        let mut buckets = BTreeMap::<u64, Vec<usize>>::new();
+
+        for key in order {
+            let Some(node) = tree.raw_nodes_mut().remove(key) else {
+                continue;
+            };
+            let children = node.children()
+                .iter()
+                .filter_map(|&child| mapping.get(child).copied())
+                .collect::<Vec<_>>();
+            let val = node.into_val();
+
+            let mut hasher = util::Fnv::default();
+            val.hash(&mut hasher);
+            children.hash(&mut hasher);
+            let bucket = buckets.entry(hasher.finish()).or_default();
+
+            let existing = bucket.iter()
+                .copied()
+                .find(|&idx| dag.vals[idx] == val && dag.children[idx] == children);
+            let idx = match existing {
+                Some(idx) => idx,
+                None => {
+                    bucket.push(dag.vals.len());
+                    dag.vals.push(val);
+                    dag.children.push(children);
+                    dag.vals.len() - 1
+                }
+            };
+            mapping.insert(key, idx);
+        }
+
+        dag.roots = roots.into_iter()
+            .filter_map(|root| mapping.get(root).copied())
+            .collect();
+        (dag, mapping)
+    }
+}
+
+impl<T> Dag<T> {
+    /// Get the number of distinct nodes in this graph
+    pub fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    /// Check whether this graph has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.vals.is_empty()
+    }
+
+    /// Get the value of the node at an index
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.vals.get(idx)
+    }
+
+    /// Get the values of every node, with each node after its children
+    pub fn values(&self) -> &[T] {
+        &self.vals
+    }
+
+    /// Get the indices of the roots of the original tree, in order. Identical roots share an
+    /// index.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Get the indices of a node's children, in order
+    pub fn children(&self, idx: usize) -> Option<&[usize]> {
+        self.children.get(idx).map(Vec::as_slice)
+    }
+}
+
+impl<T> Index<usize> for Dag<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        &self.vals[idx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut tree = Tree::new();
+        let root = tree.add_root("add");
+        let lhs = tree.add_child("mul", root).unwrap();
+        let x1 = tree.add_child("x", lhs).unwrap();
+        tree.add_child("x", lhs).unwrap();
+        let rhs = tree.add_child("mul", root).unwrap();
+        tree.add_child("x", rhs).unwrap();
+        tree.add_child("x", rhs).unwrap();
+        let other = tree.add_root("mul");
+        tree.add_child("x", other).unwrap();
+
+        let (dag, mapping) = tree.intern_shared_subtrees();
+        assert_eq!(dag.len(), 4);
+        assert_eq!(dag.values(), ["x", "mul", "add", "mul"]);
+        assert_eq!(mapping[lhs], mapping[rhs]);
+        assert_ne!(mapping[lhs], mapping[other]);
+        assert_eq!(dag.roots(), [mapping[root], mapping[other]]);
+        assert_eq!(dag.children(mapping[root]).unwrap(), [mapping[lhs], mapping[lhs]]);
+        assert_eq!(dag.children(mapping[lhs]).unwrap(), [mapping[x1], mapping[x1]]);
+        assert_eq!(dag[mapping[other]], "mul");
+        assert!(dag.children(4).is_none());
+    }
+}
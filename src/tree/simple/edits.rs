@@ -0,0 +1,56 @@
+use alloc::vec::Vec;
+use crate::tree::OrphanPolicy;
+use crate::tree::simple::TreeKey;
+
+pub(super) enum Edit<T> {
+    Add(Option<TreeKey>, T),
+    Remove(TreeKey, OrphanPolicy),
+    Move(TreeKey, Option<TreeKey>),
+}
+
+/// A queue of structural changes made during [`Tree::visit_mut`](super::Tree::visit_mut), which
+/// are applied in order once the traversal completes
+pub struct Edits<T> {
+    edits: Vec<Edit<T>>,
+}
+
+impl<T> Edits<T> {
+    pub(super) fn new() -> Edits<T> {
+        Edits { edits: Vec::new() }
+    }
+
+    pub(super) fn into_inner(self) -> Vec<Edit<T>> {
+        self.edits
+    }
+
+    /// Queue adding a new root with the provided value
+    pub fn add_root(&mut self, val: T) {
+        self.edits.push(Edit::Add(None, val));
+    }
+
+    /// Queue adding a new child with the provided value to the end of a node's children
+    pub fn add_child(&mut self, parent: TreeKey, val: T) {
+        self.edits.push(Edit::Add(Some(parent), val));
+    }
+
+    /// Queue removing a node, with its children handled according to the provided policy
+    pub fn remove(&mut self, key: TreeKey, policy: OrphanPolicy) {
+        self.edits.push(Edit::Remove(key, policy));
+    }
+
+    /// Queue moving a node to the end of a new parent's children, or making it a root if the
+    /// parent is `None`. Moves which would form a cycle are skipped.
+    pub fn reparent(&mut self, key: TreeKey, parent: Option<TreeKey>) {
+        self.edits.push(Edit::Move(key, parent));
+    }
+
+    /// Get the number of queued edits
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    /// Check whether no edits are queued
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
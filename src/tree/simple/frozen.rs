@@ -0,0 +1,189 @@
+use core::ops::{Index, Range};
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// An immutable snapshot of a [`Tree`], with nodes stored contiguously in pre-order and addressed
+/// by index. Every subtree occupies a contiguous range, so slicing one is `O(1)`.
+///
+/// Created by [`Tree::freeze`], and turned back into a mutable tree with
+/// [`thaw`](FrozenTree::thaw).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenTree<T> {
+    vals: Vec<T>,
+    parents: Vec<Option<usize>>,
+    /// Exclusive end of the subtree rooted at each node
+    ends: Vec<usize>,
+}
+
+impl<T> FrozenTree<T> {
+    pub(super) fn from_tree<E>(tree: Tree<T, E>) -> FrozenTree<T> {
+        let mut vals = Vec::with_capacity(tree.len());
+        let mut parents = Vec::with_capacity(tree.len());
+        let mut indices = SecondaryMap::<TreeKey, usize>::new();
+
+        tree.drain_pre_order(|parent, key, val| {
+            indices.insert(key, vals.len());
+            parents.push(parent.and_then(|parent| indices.get(parent).copied()));
+            vals.push(val);
+        });
+
+        // Children always follow their parent, so walking backwards finalizes every subtree end
+        // before it is propagated to the parent
+        let mut ends = (1..=vals.len()).collect::<Vec<_>>();
+        for idx in (0..ends.len()).rev() {
+            if let Some(parent) = parents[idx] {
+                ends[parent] = ends[parent].max(ends[idx]);
+            }
+        }
+
+        FrozenTree { vals, parents, ends }
+    }
+
+    /// Turn this snapshot back into a mutable tree, preserving its structure and order
+    pub fn thaw(self) -> Tree<T> {
+        self.vals.into_iter()
+            .zip(self.parents)
+            .collect()
+    }
+
+    /// Get the number of nodes in this tree
+    pub fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    /// Check whether this tree has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.vals.is_empty()
+    }
+
+    /// Get the value of the node at an index
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.vals.get(idx)
+    }
+
+    /// Get the values of every node, in pre-order
+    pub fn values(&self) -> &[T] {
+        &self.vals
+    }
+
+    /// Get the index of a node's parent, if it has one
+    pub fn parent(&self, idx: usize) -> Option<usize> {
+        self.parents.get(idx).copied().flatten()
+    }
+
+    /// Iterate over the indices of the roots of this tree, in order
+    pub fn roots(&self) -> FrozenChildren<'_, T> {
+        FrozenChildren { tree: self, next: 0, end: self.len() }
+    }
+
+    /// Iterate over the indices of a node's children, in order
+    pub fn children(&self, idx: usize) -> Option<FrozenChildren<'_, T>> {
+        let range = self.subtree_range(idx)?;
+        Some(FrozenChildren { tree: self, next: range.start + 1, end: range.end })
+    }
+
+    /// Get the range of indices covered by the subtree rooted at a node, including the node itself
+    pub fn subtree_range(&self, idx: usize) -> Option<Range<usize>> {
+        Some(idx..*self.ends.get(idx)?)
+    }
+
+    /// Get the values of the subtree rooted at a node in pre-order, starting with the node itself
+    pub fn subtree(&self, idx: usize) -> Option<&[T]> {
+        self.subtree_range(idx).map(|range| &self.vals[range])
+    }
+}
+
+impl<T> Index<usize> for FrozenTree<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        &self.vals[idx]
+    }
+}
+
+/// Iterator over the indices of sibling nodes in a [`FrozenTree`]
+pub struct FrozenChildren<'a, T> {
+    tree: &'a FrozenTree<T>,
+    next: usize,
+    end: usize,
+}
+
+impl<T> Iterator for FrozenChildren<'_, T> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next >= self.end {
+            return None;
+        }
+        let idx = self.next;
+        self.next = self.tree.ends[idx];
+        Some(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freeze() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, root).unwrap();
+        let other = tree.add_root(4);
+        tree.add_child(5, other).unwrap();
+
+        let frozen = tree.freeze();
+        assert_eq!(frozen.len(), 6);
+        assert_eq!(frozen.values(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(frozen.roots().collect::<Vec<_>>(), [0, 4]);
+        assert_eq!(frozen.children(0).unwrap().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(frozen.children(2).unwrap().count(), 0);
+        assert!(frozen.children(6).is_none());
+        assert_eq!(frozen.parent(2), Some(1));
+        assert_eq!(frozen.parent(4), None);
+        assert_eq!(frozen.subtree(0).unwrap(), [0, 1, 2, 3]);
+        assert_eq!(frozen.subtree(4).unwrap(), [4, 5]);
+        assert_eq!(frozen[3], 3);
+    }
+
+    #[test]
+    fn test_freeze_order() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let late = tree.add_root(1);
+        tree.add_child(2, root).unwrap();
+        tree.set_child(root, late).unwrap();
+
+        let frozen = tree.freeze();
+        assert_eq!(frozen.values(), [0, 2, 1]);
+        assert_eq!(frozen.subtree_range(0), Some(0..3));
+    }
+
+    #[test]
+    fn test_thaw() {
+        // Removed slots leave gaps in the source tree, which thawing packs away
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let removed = tree.add_child(1, root).unwrap();
+        let a = tree.add_child(2, root).unwrap();
+        tree.add_child(3, a).unwrap();
+        tree.add_child(4, removed).unwrap();
+        tree.remove_recursive(removed);
+
+        let frozen = tree.freeze();
+        assert_eq!(frozen.values(), [0, 2, 3]);
+        let tree = frozen.clone().thaw();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val)),
+            "0\n`-- 2\n    `-- 3\n",
+        );
+        assert_eq!(tree.freeze(), frozen);
+        assert!(Tree::<i32>::new().freeze().thaw().is_empty());
+        assert_eq!(FrozenTree::from_tree(Tree::<i32>::new()).values(), Vec::<i32>::new());
+    }
+}
@@ -0,0 +1,335 @@
+use core::fmt;
+use core::ops::Range;
+use alloc::vec::Vec;
+use crate::tree::simple::FrozenTree;
+
+const MAGIC: &[u8; 4] = b"FRZT";
+const VERSION: u32 = 1;
+const HEADER: usize = 12;
+const NO_PARENT: u32 = u32::MAX;
+
+/// Reasons bytes can't be read as a frozen tree
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The bytes don't start with the expected magic bytes
+    BadMagic,
+    /// The bytes were written by an unknown version of the format
+    UnsupportedVersion(u32),
+    /// The bytes end before the data they declare
+    Truncated,
+    /// The node at an index has an invalid parent, subtree end or value range
+    BadNode(usize),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::BadMagic => write!(f, "Bytes aren't a frozen tree"),
+            FormatError::UnsupportedVersion(version) => write!(f, "Unsupported frozen tree version {}", version),
+            FormatError::Truncated => write!(f, "Frozen tree bytes are truncated"),
+            FormatError::BadNode(idx) => write!(f, "Frozen tree node {} is malformed", idx),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FormatError {}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    let word = bytes.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+impl<T: AsRef<[u8]>> FrozenTree<T> {
+    /// Write this tree in a flat format which can be read back in place with
+    /// [`FrozenTree::from_bytes`]. Returns `None` if the tree or its values are too large for
+    /// the format's 32-bit offsets.
+    ///
+    /// All integers are little-endian `u32`s. The layout is:
+    ///
+    /// - The magic bytes `FRZT`, the format version, and the node count `n`
+    /// - `n` parent indices, with `u32::MAX` for roots
+    /// - `n` subtree ends, as in [`subtree_range`](FrozenTree::subtree_range)
+    /// - `n + 1` offsets into the value bytes, where node `i` has the bytes between offsets `i`
+    ///   and `i + 1`
+    /// - The value bytes
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let values_len = self.values().iter().map(|val| val.as_ref().len()).sum::<usize>();
+        let tables = 4 * (3 * self.len() + 1);
+        let mut out = Vec::with_capacity(HEADER + tables + values_len);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&u32::try_from(self.len()).ok()?.to_le_bytes());
+
+        for idx in 0..self.len() {
+            let parent = match self.parent(idx) {
+                Some(parent) => u32::try_from(parent).ok()?,
+                None => NO_PARENT,
+            };
+            out.extend_from_slice(&parent.to_le_bytes());
+        }
+        for idx in 0..self.len() {
+            let end = self.subtree_range(idx)?.end;
+            out.extend_from_slice(&u32::try_from(end).ok()?.to_le_bytes());
+        }
+        let mut offset = 0_usize;
+        out.extend_from_slice(&0_u32.to_le_bytes());
+        for val in self.values() {
+            offset += val.as_ref().len();
+            out.extend_from_slice(&u32::try_from(offset).ok()?.to_le_bytes());
+        }
+        for val in self.values() {
+            out.extend_from_slice(val.as_ref());
+        }
+        Some(out)
+    }
+}
+
+impl<'a> FrozenTree<&'a [u8]> {
+    /// Read a tree written by [`FrozenTree::to_bytes`] in place, such as from a memory-mapped
+    /// file. The whole layout is validated up front, taking time linear in the number of nodes
+    /// but never allocating, so the returned view can't fail or panic later.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<FrozenTreeView<'a>, FormatError> {
+        FrozenTreeView::new(bytes)
+    }
+}
+
+/// A read-only view of a [`FrozenTree`] in the byte format of [`FrozenTree::to_bytes`], with
+/// node values as byte slices borrowed from the input
+#[derive(Debug, Copy, Clone)]
+pub struct FrozenTreeView<'a> {
+    len: usize,
+    /// The parent, end and offset tables
+    tables: &'a [u8],
+    values: &'a [u8],
+}
+
+impl<'a> FrozenTreeView<'a> {
+    fn new(bytes: &'a [u8]) -> Result<FrozenTreeView<'a>, FormatError> {
+        if bytes.get(..4) != Some(&MAGIC[..]) {
+            return Err(FormatError::BadMagic);
+        }
+        let version = read_u32(bytes, 4).ok_or(FormatError::Truncated)?;
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        let len = read_u32(bytes, 8).ok_or(FormatError::Truncated)? as usize;
+        let tables_len = len.checked_mul(3)
+            .and_then(|words| words.checked_add(1))
+            .and_then(|words| words.checked_mul(4))
+            .ok_or(FormatError::Truncated)?;
+        let values_start = HEADER.checked_add(tables_len).ok_or(FormatError::Truncated)?;
+        let tables = bytes.get(HEADER..values_start).ok_or(FormatError::Truncated)?;
+        let values = &bytes[values_start..];
+
+        let view = FrozenTreeView { len, tables, values };
+        view.validate()?;
+        Ok(view)
+    }
+
+    fn parent_raw(&self, idx: usize) -> u32 {
+        read_u32(self.tables, 4 * idx).unwrap_or(NO_PARENT)
+    }
+
+    fn end_raw(&self, idx: usize) -> usize {
+        read_u32(self.tables, 4 * (self.len + idx)).map_or(idx + 1, |end| end as usize)
+    }
+
+    fn offset_raw(&self, idx: usize) -> usize {
+        read_u32(self.tables, 4 * (2 * self.len + idx)).map_or(0, |offset| offset as usize)
+    }
+
+    /// Check that every node is consistent with a pre-order layout
+    fn validate(&self) -> Result<(), FormatError> {
+        if self.offset_raw(0) != 0 || self.offset_raw(self.len) != self.values.len() {
+            return Err(FormatError::Truncated);
+        }
+        // The innermost subtree containing the current node. The subtrees still open form a
+        // stack, which is the chain of already validated parents from here.
+        let mut open = None;
+        for idx in 0..self.len {
+            while let Some(top) = open {
+                if self.end_raw(top) > idx {
+                    break;
+                }
+                open = self.parent(top);
+            }
+            let end = self.end_raw(idx);
+            let good_end = end > idx && end <= self.len;
+            let good_parent = match (self.parent_raw(idx), open) {
+                (NO_PARENT, None) => true,
+                (parent, Some(top)) => parent as usize == top && end <= self.end_raw(top),
+                _ => false,
+            };
+            let good_offsets = self.offset_raw(idx) <= self.offset_raw(idx + 1);
+            if !(good_end && good_parent && good_offsets) {
+                return Err(FormatError::BadNode(idx));
+            }
+            open = Some(idx);
+        }
+        Ok(())
+    }
+
+    /// Get the number of nodes in this tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether this tree has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the value of the node at an index
+    pub fn get(&self, idx: usize) -> Option<&'a [u8]> {
+        if idx >= self.len {
+            return None;
+        }
+        self.values.get(self.offset_raw(idx)..self.offset_raw(idx + 1))
+    }
+
+    /// Get the index of a node's parent, if it has one
+    pub fn parent(&self, idx: usize) -> Option<usize> {
+        match self.parent_raw(idx) {
+            _ if idx >= self.len => None,
+            NO_PARENT => None,
+            parent => Some(parent as usize),
+        }
+    }
+
+    /// Get the range of indices covered by the subtree rooted at a node, including the node itself
+    pub fn subtree_range(&self, idx: usize) -> Option<Range<usize>> {
+        (idx < self.len).then(|| idx..self.end_raw(idx))
+    }
+
+    fn siblings(&self, range: Range<usize>) -> impl Iterator<Item = usize> + 'a {
+        let view = *self;
+        let mut next = range.start;
+        core::iter::from_fn(move || {
+            let idx = next;
+            (idx < range.end).then(|| {
+                next = view.end_raw(idx);
+                idx
+            })
+        })
+    }
+
+    /// Iterate over the indices of the roots of this tree, in order
+    pub fn roots(&self) -> impl Iterator<Item = usize> + 'a {
+        self.siblings(0..self.len)
+    }
+
+    /// Iterate over the indices of a node's children, in order
+    pub fn children(&self, idx: usize) -> Option<impl Iterator<Item = usize> + 'a> {
+        let range = self.subtree_range(idx)?;
+        Some(self.siblings(range.start + 1..range.end))
+    }
+
+    /// Copy the structure of this view into a [`FrozenTree`], still borrowing the values
+    pub fn to_frozen(&self) -> FrozenTree<&'a [u8]> {
+        (0..self.len)
+            .map(|idx| (self.get(idx).unwrap_or_default(), self.parent(idx)))
+            .collect::<crate::tree::simple::Tree<_>>()
+            .freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use crate::tree::simple::Tree;
+    use super::*;
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        // Values are stored as raw bytes, so lengths are in bytes rather than chars
+        let mut tree = Tree::new();
+        let root = tree.add_root("root");
+        let a = tree.add_child("\u{e9}t\u{e9}", root).unwrap();
+        tree.add_child("", a).unwrap();
+        tree.add_root("other");
+
+        let bytes = tree.freeze().to_bytes().unwrap();
+        let view = FrozenTree::from_bytes(&bytes).unwrap();
+        assert_eq!(view.len(), 4);
+        assert_eq!(view.get(1).map(<[u8]>::len), Some(5));
+        assert_eq!(view.get(1), Some("\u{e9}t\u{e9}".as_bytes()));
+        assert_eq!(view.get(2), Some(&b""[..]));
+        assert_eq!(view.get(4), None);
+        assert_eq!(view.parent(2), Some(1));
+        assert_eq!(view.parent(3), None);
+        assert_eq!(view.roots().collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(view.children(0).unwrap().collect::<Vec<_>>(), [1]);
+        assert_eq!(view.subtree_range(0), Some(0..3));
+
+        let copy = view.to_frozen();
+        assert_eq!(copy.values(), [&b"root"[..], "\u{e9}t\u{e9}".as_bytes(), b"", b"other"]);
+        assert_eq!(copy.to_bytes().unwrap(), bytes);
+        let empty = Tree::<&[u8]>::new().freeze().to_bytes().unwrap();
+        assert!(FrozenTree::from_bytes(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bytes_invalid() {
+        let mut tree = Tree::new();
+        let root = tree.add_root("root");
+        let a = tree.add_child("a", root).unwrap();
+        tree.add_child("a1", a).unwrap();
+        tree.add_child("b", root).unwrap();
+        tree.add_root("other");
+        let bytes = tree.freeze().to_bytes().unwrap();
+        assert_eq!(FrozenTree::from_bytes(b"nope").unwrap_err(), FormatError::BadMagic);
+        assert_eq!(FrozenTree::from_bytes(&bytes[..20]).unwrap_err(), FormatError::Truncated);
+        assert_eq!(FrozenTree::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), FormatError::Truncated);
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(FrozenTree::from_bytes(&version).unwrap_err(), FormatError::UnsupportedVersion(9));
+
+        // Node 2 claims node 3 as its parent, which comes after it
+        let mut parent = bytes.clone();
+        parent[HEADER + 8..HEADER + 12].copy_from_slice(&3_u32.to_le_bytes());
+        assert_eq!(FrozenTree::from_bytes(&parent).unwrap_err(), FormatError::BadNode(2));
+
+        // Node 1's subtree extends past its parent's
+        let mut end = bytes;
+        end[HEADER + 24..HEADER + 28].copy_from_slice(&5_u32.to_le_bytes());
+        assert_eq!(FrozenTree::from_bytes(&end).unwrap_err(), FormatError::BadNode(1));
+    }
+
+    /// Build the bytes of a tree with the provided parent and end tables, and empty values
+    fn raw(parents: &[u32], ends: &[u32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(parents.len() as u32).to_le_bytes());
+        for word in parents.iter().chain(ends).chain(&vec![0; parents.len() + 1]) {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn test_bytes_invalid_nesting() {
+        let valid = raw(&[NO_PARENT, 0, 1, 0], &[4, 3, 3, 4]);
+        let view = FrozenTree::from_bytes(&valid).unwrap();
+        assert_eq!(view.children(0).unwrap().collect::<Vec<_>>(), [1, 3]);
+
+        // Node 2 lies inside node 1's subtree, but claims node 0 as its parent
+        let skipped = raw(&[NO_PARENT, 0, 0], &[3, 3, 3]);
+        assert_eq!(FrozenTree::from_bytes(&skipped).unwrap_err(), FormatError::BadNode(2));
+        // Node 2 claims node 1 as its parent, but lies after node 1's subtree
+        let closed = raw(&[NO_PARENT, 0, 1], &[3, 2, 3]);
+        assert_eq!(FrozenTree::from_bytes(&closed).unwrap_err(), FormatError::BadNode(2));
+        // Node 2 is a root, but lies inside node 0's subtree
+        let root = raw(&[NO_PARENT, 0, NO_PARENT], &[3, 2, 3]);
+        assert_eq!(FrozenTree::from_bytes(&root).unwrap_err(), FormatError::BadNode(2));
+        // Node 1 is a root, but node 0's subtree claims it
+        let crossed = raw(&[NO_PARENT, NO_PARENT], &[2, 2]);
+        assert_eq!(FrozenTree::from_bytes(&crossed).unwrap_err(), FormatError::BadNode(1));
+
+        let mut huge = raw(&[], &[]);
+        huge[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(FrozenTree::from_bytes(&huge).unwrap_err(), FormatError::Truncated);
+    }
+}
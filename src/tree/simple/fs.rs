@@ -0,0 +1,524 @@
+//! Building a [`Tree`] from a directory on the filesystem
+
+use std::boxed::Box;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::vec;
+use std::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// The kind of a filesystem entry
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    /// A regular file
+    File,
+    /// A directory, or a followed symlink to one
+    Dir,
+    /// A symlink which wasn't followed
+    Symlink,
+    /// Anything else, such as a socket or device
+    Other,
+}
+
+impl EntryKind {
+    fn of(file_type: fs::FileType) -> EntryKind {
+        if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// Information about a filesystem entry, the value of each node built by [`Tree::from_dir`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntryInfo {
+    /// The path of the entry, starting with the path the scan started from
+    pub path: PathBuf,
+    /// The kind of the entry
+    pub kind: EntryKind,
+    /// The size of the entry in bytes, as reported by its metadata
+    pub len: u64,
+    /// The number of directories between the entry and the scan's starting directory, which is
+    /// at depth zero
+    pub depth: usize,
+}
+
+impl DirEntryInfo {
+    /// Get the final component of this entry's path
+    pub fn file_name(&self) -> Option<&std::ffi::OsStr> {
+        self.path.file_name()
+    }
+}
+
+/// How [`Tree::from_dir`] treats symlinks
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave symlinks out of the tree
+    Skip,
+    /// Add symlinks as leaves, without following them
+    #[default]
+    Keep,
+    /// Follow symlinks, adding what they point to. Symlinks to a directory which is already
+    /// being scanned, which would loop forever, are added as leaves instead.
+    Follow,
+}
+
+type Filter = Box<dyn Fn(&DirEntryInfo) -> bool + Send + Sync>;
+
+/// A glob pattern added with [`ScanOptions::ignore`]
+#[derive(Debug, Clone)]
+struct Ignore {
+    glob: Vec<char>,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Ignore {
+    fn matches(&self, relative: &[char], kind: EntryKind) -> bool {
+        if self.dir_only && kind != EntryKind::Dir {
+            return false;
+        }
+        if self.anchored {
+            return glob_match(&self.glob, relative);
+        }
+        let name = relative.rsplit(|&c| c == '/').next().unwrap_or(relative);
+        glob_match(&self.glob, name)
+    }
+}
+
+/// Match text against a glob, where `*` doesn't cross `/` but `**` does.
+///
+/// Every suffix of the glob is matched against every suffix of the text once, working backwards,
+/// so this takes `O(glob * text)` time however many stars the glob contains.
+fn glob_match(glob: &[char], text: &[char]) -> bool {
+    let width = text.len() + 1;
+    // `matched[gi * width + ti]` is whether `glob[gi..]` matches `text[ti..]`
+    let mut matched = vec![false; (glob.len() + 1) * width];
+    matched[glob.len() * width + text.len()] = true;
+    for gi in (0..glob.len()).rev() {
+        // Whether the glob after a `**` matches the text from any position at or after `ti`
+        let mut any_after = false;
+        for ti in (0..width).rev() {
+            let next = text.get(ti);
+            let is_match = match (glob[gi], glob.get(gi + 1)) {
+                ('*', Some('*')) => {
+                    let rest = gi + 2;
+                    any_after |= matched[rest * width + ti];
+                    // Let `**/` also match nothing, so `a/**/b` matches `a/b`
+                    let after_slash = if glob.get(rest) == Some(&'/') { rest + 1 } else { rest };
+                    matched[after_slash * width + ti] || any_after
+                }
+                ('*', _) => {
+                    matched[(gi + 1) * width + ti]
+                        || (next.is_some_and(|&c| c != '/') && matched[gi * width + ti + 1])
+                }
+                ('?', _) => next.is_some_and(|&c| c != '/') && matched[(gi + 1) * width + ti + 1],
+                (c, _) => next == Some(&c) && matched[(gi + 1) * width + ti + 1],
+            };
+            matched[gi * width + ti] = is_match;
+        }
+    }
+    matched[0]
+}
+
+/// Options for [`Tree::from_dir`]
+#[derive(Default)]
+pub struct ScanOptions {
+    max_depth: Option<usize>,
+    symlinks: SymlinkPolicy,
+    skip_errors: bool,
+    filter: Option<Filter>,
+    ignores: Vec<Ignore>,
+}
+
+impl ScanOptions {
+    /// Create the default options, scanning every entry at any depth and keeping symlinks as
+    /// leaves
+    pub fn new() -> ScanOptions {
+        ScanOptions::default()
+    }
+
+    /// Only add entries at most `depth` directories below the starting directory, so a depth of
+    /// `1` adds just its entries
+    #[must_use]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Set how symlinks are treated
+    #[must_use]
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Leave out entries which can't be read, instead of failing the whole scan
+    #[must_use]
+    pub fn skip_errors(mut self, skip: bool) -> Self {
+        self.skip_errors = skip;
+        self
+    }
+
+    /// Only add entries for which `f` returns `true`. Directories which are left out aren't
+    /// scanned. The starting directory is always added.
+    #[must_use]
+    pub fn filter(mut self, f: impl Fn(&DirEntryInfo) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Box::new(f));
+        self
+    }
+
+    /// Leave out entries matching a gitignore-style glob, without scanning ignored directories.
+    ///
+    /// `*` matches anything but `/`, `**` matches anything, and `?` matches one character other
+    /// than `/`. A pattern ending in `/` only matches directories. A pattern containing any other
+    /// `/` is matched against the whole path relative to the starting directory, so `/target/`
+    /// ignores a directory by prefix, and any other pattern is matched against file names.
+    #[must_use]
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        let anchored = pattern.contains('/');
+        let glob = pattern.trim_start_matches('/').chars().collect();
+        self.ignores.push(Ignore { glob, dir_only, anchored });
+        self
+    }
+
+    fn keep(&self, root: &Path, info: &DirEntryInfo) -> bool {
+        if !self.ignores.is_empty() {
+            let relative = info.path.strip_prefix(root).unwrap_or(&info.path);
+            let relative = relative.components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+                .chars()
+                .collect::<Vec<_>>();
+            if self.ignores.iter().any(|ignore| ignore.matches(&relative, info.kind)) {
+                return false;
+            }
+        }
+        self.filter.as_ref().is_none_or(|filter| filter(info))
+    }
+}
+
+/// Read the entries of a directory, sorted by name
+fn read_dir(path: &Path, depth: usize, options: &ScanOptions) -> io::Result<Vec<DirEntryInfo>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+            Ok(entry) => entry,
+            Err(_) if options.skip_errors => continue,
+            Err(err) => return Err(err),
+        };
+        let (path, file_type) = entry;
+        let info = match (EntryKind::of(file_type), options.symlinks) {
+            (EntryKind::Symlink, SymlinkPolicy::Skip) => continue,
+            (EntryKind::Symlink, SymlinkPolicy::Follow) => match fs::metadata(&path) {
+                Ok(meta) => DirEntryInfo { path, kind: EntryKind::of(meta.file_type()), len: meta.len(), depth },
+                // Dangling links are kept as they are
+                Err(_) => entry_info(path, depth, options)?,
+            },
+            _ => entry_info(path, depth, options)?,
+        };
+        entries.push(info);
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn entry_info(path: PathBuf, depth: usize, options: &ScanOptions) -> io::Result<DirEntryInfo> {
+    let (kind, len) = match fs::symlink_metadata(&path) {
+        Ok(meta) => (EntryKind::of(meta.file_type()), meta.len()),
+        Err(_) if options.skip_errors => (EntryKind::Other, 0),
+        Err(err) => return Err(err),
+    };
+    Ok(DirEntryInfo { path, kind, len, depth })
+}
+
+/// The state of a scan, shared by the serial and parallel walks
+struct Scan<'a> {
+    options: &'a ScanOptions,
+    root: &'a Path,
+    tree: Tree<DirEntryInfo>,
+    /// Canonical paths of followed directories, to find symlink loops
+    canonical: SecondaryMap<TreeKey, PathBuf>,
+}
+
+impl<'a> Scan<'a> {
+    /// Start a scan, returning the root if it's a directory to be read
+    fn new(root: &'a Path, options: &'a ScanOptions) -> io::Result<(Scan<'a>, Option<TreeKey>)> {
+        let meta = fs::metadata(root)?;
+        let kind = EntryKind::of(meta.file_type());
+        let info = DirEntryInfo { path: root.to_path_buf(), kind, len: meta.len(), depth: 0 };
+        let mut scan = Scan { options, root, tree: Tree::new(), canonical: SecondaryMap::new() };
+        let is_dir = info.kind == EntryKind::Dir;
+        let key = scan.tree.add_root(info);
+        if is_dir && options.symlinks == SymlinkPolicy::Follow {
+            match fs::canonicalize(root) {
+                Ok(path) => {
+                    scan.canonical.insert(key, path);
+                }
+                Err(_) if options.skip_errors => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((scan, is_dir.then_some(key)))
+    }
+
+    /// Get the path and child depth of a directory, if its entries should be read
+    fn job(&self, dir: TreeKey) -> Option<(TreeKey, PathBuf, usize)> {
+        let info = self.tree.get(dir)?;
+        let depth = info.depth + 1;
+        match self.options.max_depth {
+            Some(max) if depth > max => None,
+            _ => Some((dir, info.path.clone(), depth)),
+        }
+    }
+
+    /// Add the entries read from a directory, returning the new directories to read
+    fn insert(&mut self, dir: TreeKey, entries: io::Result<Vec<DirEntryInfo>>) -> io::Result<Vec<TreeKey>> {
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(_) if self.options.skip_errors => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut dirs = Vec::new();
+        for mut entry in entries {
+            if !self.options.keep(self.root, &entry) {
+                continue;
+            }
+            let mut real = None;
+            if entry.kind == EntryKind::Dir && self.options.symlinks == SymlinkPolicy::Follow {
+                let path = match fs::canonicalize(&entry.path) {
+                    Ok(path) => path,
+                    Err(_) if self.options.skip_errors => continue,
+                    Err(err) => return Err(err),
+                };
+                let looped = core::iter::successors(Some(dir), |&key| self.tree.parent_key_of(key))
+                    .any(|key| self.canonical.get(key) == Some(&path));
+                if looped {
+                    entry.kind = EntryKind::Symlink;
+                } else {
+                    real = Some(path);
+                }
+            }
+
+            let is_dir = entry.kind == EntryKind::Dir;
+            let Some(key) = self.tree.add_child(entry, dir) else {
+                continue;
+            };
+            if let Some(real) = real {
+                self.canonical.insert(key, real);
+            }
+            if is_dir {
+                dirs.push(key);
+            }
+        }
+        Ok(dirs)
+    }
+}
+
+impl Tree<DirEntryInfo> {
+    /// Scan a directory and everything below it into a new tree, with the directory as the
+    /// only root. Entries are added in order of their paths.
+    pub fn from_dir(path: impl AsRef<Path>, options: &ScanOptions) -> io::Result<Tree<DirEntryInfo>> {
+        let (mut scan, root) = Scan::new(path.as_ref(), options)?;
+        let mut pending = Vec::from_iter(root);
+        while let Some(dir) = pending.pop() {
+            let Some((dir, path, depth)) = scan.job(dir) else {
+                continue;
+            };
+            let entries = read_dir(&path, depth, options);
+            pending.extend(scan.insert(dir, entries)?);
+        }
+        Ok(scan.tree)
+    }
+
+    /// Scan a directory like [`from_dir`](Tree::from_dir), reading every directory at the same
+    /// depth in parallel on the rayon thread pool. The resulting tree is the same, but its keys
+    /// may differ.
+    #[cfg(feature = "parallel")]
+    pub fn from_dir_parallel(path: impl AsRef<Path>, options: &ScanOptions) -> io::Result<Tree<DirEntryInfo>> {
+        use rayon::prelude::*;
+
+        let (mut scan, root) = Scan::new(path.as_ref(), options)?;
+        let mut level = Vec::from_iter(root);
+        while !level.is_empty() {
+            let jobs = level.iter().filter_map(|&dir| scan.job(dir)).collect::<Vec<_>>();
+            let read = jobs.into_par_iter()
+                .map(|(dir, path, depth)| (dir, read_dir(&path, depth, options)))
+                .collect::<Vec<_>>();
+            level.clear();
+            for (dir, entries) in read {
+                level.extend(scan.insert(dir, entries)?);
+            }
+        }
+        Ok(scan.tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+    use super::*;
+
+    /// A directory in the system's temporary directory, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[&str]) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("forest-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            for file in files {
+                let path = dir.join(file);
+                match file.strip_suffix('/') {
+                    Some(_) => fs::create_dir_all(&path).unwrap(),
+                    None => {
+                        fs::create_dir_all(path.parent().unwrap()).unwrap();
+                        fs::write(&path, file).unwrap();
+                    }
+                }
+            }
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(tree: &Tree<DirEntryInfo>) -> String {
+        tree.render_ascii(|out, info| {
+            let name = info.file_name().unwrap_or_default().to_string_lossy();
+            match info.kind {
+                EntryKind::Dir => write!(out, "{}/", name),
+                EntryKind::Symlink => write!(out, "{}@", name),
+                _ => write!(out, "{}", name),
+            }
+        })
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = TempDir::new("scan", &["b.txt", "a/x.rs", "a/y/z.rs", "empty/"]);
+        let tree = Tree::from_dir(&dir.0, &ScanOptions::new()).unwrap();
+        let root = format!("forest-scan-{}/", std::process::id());
+        assert_eq!(
+            names(&tree),
+            format!("{}\n|-- a/\n|   |-- x.rs\n|   `-- y/\n|       `-- z.rs\n|-- b.txt\n`-- empty/\n", root),
+        );
+        let z = tree.unordered_iter().find(|info| info.path.ends_with("z.rs")).unwrap();
+        assert_eq!((z.depth, z.len, z.kind), (3, 8, EntryKind::File));
+
+        let shallow = Tree::from_dir(&dir.0, &ScanOptions::new().max_depth(1)).unwrap();
+        assert_eq!(names(&shallow), format!("{}\n|-- a/\n|-- b.txt\n`-- empty/\n", root));
+
+        let options = ScanOptions::new().filter(|info| info.file_name().is_some_and(|name| name != "y"));
+        let filtered = Tree::from_dir(&dir.0, &options).unwrap();
+        assert_eq!(filtered.len(), 5);
+
+        assert!(Tree::from_dir(dir.0.join("missing"), &ScanOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let glob = |glob: &str, text: &str| glob_match(&glob.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>());
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("src/**/*.rs", "src/a/b/main.rs"));
+        assert!(glob("src/**/*.rs", "src/main.rs"));
+        assert!(glob("fo?", "foo"));
+        assert!(!glob("fo?", "fo/"));
+        assert!(glob("**", "a/b"));
+        assert!(!glob("a*", "ba"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(!glob("a**/b", "axb"));
+        assert!(glob("**b", "a/b"));
+        assert!(glob("*", ""));
+        assert!(!glob("?", ""));
+
+        // Many stars mustn't take exponential time
+        let name = "a".repeat(40);
+        assert!(!glob(&("*a".repeat(20) + "b"), &name));
+        assert!(glob(&"*a".repeat(20), &name));
+        assert!(!glob(&("**a".repeat(20) + "b"), &name));
+    }
+
+    #[test]
+    fn test_from_dir_ignore() {
+        let dir = TempDir::new("ignore", &["a.rs", "a.txt", "target/out", "src/target/x.rs", "src/b.rs"]);
+        let root = format!("forest-ignore-{}/", std::process::id());
+
+        let options = ScanOptions::new().ignore("*.txt").ignore("/target/");
+        let tree = Tree::from_dir(&dir.0, &options).unwrap();
+        assert_eq!(
+            names(&tree),
+            format!("{}\n|-- a.rs\n`-- src/\n    |-- b.rs\n    `-- target/\n        `-- x.rs\n", root),
+        );
+
+        let options = ScanOptions::new().ignore("target/").ignore("src/*.rs");
+        let tree = Tree::from_dir(&dir.0, &options).unwrap();
+        assert_eq!(names(&tree), format!("{}\n|-- a.rs\n|-- a.txt\n`-- src/\n", root));
+
+        // A directory-only pattern doesn't match files
+        let options = ScanOptions::new().ignore("a.rs/");
+        assert_eq!(Tree::from_dir(&dir.0, &options).unwrap().len(), 9);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_from_dir_parallel() {
+        let dir = TempDir::new("parallel", &["b.txt", "a/x.rs", "a/y/z.rs", "c/d/e/f", "empty/"]);
+        let options = ScanOptions::new().ignore("x.rs");
+        let serial = Tree::from_dir(&dir.0, &options).unwrap();
+        let parallel = Tree::from_dir_parallel(&dir.0, &options).unwrap();
+        assert_eq!(names(&parallel), names(&serial));
+
+        let shallow = Tree::from_dir_parallel(&dir.0, &ScanOptions::new().max_depth(2)).unwrap();
+        assert_eq!(shallow.len(), 8);
+        assert!(Tree::from_dir_parallel(dir.0.join("missing"), &options).is_err());
+    }
+
+    #[test]
+    fn test_scan_skip_errors() {
+        // A directory removed between being read and being followed can't be canonicalized
+        let dir = TempDir::new("vanished", &["a/"]);
+        let gone = DirEntryInfo { path: dir.0.join("gone"), kind: EntryKind::Dir, len: 0, depth: 1 };
+
+        let options = ScanOptions::new().symlinks(SymlinkPolicy::Follow);
+        let (mut scan, root) = Scan::new(&dir.0, &options).unwrap();
+        assert!(scan.insert(root.unwrap(), Ok(vec![gone.clone()])).is_err());
+
+        let options = options.skip_errors(true);
+        let (mut scan, root) = Scan::new(&dir.0, &options).unwrap();
+        assert_eq!(scan.insert(root.unwrap(), Ok(vec![gone])).unwrap(), []);
+        assert_eq!(scan.tree.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_from_dir_symlinks() {
+        let dir = TempDir::new("links", &["a/file"]);
+        std::os::unix::fs::symlink(&dir.0, dir.0.join("a/up")).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("a/file"), dir.0.join("link")).unwrap();
+
+        let kept = Tree::from_dir(&dir.0, &ScanOptions::new()).unwrap();
+        assert!(names(&kept).ends_with("|   `-- up@\n`-- link@\n"));
+        let skipped = Tree::from_dir(&dir.0, &ScanOptions::new().symlinks(SymlinkPolicy::Skip)).unwrap();
+        assert_eq!(skipped.len(), 3);
+        let followed = Tree::from_dir(&dir.0, &ScanOptions::new().symlinks(SymlinkPolicy::Follow)).unwrap();
+        assert!(names(&followed).ends_with("|   `-- up@\n`-- link\n"));
+    }
+}
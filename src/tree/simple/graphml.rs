@@ -0,0 +1,89 @@
+//! Export of [`Tree`] as `GraphML`, for graph tools such as Gephi and yEd
+
+use core::fmt::{self, Write};
+use alloc::string::String;
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+impl<T, E> Tree<T, E> {
+    /// Write this tree as a directed `GraphML` graph, with an edge from each parent to each of its
+    /// children. `f` writes the label for each node's value, which is stored in the `value` data
+    /// key of the node.
+    ///
+    /// Nodes are given the IDs `n0`, `n1`, and so on in pre-order, and appear in that order.
+    pub fn to_graphml(&self, mut f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> Result<String, fmt::Error> {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"value\" for=\"node\" attr.name=\"value\" attr.type=\"string\"/>\n",
+            "  <graph id=\"tree\" edgedefault=\"directed\">\n",
+        ));
+        let mut ids = SecondaryMap::<TreeKey, usize>::new();
+        let mut edges = Vec::new();
+        let mut label = String::new();
+        let mut stack = self.raw_roots().iter().rev().copied().collect::<Vec<_>>();
+
+        while let Some(key) = stack.pop() {
+            let Some(node) = self.get(key) else {
+                continue;
+            };
+            let id = ids.len();
+            ids.insert(key, id);
+            if let Some(&parent) = node.parent().and_then(|parent| ids.get(parent)) {
+                edges.push((parent, id));
+            }
+
+            label.clear();
+            f(&mut label, &*node)?;
+            write!(out, "    <node id=\"n{}\"><data key=\"value\">", id)?;
+            escape(&mut out, &label);
+            out.push_str("</data></node>\n");
+            stack.extend(node.children().iter().rev());
+        }
+
+        for (idx, (source, target)) in edges.into_iter().enumerate() {
+            writeln!(out, "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"/>", idx, source, target)?;
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphml() {
+        let tree = [("root", None), ("a & b", Some(0)), ("<c>", Some(0)), ("other", None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+        let graphml = tree.to_graphml(|out, val| write!(out, "{}", val)).unwrap();
+
+        let body = graphml.lines().skip(4).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "    <node id=\"n0\"><data key=\"value\">root</data></node>",
+            "    <node id=\"n1\"><data key=\"value\">a &amp; b</data></node>",
+            "    <node id=\"n2\"><data key=\"value\">&lt;c&gt;</data></node>",
+            "    <node id=\"n3\"><data key=\"value\">other</data></node>",
+            "    <edge id=\"e0\" source=\"n0\" target=\"n1\"/>",
+            "    <edge id=\"e1\" source=\"n0\" target=\"n2\"/>",
+            "  </graph>",
+            "</graphml>",
+        ]);
+    }
+}
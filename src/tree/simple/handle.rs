@@ -0,0 +1,60 @@
+use crate::tree::simple::{KeyMap, NodeRef, Tree, TreeKey};
+
+/// A weak handle to a node in a [`Tree`], created by [`Tree::weak_handle`]
+///
+/// Unlike a bare [`TreeKey`], a handle knows when its key was made invalid by
+/// [`Tree::compact`], after which the key could refer to a different node. A handle can be
+/// carried across a compaction with [`remap`](NodeHandle::remap).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+    key: TreeKey,
+    epoch: u64,
+}
+
+impl NodeHandle {
+    pub(crate) fn new(key: TreeKey, epoch: u64) -> NodeHandle {
+        NodeHandle { key, epoch }
+    }
+
+    /// Get the key this handle was created from, which may no longer be valid
+    pub fn key(&self) -> TreeKey {
+        self.key
+    }
+
+    /// Get the key of the node, or `None` if the node was removed or the tree was compacted since
+    /// this handle was made
+    pub fn get<T, E>(&self, tree: &Tree<T, E>) -> Option<TreeKey> {
+        if self.epoch == tree.epoch() && tree.raw_nodes().contains_key(self.key) {
+            Some(self.key)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether the node still exists, and the tree hasn't been compacted since this handle
+    /// was made
+    pub fn is_valid<T, E>(&self, tree: &Tree<T, E>) -> bool {
+        self.get(tree).is_some()
+    }
+
+    /// Get a reference to the node, if this handle is still valid
+    pub fn upgrade<'a, T, E>(&self, tree: &'a Tree<T, E>) -> Option<NodeRef<'a, T, E>> {
+        tree.get(self.get(tree)?)
+    }
+
+    /// Move this handle to the node's new key after a compaction, returning whether the node was
+    /// relocated. Handles made before the previous compaction, or to removed nodes, can't be
+    /// remapped.
+    pub fn remap(&mut self, keys: &KeyMap) -> bool {
+        if self.epoch + 1 != keys.epoch() {
+            return false;
+        }
+        match keys.get(self.key) {
+            Some(new) => {
+                *self = NodeHandle::new(new, keys.epoch());
+                true
+            }
+            None => false,
+        }
+    }
+}
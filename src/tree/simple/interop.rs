@@ -0,0 +1,220 @@
+//! Conversions between [`Tree`] and the `ego-tree` and `indextree` representations.
+//!
+//! Every conversion preserves child order and returns a map from the source keys to the keys of
+//! the newly built tree.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+impl<T> Tree<T> {
+    /// Build a tree from an [`ego_tree::Tree`], returning it alongside a map from `ego-tree` node
+    /// IDs to keys in the new tree.
+    ///
+    /// The `ego-tree` root becomes the first root. Orphaned `ego-tree` nodes become further roots,
+    /// in the order they were created.
+    pub fn from_ego_tree(tree: ego_tree::Tree<T>) -> (Tree<T>, BTreeMap<ego_tree::NodeId, TreeKey>) {
+        let positions = tree.nodes()
+            .enumerate()
+            .map(|(idx, node)| (node.id(), idx))
+            .collect::<BTreeMap<_, _>>();
+        let order = tree.nodes()
+            .filter(|node| node.parent().is_none())
+            .flat_map(|root| root.descendants())
+            .map(|node| (node.id(), node.parent().map(|p| p.id())))
+            .collect::<Vec<_>>();
+
+        let mut values = tree.into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut out = Tree::new();
+        let mut keys = BTreeMap::new();
+
+        for (id, parent) in order {
+            let Some(val) = values[positions[&id]].take() else {
+                continue;
+            };
+            let key = match parent {
+                Some(parent) => keys.get(&parent).and_then(|&parent| out.add_child(val, parent)),
+                None => Some(out.add_root(val)),
+            };
+            if let Some(key) = key {
+                keys.insert(id, key);
+            }
+        }
+
+        (out, keys)
+    }
+
+    /// Convert this tree into an [`ego_tree::Tree`], returning it alongside a map from keys in
+    /// this tree to `ego-tree` node IDs.
+    ///
+    /// The first root becomes the `ego-tree` root, and any further roots become orphans. Returns
+    /// `None` if this tree is empty, as an `ego-tree` always has a root.
+    pub fn into_ego_tree(self) -> Option<(ego_tree::Tree<T>, SecondaryMap<TreeKey, ego_tree::NodeId>)> {
+        if self.raw_roots().is_empty() {
+            return None;
+        }
+
+        let mut out: Option<ego_tree::Tree<T>> = None;
+        let mut keys = SecondaryMap::new();
+
+        self.drain_pre_order(|parent, key, val| {
+            let id = match (parent, &mut out) {
+                (Some(parent), Some(tree)) => keys.get(parent)
+                    .and_then(|&parent| tree.get_mut(parent))
+                    .map(|mut parent| parent.append(val).id()),
+                (None, Some(tree)) => Some(tree.orphan(val).id()),
+                (_, None) => Some(out.insert(ego_tree::Tree::new(val)).root().id()),
+            };
+            if let Some(id) = id {
+                keys.insert(key, id);
+            }
+        });
+
+        out.map(|tree| (tree, keys))
+    }
+
+    /// Build a tree from an [`indextree::Arena`], returning it alongside a map from `indextree`
+    /// node IDs to keys in the new tree.
+    ///
+    /// Roots are added in arena order. Removed arena nodes are skipped.
+    pub fn from_indextree(arena: indextree::Arena<T>) -> (Tree<T>, BTreeMap<indextree::NodeId, TreeKey>) {
+        let order = arena.roots()
+            .flat_map(|root| root.descendants(&arena))
+            .map(|id| (id, arena[id].parent()))
+            .collect::<Vec<_>>();
+
+        let mut values = arena.into_iter()
+            .map(indextree::Node::into_data)
+            .collect::<Vec<_>>();
+        let mut out = Tree::new();
+        let mut keys = BTreeMap::new();
+
+        for (id, parent) in order {
+            let Some(val) = values[usize::from(id) - 1].take() else {
+                continue;
+            };
+            let key = match parent {
+                Some(parent) => keys.get(&parent).and_then(|&parent| out.add_child(val, parent)),
+                None => Some(out.add_root(val)),
+            };
+            if let Some(key) = key {
+                keys.insert(id, key);
+            }
+        }
+
+        (out, keys)
+    }
+
+    /// Convert this tree into an [`indextree::Arena`], returning it alongside a map from keys in
+    /// this tree to `indextree` node IDs.
+    ///
+    /// Every root of this tree becomes a root in the arena, in order.
+    pub fn into_indextree(self) -> (indextree::Arena<T>, SecondaryMap<TreeKey, indextree::NodeId>) {
+        let mut arena = indextree::Arena::with_capacity(self.len());
+        let mut keys: SecondaryMap<TreeKey, indextree::NodeId> = SecondaryMap::new();
+
+        self.drain_pre_order(|parent, key, val| {
+            let id = arena.new_node(val);
+            if let Some(&parent) = parent.and_then(|parent| keys.get(parent)) {
+                parent.append(id, &mut arena);
+            }
+            keys.insert(key, id);
+        });
+
+        (arena, keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+
+    fn shape(tree: &Tree<i32>) -> Vec<(i32, Vec<i32>)> {
+        let mut out = vec![];
+        let mut stack = tree.root_keys().collect::<Vec<_>>();
+        stack.reverse();
+        while let Some(key) = stack.pop() {
+            let node = tree.get(key).unwrap();
+            let children = tree.child_keys(key).unwrap();
+            out.push((*node, children.iter().map(|&c| *tree.get(c).unwrap()).collect()));
+            stack.extend(children.iter().rev());
+        }
+        out
+    }
+
+    #[test]
+    fn test_ego_tree_round_trip() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        tree.add_child(3, a).unwrap();
+        let other = tree.add_root(4);
+        let orphaned = tree.add_child(5, other).unwrap();
+        let expected = shape(&tree);
+
+        // Only the first root becomes the ego-tree root, later roots are kept as orphans
+        let (ego, keys) = tree.into_ego_tree().unwrap();
+        assert_eq!(ego.root().id(), keys[root]);
+        let children = ego.root().children().map(|n| *n.value()).collect::<Vec<_>>();
+        assert_eq!(children, [1, 2]);
+        let other_node = ego.get(keys[other]).unwrap();
+        assert!(other_node.parent().is_none());
+        assert_eq!(other_node.children().map(|n| n.id()).collect::<Vec<_>>(), [keys[orphaned]]);
+
+        let (back, back_keys) = Tree::from_ego_tree(ego);
+        assert_eq!(shape(&back), expected);
+        assert_eq!(back.root_keys().next(), Some(back_keys[&keys[root]]));
+        assert_eq!(*back.get(back_keys[&keys[b]]).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_ego_tree_empty() {
+        assert!(Tree::<i32>::new().into_ego_tree().is_none());
+    }
+
+    #[test]
+    fn test_indextree_round_trip() {
+        let mut tree = Tree::new();
+        let first = tree.add_root(0);
+        let a = tree.add_child(1, first).unwrap();
+        let deep = tree.add_child(2, a).unwrap();
+        let second = tree.add_root(3);
+        let third = tree.add_root(4);
+        tree.add_child(5, third).unwrap();
+        let expected = shape(&tree);
+
+        // Every root stays a root in the arena, in order
+        let (arena, keys) = tree.into_indextree();
+        assert_eq!(arena.roots().collect::<Vec<_>>(), [keys[first], keys[second], keys[third]]);
+        assert_eq!(arena[keys[deep]].parent(), Some(keys[a]));
+        let path = keys[deep].ancestors(&arena)
+            .map(|id| *arena[id].get())
+            .collect::<Vec<_>>();
+        assert_eq!(path, [2, 1, 0]);
+
+        let (back, back_keys) = Tree::from_indextree(arena);
+        assert_eq!(shape(&back), expected);
+        assert_eq!(*back.get(back_keys[&keys[deep]]).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_indextree_removed_nodes() {
+        let mut arena = indextree::Arena::new();
+        let root = arena.new_node(0);
+        let gone = arena.new_node(1);
+        let kept = arena.new_node(2);
+        root.append(gone, &mut arena);
+        root.append(kept, &mut arena);
+        gone.remove(&mut arena);
+
+        let (tree, keys) = Tree::from_indextree(arena);
+        assert_eq!(tree.len(), 2);
+        assert!(!keys.contains_key(&gone));
+        assert_eq!(shape(&tree), [(0, vec![2]), (2, vec![])]);
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::vec::Vec;
+use crate::tree::simple::{NodeRef, Tree, TreeKey};
+
+/// A lazy pre-order iterator over the descendants of a node
+pub struct Descendants<'a, T, E = ()> {
+    tree: &'a Tree<T, E>,
+    /// Children of the starting node, until the first call to `next`
+    start: Option<&'a [TreeKey]>,
+    /// Pending nodes, alongside their depth below the starting node
+    stack: Vec<(TreeKey, usize)>,
+    max_depth: usize,
+    max_breadth: usize,
+}
+
+impl<'a, T, E> Descendants<'a, T, E> {
+    pub(super) fn new(tree: &'a Tree<T, E>, children: &'a [TreeKey]) -> Descendants<'a, T, E> {
+        Descendants {
+            tree,
+            start: Some(children),
+            stack: Vec::new(),
+            max_depth: usize::MAX,
+            max_breadth: usize::MAX,
+        }
+    }
+
+    /// Only visit nodes at most `depth` levels below the starting node, so a depth of `1` yields
+    /// just its children
+    #[must_use]
+    pub fn take_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Only visit the first `breadth` children of each node
+    #[must_use]
+    pub fn take_breadth(mut self, breadth: usize) -> Self {
+        self.max_breadth = breadth;
+        self
+    }
+
+    fn push_children(&mut self, children: &[TreeKey], depth: usize) {
+        if depth >= self.max_depth {
+            return;
+        }
+        let len = children.len().min(self.max_breadth);
+        self.stack.extend(children[..len].iter().rev().map(|&child| (child, depth + 1)));
+    }
+}
+
+impl<'a, T, E> Iterator for Descendants<'a, T, E> {
+    type Item = NodeRef<'a, T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(children) = self.start.take() {
+            self.push_children(children, 0);
+        }
+
+        let (key, depth) = self.stack.pop()?;
+        let tree = self.tree;
+        self.push_children(tree.child_keys(key)?, depth);
+        tree.get(key)
+    }
+}
+
+/// A lazy post-order iterator over the keys of a tree, yielding children before their parents
+pub struct PostOrderKeys<'a, T, E = ()> {
+    tree: &'a Tree<T, E>,
+    /// Pending nodes, alongside whether their children have already been pushed
+    stack: Vec<(TreeKey, bool)>,
+}
+
+impl<'a, T, E> PostOrderKeys<'a, T, E> {
+    pub(super) fn new(tree: &'a Tree<T, E>, roots: &[TreeKey]) -> PostOrderKeys<'a, T, E> {
+        PostOrderKeys {
+            tree,
+            stack: roots.iter().rev().map(|&key| (key, false)).collect(),
+        }
+    }
+}
+
+impl<T, E> Iterator for PostOrderKeys<'_, T, E> {
+    type Item = TreeKey;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, expanded) = self.stack.pop()?;
+            if expanded {
+                return Some(key);
+            }
+            self.stack.push((key, true));
+            let children = self.tree.child_keys(key).unwrap_or_default();
+            self.stack.extend(children.iter().rev().map(|&child| (child, false)));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Conversions between [`Tree`] and nested JSON, where each node is an object of the form
+//! `{"value": ..., "children": [...]}`
+
+use alloc::vec::Vec;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::tree::simple::Tree;
+
+impl<T: Serialize, E> Tree<T, E> {
+    /// Convert this tree into a JSON array with an object for each root, in order. Each object
+    /// holds the node's value under `"value"`, and an array of its children under `"children"`.
+    pub fn to_json_value(&self) -> Result<Value, serde_json::Error> {
+        // Nodes to convert, and whether their children have been converted already
+        let mut stack = self.raw_roots().iter().rev().map(|&root| (root, false)).collect::<Vec<_>>();
+        // Converted nodes, in pre-order of the nodes whose parents haven't been converted yet
+        let mut done = Vec::new();
+
+        while let Some((key, visited)) = stack.pop() {
+            let node = self.get(key).ok_or_else(|| serde_json::Error::custom("Missing node"))?;
+            if !visited {
+                stack.push((key, true));
+                stack.extend(node.children().iter().rev().map(|&child| (child, false)));
+                continue;
+            }
+            let children = done.split_off(done.len() - node.children().len());
+            let mut obj = Map::new();
+            obj.insert("value".into(), serde_json::to_value(&*node)?);
+            obj.insert("children".into(), Value::Array(children));
+            done.push(Value::Object(obj));
+        }
+        Ok(Value::Array(done))
+    }
+}
+
+impl<T: DeserializeOwned> Tree<T> {
+    /// Build a tree from nested JSON, either a single node object or an array of them to become
+    /// the roots. Each object must have a `"value"`, and may have an array of `"children"`.
+    pub fn from_json_value(value: Value) -> Result<Tree<T>, serde_json::Error> {
+        let roots = match value {
+            Value::Array(roots) => roots,
+            obj @ Value::Object(_) => alloc::vec![obj],
+            _ => return Err(serde_json::Error::custom("Expected a node object or an array of them")),
+        };
+        let mut tree = Tree::new();
+        let mut stack = roots.into_iter().rev().map(|node| (None, node)).collect::<Vec<_>>();
+
+        while let Some((parent, node)) = stack.pop() {
+            let Value::Object(mut obj) = node else {
+                return Err(serde_json::Error::custom("Expected a node object"));
+            };
+            let value = obj.remove("value").ok_or_else(|| serde_json::Error::missing_field("value"))?;
+            let value = serde_json::from_value(value)?;
+            let key = match parent {
+                Some(parent) => tree.add_child(value, parent)
+                    .ok_or_else(|| serde_json::Error::custom("Missing parent node"))?,
+                None => tree.add_root(value),
+            };
+            match obj.remove("children") {
+                Some(Value::Array(children)) => stack.extend(children.into_iter().rev().map(|child| (Some(key), child))),
+                None | Some(Value::Null) => (),
+                Some(_) => return Err(serde_json::Error::custom("Expected an array of children")),
+            }
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let tree = [(0, None), (1, Some(0)), (2, Some(1)), (3, Some(0)), (4, None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+        let value = tree.to_json_value().unwrap();
+        assert_eq!(value, json!([
+            {"value": 0, "children": [
+                {"value": 1, "children": [{"value": 2, "children": []}]},
+                {"value": 3, "children": []},
+            ]},
+            {"value": 4, "children": []},
+        ]));
+
+        let back = Tree::<i32>::from_json_value(value).unwrap();
+        assert_eq!(back.to_parent_list(), tree.to_parent_list());
+    }
+
+    #[test]
+    fn test_json_deep() {
+        let tree = (0..100_000_usize)
+            .map(|idx| (idx, idx.checked_sub(1)))
+            .collect::<Tree<_>>();
+        // Importing consumes the value without recursing, where dropping it whole wouldn't
+        let back = Tree::<usize>::from_json_value(tree.to_json_value().unwrap()).unwrap();
+        assert_eq!(back.len(), 100_000);
+        assert_eq!(back.to_parent_list(), tree.to_parent_list());
+    }
+
+    #[test]
+    fn test_json_import() {
+        let tree = Tree::<String>::from_json_value(json!({
+            "value": "root",
+            "children": [{"value": "leaf"}, {"value": "null", "children": null}],
+        })).unwrap();
+        assert_eq!(tree.render_ascii(|out, val| write!(out, "{}", val)), "root\n|-- leaf\n`-- null\n");
+
+        assert!(Tree::<i32>::from_json_value(json!(1)).is_err());
+        assert!(Tree::<i32>::from_json_value(json!([{"children": []}])).is_err());
+        assert!(Tree::<i32>::from_json_value(json!({"value": "a"})).is_err());
+        assert!(Tree::<i32>::from_json_value(json!({"value": 1, "children": {}})).is_err());
+        assert!(Tree::<i32>::from_json_value(json!([])).unwrap().is_empty());
+    }
+}
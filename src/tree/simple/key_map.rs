@@ -0,0 +1,111 @@
+use slotmap::SecondaryMap;
+use crate::tree::simple::{NodeHandle, TreeKey};
+
+/// A mapping from the old keys of a tree's nodes to their new keys, produced when the tree
+/// relocates its nodes, such as by [`Tree::compact`](crate::tree::simple::Tree::compact)
+#[derive(Debug, Clone, Default)]
+pub struct KeyMap {
+    keys: SecondaryMap<TreeKey, TreeKey>,
+    /// The compaction epoch of the new keys
+    epoch: u64,
+}
+
+impl KeyMap {
+    pub(crate) fn with_capacity(capacity: usize, epoch: u64) -> KeyMap {
+        KeyMap { keys: SecondaryMap::with_capacity(capacity), epoch }
+    }
+
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub(crate) fn insert(&mut self, old: TreeKey, new: TreeKey) {
+        self.keys.insert(old, new);
+    }
+
+    /// Replace an old key with its new key, returning `false` if it didn't refer to a node
+    pub(crate) fn remap_key(&self, key: &mut TreeKey) -> bool {
+        match self.get(*key) {
+            Some(new) => {
+                *key = new;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the new key for a node, or `None` if the old key didn't refer to a node
+    pub fn get(&self, old: TreeKey) -> Option<TreeKey> {
+        self.keys.get(old).copied()
+    }
+
+    /// Get the number of nodes which were relocated
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check whether no nodes were relocated
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Iterate over the old and new key of every node, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (TreeKey, TreeKey)> + '_ {
+        self.keys.iter().map(|(old, &new)| (old, new))
+    }
+
+    /// Move the values of a map keyed by old keys to their new keys. Values of keys which didn't
+    /// refer to a node are dropped.
+    pub fn remap<V>(&self, map: SecondaryMap<TreeKey, V>) -> SecondaryMap<TreeKey, V> {
+        map.into_iter()
+            .filter_map(|(old, val)| Some((self.get(old)?, val)))
+            .collect()
+    }
+
+    /// Move the values of a map keyed by old keys to their new keys, in place
+    pub(crate) fn remap_in_place<V>(&self, map: &mut SecondaryMap<TreeKey, V>) {
+        *map = self.remap(core::mem::take(map));
+    }
+}
+
+/// Notified whenever a [`Tree`](crate::tree::simple::Tree) it's registered with relocates its
+/// nodes, so data keyed by the tree's keys can follow them. Registered with
+/// [`Tree::add_remap_listener`](crate::tree::simple::Tree::add_remap_listener).
+///
+/// Closures taking a [`KeyMap`] are listeners, so data held outside the tree can be updated
+/// through a shared handle such as an `Arc<Mutex<_>>`.
+pub trait KeyRemapListener: Send + Sync {
+    /// Called after nodes are relocated, with the new key of every node
+    fn on_remap(&mut self, keys: &KeyMap);
+}
+
+impl<F: FnMut(&KeyMap) + Send + Sync> KeyRemapListener for F {
+    fn on_remap(&mut self, keys: &KeyMap) {
+        self(keys);
+    }
+}
+
+impl<V: Send + Sync> KeyRemapListener for SecondaryMap<TreeKey, V> {
+    fn on_remap(&mut self, keys: &KeyMap) {
+        keys.remap_in_place(self);
+    }
+}
+
+impl KeyRemapListener for NodeHandle {
+    fn on_remap(&mut self, keys: &KeyMap) {
+        self.remap(keys);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<L: KeyRemapListener> KeyRemapListener for std::sync::Arc<std::sync::Mutex<L>> {
+    fn on_remap(&mut self, keys: &KeyMap) {
+        let mut listener = self.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        listener.on_remap(keys);
+    }
+}
+
+/// Identifies a listener registered with a [`Tree`](crate::tree::simple::Tree) by
+/// [`add_remap_listener`](crate::tree::simple::Tree::add_remap_listener)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RemapListenerId(pub(super) usize);
@@ -0,0 +1,249 @@
+use core::num::NonZeroU32;
+use alloc::vec::Vec;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// Key for a node in an [`LcrsTree`]. Only four bytes, and the same size when wrapped in an
+/// `Option`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LcrsKey(NonZeroU32);
+
+impl LcrsKey {
+    fn new(idx: usize) -> Option<LcrsKey> {
+        u32::try_from(idx + 1).ok().and_then(NonZeroU32::new).map(LcrsKey)
+    }
+
+    fn idx(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LcrsNode<T> {
+    val: T,
+    child: Option<LcrsKey>,
+    sibling: Option<LcrsKey>,
+}
+
+/// A tree where each node stores only its value and two keys, one for its first child and one
+/// for its next sibling, for targets where memory is tight. Nodes can be added but not removed.
+///
+/// Finding the last child of a node takes time linear in its number of children, so adding
+/// children is slower than in a [`Tree`]. Traversals don't recurse, and use memory proportional
+/// to the depth of the tree at most.
+#[derive(Debug, Clone)]
+pub struct LcrsTree<T> {
+    nodes: Vec<LcrsNode<T>>,
+    root: Option<LcrsKey>,
+}
+
+impl<T> LcrsTree<T> {
+    /// Create a new, empty tree
+    pub const fn new() -> LcrsTree<T> {
+        LcrsTree { nodes: Vec::new(), root: None }
+    }
+
+    pub(super) fn from_tree<E>(tree: Tree<T, E>) -> Option<LcrsTree<T>> {
+        LcrsKey::new(tree.len())?;
+        let mut out = LcrsTree { nodes: Vec::with_capacity(tree.len()), root: None };
+        let mut keys = SecondaryMap::<TreeKey, LcrsKey>::new();
+        // The last child added to each node, or the last root
+        let mut last_child = SecondaryMap::<TreeKey, LcrsKey>::new();
+        let mut last_root = None;
+
+        tree.drain_pre_order(|parent, key, val| {
+            let Some(new) = LcrsKey::new(out.nodes.len()) else {
+                return;
+            };
+            out.nodes.push(LcrsNode { val, child: None, sibling: None });
+            keys.insert(key, new);
+
+            let prev = match parent {
+                Some(parent) => last_child.insert(parent, new),
+                None => last_root.replace(new),
+            };
+            match (prev, parent.and_then(|parent| keys.get(parent))) {
+                (Some(prev), _) => out.nodes[prev.idx()].sibling = Some(new),
+                (None, Some(parent)) => out.nodes[parent.idx()].child = Some(new),
+                (None, None) => out.root = Some(new),
+            }
+        });
+        Some(out)
+    }
+
+    /// Get the number of nodes in this tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this tree has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Get the value of a node
+    pub fn get(&self, key: LcrsKey) -> Option<&T> {
+        self.nodes.get(key.idx()).map(|node| &node.val)
+    }
+
+    /// Get the value of a node mutably
+    pub fn get_mut(&mut self, key: LcrsKey) -> Option<&mut T> {
+        self.nodes.get_mut(key.idx()).map(|node| &mut node.val)
+    }
+
+    /// Get the key of the first root
+    pub fn first_root(&self) -> Option<LcrsKey> {
+        self.root
+    }
+
+    /// Get the key of a node's first child
+    pub fn first_child(&self, key: LcrsKey) -> Option<LcrsKey> {
+        self.nodes.get(key.idx())?.child
+    }
+
+    /// Get the key of the sibling after a node
+    pub fn next_sibling(&self, key: LcrsKey) -> Option<LcrsKey> {
+        self.nodes.get(key.idx())?.sibling
+    }
+
+    fn siblings(&self, first: Option<LcrsKey>) -> impl Iterator<Item = LcrsKey> + '_ {
+        core::iter::successors(first, |&key| self.next_sibling(key))
+    }
+
+    /// Iterate over the keys of the roots of this tree, in order
+    pub fn roots(&self) -> impl Iterator<Item = LcrsKey> + '_ {
+        self.siblings(self.root)
+    }
+
+    /// Iterate over the keys of a node's children, in order
+    pub fn children(&self, key: LcrsKey) -> impl Iterator<Item = LcrsKey> + '_ {
+        self.siblings(self.first_child(key))
+    }
+
+    /// Add a node after the last of the siblings starting at `first`
+    fn push_sibling(&mut self, first: Option<LcrsKey>, val: T) -> Option<(LcrsKey, Option<LcrsKey>)> {
+        let new = LcrsKey::new(self.nodes.len())?;
+        let last = self.siblings(first).last();
+        self.nodes.push(LcrsNode { val, child: None, sibling: None });
+        if let Some(last) = last {
+            self.nodes[last.idx()].sibling = Some(new);
+        }
+        Some((new, last))
+    }
+
+    /// Add a new root after the existing ones. Returns `None` if the tree is full.
+    pub fn add_root(&mut self, val: T) -> Option<LcrsKey> {
+        let (new, last) = self.push_sibling(self.root, val)?;
+        if last.is_none() {
+            self.root = Some(new);
+        }
+        Some(new)
+    }
+
+    /// Add a new child after the existing children of a node. Returns `None` if the parent
+    /// doesn't exist or the tree is full.
+    pub fn add_child(&mut self, parent: LcrsKey, val: T) -> Option<LcrsKey> {
+        let first = self.nodes.get(parent.idx())?.child;
+        let (new, last) = self.push_sibling(first, val)?;
+        if last.is_none() {
+            self.nodes[parent.idx()].child = Some(new);
+        }
+        Some(new)
+    }
+
+    /// Iterate over every node in pre-order, along with its depth below the roots
+    pub fn pre_order(&self) -> impl Iterator<Item = (LcrsKey, usize)> + '_ {
+        // Siblings still to visit once the current subtree is done
+        let mut pending = Vec::new();
+        let mut cur = self.root.map(|root| (root, 0));
+        core::iter::from_fn(move || {
+            let (key, depth) = cur?;
+            let node = self.nodes.get(key.idx())?;
+            cur = match (node.child, node.sibling) {
+                (Some(child), sibling) => {
+                    pending.extend(sibling.map(|sibling| (sibling, depth)));
+                    Some((child, depth + 1))
+                }
+                (None, Some(sibling)) => Some((sibling, depth)),
+                (None, None) => pending.pop(),
+            };
+            Some((key, depth))
+        })
+    }
+
+    /// Turn this tree back into a [`Tree`], preserving its structure and order
+    pub fn into_tree(self) -> Tree<T> {
+        // Pairs of parent position in `order` and node index, with the current ancestors in `path`
+        let mut order = Vec::with_capacity(self.len());
+        let mut path = Vec::new();
+        for (key, depth) in self.pre_order() {
+            path.truncate(depth);
+            order.push((path.last().copied(), key.idx()));
+            path.push(order.len() - 1);
+        }
+
+        let mut vals = self.nodes.into_iter().map(|node| Some(node.val)).collect::<Vec<_>>();
+        order.into_iter()
+            .filter_map(|(parent, idx)| Some((vals.get_mut(idx)?.take()?, parent)))
+            .collect()
+    }
+}
+
+impl<T> Default for LcrsTree<T> {
+    fn default() -> Self {
+        LcrsTree::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcrs() {
+        assert_eq!(core::mem::size_of::<Option<LcrsKey>>(), 4);
+        assert_eq!(core::mem::size_of::<LcrsNode<u32>>(), 12);
+
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, a).unwrap();
+        tree.add_child(4, root).unwrap();
+        tree.add_child(5, root).unwrap();
+        tree.add_root(6);
+        let tree = tree.into_lcrs().unwrap();
+        assert_eq!(tree.len(), 7);
+
+        // Siblings are reached through next-sibling links, in insertion order
+        let roots = tree.roots().collect::<Vec<_>>();
+        assert_eq!(roots.iter().map(|&key| tree.get(key)).collect::<Vec<_>>(), [Some(&0), Some(&6)]);
+        let children = tree.children(roots[0]).filter_map(|key| tree.get(key)).collect::<Vec<_>>();
+        assert_eq!(children, [&1, &4, &5]);
+        assert_eq!(tree.children(roots[1]).count(), 0);
+        let order = tree.pre_order()
+            .map(|(key, depth)| (*tree.get(key).unwrap(), depth))
+            .collect::<Vec<_>>();
+        assert_eq!(order, [(0, 0), (1, 1), (2, 2), (3, 2), (4, 1), (5, 1), (6, 0)]);
+    }
+
+    #[test]
+    fn test_lcrs_build() {
+        let mut tree = LcrsTree::new();
+        assert_eq!(tree.pre_order().count(), 0);
+        let root = tree.add_root(0).unwrap();
+        let a = tree.add_child(root, 1).unwrap();
+        tree.add_child(a, 2).unwrap();
+        tree.add_child(root, 3).unwrap();
+        let other = tree.add_root(4).unwrap();
+        tree.add_child(other, 5).unwrap();
+        *tree.get_mut(a).unwrap() = 1;
+
+        let thawed = tree.into_tree();
+        assert_eq!(
+            thawed.render_ascii(|out, val| write!(out, "{}", val)),
+            "0\n|-- 1\n|   `-- 2\n`-- 3\n4\n`-- 5\n",
+        );
+        assert_eq!(thawed.into_lcrs().unwrap().into_tree().len(), 6);
+    }
+}
@@ -0,0 +1,46 @@
+/// Build a [`Tree`](crate::tree::simple::Tree) by declaring its structure literally.
+///
+/// Each node is a value, optionally followed by `=> [...]` containing its children. Top-level
+/// nodes become roots. Prefixing a node with `name @` records its key under `"name"` in the
+/// returned map, so the macro evaluates to `(Tree<T>, BTreeMap<&'static str, TreeKey>)`.
+#[macro_export]
+macro_rules! tree {
+    (@nodes $tree:ident, $keys:ident, $parent:expr;) => {};
+    (@nodes $tree:ident, $keys:ident, $parent:expr; $name:ident @ $val:expr => [$($children:tt)*] $(, $($rest:tt)*)?) => {
+        {
+            let key = $crate::tree!(@add $tree, $parent, $val);
+            $keys.insert(stringify!($name), key);
+            $crate::tree!(@nodes $tree, $keys, Some(key); $($children)*);
+        }
+        $crate::tree!(@nodes $tree, $keys, $parent; $($($rest)*)?);
+    };
+    (@nodes $tree:ident, $keys:ident, $parent:expr; $name:ident @ $val:expr $(, $($rest:tt)*)?) => {
+        $keys.insert(stringify!($name), $crate::tree!(@add $tree, $parent, $val));
+        $crate::tree!(@nodes $tree, $keys, $parent; $($($rest)*)?);
+    };
+    (@nodes $tree:ident, $keys:ident, $parent:expr; $val:expr => [$($children:tt)*] $(, $($rest:tt)*)?) => {
+        {
+            let key = $crate::tree!(@add $tree, $parent, $val);
+            $crate::tree!(@nodes $tree, $keys, Some(key); $($children)*);
+        }
+        $crate::tree!(@nodes $tree, $keys, $parent; $($($rest)*)?);
+    };
+    (@nodes $tree:ident, $keys:ident, $parent:expr; $val:expr $(, $($rest:tt)*)?) => {
+        $crate::tree!(@add $tree, $parent, $val);
+        $crate::tree!(@nodes $tree, $keys, $parent; $($($rest)*)?);
+    };
+    (@add $tree:ident, $parent:expr, $val:expr) => {
+        match $parent {
+            Some(parent) => $tree.add_child($val, parent).expect("Parent was just added"),
+            None => $tree.add_root($val),
+        }
+    };
+    ($($body:tt)*) => {{
+        let mut tree = $crate::tree::simple::Tree::new();
+        #[allow(unused_mut)]
+        let mut keys: $crate::__private::BTreeMap<&'static str, $crate::tree::simple::TreeKey> =
+            $crate::__private::BTreeMap::new();
+        $crate::tree!(@nodes tree, keys, None; $($body)*);
+        (tree, keys)
+    }};
+}
@@ -0,0 +1,302 @@
+//! Reading and writing [`Tree`]s in the Newick format used for phylogenetic trees, with branch
+//! lengths stored as `f64` edge data
+
+use core::fmt::{self, Write};
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// Characters which end an unquoted label
+const DELIMITERS: &[char] = &['(', ')', '[', ']', '\'', ':', ';', ','];
+
+/// Reasons text can't be read as Newick
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NewickError {
+    /// An unexpected character was found at a byte offset
+    Unexpected(usize, char),
+    /// The text ended inside a tree, quoted label or comment
+    UnexpectedEnd,
+    /// The branch length at a byte offset isn't a number
+    BadLength(usize),
+}
+
+impl fmt::Display for NewickError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewickError::Unexpected(pos, c) => write!(f, "Unexpected {:?} at offset {}", c, pos),
+            NewickError::UnexpectedEnd => write!(f, "Unexpected end of Newick text"),
+            NewickError::BadLength(pos) => write!(f, "Invalid branch length at offset {}", pos),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NewickError {}
+
+/// A node which has been read, but not added to the tree yet
+struct Pending {
+    parent: Option<usize>,
+    label: String,
+    length: Option<f64>,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) {
+        self.pos += self.peek().map_or(0, char::len_utf8);
+    }
+
+    /// Skip whitespace and bracketed comments
+    fn skip(&mut self) -> Result<(), NewickError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => self.bump(),
+                Some('[') => {
+                    let end = self.text[self.pos..].find(']').ok_or(NewickError::UnexpectedEnd)?;
+                    self.pos += end + 1;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn unquoted(&mut self) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace() && !DELIMITERS.contains(&c)) {
+            self.bump();
+        }
+        &self.text[start..self.pos]
+    }
+
+    /// Read an optional label and branch length
+    fn label(&mut self, node: &mut Pending) -> Result<(), NewickError> {
+        self.skip()?;
+        if self.peek() == Some('\'') {
+            self.bump();
+            loop {
+                match self.peek() {
+                    Some('\'') => {
+                        self.bump();
+                        if self.peek() != Some('\'') {
+                            break;
+                        }
+                        node.label.push('\'');
+                        self.bump();
+                    }
+                    Some(c) => {
+                        node.label.push(c);
+                        self.bump();
+                    }
+                    None => return Err(NewickError::UnexpectedEnd),
+                }
+            }
+        } else {
+            let label = self.unquoted();
+            node.label.push_str(label);
+        }
+
+        self.skip()?;
+        if self.peek() == Some(':') {
+            self.bump();
+            self.skip()?;
+            let start = self.pos;
+            let length = self.unquoted().parse().map_err(|_| NewickError::BadLength(start))?;
+            node.length = Some(length);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Tree<T, f64> {
+    /// Read one or more Newick trees, each ending in `;`, as the roots of a new tree. `f` turns
+    /// each node's label into its value, and is given an empty string for unlabelled nodes.
+    /// Branch lengths become edge data, except on roots, where they are ignored.
+    ///
+    /// Quoted labels and bracketed comments are supported. Underscores in unquoted labels are
+    /// left as they are, rather than turned into spaces.
+    pub fn from_newick(text: &str, mut f: impl FnMut(&str) -> T) -> Result<Tree<T, f64>, NewickError> {
+        let mut parser = Parser { text, pos: 0 };
+        let mut nodes = Vec::<Pending>::new();
+        // Internal nodes whose children are still being read
+        let mut open = Vec::<usize>::new();
+        // Whether a subtree should come next, rather than a separator
+        let mut expect_node = true;
+        let mut in_tree = false;
+
+        loop {
+            parser.skip()?;
+            let next = parser.peek();
+            if expect_node {
+                if next.is_none() && !in_tree {
+                    break;
+                }
+                in_tree = true;
+                nodes.push(Pending { parent: open.last().copied(), label: String::new(), length: None });
+                if next == Some('(') {
+                    parser.bump();
+                    open.push(nodes.len() - 1);
+                    continue;
+                }
+                let last = nodes.len() - 1;
+                parser.label(&mut nodes[last])?;
+                expect_node = false;
+                continue;
+            }
+
+            match next {
+                Some(',') if !open.is_empty() => {
+                    parser.bump();
+                    expect_node = true;
+                }
+                Some(')') => {
+                    let Some(idx) = open.pop() else {
+                        return Err(NewickError::Unexpected(parser.pos, ')'));
+                    };
+                    parser.bump();
+                    parser.label(&mut nodes[idx])?;
+                }
+                Some(';') if open.is_empty() => {
+                    parser.bump();
+                    expect_node = true;
+                    in_tree = false;
+                }
+                Some(c) => return Err(NewickError::Unexpected(parser.pos, c)),
+                None => return Err(NewickError::UnexpectedEnd),
+            }
+        }
+
+        let mut tree = Tree::with_edges();
+        let mut keys = Vec::<TreeKey>::with_capacity(nodes.len());
+        for node in nodes {
+            let val = f(&node.label);
+            let key = match (node.parent.and_then(|parent| keys.get(parent)), node.length) {
+                (Some(&parent), Some(length)) => tree.add_child_with_edge(val, parent, length),
+                (Some(&parent), None) => tree.add_child(val, parent),
+                (None, _) => Some(tree.add_root(val)),
+            };
+            keys.extend(key);
+        }
+        Ok(tree)
+    }
+
+    /// Write this tree in the Newick format, with each root as a separate tree on its own line.
+    /// `f` writes the label for each node's value, which is quoted if it contains whitespace or
+    /// characters with special meaning. Edge data is written as branch lengths.
+    pub fn to_newick(&self, mut f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> Result<String, fmt::Error> {
+        enum Step {
+            Enter(TreeKey),
+            Comma,
+            Exit(TreeKey),
+        }
+
+        let mut out = String::new();
+        let mut label = String::new();
+        let mut write_label = |out: &mut String, key: TreeKey| -> fmt::Result {
+            let Some(node) = self.get(key) else {
+                return Ok(());
+            };
+            label.clear();
+            f(&mut label, &*node)?;
+            if label.contains(|c: char| c.is_whitespace() || DELIMITERS.contains(&c)) {
+                write!(out, "'{}'", label.replace('\'', "''"))?;
+            } else {
+                out.push_str(&label);
+            }
+            match node.parent().and_then(|parent| self.edge(parent, key)) {
+                Some(length) => write!(out, ":{}", length),
+                None => Ok(()),
+            }
+        };
+
+        for &root in self.raw_roots() {
+            let mut stack = alloc::vec![Step::Enter(root)];
+            while let Some(step) = stack.pop() {
+                match step {
+                    Step::Enter(key) => match self.child_keys(key) {
+                        Some(children) if !children.is_empty() => {
+                            out.push('(');
+                            stack.push(Step::Exit(key));
+                            for (idx, &child) in children.iter().enumerate().rev() {
+                                stack.push(Step::Enter(child));
+                                if idx > 0 {
+                                    stack.push(Step::Comma);
+                                }
+                            }
+                        }
+                        _ => write_label(&mut out, key)?,
+                    },
+                    Step::Comma => out.push(','),
+                    Step::Exit(key) => {
+                        out.push(')');
+                        write_label(&mut out, key)?;
+                    }
+                }
+            }
+            out.push_str(";\n");
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Tree<String, f64>, NewickError> {
+        Tree::from_newick(text, |label| String::from(label))
+    }
+
+    fn write(tree: &Tree<String, f64>) -> String {
+        tree.to_newick(|out, val| out.write_str(val)).unwrap()
+    }
+
+    #[test]
+    fn test_newick_read() {
+        let tree = read("((A:0.1,B:0.2)AB:0.5, 'C d':1e-3, [comment] )root:4;\n(E);").unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{:?}", val)),
+            "\"root\"\n|-- \"AB\"\n|   |-- \"A\"\n|   `-- \"B\"\n|-- \"C d\"\n`-- \"\"\n\"\"\n`-- \"E\"\n",
+        );
+
+        let root = tree.root_keys().next().unwrap();
+        let ab = tree.child_keys(root).unwrap()[0];
+        let a = tree.child_keys(ab).unwrap()[0];
+        assert_eq!(tree.edge(ab, a), Some(&0.1));
+        assert_eq!(tree.edge(root, ab), Some(&0.5));
+        let c = tree.child_keys(root).unwrap()[1];
+        assert_eq!(tree.edge(root, c), Some(&0.001));
+        let empty = tree.child_keys(root).unwrap()[2];
+        assert_eq!(tree.edge(root, empty), None);
+
+        let counted = Tree::from_newick("(a,b,(c));", |label| label.len()).unwrap();
+        assert_eq!(counted.len(), 5);
+        assert!(read("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_newick_write() {
+        let text = "((A:0.1,B:0.25)AB:0.5,'C d':3,'it''s')root;\nE;\n";
+        let tree = read(text).unwrap();
+        assert_eq!(write(&tree), text);
+        assert_eq!(write(&read(&write(&tree)).unwrap()), text);
+    }
+
+    #[test]
+    fn test_newick_errors() {
+        assert_eq!(read("(A,B)").err(), Some(NewickError::UnexpectedEnd));
+        assert_eq!(read("(A,B;").err(), Some(NewickError::Unexpected(4, ';')));
+        assert_eq!(read("A,B;").err(), Some(NewickError::Unexpected(1, ',')));
+        assert_eq!(read("A);").err(), Some(NewickError::Unexpected(1, ')')));
+        assert_eq!(read("(A:x);").err(), Some(NewickError::BadLength(3)));
+        assert_eq!(read("('A);").err(), Some(NewickError::UnexpectedEnd));
+        assert_eq!(read("(A[);").err(), Some(NewickError::UnexpectedEnd));
+    }
+}
@@ -2,9 +2,11 @@ use core::ops::{Deref, DerefMut};
 use alloc::vec::Vec;
 use crate::tree::simple::TreeKey;
 
+/// A node in a [`Tree`](crate::tree::simple::Tree), containing its value and relationships
 pub struct Node<T: ?Sized> {
     parent: Option<TreeKey>,
     children: Vec<TreeKey>,
+    subtree_len: usize,
     val: T,
 }
 
@@ -13,22 +15,42 @@ impl<T: ?Sized> Node<T> {
         &mut self.children
     }
 
+    pub(crate) fn children_capacity(&self) -> usize {
+        self.children.capacity()
+    }
+
     pub(crate) fn set_parent(&mut self, parent: Option<TreeKey>) {
         self.parent = parent;
     }
 
+    pub(crate) fn parts_mut(&mut self) -> (&mut T, &[TreeKey]) {
+        (&mut self.val, &self.children)
+    }
+
+    pub(crate) fn subtree_len(&self) -> usize {
+        self.subtree_len
+    }
+
+    pub(crate) fn set_subtree_len(&mut self, len: usize) {
+        self.subtree_len = len;
+    }
+
+    /// Get the key of this node's parent, if it has one
     pub fn parent(&self) -> Option<TreeKey> {
         self.parent
     }
 
+    /// Get the keys of this node's children
     pub fn children(&self) -> &[TreeKey] {
         &self.children
     }
 
+    /// Get a reference to the value of this node
     pub fn val(&self) -> &T {
         &self.val
     }
 
+    /// Get a mutable reference to the value of this node
     pub fn val_mut(&mut self) -> &mut T {
         &mut self.val
     }
@@ -39,9 +61,14 @@ impl<T> Node<T> {
         Node {
             parent,
             children: Vec::new(),
+            subtree_len: 1,
             val,
         }
     }
+
+    pub(crate) fn into_val(self) -> T {
+        self.val
+    }
 }
 
 impl<T: ?Sized> Deref for Node<T> {
@@ -2,21 +2,29 @@ use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::borrow::{Borrow, BorrowMut};
-use crate::tree::simple::{Node, Tree, TreeKey};
+use crate::tree::simple::{Descendants, Node, Tree, TreeKey};
+use crate::util;
 
 macro_rules! impl_common {
-    ($ty:ident) => {
-        impl<T> $ty<'_, T> {
+    ([$($gen:ident),*] $ty:ty) => {
+        impl<$($gen),*> $ty {
+            /// Get the key of this node's parent, if it has one
             pub fn parent(&self) -> Option<TreeKey> {
                 self.node().parent()
             }
 
+            /// Get the keys of this node's children
             pub fn children(&self) -> &[TreeKey] {
                 self.node().children()
             }
+
+            /// Get the keys of this node's children, in the order they were attached
+            pub fn child_keys(&self) -> &[TreeKey] {
+                self.node().children()
+            }
         }
 
-        impl<T> Deref for $ty<'_, T> {
+        impl<$($gen),*> Deref for $ty {
             type Target = T;
 
             fn deref(&self) -> &Self::Target {
@@ -24,13 +32,13 @@ macro_rules! impl_common {
             }
         }
 
-        impl<T> AsRef<T> for $ty<'_, T> {
+        impl<$($gen),*> AsRef<T> for $ty {
             fn as_ref(&self) -> &T {
                 self
             }
         }
 
-        impl<T> Borrow<T> for $ty<'_, T> {
+        impl<$($gen),*> Borrow<T> for $ty {
             fn borrow(&self) -> &T {
                 self
             }
@@ -39,20 +47,20 @@ macro_rules! impl_common {
 }
 
 macro_rules! impl_mut {
-    ($ty:ident) => {
-        impl<T> DerefMut for $ty<'_, T> {
+    ([$($gen:ident),*] $ty:ty) => {
+        impl<$($gen),*> DerefMut for $ty {
             fn deref_mut(&mut self) -> &mut Self::Target {
                 self.node_mut().val_mut()
             }
         }
 
-        impl<T> AsMut<T> for $ty<'_, T> {
+        impl<$($gen),*> AsMut<T> for $ty {
             fn as_mut(&mut self) -> &mut T {
                 self
             }
         }
 
-        impl<T> BorrowMut<T> for $ty<'_, T> {
+        impl<$($gen),*> BorrowMut<T> for $ty {
             fn borrow_mut(&mut self) -> &mut T {
                 self
             }
@@ -62,24 +70,33 @@ macro_rules! impl_mut {
 
 macro_rules! impl_traverse {
     ($ty:ident) => {
-        impl<T> $ty<'_, T> {
-            pub fn traverse_parent(&self) -> Option<NodeRef<'_, T>> {
+        impl<T, E> $ty<'_, T, E> {
+            /// Get a reference to the parent of this node
+            pub fn traverse_parent(&self) -> Option<NodeRef<'_, T, E>> {
                 let parent_key = self.parent()?;
-                self.tree().try_get(parent_key)
+                self.tree().get(parent_key)
             }
 
-            pub fn traverse_child(&self, child: TreeKey) -> Option<NodeRef<'_, T>> {
+            /// Get a reference to a child of this node, returning `None` if the key isn't a child
+            pub fn traverse_child(&self, child: TreeKey) -> Option<NodeRef<'_, T, E>> {
                 if !self.children().contains(&child) {
                     return None;
                 }
 
-                self.tree().try_get(child)
+                self.tree().get(child)
             }
 
-            pub fn traverse_children(&self) -> impl Iterator<Item = NodeRef<'_, T>> + '_ {
+            /// Iterate over references to the children of this node
+            pub fn traverse_children(&self) -> impl Iterator<Item = NodeRef<'_, T, E>> + '_ {
                 self.children()
                     .iter()
-                    .map(|&key| self.tree().try_get(key).unwrap())
+                    .map(|&key| self.tree().get(key).unwrap())
+            }
+
+            /// Lazily iterate over the descendants of this node in pre-order, not including this
+            /// node itself
+            pub fn descendants(&self) -> Descendants<'_, T, E> {
+                Descendants::new(self.tree(), self.children())
             }
         }
     }
@@ -87,50 +104,61 @@ macro_rules! impl_traverse {
 
 macro_rules! impl_traverse_mut {
     ($ty:ident) => {
-        impl<T> $ty<'_, T> {
-            pub fn traverse_parent_mut(&mut self) -> Option<NodeMut<'_, T>> {
+        impl<T, E> $ty<'_, T, E> {
+            /// Transfer mutable access to the parent of this node
+            pub fn traverse_parent_mut(&mut self) -> Option<NodeMut<'_, T, E>> {
                 let parent_key = self.parent()?;
                 self.node = None;
-                self.tree_mut().try_get_mut(parent_key)
+                self.tree_mut().get_mut(parent_key)
             }
 
-            pub fn traverse_child_mut(&mut self, child: TreeKey) -> Option<NodeMut<'_, T>> {
+            /// Transfer mutable access to a child of this node, returning `None` if the key isn't
+            /// a child
+            pub fn traverse_child_mut(&mut self, child: TreeKey) -> Option<NodeMut<'_, T, E>> {
                 if !self.children().contains(&child) {
                     return None;
                 }
                 self.node = None;
-                self.tree_mut().try_get_mut(child)
-            }
-
-            // pub fn traverse_children_mut(
-            //     &mut self
-            // ) -> impl Iterator<Item = NodeMutLimited<'_, T>> + '_ {
-            //     self.children()
-            //         .to_owned()
-            //         .into_iter()
-            //         .map(move |key| {
-            //             let node = self.tree_mut().try_get_mut(key).unwrap().downgrade();
-            //             core::mem::transmute::<NodeMutLimited<'_, T>, NodeMutLimited<'_, T>>(node)
-            //         })
-            // }
+                self.tree_mut().get_mut(child)
+            }
+
+            /// Iterate over mutable references to the children of this node
+            pub fn traverse_children_mut(&mut self) -> impl Iterator<Item = NodeMutLimited<'_, T>> + '_ {
+                let children = self.children().to_vec();
+                self.node = None;
+                let tree = self.tree_mut();
+                for &child in &children {
+                    tree.invalidate_hashes(Some(child));
+                }
+                let nodes = tree.raw_nodes_mut();
+                children
+                    .into_iter()
+                    .filter_map(move |key| {
+                        let node = nodes.get_mut(key)?;
+                        // SAFETY: The children of a node are unique, so each is only yielded once
+                        let node = unsafe { &mut *(node as *mut Node<T>) };
+                        Some(NodeMutLimited::new(node))
+                    })
+            }
         }
     }
 }
 
-pub struct NodeRef<'a, T> {
-    tree: &'a Tree<T>,
+/// An immutable reference to a node in a [`Tree`], which can traverse to related nodes
+pub struct NodeRef<'a, T, E = ()> {
+    tree: &'a Tree<T, E>,
     node: &'a Node<T>,
 }
 
-impl<'a, T> NodeRef<'a, T> {
-    pub(crate) fn new(tree: &'a Tree<T>, node: &'a Node<T>) -> NodeRef<'a, T> {
+impl<'a, T, E> NodeRef<'a, T, E> {
+    pub(crate) fn new(tree: &'a Tree<T, E>, node: &'a Node<T>) -> NodeRef<'a, T, E> {
         NodeRef {
             tree,
             node,
         }
     }
 
-    fn tree(&self) -> &Tree<T> {
+    fn tree(&self) -> &Tree<T, E> {
         self.tree
     }
 
@@ -139,18 +167,21 @@ impl<'a, T> NodeRef<'a, T> {
     }
 }
 
-impl_common!(NodeRef);
+impl_common!([T, E] NodeRef<'_, T, E>);
+util::forward_value_traits!(impl<T, E> for NodeRef<'_, T, E>);
 impl_traverse!(NodeRef);
 
-pub struct NodeMut<'a, T> {
-    tree: NonNull<Tree<T>>,
+/// A mutable reference to a node in a [`Tree`], which can transfer mutable access to related
+/// nodes
+pub struct NodeMut<'a, T, E = ()> {
+    tree: NonNull<Tree<T, E>>,
     node: Option<NonNull<Node<T>>>,
     key: TreeKey,
     _phantom: PhantomData<&'a mut T>,
 }
 
-impl<'a, T> NodeMut<'a, T> {
-    pub(crate) fn new(tree: NonNull<Tree<T>>, node: NonNull<Node<T>>, key: TreeKey) -> NodeMut<'a, T> {
+impl<'a, T, E> NodeMut<'a, T, E> {
+    pub(crate) fn new(tree: NonNull<Tree<T, E>>, node: NonNull<Node<T>>, key: TreeKey) -> NodeMut<'a, T, E> {
         NodeMut {
             tree,
             node: Some(node),
@@ -166,11 +197,11 @@ impl<'a, T> NodeMut<'a, T> {
         NodeMutLimited::new(r)
     }
 
-    fn tree(&self) -> &Tree<T> {
+    fn tree(&self) -> &Tree<T, E> {
         unsafe { self.tree.as_ref() }
     }
 
-    fn tree_mut(&mut self) -> &mut Tree<T> {
+    fn tree_mut(&mut self) -> &mut Tree<T, E> {
         unsafe { self.tree.as_mut() }
     }
 
@@ -202,11 +233,13 @@ impl<'a, T> NodeMut<'a, T> {
     }
 }
 
-impl_common!(NodeMut);
-impl_mut!(NodeMut);
+impl_common!([T, E] NodeMut<'_, T, E>);
+util::forward_value_traits!(impl<T, E> for NodeMut<'_, T, E>);
+impl_mut!([T, E] NodeMut<'_, T, E>);
 impl_traverse!(NodeMut);
 impl_traverse_mut!(NodeMut);
 
+/// A mutable reference to a node in a [`Tree`], which can't traverse to related nodes
 pub struct NodeMutLimited<'a, T> {
     node: &'a mut Node<T>,
 }
@@ -225,5 +258,6 @@ impl<'a, T> NodeMutLimited<'a, T> {
     }
 }
 
-impl_common!(NodeMutLimited);
-impl_mut!(NodeMutLimited);
+impl_common!([T] NodeMutLimited<'_, T>);
+util::forward_value_traits!(impl<T> for NodeMutLimited<'_, T>);
+impl_mut!([T] NodeMutLimited<'_, T>);
@@ -0,0 +1,138 @@
+use core::any::Any;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use slotmap::{SecondaryMap, SlotMap};
+use crate::tree::simple::{KeyMap, Node, TreeKey};
+use crate::util;
+
+/// A secondary map with its value type erased, so a tree can drop entries for removed nodes
+pub(super) trait ErasedSecondary: Send + Sync {
+    fn remove(&mut self, key: TreeKey);
+    fn clear(&mut self);
+    fn remap(&mut self, keys: &KeyMap);
+    /// Approximate heap usage of the map's storage, including the allocation holding the map
+    fn heap_size(&self) -> usize;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<V: Send + Sync + 'static> ErasedSecondary for SecondaryMap<TreeKey, V> {
+    fn remove(&mut self, key: TreeKey) {
+        SecondaryMap::remove(self, key);
+    }
+
+    fn clear(&mut self) {
+        SecondaryMap::clear(self);
+    }
+
+    fn remap(&mut self, keys: &KeyMap) {
+        keys.remap_in_place(self);
+    }
+
+    fn heap_size(&self) -> usize {
+        mem::size_of::<Self>() + util::secondary_heap_size(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Source of tree identities, starting from one so zero can mean unassigned
+static NEXT_TREE: AtomicUsize = AtomicUsize::new(1);
+
+/// Get a new identity for a tree, distinct from every other tree's
+pub(super) fn next_tree_id() -> usize {
+    NEXT_TREE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Identifies a secondary map registered with a [`Tree`](crate::tree::simple::Tree) by
+/// [`add_secondary`](crate::tree::simple::Tree::add_secondary). IDs remember the tree that made
+/// them, and don't refer to any map in other trees.
+pub struct SecondaryId<V> {
+    pub(super) tree: usize,
+    pub(super) idx: usize,
+    _phantom: PhantomData<fn() -> V>,
+}
+
+impl<V> SecondaryId<V> {
+    pub(super) fn new(tree: usize, idx: usize) -> SecondaryId<V> {
+        SecondaryId { tree, idx, _phantom: PhantomData }
+    }
+}
+
+impl<V> Copy for SecondaryId<V> {}
+
+impl<V> Clone for SecondaryId<V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<V> fmt::Debug for SecondaryId<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondaryId")
+            .field("tree", &self.tree)
+            .field("idx", &self.idx)
+            .finish()
+    }
+}
+
+/// Mutable access to a secondary map registered with a tree. Values can only be inserted for
+/// nodes currently in the tree, and are dropped when their node is removed.
+pub struct TreeSecondaryMap<'a, T, V> {
+    nodes: &'a SlotMap<TreeKey, Node<T>>,
+    map: &'a mut SecondaryMap<TreeKey, V>,
+}
+
+impl<'a, T, V> TreeSecondaryMap<'a, T, V> {
+    pub(super) fn new(nodes: &'a SlotMap<TreeKey, Node<T>>, map: &'a mut SecondaryMap<TreeKey, V>) -> TreeSecondaryMap<'a, T, V> {
+        TreeSecondaryMap { nodes, map }
+    }
+
+    /// Set the value for a node, returning the previous value. Returns `None` without storing
+    /// anything if the node isn't in the tree.
+    pub fn insert(&mut self, key: TreeKey, val: V) -> Option<V> {
+        if self.nodes.contains_key(key) {
+            self.map.insert(key, val)
+        } else {
+            None
+        }
+    }
+
+    /// Remove the value for a node, returning it if there was one
+    pub fn remove(&mut self, key: TreeKey) -> Option<V> {
+        self.map.remove(key)
+    }
+
+    /// Get a mutable reference to the value for a node
+    pub fn get_mut(&mut self, key: TreeKey) -> Option<&mut V> {
+        self.map.get_mut(key)
+    }
+
+    /// Remove every value
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl<T, V> Deref for TreeSecondaryMap<'_, T, V> {
+    type Target = SecondaryMap<TreeKey, V>;
+
+    fn deref(&self) -> &Self::Target {
+        self.map
+    }
+}
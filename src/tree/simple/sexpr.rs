@@ -0,0 +1,203 @@
+//! Reading and writing [`Tree`]s as S-expressions, where a list's first atom is a node's value
+//! and the rest of the list its children
+
+use core::fmt::{self, Write};
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// Reasons text can't be read as S-expressions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SexprError {
+    /// An unexpected character was found at a byte offset
+    Unexpected(usize, char),
+    /// The text ended inside a list or quoted atom
+    UnexpectedEnd,
+    /// The list at a byte offset has no atom for its value
+    EmptyList(usize),
+    /// The atom parser rejected the atom at a byte offset
+    BadAtom(usize),
+}
+
+impl fmt::Display for SexprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SexprError::Unexpected(pos, c) => write!(f, "Unexpected {:?} at offset {}", c, pos),
+            SexprError::UnexpectedEnd => write!(f, "Unexpected end of S-expression"),
+            SexprError::EmptyList(pos) => write!(f, "List at offset {} has no value", pos),
+            SexprError::BadAtom(pos) => write!(f, "Invalid atom at offset {}", pos),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SexprError {}
+
+fn is_plain(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | ';')
+}
+
+/// Read the atom starting at `pos`, returning its text and the offset after it
+fn read_atom(text: &str, pos: usize) -> Result<(String, usize), SexprError> {
+    let rest = &text[pos..];
+    if !rest.starts_with('"') {
+        let len = rest.find(|c| !is_plain(c)).unwrap_or(rest.len());
+        return Ok((String::from(&rest[..len]), pos + len));
+    }
+
+    let mut atom = String::new();
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((atom, pos + idx + 1)),
+            '\\' => atom.push(chars.next().ok_or(SexprError::UnexpectedEnd)?.1),
+            _ => atom.push(c),
+        }
+    }
+    Err(SexprError::UnexpectedEnd)
+}
+
+impl<T> Tree<T> {
+    /// Read S-expressions as the roots of a new tree. A bare atom becomes a leaf, and a list
+    /// becomes a node whose value is the list's first atom, with the rest of the list as its
+    /// children. `f` parses each atom into a value, returning `None` to reject it.
+    ///
+    /// Atoms are separated by whitespace and parentheses, unless quoted with `"`, in which case
+    /// `\` escapes the next character. `;` starts a comment running to the end of the line.
+    pub fn from_sexpr(text: &str, mut f: impl FnMut(&str) -> Option<T>) -> Result<Tree<T>, SexprError> {
+        let mut tree = Tree::new();
+        // Open lists, with the offset of each and its node once its first atom has been read
+        let mut open = Vec::<(usize, Option<TreeKey>)>::new();
+        let mut pos = 0;
+
+        while let Some(c) = text[pos..].chars().next() {
+            match c {
+                ';' => pos = text[pos..].find('\n').map_or(text.len(), |end| pos + end),
+                c if c.is_whitespace() => pos += c.len_utf8(),
+                '(' => {
+                    if let Some((_, None)) = open.last() {
+                        return Err(SexprError::Unexpected(pos, '('));
+                    }
+                    open.push((pos, None));
+                    pos += 1;
+                }
+                ')' => match open.pop() {
+                    Some((_, Some(_))) => pos += 1,
+                    Some((start, None)) => return Err(SexprError::EmptyList(start)),
+                    None => return Err(SexprError::Unexpected(pos, ')')),
+                },
+                _ => {
+                    let (atom, end) = read_atom(text, pos)?;
+                    let val = f(&atom).ok_or(SexprError::BadAtom(pos))?;
+                    let parent = open.iter().rev().find_map(|&(_, key)| key);
+                    let key = match parent {
+                        Some(parent) => tree.add_child(val, parent).ok_or(SexprError::BadAtom(pos))?,
+                        None => tree.add_root(val),
+                    };
+                    if let Some((_, head @ None)) = open.last_mut() {
+                        *head = Some(key);
+                    }
+                    pos = end;
+                }
+            }
+        }
+
+        if !open.is_empty() {
+            return Err(SexprError::UnexpectedEnd);
+        }
+        Ok(tree)
+    }
+
+    /// Write this tree as S-expressions, one root per line. Leaves are written as bare atoms,
+    /// and nodes with children as lists starting with their value. `f` writes the atom for each
+    /// node's value, which is quoted if it's empty or contains whitespace or special characters.
+    pub fn to_sexpr(&self, mut f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+        let mut atom = String::new();
+        for &root in self.raw_roots() {
+            // Nodes to write, or `None` to close a list
+            let mut stack = alloc::vec![Some(root)];
+            let mut first = true;
+            while let Some(step) = stack.pop() {
+                let Some(key) = step else {
+                    out.push(')');
+                    continue;
+                };
+                let Some(node) = self.get(key) else {
+                    continue;
+                };
+                if !first {
+                    out.push(' ');
+                }
+                first = false;
+                if !node.children().is_empty() {
+                    out.push('(');
+                    stack.push(None);
+                    stack.extend(node.children().iter().rev().map(|&child| Some(child)));
+                }
+
+                atom.clear();
+                f(&mut atom, &*node)?;
+                if !atom.is_empty() && atom.chars().all(is_plain) {
+                    out.push_str(&atom);
+                } else {
+                    out.push('"');
+                    for c in atom.chars() {
+                        if matches!(c, '"' | '\\') {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                    out.push('"');
+                }
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Tree<String>, SexprError> {
+        Tree::from_sexpr(text, |atom| Some(String::from(atom)))
+    }
+
+    fn write(tree: &Tree<String>) -> String {
+        tree.to_sexpr(|out, val| out.write_str(val)).unwrap()
+    }
+
+    #[test]
+    fn test_sexpr_read() {
+        let tree = read("(root a (b c d) \"e f\") ; comment\nother").unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| out.write_str(val)),
+            "root\n|-- a\n|-- b\n|   |-- c\n|   `-- d\n`-- e f\nother\n",
+        );
+
+        let nums = Tree::from_sexpr("(1 2 (3 4))", |atom| atom.parse::<i32>().ok()).unwrap();
+        assert_eq!(nums.to_parent_list(), [(1, None), (2, Some(0)), (3, Some(0)), (4, Some(2))]);
+        assert!(read("  ; nothing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sexpr_write() {
+        let text = "(root a (b c d) \"e f\" \"q\\\"\" \"\")\nother\n";
+        let tree = read(text).unwrap();
+        assert_eq!(write(&tree), text);
+        assert_eq!(write(&read(&write(&tree)).unwrap()), text);
+    }
+
+    #[test]
+    fn test_sexpr_errors() {
+        assert_eq!(read("(a (b)").err(), Some(SexprError::UnexpectedEnd));
+        assert_eq!(read("a)").err(), Some(SexprError::Unexpected(1, ')')));
+        assert_eq!(read("(a ())").err(), Some(SexprError::EmptyList(3)));
+        assert_eq!(read("((a) b)").err(), Some(SexprError::Unexpected(1, '(')));
+        assert_eq!(read("(a \"b)").err(), Some(SexprError::UnexpectedEnd));
+        let nums = Tree::from_sexpr("(1 x)", |atom| atom.parse::<i32>().ok());
+        assert_eq!(nums.err(), Some(SexprError::BadAtom(3)));
+    }
+}
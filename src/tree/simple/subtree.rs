@@ -0,0 +1,63 @@
+use slotmap::SecondaryMap;
+use crate::tree::simple::TreeKey;
+
+/// A mutable view of one subtree of a [`Tree`](crate::tree::simple::Tree), disjoint from every
+/// other view created alongside it. Created by
+/// [`Tree::split_at`](crate::tree::simple::Tree::split_at).
+///
+/// Values can be changed, but the shape of the subtree can't.
+#[derive(Debug)]
+pub struct SubtreeMut<'a, T> {
+    root: TreeKey,
+    nodes: SecondaryMap<TreeKey, (&'a mut T, &'a [TreeKey])>,
+}
+
+impl<'a, T> SubtreeMut<'a, T> {
+    pub(super) fn new(root: TreeKey) -> SubtreeMut<'a, T> {
+        SubtreeMut { root, nodes: SecondaryMap::new() }
+    }
+
+    pub(super) fn insert(&mut self, key: TreeKey, val: &'a mut T, children: &'a [TreeKey]) {
+        self.nodes.insert(key, (val, children));
+    }
+
+    /// Get the key of the root of this subtree
+    pub fn root(&self) -> TreeKey {
+        self.root
+    }
+
+    /// Get the number of nodes in this subtree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check whether this subtree has no nodes, which is never the case
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Check whether a node is part of this subtree
+    pub fn contains(&self, key: TreeKey) -> bool {
+        self.nodes.contains_key(key)
+    }
+
+    /// Get the value of a node in this subtree
+    pub fn get(&self, key: TreeKey) -> Option<&T> {
+        self.nodes.get(key).map(|(val, _)| &**val)
+    }
+
+    /// Get the value of a node in this subtree mutably
+    pub fn get_mut(&mut self, key: TreeKey) -> Option<&mut T> {
+        self.nodes.get_mut(key).map(|(val, _)| &mut **val)
+    }
+
+    /// Get the child keys of a node in this subtree, in order
+    pub fn child_keys(&self, key: TreeKey) -> Option<&'a [TreeKey]> {
+        self.nodes.get(key).map(|&(_, children)| children)
+    }
+
+    /// Iterate over every node in this subtree mutably, in no particular order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (TreeKey, &mut T)> + use<'_, 'a, T> {
+        self.nodes.iter_mut().map(|(key, (val, _))| (key, &mut **val))
+    }
+}
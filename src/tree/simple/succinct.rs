@@ -0,0 +1,269 @@
+use core::ops::Range;
+use alloc::vec::Vec;
+use crate::tree::simple::Tree;
+
+/// A bitvector with rank and select support
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+    /// Number of ones before each word
+    ranks: Vec<usize>,
+}
+
+impl BitVec {
+    fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(64) {
+            let ones = self.ranks.last().copied().unwrap_or(0)
+                + self.words.last().map_or(0, |word| word.count_ones() as usize);
+            self.words.push(0);
+            self.ranks.push(ones);
+        }
+        if bit {
+            if let Some(word) = self.words.last_mut() {
+                *word |= 1 << (self.len % 64);
+            }
+        }
+        self.len += 1;
+    }
+
+    fn get(&self, idx: usize) -> bool {
+        idx < self.len && self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    /// Count the ones before a position
+    fn rank1(&self, idx: usize) -> usize {
+        let idx = idx.min(self.len);
+        let (word, bit) = (idx / 64, idx % 64);
+        match self.words.get(word) {
+            Some(&bits) => self.ranks[word] + (bits & ((1 << bit) - 1)).count_ones() as usize,
+            None => self.ranks.last().copied().unwrap_or(0) + self.words.last().map_or(0, |w| w.count_ones() as usize),
+        }
+    }
+
+    /// Count the zeros before a position
+    fn rank0(&self, idx: usize) -> usize {
+        idx.min(self.len) - self.rank1(idx)
+    }
+
+    /// Find the position of a bit, given the number of matching bits before each word and the
+    /// word's bits with matching bits set
+    fn select(&self, nth: usize, before: impl Fn(usize) -> usize, bits: impl Fn(u64) -> u64) -> Option<usize> {
+        let (mut low, mut high) = (0, self.words.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if before(mid) <= nth {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let word = low.checked_sub(1)?;
+        let mut bits = bits(self.words[word]);
+        for _ in 0..nth - before(word) {
+            bits &= bits.wrapping_sub(1);
+        }
+        let pos = word * 64 + bits.trailing_zeros() as usize;
+        (bits != 0 && pos < self.len).then_some(pos)
+    }
+
+    /// Find the position of the `nth` one, counting from zero
+    fn select1(&self, nth: usize) -> Option<usize> {
+        self.select(nth, |word| self.ranks[word], |bits| bits)
+    }
+
+    /// Find the position of the `nth` zero, counting from zero
+    fn select0(&self, nth: usize) -> Option<usize> {
+        self.select(nth, |word| word * 64 - self.ranks[word], |bits| !bits)
+    }
+}
+
+/// An immutable snapshot of a [`Tree`], storing its shape in about two bits per node using a
+/// level-order unary degree sequence (LOUDS). Nodes are numbered in breadth-first order, so the
+/// roots of the tree, and the children of any node, have consecutive indices.
+///
+/// Created by [`Tree::freeze_succinct`], and turned back into a mutable tree with
+/// [`thaw`](FrozenSuccinctTree::thaw).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenSuccinctTree<T> {
+    vals: Vec<T>,
+    /// For a virtual root above the roots and then each node, a one per child followed by a zero
+    bits: BitVec,
+}
+
+impl<T> FrozenSuccinctTree<T> {
+    pub(super) fn from_tree<E>(mut tree: Tree<T, E>) -> FrozenSuccinctTree<T> {
+        let mut bits = BitVec::default();
+        let mut keys = Vec::with_capacity(tree.len());
+        for _ in tree.raw_roots() {
+            bits.push(true);
+        }
+        bits.push(false);
+        for level in tree.levels() {
+            for key in level {
+                let children = tree.child_keys(key).map_or(0, <[_]>::len);
+                for _ in 0..children {
+                    bits.push(true);
+                }
+                bits.push(false);
+                keys.push(key);
+            }
+        }
+
+        let nodes = tree.raw_nodes_mut();
+        let vals = keys.into_iter()
+            .filter_map(|key| nodes.remove(key))
+            .map(|node| node.into_val())
+            .collect();
+        FrozenSuccinctTree { vals, bits }
+    }
+
+    /// Turn this snapshot back into a mutable tree, preserving its structure and order
+    pub fn thaw(self) -> Tree<T> {
+        let parents = (0..self.len()).map(|idx| self.parent(idx)).collect::<Vec<_>>();
+        self.vals.into_iter()
+            .zip(parents)
+            .collect()
+    }
+
+    /// Get the number of nodes in this tree
+    pub fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    /// Check whether this tree has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.vals.is_empty()
+    }
+
+    /// Get the number of bits used to store the shape of this tree
+    pub fn shape_bits(&self) -> usize {
+        self.bits.len
+    }
+
+    /// Get the value of the node at an index
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.vals.get(idx)
+    }
+
+    /// Get the values of every node, in breadth-first order
+    pub fn values(&self) -> &[T] {
+        &self.vals
+    }
+
+    /// Get the indices of the nodes whose degree is stored in a run of bits. Run zero is the
+    /// virtual root, and run `n + 1` is node `n`.
+    fn run(&self, run: usize) -> Option<Range<usize>> {
+        let start = match run {
+            0 => 0,
+            _ => self.bits.select0(run - 1)? + 1,
+        };
+        let end = self.bits.select0(run)?;
+        let first = self.bits.rank1(start);
+        Some(first..first + (end - start))
+    }
+
+    /// Get the indices of the roots of this tree
+    pub fn roots(&self) -> Range<usize> {
+        self.run(0).unwrap_or(0..0)
+    }
+
+    /// Get the indices of a node's children
+    pub fn children(&self, idx: usize) -> Option<Range<usize>> {
+        if idx >= self.len() {
+            return None;
+        }
+        self.run(idx + 1)
+    }
+
+    /// Get the index of a node's parent, if it has one
+    pub fn parent(&self, idx: usize) -> Option<usize> {
+        let pos = self.bits.select1(idx)?;
+        self.bits.rank0(pos).checked_sub(1)
+    }
+
+    /// Get the number of nodes in the subtree rooted at a node, including the node itself. Takes
+    /// time proportional to the height of the subtree, as each level of it is a range of indices.
+    pub fn subtree_len(&self, idx: usize) -> Option<usize> {
+        self.children(idx)?;
+        let mut level = idx..idx + 1;
+        let mut len = 0;
+        while !level.is_empty() {
+            len += level.len();
+            let start = self.run(level.start + 1).map_or(0, |run| run.start);
+            let end = self.run(level.end).map_or(start, |run| run.end);
+            level = start..end;
+        }
+        Some(len)
+    }
+
+    /// Check whether a node is a leaf
+    pub fn is_leaf(&self, idx: usize) -> bool {
+        self.children(idx).is_some_and(|children| children.is_empty())
+    }
+
+    /// Get the index of the sibling after a node
+    pub fn next_sibling(&self, idx: usize) -> Option<usize> {
+        // Siblings are consecutive ones, so a following one is the next sibling
+        let pos = self.bits.select1(idx)?;
+        self.bits.get(pos + 1).then_some(idx + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_succinct() {
+        // Node 6 is added last, but is stored next to its sibling in level order
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, root).unwrap();
+        let other = tree.add_root(4);
+        tree.add_child(5, other).unwrap();
+        tree.add_child(6, a).unwrap();
+
+        let tree = tree.freeze_succinct();
+        assert_eq!(tree.len(), 7);
+        assert_eq!(tree.shape_bits(), 15);
+        assert_eq!(tree.values(), [0, 4, 1, 3, 5, 2, 6]);
+        assert_eq!(tree.roots(), 0..2);
+        assert_eq!(tree.children(0), Some(2..4));
+        assert_eq!(tree.children(1), Some(4..5));
+        assert_eq!(tree.children(2), Some(5..7));
+        assert_eq!(tree.children(6), Some(7..7));
+        assert_eq!(tree.children(7), None);
+        assert_eq!((0..7).map(|idx| tree.parent(idx)).collect::<Vec<_>>(), [None, None, Some(0), Some(0), Some(1), Some(2), Some(2)]);
+        assert_eq!(tree.subtree_len(0), Some(5));
+        assert_eq!(tree.subtree_len(1), Some(2));
+        assert_eq!(tree.subtree_len(3), Some(1));
+        assert_eq!(tree.subtree_len(7), None);
+        assert_eq!(tree.next_sibling(0), Some(1));
+        assert_eq!(tree.next_sibling(1), None);
+        assert_eq!(tree.next_sibling(5), Some(6));
+        assert!(tree.is_leaf(3) && !tree.is_leaf(2));
+    }
+
+    #[test]
+    fn test_succinct_large() {
+        // A complete ternary tree, spanning many words of bits
+        let mut rows = alloc::vec![(0, None)];
+        rows.extend((1..3000).map(|idx| (idx, Some((idx - 1) / 3))));
+        let tree = rows.into_iter().collect::<Tree<_>>().freeze_succinct();
+        assert_eq!(tree.values(), (0..3000).collect::<Vec<_>>());
+        for idx in [1, 63, 64, 65, 1000, 2999] {
+            assert_eq!(tree.parent(idx), Some((idx - 1) / 3));
+        }
+        assert_eq!(tree.children(700), Some(2101..2104));
+        assert_eq!(tree.subtree_len(0), Some(3000));
+        assert_eq!(tree.subtree_len(1), Some(1 + 3 + 9 + 27 + 81 + 243 + 729));
+
+        let thawed = tree.thaw();
+        assert_eq!(thawed.len(), 3000);
+        assert_eq!(thawed.freeze_succinct().values().len(), 3000);
+        assert!(Tree::<()>::new().freeze_succinct().thaw().is_empty());
+    }
+}
@@ -1,8 +1,25 @@
 
-use slotmap::{new_key_type, SlotMap};
+use slotmap::{new_key_type, KeyData, SecondaryMap, SlotMap};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
 use alloc::vec::Vec;
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::hash::{Hash, Hasher};
+use core::convert::Infallible;
+use core::mem;
 use core::ptr::NonNull;
-use crate::tree::simple::{Node, NodeMut, NodeMutLimited, NodeRef};
+use crate::tree::simple::edits::Edit;
+use crate::tree::simple::secondary::{self, ErasedSecondary};
+use crate::tree::simple::{Dag, Edits, FrozenSuccinctTree, FrozenTree, KeyMap, KeyRemapListener, LcrsTree, Node, NodeHandle, SecondaryId, TreeSecondaryMap, NodeMut, NodeMutLimited, NodeRef, PostOrderKeys, RemapListenerId, SubtreeMut, Zipper};
+use crate::tree::{render, validate, ChildSelect, Error, InvariantViolation, OrphanPolicy, Rebalance, TreeStats};
+use crate::util;
+
+/// The node itself is dirty
+const DIRTY: u8 = 0b01;
+/// Some descendant of the node is dirty
+const DIRTY_BELOW: u8 = 0b10;
 
 new_key_type! {
     /// Key for a node in a tree. Altering the tree will not invalidate the key, as long
@@ -10,15 +27,55 @@ new_key_type! {
     pub struct TreeKey;
 }
 
+impl TreeKey {
+    /// Convert this key into an opaque integer, which can cross an FFI boundary or be stored
+    /// externally
+    #[must_use]
+    pub fn to_ffi(self) -> u64 {
+        self.0.as_ffi()
+    }
+
+    /// Recreate a key from an integer produced by [`TreeKey::to_ffi`]. Invalid values produce a
+    /// key that doesn't refer to any node.
+    #[must_use]
+    pub fn from_ffi(val: u64) -> TreeKey {
+        TreeKey(KeyData::from_ffi(val))
+    }
+}
+
 /// A simple one-to-many tree implementation, relatively performant and with no particular special
 /// characteristics. Multiple root nodes are supported.
 ///
 /// Traversing the tree, mutable access can be transferred from one node to parent or child nodes
 /// if the node was derived from an operation that prevents multiple mutable nodes being created
 /// at once.
-pub struct Tree<T> {
+pub struct Tree<T, E = ()> {
     nodes: SlotMap<TreeKey, Node<T>>,
     roots: Vec<TreeKey>,
+    track_subtree_len: bool,
+    /// Identity of this tree, checked against secondary map IDs. Zero until the first map is
+    /// registered.
+    identity: usize,
+    /// Maps registered with [`add_secondary`](Tree::add_secondary), or `None` once removed
+    secondaries: Vec<Option<Box<dyn ErasedSecondary>>>,
+    /// Listeners registered with [`add_remap_listener`](Tree::add_remap_listener), or `None` once
+    /// removed
+    listeners: Vec<Option<Box<dyn KeyRemapListener>>>,
+    /// Dirty flags for nodes, holding only nodes with a flag set
+    dirty: SecondaryMap<TreeKey, u8>,
+    /// Data for the edge between each child node and its parent
+    edges: SecondaryMap<TreeKey, E>,
+    /// Names of named children
+    names: SecondaryMap<TreeKey, Box<str>>,
+    /// Named children of each node, by name
+    named: SecondaryMap<TreeKey, BTreeMap<Box<str>, TreeKey>>,
+    /// Cached subtree hashes. A node only has a cached hash if all its descendants do.
+    hashes: SecondaryMap<TreeKey, u64>,
+    /// Sequence number of each node, counting every node ever added
+    order: SecondaryMap<TreeKey, u64>,
+    next_order: u64,
+    /// Number of times this tree has been compacted, invalidating every key
+    epoch: u64,
 }
 
 impl<T> Tree<T> {
@@ -27,14 +84,282 @@ impl<T> Tree<T> {
         Tree::default()
     }
 
+    /// Create a new tree which tracks the size of every subtree, making
+    /// [`subtree_len`](Tree::subtree_len) `O(1)` at the cost of updating every ancestor when nodes
+    /// are added, removed, or moved.
+    pub fn with_subtree_len() -> Tree<T> {
+        Tree {
+            track_subtree_len: true,
+            ..Tree::default()
+        }
+    }
+
+    /// Rebuild a tree from nodes with previously exported keys, provided as
+    /// `(key, parent, value)`. Children and roots are ordered as they appear.
+    ///
+    /// Fails with [`Error::InvalidKey`] if any key is malformed, repeated, or has a slot index or
+    /// version too large to restore quickly, [`Error::Missing`] if a parent isn't one of the
+    /// provided keys, or [`Error::Cycle`] if the parent links form a cycle.
+    pub fn from_parts(nodes: impl IntoIterator<Item = (TreeKey, Option<TreeKey>, T)>) -> Result<Tree<T>, Error> {
+        let mut links = Vec::new();
+        let nodes = nodes.into_iter()
+            .map(|(key, parent, val)| {
+                links.push((key, parent));
+                (key, Node::new(val, parent))
+            })
+            .collect();
+        let mut nodes = util::slotmap_from_keys(nodes, util::MAX_RESTORE_WORK).ok_or(Error::InvalidKey)?;
+
+        let mut parents = SecondaryMap::new();
+        for &(key, parent) in &links {
+            if let Some(parent) = parent {
+                if !nodes.contains_key(parent) {
+                    return Err(Error::Missing);
+                }
+                parents.insert(key, parent);
+            }
+        }
+        if util::has_cycle(&parents) {
+            return Err(Error::Cycle);
+        }
+
+        let mut tree = Tree::default();
+        for (key, parent) in links {
+            match parent {
+                Some(parent) => nodes[parent].children_mut().push(key),
+                None => tree.roots.push(key),
+            }
+            tree.record_insertion(key);
+        }
+        tree.nodes = nodes;
+        Ok(tree)
+    }
+
+    /// Build a tree from `(value, parent)` rows, where each parent is the index of another row.
+    /// Parents may come before or after their children. Children and roots are ordered as they
+    /// appear. Also returns the key of the node made from each row.
+    ///
+    /// Fails with [`Error::Missing`] if a parent index is out of range, or [`Error::Cycle`] if
+    /// the parent links form a cycle.
+    pub fn from_parent_list(items: impl IntoIterator<Item = (T, Option<usize>)>) -> Result<(Tree<T>, Vec<TreeKey>), Error> {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let mut minted = SlotMap::<TreeKey, ()>::with_capacity_and_key(items.len());
+        let keys = items.iter().map(|_| minted.insert(())).collect::<Vec<_>>();
+        let parts = items.into_iter()
+            .zip(&keys)
+            .map(|((val, parent), &key)| {
+                let parent = match parent {
+                    Some(idx) => Some(*keys.get(idx).ok_or(Error::Missing)?),
+                    None => None,
+                };
+                Ok((key, parent, val))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Tree::from_parts(parts).map(|tree| (tree, keys))
+    }
+}
+
+impl<K: Ord + Clone> Tree<K> {
+    /// Build a tree from `(parent, child)` edges between identifiers, with each distinct
+    /// identifier becoming a node holding it. Children are ordered as their edges appear, and
+    /// roots as they first appear.
+    ///
+    /// Fails with [`Error::MultipleParents`] if any identifier has more than one parent, or
+    /// [`Error::Cycle`] if the edges form a cycle.
+    pub fn from_edges(edges: impl IntoIterator<Item = (K, K)>) -> Result<Tree<K>, Error> {
+        let mut tree = Tree::new();
+        let mut keys = BTreeMap::new();
+        for (parent, child) in edges {
+            let parent = *keys.entry(parent.clone()).or_insert_with(|| tree.add_root(parent));
+            let child = *keys.entry(child.clone()).or_insert_with(|| tree.add_root(child));
+            if tree.parent_key_of(child).is_some() {
+                return Err(Error::MultipleParents);
+            }
+            let mut ancestor = Some(parent);
+            while let Some(key) = ancestor {
+                if key == child {
+                    return Err(Error::Cycle);
+                }
+                ancestor = tree.parent_key_of(key);
+            }
+            tree.set_child(parent, child).ok_or(Error::Missing)?;
+        }
+        Ok(tree)
+    }
+}
+
+impl<T, E> Tree<T, E> {
+    /// Create a new tree storing data of type `E` on the edges between parents and children
+    pub fn with_edges() -> Tree<T, E> {
+        Tree::default()
+    }
+
     pub(crate) fn raw_nodes(&self) -> &SlotMap<TreeKey, Node<T>> {
         &self.nodes
     }
 
+    pub(crate) fn raw_roots(&self) -> &[TreeKey] {
+        &self.roots
+    }
+
     pub(crate) fn raw_nodes_mut(&mut self) -> &mut SlotMap<TreeKey, Node<T>> {
         &mut self.nodes
     }
 
+    /// Consume this tree, handing each node's parent, key and value to `emit` in pre-order.
+    /// Parents are always emitted before their children, and siblings in order.
+    pub(crate) fn drain_pre_order(mut self, mut emit: impl FnMut(Option<TreeKey>, TreeKey, T)) {
+        let mut stack: Vec<(Option<TreeKey>, TreeKey)> = self.roots
+            .iter()
+            .rev()
+            .map(|&key| (None, key))
+            .collect();
+
+        while let Some((parent, key)) = stack.pop() {
+            let Some(node) = self.nodes.remove(key) else {
+                continue;
+            };
+            stack.extend(node.children().iter().rev().map(|&child| (Some(key), child)));
+            emit(parent, key, node.into_val());
+        }
+    }
+
+    /// Get the sequence number of a node, counting every node ever added to this tree. Nodes
+    /// added later always have a greater index, and moving a node doesn't change it.
+    pub fn insertion_index(&self, key: TreeKey) -> Option<u64> {
+        self.order.get(key).copied()
+    }
+
+    /// Get the child of a node which was most recently added to the tree
+    pub fn newest_child(&self, parent: TreeKey) -> Option<TreeKey> {
+        self.nodes.get(parent)?
+            .children()
+            .iter()
+            .copied()
+            .max_by_key(|&child| self.insertion_index(child))
+    }
+
+    /// Give a newly added node the next sequence number
+    fn record_insertion(&mut self, key: TreeKey) {
+        self.order.insert(key, self.next_order);
+        self.next_order += 1;
+    }
+
+    /// Drop the values of a removed node from every secondary map
+    fn forget(&mut self, key: TreeKey, parent: Option<TreeKey>) {
+        self.dirty.remove(key);
+        self.edges.remove(key);
+        self.unname(parent, key);
+        self.named.remove(key);
+        self.hashes.remove(key);
+        self.order.remove(key);
+        for map in self.secondaries.iter_mut().flatten() {
+            map.remove(key);
+        }
+    }
+
+    /// Register a new secondary map with this tree, associating values of type `V` with nodes.
+    /// Values are dropped automatically when their node is removed.
+    pub fn add_secondary<V: Send + Sync + 'static>(&mut self) -> SecondaryId<V> {
+        if self.identity == 0 {
+            self.identity = secondary::next_tree_id();
+        }
+        self.secondaries.push(Some(Box::new(SecondaryMap::<TreeKey, V>::new())));
+        SecondaryId::new(self.identity, self.secondaries.len() - 1)
+    }
+
+    /// Unregister a secondary map, returning its contents. Returns `None` if the map was already
+    /// removed, or the ID came from a different tree.
+    pub fn remove_secondary<V: Send + Sync + 'static>(&mut self, id: SecondaryId<V>) -> Option<SecondaryMap<TreeKey, V>> {
+        if id.tree != self.identity {
+            return None;
+        }
+        let slot = self.secondaries.get_mut(id.idx)?;
+        if !slot.as_ref()?.as_any().is::<SecondaryMap<TreeKey, V>>() {
+            return None;
+        }
+        let map = slot.take()?.into_any();
+        map.downcast().ok().map(|map| *map)
+    }
+
+    /// Get a secondary map registered with this tree. Returns `None` if the map was removed, or
+    /// the ID came from a different tree.
+    pub fn secondary<V: Send + Sync + 'static>(&self, id: SecondaryId<V>) -> Option<&SecondaryMap<TreeKey, V>> {
+        if id.tree != self.identity {
+            return None;
+        }
+        self.secondaries.get(id.idx)?
+            .as_ref()?
+            .as_any()
+            .downcast_ref()
+    }
+
+    /// Get mutable access to a secondary map registered with this tree. Returns `None` if the map
+    /// was removed, or the ID came from a different tree.
+    pub fn secondary_mut<V: Send + Sync + 'static>(&mut self, id: SecondaryId<V>) -> Option<TreeSecondaryMap<'_, T, V>> {
+        if id.tree != self.identity {
+            return None;
+        }
+        let map = self.secondaries.get_mut(id.idx)?
+            .as_mut()?
+            .as_any_mut()
+            .downcast_mut()?;
+        Some(TreeSecondaryMap::new(&self.nodes, map))
+    }
+
+    /// Register a listener to be notified whenever this tree relocates its nodes, such as by
+    /// [`compact`](Tree::compact)
+    pub fn add_remap_listener(&mut self, listener: impl KeyRemapListener + 'static) -> RemapListenerId {
+        self.listeners.push(Some(Box::new(listener)));
+        RemapListenerId(self.listeners.len() - 1)
+    }
+
+    /// Unregister a listener, returning it. Returns `None` if the listener was already removed.
+    pub fn remove_remap_listener(&mut self, id: RemapListenerId) -> Option<Box<dyn KeyRemapListener>> {
+        self.listeners.get_mut(id.0)?.take()
+    }
+
+    /// Add to the subtree length of a node and all its ancestors, if lengths are tracked
+    fn grow_ancestors(&mut self, mut cur: Option<TreeKey>, len: usize) {
+        if !self.track_subtree_len {
+            return;
+        }
+        while let Some(node) = cur.and_then(|key| self.nodes.get_mut(key)) {
+            node.set_subtree_len(node.subtree_len() + len);
+            cur = node.parent();
+        }
+    }
+
+    /// Subtract from the subtree length of a node and all its ancestors, if lengths are tracked
+    fn shrink_ancestors(&mut self, mut cur: Option<TreeKey>, len: usize) {
+        if !self.track_subtree_len {
+            return;
+        }
+        while let Some(node) = cur.and_then(|key| self.nodes.get_mut(key)) {
+            node.set_subtree_len(node.subtree_len() - len);
+            cur = node.parent();
+        }
+    }
+
+    /// Get the number of nodes in the subtree rooted at a node, including the node itself.
+    ///
+    /// This is `O(1)` for trees created with [`with_subtree_len`](Tree::with_subtree_len), and
+    /// walks the subtree otherwise.
+    pub fn subtree_len(&self, key: TreeKey) -> Option<usize> {
+        let node = self.nodes.get(key)?;
+        if self.track_subtree_len {
+            return Some(node.subtree_len());
+        }
+
+        let mut len = 0;
+        let mut stack = vec![key];
+        while let Some(key) = stack.pop() {
+            len += 1;
+            stack.extend_from_slice(self.nodes.get(key)?.children());
+        }
+        Some(len)
+    }
+
     /// Get the length of this tree, the total number of nodes
     pub fn len(&self) -> usize {
         self.nodes.len()
@@ -45,27 +370,203 @@ impl<T> Tree<T> {
         self.nodes.is_empty()
     }
 
+    /// Get the approximate number of bytes of heap memory used by this tree, including spare
+    /// capacity. This counts the storage for nodes, their children, edge data, names, cached
+    /// hashes, dirty flags, and [secondary maps](Tree::add_secondary). Heap memory owned by node
+    /// values, edge data, secondary values or [listeners](Tree::add_remap_listener), and the
+    /// name strings and name indexes themselves, isn't counted.
+    pub fn memory_footprint(&self) -> usize {
+        let children = self.nodes.values()
+            .map(|node| node.children_capacity() * mem::size_of::<TreeKey>())
+            .sum::<usize>();
+        let secondaries = self.secondaries.iter()
+            .flatten()
+            .map(|map| map.heap_size())
+            .sum::<usize>();
+        util::slotmap_heap_size(&self.nodes)
+            + self.roots.capacity() * mem::size_of::<TreeKey>()
+            + children
+            + self.secondaries.capacity() * mem::size_of::<Option<Box<dyn ErasedSecondary>>>()
+            + secondaries
+            + self.listeners.capacity() * mem::size_of::<Option<Box<dyn KeyRemapListener>>>()
+            + util::secondary_heap_size(&self.dirty)
+            + util::secondary_heap_size(&self.edges)
+            + util::secondary_heap_size(&self.names)
+            + util::secondary_heap_size(&self.named)
+            + util::secondary_heap_size(&self.hashes)
+            + util::secondary_heap_size(&self.order)
+    }
+
+    /// Remove all nodes from this tree. Keys to removed nodes remain invalid, and will not refer
+    /// to any nodes added later.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.roots.clear();
+        for map in self.secondaries.iter_mut().flatten() {
+            map.clear();
+        }
+        self.dirty.clear();
+        self.edges.clear();
+        self.names.clear();
+        self.named.clear();
+        self.hashes.clear();
+        self.order.clear();
+    }
+
+    /// Release excess capacity held for roots and children after nodes are removed.
+    ///
+    /// Slot storage for nodes is retained, as it tracks which keys have been used so that stale
+    /// keys can never refer to new nodes. Freed slots will be reused by later insertions, or released
+    /// by [`compact`](Tree::compact).
+    pub fn shrink_to_fit(&mut self) {
+        self.roots.shrink_to_fit();
+        for node in self.nodes.values_mut() {
+            node.children_mut().shrink_to_fit();
+        }
+    }
+
+    /// Move every node into a dense block of slots, in pre-order, releasing the slots of removed
+    /// nodes. Returns the new key of every node.
+    ///
+    /// Every key from before compaction is invalidated, including keys to nodes removed earlier
+    /// and keys exported with [`TreeKey::to_ffi`]. They may refer to different nodes afterwards,
+    /// so must be translated through the returned map. Secondary maps registered with
+    /// [`add_secondary`](Tree::add_secondary) and listeners registered with
+    /// [`add_remap_listener`](Tree::add_remap_listener) are updated automatically, while other
+    /// handles from [`weak_handle`](Tree::weak_handle) are invalid until
+    /// [remapped](NodeHandle::remap).
+    pub fn compact(&mut self) -> KeyMap {
+        let mut old = mem::take(&mut self.nodes);
+        let mut nodes = SlotMap::with_capacity_and_key(old.len());
+        self.epoch += 1;
+        let mut keys = KeyMap::with_capacity(old.len(), self.epoch);
+
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            let Some(node) = old.remove(key) else {
+                continue;
+            };
+            stack.extend(node.children().iter().rev());
+            keys.insert(key, nodes.insert(node));
+        }
+        // Nodes unreachable from a root break the tree's invariants, but are kept rather than lost
+        for (key, node) in old.drain() {
+            keys.insert(key, nodes.insert(node));
+        }
+
+        for node in nodes.values_mut() {
+            node.set_parent(node.parent().and_then(|parent| keys.get(parent)));
+            node.children_mut().retain_mut(|child| keys.remap_key(child));
+        }
+        self.nodes = nodes;
+        self.roots.retain_mut(|root| keys.remap_key(root));
+
+        for map in self.secondaries.iter_mut().flatten() {
+            map.remap(&keys);
+        }
+        keys.remap_in_place(&mut self.dirty);
+        keys.remap_in_place(&mut self.edges);
+        keys.remap_in_place(&mut self.names);
+        keys.remap_in_place(&mut self.named);
+        for named in self.named.values_mut() {
+            named.retain(|_, child| keys.remap_key(child));
+        }
+        keys.remap_in_place(&mut self.hashes);
+        keys.remap_in_place(&mut self.order);
+        for listener in self.listeners.iter_mut().flatten() {
+            listener.on_remap(&keys);
+        }
+        keys
+    }
+
     /// Add a new root node to this tree
     pub fn add_root(&mut self, val: T) -> TreeKey {
-        let new_root = self.nodes.insert(Node::new(val, None));
+        self.add_root_with(|_| val)
+    }
+
+    /// Add a new root node to this tree, initialized with the value returned by `f` given the
+    /// new key
+    pub fn add_root_with(&mut self, f: impl FnOnce(TreeKey) -> T) -> TreeKey {
+        match self.try_add_root_with(|key| Ok::<_, Infallible>(f(key))) {
+            Ok(key) => key,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Add a new root node to this tree, initialized with the value returned by `f` given the
+    /// new key. If `f` fails, nothing is inserted and its error is returned.
+    pub fn try_add_root_with<X>(&mut self, f: impl FnOnce(TreeKey) -> Result<T, X>) -> Result<TreeKey, X> {
+        let new_root = self.nodes.try_insert_with_key(|key| f(key).map(|val| Node::new(val, None)))?;
         self.roots.push(new_root);
-        new_root
+        self.record_insertion(new_root);
+        Ok(new_root)
     }
 
     /// Add a new child node to the referenced parent
     pub fn add_child(&mut self, val: T, parent: TreeKey) -> Option<TreeKey> {
+        self.add_child_with(parent, |_| val)
+    }
+
+    /// Add a new node directly after a sibling, under the same parent. Returns `None` if the
+    /// sibling doesn't exist or is a root.
+    pub(crate) fn add_sibling_after(&mut self, sibling: TreeKey, val: T) -> Option<TreeKey> {
+        let parent = self.parent_key_of(sibling)?;
+        let key = self.add_child(val, parent)?;
+        let children = self.nodes.get_mut(parent)?.children_mut();
+        let pos = children.iter().position(|&k| k == sibling)?;
+        children.pop();
+        children.insert(pos + 1, key);
+        Some(key)
+    }
+
+    /// Add a new child node to the referenced parent, initialized with the value returned by `f`
+    /// given the new key. Returns `None` without calling `f` if the parent doesn't exist.
+    pub fn add_child_with(&mut self, parent: TreeKey, f: impl FnOnce(TreeKey) -> T) -> Option<TreeKey> {
+        match self.try_add_child_with(parent, |key| Ok::<_, Infallible>(f(key)))? {
+            Ok(key) => Some(key),
+            Err(never) => match never {},
+        }
+    }
+
+    /// Add a new child node to the referenced parent, initialized with the value returned by `f`
+    /// given the new key. If `f` fails, nothing is inserted and its error is returned. Returns
+    /// `None` without calling `f` if the parent doesn't exist.
+    pub fn try_add_child_with<X>(
+        &mut self,
+        parent: TreeKey,
+        f: impl FnOnce(TreeKey) -> Result<T, X>,
+    ) -> Option<Result<TreeKey, X>> {
         if !self.nodes.contains_key(parent) {
             return None;
         }
-        let new_child = self.nodes.insert(Node::new(val, Some(parent)));
+        let new_child = match self.nodes.try_insert_with_key(|key| f(key).map(|val| Node::new(val, Some(parent)))) {
+            Ok(key) => key,
+            Err(err) => return Some(Err(err)),
+        };
         self.nodes.get_mut(parent)?.children_mut().push(new_child);
-        Some(new_child)
+        self.record_insertion(new_child);
+        self.grow_ancestors(Some(parent), 1);
+        self.invalidate_hashes(Some(parent));
+        Some(Ok(new_child))
     }
 
     /// Set the first node as the parent of the second node,
-    /// unsetting the current parent if there is one
+    /// unsetting the current parent if there is one. Returns `None` without changing anything if
+    /// either node doesn't exist, or the parent is the child or one of its descendants.
     pub fn set_child(&mut self, parent: TreeKey, child: TreeKey) -> Option<()> {
-        let old_parent = self.nodes.get(child)?.parent();
+        if !self.nodes.contains_key(parent) {
+            return None;
+        }
+        let mut cur = Some(parent);
+        while let Some(ancestor) = cur {
+            if ancestor == child {
+                return None;
+            }
+            cur = self.parent_key_of(ancestor);
+        }
+        let child_node = self.nodes.get(child)?;
+        let old_parent = child_node.parent();
+        let len = child_node.subtree_len();
 
         // Remove child's existing parent (remove it as a root, if it had no parent)
         match old_parent {
@@ -76,33 +577,146 @@ impl<T> Tree<T> {
             None => self.roots.retain(|&k| k != child),
         }
 
+        self.shrink_ancestors(old_parent, len);
+
+        self.edges.remove(child);
+        self.unname(old_parent, child);
         self.nodes.get_mut(child)?.set_parent(Some(parent));
         self.nodes.get_mut(parent)?.children_mut().push(child);
+        self.grow_ancestors(Some(parent), len);
+        self.invalidate_hashes(old_parent);
+        self.invalidate_hashes(Some(parent));
+        if self.dirty.contains_key(child) {
+            self.unflag_dirty_below(old_parent);
+            self.flag_dirty_below(Some(parent));
+        }
 
         Some(())
     }
 
-    /// Remove the second node as a child of the first node
+    /// Remove the second node as a child of the first node, making it a root. Returns `None`
+    /// without changing anything if the second node isn't a child of the first.
     pub fn remove_child(&mut self, parent: TreeKey, child: TreeKey) -> Option<()> {
-        let parent = self.nodes.get_mut(parent)?;
-        parent.children_mut().retain(|&k| k != child);
+        if self.parent_key_of(child) != Some(parent) {
+            return None;
+        }
+        self.unname(Some(parent), child);
+        let parent_node = self.nodes.get_mut(parent)?;
+        parent_node.children_mut().retain(|&k| k != child);
+        self.unflag_dirty_below(Some(parent));
+        self.invalidate_hashes(Some(parent));
+        self.edges.remove(child);
         let child_node = self.nodes.get_mut(child)?;
         child_node.set_parent(None);
+        let len = child_node.subtree_len();
         self.roots.push(child);
+        self.shrink_ancestors(Some(parent), len);
+        Some(())
+    }
+
+    /// Add a new child node to the referenced parent, with data for the edge between them
+    pub fn add_child_with_edge(&mut self, val: T, parent: TreeKey, edge: E) -> Option<TreeKey> {
+        let child = self.add_child(val, parent)?;
+        self.edges.insert(child, edge);
+        Some(child)
+    }
+
+    /// Set the first node as the parent of the second node with data for the edge between them,
+    /// unsetting the current parent if there is one. Fails as [`set_child`](Tree::set_child) does.
+    pub fn set_child_with_edge(&mut self, parent: TreeKey, child: TreeKey, edge: E) -> Option<()> {
+        self.set_child(parent, child)?;
+        self.edges.insert(child, edge);
         Some(())
     }
 
+    /// Get the data for the edge between a parent and its child, if the nodes are related and
+    /// the edge has data
+    pub fn edge(&self, parent: TreeKey, child: TreeKey) -> Option<&E> {
+        if self.parent_key_of(child)? != parent {
+            return None;
+        }
+        self.edges.get(child)
+    }
+
+    /// Get mutable access to the data for the edge between a parent and its child, if the nodes
+    /// are related and the edge has data
+    pub fn edge_mut(&mut self, parent: TreeKey, child: TreeKey) -> Option<&mut E> {
+        if self.parent_key_of(child)? != parent {
+            return None;
+        }
+        self.edges.get_mut(child)
+    }
+
+    /// Set the data for the edge between a parent and its child, returning the previous data.
+    /// Returns `None` without storing anything if the nodes aren't related.
+    pub fn set_edge(&mut self, parent: TreeKey, child: TreeKey, edge: E) -> Option<E> {
+        if self.parent_key_of(child)? != parent {
+            return None;
+        }
+        self.edges.insert(child, edge)
+    }
+
+    /// Remove the data for the edge between a parent and its child, leaving the nodes related
+    pub fn remove_edge(&mut self, parent: TreeKey, child: TreeKey) -> Option<E> {
+        if self.parent_key_of(child)? != parent {
+            return None;
+        }
+        self.edges.remove(child)
+    }
+
+    /// Add a new child node to the referenced parent, which can be looked up by name with
+    /// [`child_by_name`](Tree::child_by_name). Returns `None` if the parent doesn't exist or
+    /// already has a child with this name.
+    pub fn add_child_named(&mut self, parent: TreeKey, name: &str, val: T) -> Option<TreeKey> {
+        if self.child_by_name(parent, name).is_some() {
+            return None;
+        }
+        let child = self.add_child(val, parent)?;
+        self.names.insert(child, name.into());
+        self.named.entry(parent)?
+            .or_default()
+            .insert(name.into(), child);
+        Some(child)
+    }
+
+    /// Get the key of a parent's child with the given name
+    pub fn child_by_name(&self, parent: TreeKey, name: &str) -> Option<TreeKey> {
+        self.named.get(parent)?.get(name).copied()
+    }
+
+    /// Get the name of a node, if it was added as a named child. Names are dropped when the node
+    /// is moved to a different parent.
+    pub fn name_of(&self, child: TreeKey) -> Option<&str> {
+        self.names.get(child).map(|name| &**name)
+    }
+
+    /// Drop the name of a child from its parent's name index
+    fn unname(&mut self, parent: Option<TreeKey>, child: TreeKey) {
+        let Some(name) = self.names.remove(child) else {
+            return;
+        };
+        if let Some(named) = parent.and_then(|parent| self.named.get_mut(parent)) {
+            named.remove(&name);
+        }
+    }
+
     /// Remove a node from the tree, removing all children as well. Fails if the node or any
     /// of its children are currently borrowed.
     pub fn remove_recursive(&mut self, node_id: TreeKey) -> Option<()> {
         let node = self.nodes.remove(node_id)?;
+        self.forget(node_id, node.parent());
 
         for child in node.children() {
             let _ = self.remove_recursive(*child);
         }
 
         if let Some(parent) = node.parent() {
-            self.remove_child(parent, node_id);
+            if let Some(parent_node) = self.nodes.get_mut(parent) {
+                parent_node.children_mut().retain(|&k| k != node_id);
+            }
+            self.unflag_dirty_below(Some(parent));
+            self.invalidate_hashes(Some(parent));
+            self.shrink_ancestors(Some(parent), node.subtree_len());
         } else {
             self.roots.retain(|&k| k != node_id);
         }
@@ -110,15 +724,530 @@ impl<T> Tree<T> {
         Some(())
     }
 
+    /// Replace the subtree rooted at a node with the roots of another tree, in the node's place
+    /// among its siblings. Returns the removed subtree as a tree of its own, or `None` if the
+    /// node doesn't exist.
+    ///
+    /// Nodes are given new keys in the tree they are moved to. Edge data and names below the
+    /// moved roots move with them.
+    pub fn splice(&mut self, target: TreeKey, mut replacement: Tree<T, E>) -> Option<Tree<T, E>> {
+        let node = self.nodes.get(target)?;
+        let parent = node.parent();
+        let len = node.subtree_len();
+
+        let siblings = match parent {
+            Some(parent) => self.nodes.get_mut(parent)?.children_mut(),
+            None => &mut self.roots,
+        };
+        let pos = siblings.iter().position(|&k| k == target)?;
+        siblings.remove(pos);
+        self.unname(parent, target);
+        self.shrink_ancestors(parent, len);
+        self.unflag_dirty_below(parent);
+
+        let mut removed = Tree {
+            track_subtree_len: self.track_subtree_len,
+            ..Tree::default()
+        };
+        let (root, _) = removed.adopt(self, target, None)?;
+        removed.roots.push(root);
+
+        let mut new_roots = Vec::with_capacity(replacement.roots.len());
+        let mut added = 0;
+        for root in mem::take(&mut replacement.roots) {
+            let (root, len) = self.adopt(&mut replacement, root, parent)?;
+            new_roots.push(root);
+            added += len;
+        }
+        let siblings = match parent {
+            Some(parent) => self.nodes.get_mut(parent)?.children_mut(),
+            None => &mut self.roots,
+        };
+        siblings.splice(pos..pos, new_roots);
+        self.grow_ancestors(parent, added);
+        self.invalidate_hashes(parent);
+
+        Some(removed)
+    }
+
+    /// Copy the subtree rooted at `src` in another tree to be the last child of `dst_parent`,
+    /// cloning its values and keeping children in order. Returns the key of the copied root, or
+    /// `None` if either node doesn't exist.
+    ///
+    /// Names below the copied root are copied with it, but edge data isn't.
+    pub fn copy_subtree_from<E2>(&mut self, other: &Tree<T, E2>, src: TreeKey, dst_parent: TreeKey) -> Option<TreeKey>
+    where
+        T: Clone,
+    {
+        if !other.nodes.contains_key(src) || !self.nodes.contains_key(dst_parent) {
+            return None;
+        }
+        let mut root = None;
+        let mut stack = vec![(src, dst_parent)];
+        while let Some((key, parent)) = stack.pop() {
+            let Some(node) = other.nodes.get(key) else {
+                continue;
+            };
+            // The copied root's name belonged to its old parent
+            let new = match other.names.get(key).filter(|_| root.is_some()) {
+                Some(name) => self.add_child_named(parent, name, node.val().clone())?,
+                None => self.add_child(node.val().clone(), parent)?,
+            };
+            root.get_or_insert(new);
+            stack.extend(node.children().iter().rev().map(|&child| (child, new)));
+        }
+        root
+    }
+
+    /// Copy the subtree rooted at `template` to be the last child of `parent`, making each new
+    /// value from the template's value with `f`, so values such as IDs can be made unique while
+    /// copying. Children keep their order and names below the copied root are copied with it.
+    /// Returns the key of the copied root, or `None` if either node doesn't exist.
+    ///
+    /// Every value is made before any node is added, so a template can be instantiated inside
+    /// itself.
+    pub fn instantiate_template(&mut self, template: TreeKey, parent: TreeKey, mut f: impl FnMut(&T) -> T) -> Option<TreeKey> {
+        if !self.nodes.contains_key(parent) {
+            return None;
+        }
+        // Rows of new value, index of the parent's row, and name, in pre-order
+        let mut rows = Vec::new();
+        let mut stack = vec![(template, None)];
+        while let Some((key, up)) = stack.pop() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            let name = up.and(self.names.get(key).cloned());
+            stack.extend(node.children().iter().rev().map(|&child| (child, Some(rows.len()))));
+            rows.push((f(node.val()), up, name));
+        }
+
+        let mut keys = Vec::<TreeKey>::with_capacity(rows.len());
+        for (val, up, name) in rows {
+            let parent = match up {
+                Some(idx) => *keys.get(idx)?,
+                None => parent,
+            };
+            let new = match name {
+                Some(name) => self.add_child_named(parent, &name, val)?,
+                None => self.add_child(val, parent)?,
+            };
+            keys.push(new);
+        }
+        keys.first().copied()
+    }
+
+    /// Move the subtree rooted at `key` out of `src` and into this tree, with its root given
+    /// `parent` but not yet listed among its siblings. Returns the new key of the root and the
+    /// number of nodes moved.
+    fn adopt(&mut self, src: &mut Tree<T, E>, key: TreeKey, parent: Option<TreeKey>) -> Option<(TreeKey, usize)> {
+        let mut moved = Vec::new();
+        let mut stack = vec![(parent, key)];
+        while let Some((parent, key)) = stack.pop() {
+            let Some(mut node) = src.nodes.remove(key) else {
+                continue;
+            };
+            let edge = src.edges.remove(key);
+            let name = src.names.remove(key);
+            src.forget(key, None);
+
+            let children = mem::take(node.children_mut());
+            let new = self.nodes.insert(Node::new(node.into_val(), parent));
+            self.record_insertion(new);
+            stack.extend(children.iter().rev().map(|&child| (Some(new), child)));
+
+            // The moved root's edge and name belonged to its old parent
+            if let (false, Some(parent)) = (moved.is_empty(), parent) {
+                self.nodes.get_mut(parent)?.children_mut().push(new);
+                if let Some(edge) = edge {
+                    self.edges.insert(new, edge);
+                }
+                if let Some(name) = name {
+                    self.named.entry(parent)?
+                        .or_default()
+                        .insert(name.clone(), new);
+                    self.names.insert(new, name);
+                }
+            }
+            moved.push(new);
+        }
+
+        if self.track_subtree_len {
+            // Pre-order reversed visits children before their parents
+            for &key in moved.iter().rev() {
+                let len = 1 + self.nodes.get(key)?
+                    .children()
+                    .iter()
+                    .filter_map(|&child| self.nodes.get(child))
+                    .map(Node::subtree_len)
+                    .sum::<usize>();
+                self.nodes.get_mut(key)?.set_subtree_len(len);
+            }
+        }
+
+        Some((*moved.first()?, moved.len()))
+    }
+
+    /// Remove a node from the tree, moving its children into its place among its siblings
+    fn remove_reparenting(&mut self, node_id: TreeKey) -> Option<()> {
+        let node = self.nodes.remove(node_id)?;
+        self.forget(node_id, node.parent());
+        let parent = node.parent();
+
+        for &child in node.children() {
+            self.edges.remove(child);
+            self.names.remove(child);
+            self.nodes.get_mut(child)?.set_parent(parent);
+        }
+
+        let siblings = match parent {
+            Some(parent) => self.nodes.get_mut(parent)?.children_mut(),
+            None => &mut self.roots,
+        };
+        let pos = siblings.iter().position(|&k| k == node_id)?;
+        siblings.splice(pos..=pos, node.children().iter().copied());
+        self.shrink_ancestors(parent, 1);
+        self.unflag_dirty_below(parent);
+        self.invalidate_hashes(parent);
+
+        Some(())
+    }
+
+    /// Remove all nodes for which the predicate returns `false`. The children of removed nodes
+    /// are handled according to the provided policy.
+    pub fn retain(&mut self, policy: OrphanPolicy, mut f: impl FnMut(TreeKey, &T) -> bool) {
+        let removed = self.nodes
+            .iter()
+            .filter(|(key, node)| !f(*key, node.val()))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+
+        for key in removed {
+            let _ = match policy {
+                OrphanPolicy::Remove => self.remove_recursive(key),
+                OrphanPolicy::Reparent => self.remove_reparenting(key),
+            };
+        }
+    }
+
+    /// Visit every node mutably in pre-order. Structural changes can be queued on the provided
+    /// [`Edits`], and are applied in order after the traversal completes. Returns the key of each
+    /// queued node addition, or `None` for additions whose parent no longer exists.
+    pub fn visit_mut(&mut self, mut f: impl FnMut(TreeKey, &mut T, &mut Edits<T>)) -> Vec<Option<TreeKey>> {
+        self.hashes.clear();
+        let mut edits = Edits::new();
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            let Some(node) = self.nodes.get_mut(key) else {
+                continue;
+            };
+            f(key, node.val_mut(), &mut edits);
+            stack.extend(node.children().iter().rev());
+        }
+
+        let mut added = Vec::new();
+        for edit in edits.into_inner() {
+            match edit {
+                Edit::Add(None, val) => added.push(Some(self.add_root(val))),
+                Edit::Add(Some(parent), val) => added.push(self.add_child(val, parent)),
+                Edit::Remove(key, OrphanPolicy::Remove) => {
+                    let _ = self.remove_recursive(key);
+                }
+                Edit::Remove(key, OrphanPolicy::Reparent) => {
+                    let _ = self.remove_reparenting(key);
+                }
+                Edit::Move(key, None) => {
+                    if let Some(parent) = self.parent_key_of(key) {
+                        self.remove_child(parent, key);
+                    }
+                }
+                Edit::Move(key, Some(parent)) => {
+                    let _ = self.set_child(parent, key);
+                }
+            }
+        }
+        added
+    }
+
+    /// Check whether the predicate returns `true` for any node, visiting nodes in pre-order and
+    /// stopping at the first match
+    pub fn any(&self, mut f: impl FnMut(TreeKey, &T) -> bool) -> bool {
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            if f(key, node.val()) {
+                return true;
+            }
+            stack.extend(node.children().iter().rev());
+        }
+        false
+    }
+
+    /// Check whether the predicate returns `true` for every node, visiting nodes in pre-order and
+    /// stopping at the first failure
+    pub fn all(&self, mut f: impl FnMut(TreeKey, &T) -> bool) -> bool {
+        !self.any(|key, val| !f(key, val))
+    }
+
+    /// Fold the subtree of a node bottom-up. Each node's result starts as `init` of its value,
+    /// then the results of its children are combined into it in order with `f`. Returns `None`
+    /// if the node doesn't exist.
+    pub fn fold_subtree<R>(
+        &self,
+        key: TreeKey,
+        mut init: impl FnMut(TreeKey, &T) -> R,
+        mut f: impl FnMut(R, R) -> R,
+    ) -> Option<R> {
+        // Nodes are visited twice, first to queue their children and then to combine them
+        let mut stack = vec![(key, None)];
+        let mut results = Vec::new();
+        while let Some((cur, expanded)) = stack.pop() {
+            let node = self.nodes.get(cur)?;
+            match expanded {
+                Some(len) => {
+                    let children = results.split_off(results.len() - len);
+                    let acc = children.into_iter().fold(init(cur, node.val()), &mut f);
+                    results.push(acc);
+                }
+                None => {
+                    stack.push((cur, Some(node.children().len())));
+                    stack.extend(node.children().iter().rev().map(|&child| (child, None)));
+                }
+            }
+        }
+        results.pop()
+    }
+
+    /// Thread a context down the subtree of a node in pre-order. Each node's context is made by
+    /// `f` from its parent's, with the node itself starting from `ctx`. Returns the context of
+    /// every node in the subtree, or `None` if the node doesn't exist.
+    pub fn accumulate<C>(
+        &self,
+        key: TreeKey,
+        ctx: C,
+        mut f: impl FnMut(&C, TreeKey, &T) -> C,
+    ) -> Option<SecondaryMap<TreeKey, C>> {
+        let mut out = SecondaryMap::new();
+        let mut stack = vec![(key, None)];
+        while let Some((cur, parent)) = stack.pop() {
+            let node = self.nodes.get(cur)?;
+            let parent_ctx = parent.and_then(|parent| out.get(parent)).unwrap_or(&ctx);
+            let cur_ctx = f(parent_ctx, cur, node.val());
+            out.insert(cur, cur_ctx);
+            stack.extend(node.children().iter().rev().map(|&child| (child, Some(cur))));
+        }
+        Some(out)
+    }
+
+    /// Propagate results down the subtree of a node in pre-order, such as world transforms from
+    /// local ones, storing them in `out`. Each node's result is made by `f` from its parent's
+    /// result and its value, with the node itself starting from `init`.
+    ///
+    /// Results already in `out` are reused where possible. A node is only recomputed if it's
+    /// dirty, has no result yet, or its parent was recomputed, and branches without dirty nodes
+    /// aren't visited, so nodes added since the last pass should be marked dirty. Dirty flags in
+    /// the subtree are cleared afterwards. Returns how many nodes were recomputed, or `None` if
+    /// the node doesn't exist.
+    pub fn propagate<A>(
+        &mut self,
+        key: TreeKey,
+        init: &A,
+        out: &mut SecondaryMap<TreeKey, A>,
+        mut f: impl FnMut(&A, &T) -> A,
+    ) -> Option<usize> {
+        self.nodes.get(key)?;
+        let mut recomputed = 0;
+        // Nodes to visit, with whether their parent was recomputed
+        let mut stack = vec![(key, false)];
+        while let Some((cur, forced)) = stack.pop() {
+            let Some(node) = self.nodes.get(cur) else {
+                continue;
+            };
+            let recompute = forced || self.is_dirty(cur) || !out.contains_key(cur);
+            if recompute {
+                let parent = match node.parent() {
+                    Some(parent) if cur != key => out.get(parent).unwrap_or(init),
+                    _ => init,
+                };
+                let val = f(parent, node.val());
+                out.insert(cur, val);
+                recomputed += 1;
+            }
+            if recompute || self.has_dirty_descendants(cur) {
+                stack.extend(node.children().iter().rev().map(|&child| (child, recompute)));
+            }
+        }
+        self.clear_dirty(key);
+        Some(recomputed)
+    }
+
+    /// Walk down from a node as a decision tree, letting `chooser` pick which child to continue
+    /// into from each node's value and a context. The walk ends when `chooser` stops it, picks a
+    /// child which doesn't exist, or reaches a leaf, which isn't passed to `chooser`. Returns the
+    /// keys of the nodes visited, starting with the node and ending where the walk stopped, or
+    /// `None` if the node doesn't exist.
+    pub fn evaluate<C: ?Sized>(
+        &self,
+        key: TreeKey,
+        ctx: &C,
+        mut chooser: impl FnMut(&T, &C) -> ChildSelect,
+    ) -> Option<Vec<TreeKey>> {
+        let mut node = self.nodes.get(key)?;
+        let mut path = vec![key];
+        while !node.children().is_empty() {
+            let ChildSelect::Child(idx) = chooser(node.val(), ctx) else {
+                break;
+            };
+            let Some(next) = node.children().get(idx).and_then(|&child| self.nodes.get(child)) else {
+                break;
+            };
+            path.push(node.children()[idx]);
+            node = next;
+        }
+        Some(path)
+    }
+
+    /// Check whether the heights of the subtrees of every node's children differ by at most
+    /// `max_skew`, where a leaf has height one
+    pub fn is_balanced(&self, max_skew: usize) -> bool {
+        let mut heights = SecondaryMap::<TreeKey, usize>::with_capacity(self.nodes.len());
+        for key in self.post_order_keys() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            let mut children = node.children().iter().filter_map(|&child| heights.get(child).copied());
+            let Some(first) = children.next() else {
+                heights.insert(key, 1);
+                continue;
+            };
+            let (min, max) = children.fold((first, first), |(min, max), h| (min.min(h), max.max(h)));
+            if max - min > max_skew {
+                return false;
+            }
+            heights.insert(key, max + 1);
+        }
+        true
+    }
+
+    /// Limit the number of children of a node and all its descendants by moving runs of
+    /// children under new grouping nodes, repeating until every node has few enough children.
+    /// The value of each grouping node is made by `group` from the keys of the children it will
+    /// hold. Returns the number of grouping nodes added, or `None` if the node doesn't exist or
+    /// the limit is less than two.
+    ///
+    /// Children keep their order in a pre-order traversal. Moved children lose their edge data
+    /// and names, as with [`set_child`](Tree::set_child).
+    pub fn rebalance(
+        &mut self,
+        key: TreeKey,
+        strategy: Rebalance,
+        mut group: impl FnMut(&Tree<T, E>, &[TreeKey]) -> T,
+    ) -> Option<usize> {
+        let max = match strategy {
+            Rebalance::Packed(max) | Rebalance::Even(max) => max,
+        };
+        if max < 2 || !self.nodes.contains_key(key) {
+            return None;
+        }
+
+        let mut added = 0;
+        let mut stack = vec![key];
+        while let Some(parent) = stack.pop() {
+            let mut children = self.nodes.get(parent)?.children().to_vec();
+            // Grouping nodes have at most `max` children, so only need visiting as parents
+            stack.extend(&children);
+            while children.len() > max {
+                let len = children.len();
+                let count = len.div_ceil(max);
+                let mut groups = Vec::with_capacity(count);
+                let mut rest = &children[..];
+                for idx in 0..count {
+                    let size = match strategy {
+                        Rebalance::Packed(_) => max.min(rest.len()),
+                        Rebalance::Even(_) => len / count + usize::from(idx < len % count),
+                    };
+                    let (chunk, next) = rest.split_at(size);
+                    rest = next;
+                    let val = group(self, chunk);
+                    let new = self.add_child(val, parent)?;
+                    for &child in chunk {
+                        self.set_child(new, child)?;
+                    }
+                    groups.push(new);
+                }
+                added += groups.len();
+                children = groups;
+            }
+        }
+        Some(added)
+    }
+
+    /// Split the subtrees rooted at the provided keys into disjoint mutable views, which can be
+    /// used independently, such as from different threads. Views are returned in the same order
+    /// as the keys.
+    ///
+    /// Returns `None` if any node doesn't exist, or any key is repeated or is an ancestor of
+    /// another.
+    pub fn split_at(&mut self, keys: &[TreeKey]) -> Option<Vec<SubtreeMut<'_, T>>> {
+        let mut owner = SecondaryMap::new();
+        for (idx, &root) in keys.iter().enumerate() {
+            let mut stack = vec![root];
+            while let Some(key) = stack.pop() {
+                if owner.insert(key, idx).is_some() {
+                    return None;
+                }
+                stack.extend(self.nodes.get(key)?.children());
+            }
+        }
+
+        for &root in keys {
+            let parent = self.nodes.get(root).and_then(Node::parent);
+            self.invalidate_hashes(parent);
+        }
+        for key in owner.keys() {
+            self.hashes.remove(key);
+        }
+
+        let mut views = keys.iter()
+            .map(|&root| SubtreeMut::new(root))
+            .collect::<Vec<_>>();
+        for (key, node) in self.nodes.iter_mut() {
+            if let Some(&idx) = owner.get(key) {
+                let (val, children) = node.parts_mut();
+                views[idx].insert(key, val, children);
+            }
+        }
+        Some(views)
+    }
+
+    /// Get a weak handle to a node, which can be checked for validity after the tree changes. The
+    /// handle is invalid if the node doesn't exist.
+    pub fn weak_handle(&self, key: TreeKey) -> NodeHandle {
+        NodeHandle::new(key, self.epoch)
+    }
+
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
     /// Get an immutable reference to a node identified by the provided key, returning `None` if
     /// the node doesn't exist.
-    pub fn get(&self, key: TreeKey) -> Option<NodeRef<'_, T>> {
+    pub fn get(&self, key: TreeKey) -> Option<NodeRef<'_, T, E>> {
         Some(NodeRef::new(self, self.nodes.get(key)?))
     }
 
+    /// Get a functional [`Zipper`] focused on the node identified by the provided key, returning
+    /// `None` if the node doesn't exist
+    pub fn zipper(&self, key: TreeKey) -> Option<Zipper<'_, T, E>> {
+        Zipper::new(self, key)
+    }
+
     /// Get a mutable reference to a node identified by the provided key, returning `None` if the
     /// node doesn't exist
-    pub fn get_mut(&mut self, key: TreeKey) -> Option<NodeMut<'_, T>> {
+    pub fn get_mut(&mut self, key: TreeKey) -> Option<NodeMut<'_, T, E>> {
+        self.invalidate_hashes(Some(key));
         let this_ptr = unsafe { NonNull::new_unchecked(self) };
         let node = NonNull::from(self.nodes.get_mut(key)?);
 
@@ -128,6 +1257,9 @@ impl<T> Tree<T> {
     /// Get a mutable reference to many nodes at once, returning `None` if any nodes don't exist or
     /// any keys in the input are repeated.
     pub fn get_many_mut<const N: usize>(&mut self, keys: [TreeKey; N]) -> Option<[NodeMutLimited<'_, T>; N]> {
+        for key in keys {
+            self.invalidate_hashes(Some(key));
+        }
         Some(
             self.nodes
                 .get_disjoint_mut(keys)?
@@ -136,7 +1268,7 @@ impl<T> Tree<T> {
     }
 
     /// Iterate over all nodes in this tree, in no particular order
-    pub fn unordered_iter(&self) -> impl Iterator<Item = NodeRef<'_, T>> + '_ {
+    pub fn unordered_iter(&self) -> impl Iterator<Item = NodeRef<'_, T, E>> + '_ {
         self.nodes
             .iter()
             .map(|(_, item)| {
@@ -146,6 +1278,7 @@ impl<T> Tree<T> {
 
     /// Iterate over all nodes in this tree mutably, in no particular order
     pub fn unordered_iter_mut(&mut self) -> impl Iterator<Item = NodeMutLimited<'_, T>> + '_ {
+        self.hashes.clear();
         self.nodes
             .iter_mut()
             .map(|(_, item)| {
@@ -158,10 +1291,21 @@ impl<T> Tree<T> {
         self.nodes.keys()
     }
 
+    /// Get the keys of all nodes in this tree, in the order they were added. Unlike
+    /// [`unordered_keys`](Tree::unordered_keys), this doesn't depend on how keys are allocated,
+    /// so is reproducible after unrelated insertions and removals.
+    pub fn sorted_keys(&self) -> Vec<TreeKey> {
+        let mut keys = self.order.iter()
+            .map(|(key, &order)| (order, key))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.into_iter().map(|(_, key)| key).collect()
+    }
+
     /// Iterate over the roots of this tree.
     ///
     /// A root is any node that has no parent
-    pub fn roots(&self) -> impl Iterator<Item = NodeRef<'_, T>> + '_ {
+    pub fn roots(&self) -> impl Iterator<Item = NodeRef<'_, T, E>> + '_ {
         self.roots
             .iter()
             .filter_map(|key| {
@@ -173,6 +1317,9 @@ impl<T> Tree<T> {
     ///
     /// A root is any node that has no parent
     pub fn roots_mut(&mut self) -> impl Iterator<Item = NodeMutLimited<'_, T>> + '_ {
+        for &root in &self.roots {
+            self.hashes.remove(root);
+        }
         self.roots
             .iter()
             .filter_map(|key| {
@@ -195,6 +1342,12 @@ impl<T> Tree<T> {
         self.nodes.get(child)?.parent()
     }
 
+    /// Get the child keys of a node identified by the provided key as a slice, in the order they
+    /// were attached
+    pub fn child_keys(&self, parent: TreeKey) -> Option<&[TreeKey]> {
+        Some(self.nodes.get(parent)?.children())
+    }
+
     /// Get the child keys of a node identified by the provided key
     pub fn child_keys_of(&self, parent: TreeKey) -> Option<impl Iterator<Item = TreeKey> + '_> {
         Some(self.nodes
@@ -203,13 +1356,353 @@ impl<T> Tree<T> {
             .iter()
             .copied())
     }
+
+    /// Lazily iterate over the levels of this tree, yielding the keys of the nodes at each depth
+    /// starting with the roots. Each level is in pre-order.
+    pub fn levels(&self) -> impl Iterator<Item = Vec<TreeKey>> + '_ {
+        let first = Some(self.roots.clone()).filter(|roots| !roots.is_empty());
+        core::iter::successors(first, |level| {
+            let next = level.iter()
+                .filter_map(|&key| self.nodes.get(key))
+                .flat_map(|node| node.children().iter().copied())
+                .collect::<Vec<_>>();
+            Some(next).filter(|next| !next.is_empty())
+        })
+    }
+
+    /// Get the key of every node in pre-order, alongside the position of its parent in the output
+    fn parent_list_keys(&self) -> Vec<(TreeKey, Option<usize>)> {
+        let mut out = Vec::new();
+        let mut stack = self.roots.iter().rev().map(|&key| (key, None)).collect::<Vec<_>>();
+        while let Some((key, parent)) = stack.pop() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            let idx = out.len();
+            out.push((key, parent));
+            stack.extend(node.children().iter().rev().map(|&child| (child, Some(idx))));
+        }
+        out
+    }
+
+    /// Flatten this tree into `(value, parent)` rows in pre-order, where each parent is the
+    /// index of an earlier row. The inverse of [`from_parent_list`](Tree::from_parent_list).
+    pub fn to_parent_list(&self) -> Vec<(T, Option<usize>)>
+    where
+        T: Clone,
+    {
+        self.parent_list_keys()
+            .into_iter()
+            .filter_map(|(key, parent)| Some((self.nodes.get(key)?.val().clone(), parent)))
+            .collect()
+    }
+
+    /// Flatten this tree into `(value, parent)` rows in pre-order, as by
+    /// [`to_parent_list`](Self::to_parent_list), without cloning the values
+    pub fn into_parent_list(mut self) -> Vec<(T, Option<usize>)> {
+        self.parent_list_keys()
+            .into_iter()
+            .filter_map(|(key, parent)| Some((self.nodes.remove(key)?.into_val(), parent)))
+            .collect()
+    }
+
+    /// Consume this tree, yielding the value of every node in no particular order. This is
+    /// faster than removing nodes one at a time, as relations between nodes aren't maintained.
+    pub fn into_values(self) -> impl Iterator<Item = T> {
+        self.nodes.into_iter().map(|(_, node)| node.into_val())
+    }
+
+    /// Get every parent-child link in this tree as `(parent, child)` pairs, with children in
+    /// pre-order
+    pub fn to_edge_list(&self) -> Vec<(TreeKey, TreeKey)> {
+        let mut out = Vec::new();
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            if let Some(parent) = node.parent() {
+                out.push((parent, key));
+            }
+            stack.extend(node.children().iter().rev());
+        }
+        out
+    }
+
+    /// Lazily iterate over the keys of every node in post-order, so children are always yielded
+    /// before their parents. Roots are visited in order.
+    pub fn post_order_keys(&self) -> PostOrderKeys<'_, T, E> {
+        PostOrderKeys::new(self, &self.roots)
+    }
+
+    /// Lazily iterate over every node in post-order, so children are always yielded before their
+    /// parents. Roots are visited in order.
+    pub fn iter_post_order(&self) -> impl Iterator<Item = NodeRef<'_, T, E>> + '_ {
+        self.post_order_keys()
+            .filter_map(|key| self.get(key))
+    }
+
+    /// Check the internal consistency of this tree, returning every broken invariant found
+    pub fn validate(&self) -> Result<(), Vec<InvariantViolation<TreeKey>>> {
+        let keys = self.nodes.keys().collect::<Vec<_>>();
+        validate::validate(
+            Vec::new(),
+            &keys,
+            &self.roots,
+            |key| self.nodes.contains_key(key),
+            |key| self.nodes.get(key).and_then(Node::parent),
+            |key| self.nodes.get(key).map_or(&[], Node::children),
+        )
+    }
+
+    /// Mark the ancestors starting at `cur` as having a dirty descendant
+    fn flag_dirty_below(&mut self, mut cur: Option<TreeKey>) {
+        while let Some(key) = cur {
+            let Some(flags) = self.dirty.entry(key).map(|entry| entry.or_insert(0)) else {
+                return;
+            };
+            if *flags & DIRTY_BELOW != 0 {
+                return;
+            }
+            *flags |= DIRTY_BELOW;
+            cur = self.nodes.get(key).and_then(Node::parent);
+        }
+    }
+
+    /// Unmark the ancestors starting at `cur` which no longer have any dirty descendants
+    fn unflag_dirty_below(&mut self, mut cur: Option<TreeKey>) {
+        while let Some(key) = cur {
+            let Some(node) = self.nodes.get(key) else {
+                return;
+            };
+            if node.children().iter().any(|&child| self.dirty.contains_key(child)) {
+                return;
+            }
+            let Some(flags) = self.dirty.get_mut(key) else {
+                return;
+            };
+            *flags &= !DIRTY_BELOW;
+            if *flags == 0 {
+                self.dirty.remove(key);
+            }
+            cur = node.parent();
+        }
+    }
+
+    /// Mark a node as dirty, and its ancestors as having a dirty descendant. Returns `None` if
+    /// the node doesn't exist.
+    pub fn mark_dirty(&mut self, key: TreeKey) -> Option<()> {
+        let parent = self.nodes.get(key)?.parent();
+        *self.dirty.entry(key)?.or_insert(0) |= DIRTY;
+        self.flag_dirty_below(parent);
+        Some(())
+    }
+
+    /// Check whether a node is marked dirty
+    pub fn is_dirty(&self, key: TreeKey) -> bool {
+        self.dirty.get(key).is_some_and(|&flags| flags & DIRTY != 0)
+    }
+
+    /// Check whether any descendant of a node is marked dirty
+    pub fn has_dirty_descendants(&self, key: TreeKey) -> bool {
+        self.dirty.get(key).is_some_and(|&flags| flags & DIRTY_BELOW != 0)
+    }
+
+    /// Iterate over the minimal set of dirty subtrees, the dirty nodes with no dirty ancestors,
+    /// in pre-order. Only branches containing dirty nodes are visited.
+    pub fn dirty_subtrees(&self) -> impl Iterator<Item = TreeKey> + '_ {
+        let mut stack = self.roots.iter()
+            .rev()
+            .copied()
+            .filter(|&key| self.dirty.contains_key(key))
+            .collect::<Vec<_>>();
+        core::iter::from_fn(move || {
+            while let Some(key) = stack.pop() {
+                if self.is_dirty(key) {
+                    return Some(key);
+                }
+                let children = self.nodes.get(key).map_or(&[][..], Node::children);
+                stack.extend(children.iter().rev().filter(|&&child| self.dirty.contains_key(child)));
+            }
+            None
+        })
+    }
+
+    /// Clear the dirty flags of a node and all its descendants, then unmark any ancestors left
+    /// without dirty descendants. Returns `None` if the node doesn't exist.
+    pub fn clear_dirty(&mut self, key: TreeKey) -> Option<()> {
+        let parent = self.nodes.get(key)?.parent();
+        let mut stack = vec![key];
+        while let Some(key) = stack.pop() {
+            if self.dirty.remove(key).is_some() {
+                stack.extend_from_slice(self.nodes.get(key).map_or(&[], Node::children));
+            }
+        }
+        self.unflag_dirty_below(parent);
+        Some(())
+    }
+
+    /// Clear every dirty flag in this tree
+    pub fn clear_all_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Drop the cached hashes of a node and its ancestors
+    pub(crate) fn invalidate_hashes(&mut self, mut cur: Option<TreeKey>) {
+        // Ancestors of a node without a cached hash can't have one either
+        while let Some(key) = cur {
+            if self.hashes.remove(key).is_none() {
+                return;
+            }
+            cur = self.nodes.get(key).and_then(Node::parent);
+        }
+    }
+
+    /// Get a structural hash of the subtree rooted at a node, covering the values and order of
+    /// every node in it. Edge data and names aren't included. Returns `None` if the node doesn't
+    /// exist.
+    ///
+    /// Hashes are cached, and only recomputed for subtrees changed since they were last hashed.
+    /// Any mutable access to a node counts as a change. Equal subtrees always hash the same,
+    /// within a tree or across trees.
+    pub fn subtree_hash(&mut self, key: TreeKey) -> Option<u64>
+    where
+        T: Hash,
+    {
+        if !self.nodes.contains_key(key) {
+            return None;
+        }
+        let mut stack = vec![(key, false)];
+        while let Some((key, visited)) = stack.pop() {
+            if self.hashes.contains_key(key) {
+                continue;
+            }
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            if !visited {
+                stack.push((key, true));
+                stack.extend(node.children().iter().map(|&child| (child, false)));
+                continue;
+            }
+
+            let mut hasher = util::Fnv::default();
+            node.val().hash(&mut hasher);
+            hasher.write_usize(node.children().len());
+            for &child in node.children() {
+                hasher.write_u64(self.hashes.get(child).copied().unwrap_or_default());
+            }
+            self.hashes.insert(key, hasher.finish());
+        }
+        self.hashes.get(key).copied()
+    }
+
+    /// Check whether the subtrees rooted at two nodes have equal values in the same shape,
+    /// without recursing. Returns `false` if either node doesn't exist.
+    pub fn subtree_eq(&self, a: TreeKey, b: TreeKey) -> bool
+    where
+        T: PartialEq,
+    {
+        let mut stack = vec![(a, b)];
+        while let Some((a, b)) = stack.pop() {
+            let (Some(a), Some(b)) = (self.nodes.get(a), self.nodes.get(b)) else {
+                return false;
+            };
+            if a.val() != b.val() || a.children().len() != b.children().len() {
+                return false;
+            }
+            stack.extend(a.children().iter().copied().zip(b.children().iter().copied()));
+        }
+        true
+    }
+
+    /// Gather statistics about the shape of this tree in a single traversal
+    pub fn stats(&self) -> TreeStats {
+        TreeStats::collect(&self.roots, |key| self.nodes[key].children())
+    }
+
+    fn render(&self, style: &render::Style, mut f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        render::render(
+            style,
+            &self.roots,
+            |key| self.nodes[key].children().to_vec(),
+            |out, key| f(out, self.nodes[key].val()),
+        )
+    }
+
+    /// Render this tree as text, one node per line, with branches drawn using ASCII characters.
+    /// `f` writes the label for each node's value.
+    pub fn render_ascii(&self, f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        self.render(&render::ASCII, f)
+    }
+
+    /// Render this tree as text, one node per line, with branches drawn using box-drawing
+    /// characters. `f` writes the label for each node's value.
+    pub fn render_unicode(&self, f: impl FnMut(&mut dyn Write, &T) -> fmt::Result) -> String {
+        self.render(&render::UNICODE, f)
+    }
+
+    /// Consume this tree, laying its nodes out contiguously in pre-order for fast read-only
+    /// traversal
+    pub fn freeze(self) -> FrozenTree<T> {
+        FrozenTree::from_tree(self)
+    }
+
+    /// Consume this tree, storing its shape in about two bits per node with values in
+    /// breadth-first order
+    pub fn freeze_succinct(self) -> FrozenSuccinctTree<T> {
+        FrozenSuccinctTree::from_tree(self)
+    }
+
+    /// Consume this tree, storing each node with only a first-child and next-sibling key. Edge
+    /// data and names are dropped. Returns `None` if the tree has `u32::MAX` or more nodes.
+    pub fn into_lcrs(self) -> Option<LcrsTree<T>> {
+        LcrsTree::from_tree(self)
+    }
+
+    /// Consume this tree, sharing every set of identical subtrees as a single node of a DAG.
+    /// Subtrees are identical if they have equal values in the same shape. Edge data and names
+    /// are dropped.
+    ///
+    /// Also returns the index in the DAG each key of this tree was mapped to.
+    pub fn intern_shared_subtrees(self) -> (Dag<T>, SecondaryMap<TreeKey, usize>)
+    where
+        T: Hash + Eq,
+    {
+        Dag::from_tree(self)
+    }
+}
+
+/// Build a tree from `(value, parent)` rows, as by [`Tree::from_parent_list`]
+///
+/// # Panics
+///
+/// If a parent index is out of range, or the parent links form a cycle
+impl<T> FromIterator<(T, Option<usize>)> for Tree<T> {
+    fn from_iter<I: IntoIterator<Item = (T, Option<usize>)>>(iter: I) -> Self {
+        Tree::from_parent_list(iter)
+            .expect("Parent rows must form a tree")
+            .0
+    }
 }
 
-impl<T> Default for Tree<T> {
+impl<T, E> Default for Tree<T, E> {
     fn default() -> Self {
         Tree {
             nodes: SlotMap::with_key(),
             roots: Vec::new(),
+            track_subtree_len: false,
+            identity: 0,
+            secondaries: Vec::new(),
+            listeners: Vec::new(),
+            dirty: SecondaryMap::new(),
+            edges: SecondaryMap::new(),
+            names: SecondaryMap::new(),
+            named: SecondaryMap::new(),
+            hashes: SecondaryMap::new(),
+            order: SecondaryMap::new(),
+            next_order: 0,
+            epoch: 0,
         }
     }
 }
@@ -250,10 +1743,1135 @@ mod tests {
         let roots = tree.roots().collect::<Vec<_>>();
 
         assert_eq!(roots.len(), 1);
-        assert_eq!(*roots[0], true);
+        assert!(*roots[0]);
 
         let children = tree.child_keys_of(root).unwrap().collect::<Vec<_>>();
 
         assert_eq!(children.len(), 2);
     }
+
+    #[test]
+    fn tree_from_parts() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let removed = tree.add_child(1, root).unwrap();
+        let child = tree.add_child(2, root).unwrap();
+        tree.remove_recursive(removed);
+        let reused = tree.add_child(3, child).unwrap();
+        let other_root = tree.add_root(4);
+
+        let parts = [root, child, reused, other_root]
+            .into_iter()
+            .rev()
+            .map(|key| (key, tree.parent_key_of(key), *tree.get(key).unwrap()))
+            .collect::<Vec<_>>();
+
+        let mut new_tree = Tree::from_parts(parts).unwrap();
+        assert_eq!(new_tree.len(), 4);
+        for key in [root, child, reused, other_root] {
+            assert_eq!(*new_tree.get(key).unwrap(), *tree.get(key).unwrap());
+            assert_eq!(new_tree.parent_key_of(key), tree.parent_key_of(key));
+        }
+        assert_eq!(new_tree.root_keys().collect::<Vec<_>>(), [other_root, root]);
+        assert!(new_tree.get(removed).is_none());
+
+        let new = new_tree.add_root(5);
+        assert_eq!(new_tree.len(), 5);
+        assert_eq!(*new_tree.get(new).unwrap(), 5);
+    }
+
+    #[test]
+    fn tree_from_parts_invalid() {
+        let mut tree = Tree::new();
+        let a = tree.add_root(0);
+        let b = tree.add_child(1, a).unwrap();
+        let c = tree.add_root(2);
+        tree.remove_recursive(c);
+
+        assert!(matches!(Tree::from_parts([(a, None, 0), (a, None, 1)]), Err(Error::InvalidKey)));
+        assert!(matches!(Tree::from_parts([(a, None, 0), (b, Some(c), 1)]), Err(Error::Missing)));
+        assert!(matches!(Tree::from_parts([(a, Some(b), 0), (b, Some(a), 1)]), Err(Error::Cycle)));
+        assert!(matches!(Tree::from_parts([(TreeKey::default(), None, 0)]), Err(Error::InvalidKey)));
+        let high_version = TreeKey::from_ffi((0x7fff_ffff << 32) | 1);
+        assert!(matches!(Tree::from_parts([(high_version, None, 0)]), Err(Error::InvalidKey)));
+        let high_index = TreeKey::from_ffi((1 << 32) | 0xffff_fff0);
+        assert!(matches!(Tree::from_parts([(high_index, None, 0)]), Err(Error::InvalidKey)));
+    }
+
+    #[test]
+    fn tree_key_ffi() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+
+        let ffi = root.to_ffi();
+        assert_eq!(TreeKey::from_ffi(ffi), root);
+        assert_eq!(*tree.get(TreeKey::from_ffi(ffi)).unwrap(), 0);
+        assert!(tree.get(TreeKey::from_ffi(0)).is_none());
+    }
+
+    #[test]
+    fn tree_retain_remove() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        tree.add_child(3, a).unwrap();
+        tree.add_child(4, b).unwrap();
+
+        tree.retain(OrphanPolicy::Remove, |_, &val| val != 1);
+
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.child_keys_of(root).unwrap().collect::<Vec<_>>(), [b]);
+        assert!(tree.get(a).is_none());
+    }
+
+    #[test]
+    fn tree_retain_reparent() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        let d = tree.add_child(4, a).unwrap();
+
+        tree.retain(OrphanPolicy::Reparent, |_, &val| val != 1);
+
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.child_keys_of(root).unwrap().collect::<Vec<_>>(), [c, d, b]);
+        assert_eq!(tree.parent_key_of(c), Some(root));
+
+        tree.retain(OrphanPolicy::Reparent, |_, &val| val != 0);
+
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [c, d, b]);
+        assert_eq!(tree.parent_key_of(c), None);
+    }
+
+    #[test]
+    fn tree_visit_mut() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        let d = tree.add_child(4, b).unwrap();
+
+        let mut order = Vec::new();
+        let added = tree.visit_mut(|key, val, edits| {
+            order.push(*val);
+            *val *= 10;
+            match key {
+                k if k == root => edits.reparent(root, Some(c)),
+                k if k == a => {
+                    edits.add_child(a, 5);
+                    edits.remove(a, OrphanPolicy::Reparent);
+                    edits.add_child(a, 6);
+                }
+                k if k == b => edits.reparent(d, None),
+                k if k == c => edits.add_root(7),
+                _ => (),
+            }
+        });
+
+        assert_eq!(order, [0, 1, 3, 2, 4]);
+        assert!(matches!(added[..], [Some(_), None, Some(_)]));
+        let (e, f) = (added[0].unwrap(), added[2].unwrap());
+        assert_eq!(tree.len(), 6);
+        assert_eq!(tree.child_keys_of(root).unwrap().collect::<Vec<_>>(), [c, e, b]);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [root, f, d]);
+        assert_eq!(*tree.get(f).unwrap(), 7);
+        assert_eq!(*tree.get(d).unwrap(), 40);
+    }
+
+    #[test]
+    fn tree_clear() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        tree.add_child(1, root).unwrap();
+
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_keys().count(), 0);
+
+        let new = tree.add_root(2);
+        assert_ne!(new, root);
+        assert!(tree.get(root).is_none());
+    }
+
+    #[test]
+    fn tree_shrink_to_fit() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let children = (1..100)
+            .map(|i| tree.add_child(i, root).unwrap())
+            .collect::<Vec<_>>();
+        for child in &children[1..] {
+            tree.remove_recursive(*child);
+        }
+
+        tree.shrink_to_fit();
+        assert_eq!(tree.get(root).unwrap().children(), &children[..1]);
+        assert_eq!(tree.raw_nodes()[root].children().len(), 1);
+    }
+
+    #[test]
+    fn tree_compact() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let children = (1..50)
+            .map(|i| tree.add_child(i, root).unwrap())
+            .collect::<Vec<_>>();
+        let named = tree.add_child_named(children[48], "last", 100).unwrap();
+        let other = tree.add_root(200);
+        let sizes = tree.add_secondary::<u32>();
+        tree.secondary_mut(sizes).unwrap().insert(named, 7);
+        for child in &children[..40] {
+            tree.remove_recursive(*child);
+        }
+        tree.mark_dirty(named).unwrap();
+        let order = tree.insertion_index(named);
+
+        let keys = tree.compact();
+        assert_eq!(keys.len(), 12);
+        assert_eq!(keys.get(children[0]), None);
+        let new_root = keys.get(root).unwrap();
+        let new_named = keys.get(named).unwrap();
+        let new_children = children[40..].iter()
+            .map(|&child| keys.get(child).unwrap())
+            .collect::<Vec<_>>();
+
+        // Nodes are packed in pre-order
+        let mut pre_order = vec![new_root];
+        pre_order.extend(&new_children);
+        pre_order.extend([new_named, keys.get(other).unwrap()]);
+        assert_eq!(tree.raw_nodes().keys().collect::<Vec<_>>(), pre_order);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [new_root, keys.get(other).unwrap()]);
+        assert_eq!(tree.child_keys(new_root).unwrap(), new_children);
+        assert_eq!(tree.parent_key_of(new_named), Some(new_children[8]));
+        assert_eq!(tree.child_by_name(new_children[8], "last"), Some(new_named));
+        assert_eq!(tree.name_of(new_named), Some("last"));
+        assert!(tree.is_dirty(new_named));
+        assert!(tree.has_dirty_descendants(new_root));
+        assert_eq!(tree.insertion_index(new_named), order);
+        assert_eq!(tree.secondary(sizes).unwrap().get(new_named), Some(&7));
+        assert_eq!(*tree.get(new_named).unwrap(), 100);
+        assert!(tree.validate().is_ok());
+
+        let mut map = SecondaryMap::new();
+        map.insert(named, "named");
+        map.insert(children[0], "removed");
+        let map = keys.remap(map);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(new_named), Some(&"named"));
+    }
+
+    #[test]
+    fn tree_weak_handle() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let handle_a = tree.weak_handle(a);
+        let mut handle_b = tree.weak_handle(b);
+        assert!(handle_a.is_valid(&tree));
+        assert_eq!(*handle_b.upgrade(&tree).unwrap(), 2);
+
+        tree.remove_recursive(a);
+        assert!(!handle_a.is_valid(&tree));
+        assert!(handle_a.upgrade(&tree).is_none());
+
+        // After compaction, `b` moves into the slot `a` used to have
+        let keys = tree.compact();
+        assert_eq!(keys.get(b), Some(a));
+        assert!(!handle_a.is_valid(&tree));
+        let mut stale = handle_b;
+        assert!(!handle_b.is_valid(&tree));
+        assert!(handle_b.remap(&keys));
+        assert_eq!(handle_b.key(), keys.get(b).unwrap());
+        assert_eq!(*handle_b.upgrade(&tree).unwrap(), 2);
+        assert!(!handle_b.remap(&keys));
+        assert!(handle_b.is_valid(&tree));
+
+        // Handles must be remapped through every compaction in turn
+        let keys = tree.compact();
+        assert!(!stale.remap(&keys));
+        assert!(handle_b.remap(&keys));
+        assert!(handle_b.is_valid(&tree));
+        assert!(!tree.weak_handle(TreeKey::default()).is_valid(&tree));
+    }
+
+    #[test]
+    fn tree_remap_listener() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        tree.remove_recursive(a);
+
+        let held = Arc::new(AtomicU64::new(b.to_ffi()));
+        let shared = Arc::clone(&held);
+        let id = tree.add_remap_listener(move |keys: &KeyMap| {
+            let old = TreeKey::from_ffi(shared.load(Ordering::Relaxed));
+            if let Some(new) = keys.get(old) {
+                shared.store(new.to_ffi(), Ordering::Relaxed);
+            }
+        });
+        #[cfg(feature = "std")]
+        let handle = {
+            let handle = Arc::new(std::sync::Mutex::new(tree.weak_handle(b)));
+            tree.add_remap_listener(Arc::clone(&handle));
+            handle
+        };
+
+        let keys = tree.compact();
+        let new_b = TreeKey::from_ffi(held.load(Ordering::Relaxed));
+        assert_eq!(Some(new_b), keys.get(b));
+        assert_eq!(*tree.get(new_b).unwrap(), 2);
+        #[cfg(feature = "std")]
+        assert_eq!(*handle.lock().unwrap().upgrade(&tree).unwrap(), 2);
+
+        assert!(tree.remove_remap_listener(id).is_some());
+        assert!(tree.remove_remap_listener(id).is_none());
+        tree.add_root(3);
+        tree.compact();
+        assert_eq!(TreeKey::from_ffi(held.load(Ordering::Relaxed)), new_b);
+        #[cfg(feature = "std")]
+        assert!(handle.lock().unwrap().is_valid(&tree));
+    }
+
+    #[test]
+    fn tree_subtree_len() {
+        for mut tree in [Tree::new(), Tree::with_subtree_len()] {
+            let root = tree.add_root(0);
+            let a = tree.add_child(1, root).unwrap();
+            let b = tree.add_child(2, a).unwrap();
+            tree.add_child(3, b).unwrap();
+            let c = tree.add_child(4, root).unwrap();
+
+            assert_eq!(tree.subtree_len(root), Some(5));
+            assert_eq!(tree.subtree_len(a), Some(3));
+
+            tree.set_child(c, b).unwrap();
+            assert_eq!(tree.subtree_len(root), Some(5));
+            assert_eq!(tree.subtree_len(a), Some(1));
+            assert_eq!(tree.subtree_len(c), Some(3));
+
+            tree.remove_child(c, b).unwrap();
+            assert_eq!(tree.subtree_len(root), Some(3));
+            assert_eq!(tree.subtree_len(b), Some(2));
+
+            tree.set_child(a, b).unwrap();
+            tree.retain(OrphanPolicy::Reparent, |_, &val| val != 1);
+            assert_eq!(tree.subtree_len(root), Some(4));
+
+            tree.remove_recursive(b).unwrap();
+            assert_eq!(tree.subtree_len(root), Some(2));
+            assert_eq!(tree.subtree_len(b), None);
+        }
+    }
+
+    #[test]
+    fn tree_invalid_moves() {
+        for mut tree in [Tree::new(), Tree::with_subtree_len()] {
+            let root = tree.add_root(0);
+            let a = tree.add_child(1, root).unwrap();
+            let b = tree.add_child(2, a).unwrap();
+            let c = tree.add_child(3, root).unwrap();
+
+            // A node can't become a child of itself or its descendants
+            assert_eq!(tree.set_child(a, a), None);
+            assert_eq!(tree.set_child(b, a), None);
+            assert_eq!(tree.set_child(b, root), None);
+            assert_eq!(tree.set_child_with_edge(b, a, ()), None);
+            assert_eq!(tree.parent_key_of(a), Some(root));
+            assert_eq!(tree.parent_key_of(root), None);
+
+            // Nodes are only detached from their actual parent
+            assert_eq!(tree.remove_child(c, b), None);
+            assert_eq!(tree.remove_child(root, b), None);
+            assert_eq!(tree.parent_key_of(b), Some(a));
+            assert_eq!(tree.root_keys().collect::<Vec<_>>(), [root]);
+            assert_eq!(tree.subtree_len(root), Some(4));
+            assert_eq!(tree.subtree_len(a), Some(2));
+            assert_eq!(tree.subtree_len(c), Some(1));
+            assert!(tree.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn tree_child_keys() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child(3, root).unwrap();
+
+        tree.remove_child(root, b).unwrap();
+        assert_eq!(tree.child_keys(root), Some(&[a, c][..]));
+
+        tree.set_child(root, b).unwrap();
+        let node = tree.get(root).unwrap();
+        assert!(matches!(node.child_keys(), [x, _, y] if *x == a && *y == b));
+    }
+
+    #[test]
+    fn tree_add_with() {
+        let mut tree = Tree::new();
+        let root = tree.add_root_with(|key| (key, 0));
+        assert_eq!(tree.get(root).unwrap().0, root);
+
+        let child = tree.add_child_with(root, |key| (key, 1)).unwrap();
+        assert_eq!(tree.get(child).unwrap().0, child);
+        assert_eq!(tree.child_keys(root), Some(&[child][..]));
+
+        let res = tree.try_add_child_with(root, |_| Err::<_, &str>("failed"));
+        assert!(matches!(res, Some(Err("failed"))));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.child_keys(root), Some(&[child][..]));
+
+        tree.remove_recursive(child).unwrap();
+        assert!(tree.add_child_with(child, |key| (key, 2)).is_none());
+        assert!(tree.try_add_root_with(|_| Err::<(TreeKey, i32), _>(())).is_err());
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn tree_post_order() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, a).unwrap();
+        let c = tree.add_child(3, a).unwrap();
+        let d = tree.add_child(4, root).unwrap();
+        let other = tree.add_root(5);
+
+        let keys = tree.post_order_keys().collect::<Vec<_>>();
+        assert_eq!(keys, [b, c, a, d, root, other]);
+
+        let vals = tree.iter_post_order()
+            .map(|node| *node)
+            .collect::<Vec<_>>();
+        assert_eq!(vals, [2, 3, 1, 4, 0, 5]);
+    }
+
+    #[test]
+    fn tree_render() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, a).unwrap();
+        tree.add_child(3, root).unwrap();
+        tree.add_root(4);
+
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val * 10)),
+            "0\n|-- 10\n|   `-- 20\n`-- 30\n40\n",
+        );
+        assert_eq!(
+            tree.render_unicode(|out, val| write!(out, "{}", val)),
+            "0\n├── 1\n│   └── 2\n└── 3\n4\n",
+        );
+        assert_eq!(Tree::<i32>::new().render_ascii(|out, val| write!(out, "{}", val)), "");
+    }
+
+    #[test]
+    fn tree_memory_footprint() {
+        let mut tree = Tree::<u64>::new();
+        let empty = tree.memory_footprint();
+
+        let root = tree.add_root(0);
+        for i in 0..16 {
+            tree.add_child(i, root).unwrap();
+        }
+        let full = tree.memory_footprint();
+        assert!(full >= empty + 17 * mem::size_of::<u64>() + 16 * mem::size_of::<TreeKey>());
+
+        tree.clear();
+        tree.shrink_to_fit();
+        assert!(tree.memory_footprint() <= full);
+    }
+
+    #[test]
+    fn tree_memory_footprint_extras() {
+        let mut tree = Tree::<u8>::new();
+        let keys = (0..64).map(|val| tree.add_root(val)).collect::<Vec<_>>();
+        let base = tree.memory_footprint();
+
+        let id = tree.add_secondary::<[u64; 4]>();
+        let mut map = tree.secondary_mut(id).unwrap();
+        for &key in &keys {
+            map.insert(key, [0; 4]);
+        }
+        let with_secondary = tree.memory_footprint();
+        assert!(with_secondary >= base + 64 * mem::size_of::<[u64; 4]>());
+
+        for &key in &keys {
+            tree.mark_dirty(key).unwrap();
+        }
+        assert!(tree.memory_footprint() >= with_secondary + 64 * mem::size_of::<u8>());
+    }
+
+    #[test]
+    fn tree_stats() {
+        assert_eq!(Tree::<()>::new().stats(), TreeStats {
+            roots: 0,
+            max_depth: 0,
+            nodes_per_depth: vec![],
+            avg_branching: 0.0,
+        });
+
+        let tree = [(0, None), (1, Some(0)), (2, Some(1)), (3, Some(1)), (4, Some(0)), (5, Some(1)), (6, None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+        assert_eq!(tree.stats(), TreeStats {
+            roots: 2,
+            max_depth: 2,
+            nodes_per_depth: vec![2, 2, 3],
+            avg_branching: 2.5,
+        });
+    }
+
+    #[test]
+    fn tree_any_all() {
+        let tree = [(1, None), (2, Some(0)), (3, Some(1)), (4, None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+
+        let mut visited = Vec::new();
+        assert!(tree.any(|_, &val| {
+            visited.push(val);
+            val == 3
+        }));
+        assert_eq!(visited, [1, 2, 3]);
+        assert!(!tree.any(|_, &val| val > 4));
+        assert!(tree.all(|_, &val| val > 0));
+        assert!(!tree.all(|_, &val| val < 4));
+        assert!(Tree::<i32>::new().all(|_, _| false));
+    }
+
+    #[test]
+    fn tree_validate() {
+        let mut tree = [(0, None), (1, Some(0)), (2, Some(1)), (3, None)]
+            .into_iter()
+            .collect::<Tree<_>>();
+        assert_eq!(tree.validate(), Ok(()));
+
+        let keys = tree.unordered_keys().collect::<Vec<_>>();
+        let (root, a, b, other) = (keys[0], keys[1], keys[2], keys[3]);
+        tree.nodes[b].set_parent(Some(root));
+        tree.nodes[other].set_parent(Some(root));
+        assert_eq!(tree.validate(), Err(vec![
+            InvariantViolation::RootHasParent(other),
+            InvariantViolation::ParentMismatch { parent: a, child: b },
+        ]));
+
+        tree.nodes[b].set_parent(Some(a));
+        tree.nodes[other].set_parent(None);
+        tree.roots.retain(|&key| key != root);
+        tree.nodes[root].set_parent(Some(b));
+        tree.nodes[b].children_mut().push(root);
+        assert_eq!(tree.validate(), Err(vec![
+            InvariantViolation::Cycle(root),
+            InvariantViolation::Cycle(a),
+            InvariantViolation::Cycle(b),
+        ]));
+    }
+
+    #[test]
+    fn tree_secondary() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, a).unwrap();
+        let other = tree.add_root(3);
+
+        let names = tree.add_secondary::<&str>();
+        let sizes = tree.add_secondary::<u32>();
+        let mut map = tree.secondary_mut(names).unwrap();
+        map.insert(root, "root");
+        map.insert(a, "a");
+        map.insert(b, "b");
+        assert_eq!(map.len(), 3);
+        tree.secondary_mut(sizes).unwrap().insert(other, 10);
+
+        tree.remove_recursive(a).unwrap();
+        assert_eq!(tree.secondary(names).unwrap().len(), 1);
+        assert_eq!(tree.secondary(names).unwrap().get(root), Some(&"root"));
+        assert_eq!(tree.secondary_mut(names).unwrap().insert(a, "gone"), None);
+        assert!(!tree.secondary(names).unwrap().contains_key(a));
+
+        let sizes_map = tree.remove_secondary(sizes).unwrap();
+        assert_eq!(sizes_map[other], 10);
+        assert!(tree.secondary(sizes).is_none());
+        assert!(tree.remove_secondary(sizes).is_none());
+
+        tree.clear();
+        assert!(tree.secondary(names).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tree_secondary_other_tree() {
+        let mut first = Tree::new();
+        let mut second = Tree::new();
+        let a = first.add_root(0);
+        let b = second.add_root(1);
+
+        let first_id = first.add_secondary::<u32>();
+        let second_id = second.add_secondary::<u32>();
+        first.secondary_mut(first_id).unwrap().insert(a, 10);
+        second.secondary_mut(second_id).unwrap().insert(b, 20);
+
+        // Both IDs have the same index and type, but only work on their own tree
+        assert!(first.secondary(second_id).is_none());
+        assert!(first.secondary_mut(second_id).is_none());
+        assert!(first.remove_secondary(second_id).is_none());
+        assert!(Tree::<i32>::new().secondary(first_id).is_none());
+        assert_eq!(first.secondary(first_id).unwrap()[a], 10);
+        assert_eq!(second.remove_secondary(second_id).unwrap()[b], 20);
+    }
+
+    #[test]
+    fn tree_dirty() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_child(2, a).unwrap();
+        let c = tree.add_child(3, root).unwrap();
+        let d = tree.add_child(4, c).unwrap();
+        let other = tree.add_root(5);
+
+        assert_eq!(tree.dirty_subtrees().count(), 0);
+        tree.mark_dirty(b).unwrap();
+        tree.mark_dirty(d).unwrap();
+        tree.mark_dirty(c).unwrap();
+        assert!(tree.is_dirty(b));
+        assert!(!tree.is_dirty(a));
+        assert!(tree.has_dirty_descendants(root));
+        assert!(tree.has_dirty_descendants(c));
+        assert!(!tree.has_dirty_descendants(other));
+        assert_eq!(tree.dirty_subtrees().collect::<Vec<_>>(), [b, c]);
+
+        tree.clear_dirty(c).unwrap();
+        assert!(!tree.is_dirty(d));
+        assert!(tree.has_dirty_descendants(root));
+        assert_eq!(tree.dirty_subtrees().collect::<Vec<_>>(), [b]);
+
+        tree.set_child(other, a).unwrap();
+        assert!(tree.has_dirty_descendants(other));
+        assert!(!tree.has_dirty_descendants(root));
+        assert_eq!(tree.dirty_subtrees().collect::<Vec<_>>(), [b]);
+
+        tree.clear_dirty(b).unwrap();
+        assert!(!tree.has_dirty_descendants(other));
+        assert!(!tree.has_dirty_descendants(a));
+
+        tree.mark_dirty(d).unwrap();
+        tree.remove_recursive(c).unwrap();
+        assert!(!tree.has_dirty_descendants(root));
+        assert_eq!(tree.dirty_subtrees().count(), 0);
+    }
+
+    #[test]
+    fn tree_edges() {
+        let mut tree = Tree::<i32, &str>::with_edges();
+        let root = tree.add_root(0);
+        let a = tree.add_child_with_edge(1, root, "a").unwrap();
+        let b = tree.add_child(2, root).unwrap();
+        let c = tree.add_child_with_edge(3, a, "c").unwrap();
+
+        assert_eq!(tree.edge(root, a), Some(&"a"));
+        assert_eq!(tree.edge(root, b), None);
+        assert_eq!(tree.edge(b, c), None);
+        assert_eq!(tree.edge(a, root), None);
+
+        assert_eq!(tree.set_edge(root, b, "b"), None);
+        assert_eq!(tree.set_edge(b, c, "x"), None);
+        *tree.edge_mut(root, b).unwrap() = "bb";
+        assert_eq!(tree.edge(root, b), Some(&"bb"));
+
+        tree.set_child(b, c).unwrap();
+        assert_eq!(tree.edge(b, c), None);
+        tree.set_child_with_edge(a, c, "c2").unwrap();
+        assert_eq!(tree.edge(a, c), Some(&"c2"));
+        assert_eq!(tree.remove_edge(a, c), Some("c2"));
+        assert_eq!(tree.parent_key_of(c), Some(a));
+
+        tree.remove_child(root, b).unwrap();
+        assert_eq!(tree.edge(root, b), None);
+        tree.set_child(root, b).unwrap();
+        assert_eq!(tree.edge(root, b), None);
+
+        tree.remove_recursive(a).unwrap();
+        assert_eq!(tree.edge(root, a), None);
+        assert_eq!(tree.edges.len(), 0);
+    }
+
+    #[test]
+    fn tree_named() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let lhs = tree.add_child_named(root, "lhs", 1).unwrap();
+        let rhs = tree.add_child_named(root, "rhs", 2).unwrap();
+        let inner = tree.add_child_named(lhs, "lhs", 3).unwrap();
+
+        assert_eq!(tree.add_child_named(root, "lhs", 4), None);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.child_by_name(root, "lhs"), Some(lhs));
+        assert_eq!(tree.child_by_name(root, "rhs"), Some(rhs));
+        assert_eq!(tree.child_by_name(lhs, "lhs"), Some(inner));
+        assert_eq!(tree.child_by_name(rhs, "lhs"), None);
+        assert_eq!(tree.name_of(rhs), Some("rhs"));
+        assert_eq!(tree.name_of(root), None);
+
+        tree.set_child(rhs, inner).unwrap();
+        assert_eq!(tree.child_by_name(lhs, "lhs"), None);
+        assert_eq!(tree.name_of(inner), None);
+
+        tree.remove_child(root, lhs).unwrap();
+        assert_eq!(tree.child_by_name(root, "lhs"), None);
+        assert!(tree.add_child_named(root, "lhs", 5).is_some());
+
+        tree.remove_recursive(rhs).unwrap();
+        assert_eq!(tree.child_by_name(root, "rhs"), None);
+        assert!(tree.names.values().all(|name| &**name == "lhs"));
+    }
+
+    #[test]
+    fn tree_splice() {
+        let mut tree = Tree::<i32, &str>::with_edges();
+        let root = tree.add_root(0);
+        tree.add_child(1, root).unwrap();
+        let b = tree.add_child_with_edge(2, root, "b").unwrap();
+        tree.add_child_with_edge(3, b, "inner").unwrap();
+        tree.add_child(4, root).unwrap();
+
+        let mut replacement = Tree::with_edges();
+        let x = replacement.add_root(5);
+        replacement.add_child_named(x, "y", 6).unwrap();
+        let z = replacement.add_root(7);
+        replacement.add_child_with_edge(8, z, "w").unwrap();
+
+        let removed = tree.splice(b, replacement).unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val)),
+            "0\n|-- 1\n|-- 5\n|   `-- 6\n|-- 7\n|   `-- 8\n`-- 4\n",
+        );
+        assert_eq!(
+            removed.render_ascii(|out, val| write!(out, "{}", val)),
+            "2\n`-- 3\n",
+        );
+        assert!(tree.get(b).is_none());
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(removed.validate(), Ok(()));
+
+        let children = tree.child_keys(root).unwrap().to_vec();
+        assert_eq!(tree.edge(root, children[1]), None);
+        assert!(tree.child_by_name(children[1], "y").is_some());
+        let w = tree.child_keys(children[2]).unwrap()[0];
+        assert_eq!(tree.edge(children[2], w), Some(&"w"));
+        let removed_root = removed.roots[0];
+        let inner = removed.child_keys(removed_root).unwrap()[0];
+        assert_eq!(removed.edge(removed_root, inner), Some(&"inner"));
+
+        let mut tracked = Tree { track_subtree_len: true, ..Tree::default() };
+        let root = tracked.add_root(0);
+        let a2 = tracked.add_child(1, root).unwrap();
+        tracked.add_child(2, a2).unwrap();
+        let removed = tracked.splice(a2, tree).unwrap();
+        assert_eq!(removed.subtree_len(removed.roots[0]), Some(2));
+        assert_eq!(tracked.subtree_len(root), Some(8));
+        assert_eq!(tracked.len(), 8);
+    }
+
+    #[test]
+    fn tree_copy_subtree_from() {
+        let mut template = Tree::<i32, &str>::with_edges();
+        let proto = template.add_root(1);
+        let named = template.add_child_named(proto, "first", 2).unwrap();
+        template.add_child(3, named).unwrap();
+        template.add_child_with_edge(4, proto, "edge").unwrap();
+
+        let mut tree = Tree::with_subtree_len();
+        let root = tree.add_root(0);
+        let a = tree.copy_subtree_from(&template, proto, root).unwrap();
+        let b = tree.copy_subtree_from(&template, named, root).unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val)),
+            "0\n|-- 1\n|   |-- 2\n|   |   `-- 3\n|   `-- 4\n`-- 2\n    `-- 3\n",
+        );
+        assert_eq!(tree.child_keys(root).unwrap(), [a, b]);
+        assert!(tree.child_by_name(a, "first").is_some());
+        assert_eq!(tree.name_of(b), None);
+        assert_eq!(tree.subtree_len(root), Some(7));
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(template.len(), 4);
+
+        let removed = template.add_root(5);
+        template.remove_recursive(removed);
+        assert_eq!(tree.copy_subtree_from(&template, removed, root), None);
+        assert_eq!(tree.copy_subtree_from(&template, proto, TreeKey::default()), None);
+        assert_eq!(tree.len(), 7);
+    }
+
+    #[test]
+    fn tree_instantiate_template() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(String::from("root"));
+        let proto = tree.add_child(String::from("button"), root).unwrap();
+        tree.add_child_named(proto, "label", String::from("text")).unwrap();
+
+        let mut next = 0;
+        let mut uniquify = |val: &String| {
+            next += 1;
+            alloc::format!("{}-{}", val, next)
+        };
+        let a = tree.instantiate_template(proto, root, &mut uniquify).unwrap();
+        // Instantiating a template inside itself copies it as it was
+        tree.instantiate_template(proto, proto, &mut uniquify).unwrap();
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val)),
+            "root\n|-- button\n|   |-- text\n|   `-- button-3\n|       `-- text-4\n`-- button-1\n    `-- text-2\n",
+        );
+        assert_eq!(tree.name_of(tree.child_keys(a).unwrap()[0]), Some("label"));
+        assert_eq!(tree.name_of(a), None);
+        assert_eq!(tree.validate(), Ok(()));
+
+        assert_eq!(tree.instantiate_template(proto, TreeKey::default(), &mut uniquify), None);
+        assert_eq!(tree.instantiate_template(TreeKey::default(), root, &mut uniquify), None);
+        assert_eq!(next, 4);
+    }
+
+    #[test]
+    fn tree_subtree_hash() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let a1 = tree.add_child(2, a).unwrap();
+        let b = tree.add_child(1, root).unwrap();
+        tree.add_child(2, b).unwrap();
+        let c = tree.add_child(2, root).unwrap();
+        tree.add_child(1, c).unwrap();
+
+        let ha = tree.subtree_hash(a).unwrap();
+        assert_eq!(tree.subtree_hash(b), Some(ha));
+        assert_ne!(tree.subtree_hash(c), Some(ha));
+        assert!(tree.subtree_eq(a, b));
+        assert!(!tree.subtree_eq(a, c));
+        assert_eq!(tree.subtree_hash(TreeKey::default()), None);
+
+        let hroot = tree.subtree_hash(root).unwrap();
+        *tree.get_mut(a1).unwrap() = 3;
+        assert!(!tree.hashes.contains_key(root));
+        assert!(tree.hashes.contains_key(b));
+        assert_ne!(tree.subtree_hash(a), Some(ha));
+        assert_ne!(tree.subtree_hash(root), Some(hroot));
+
+        *tree.get_mut(a1).unwrap() = 2;
+        assert_eq!(tree.subtree_hash(root), Some(hroot));
+
+        tree.add_child(4, b).unwrap();
+        assert_ne!(tree.subtree_hash(b), Some(ha));
+        assert!(!tree.subtree_eq(a, b));
+        tree.remove_recursive(a).unwrap();
+        assert_ne!(tree.subtree_hash(root), Some(hroot));
+        tree.set_child(b, c).unwrap();
+        assert!(!tree.hashes.contains_key(root));
+    }
+
+    #[test]
+    fn tree_sorted_keys() {
+        let mut tree = Tree::new();
+        let a = tree.add_root(0);
+        let b = tree.add_child(1, a).unwrap();
+        let c = tree.add_root(2);
+        tree.remove_recursive(b).unwrap();
+        // Reuses the slot freed by `b`
+        let d = tree.add_child(3, c).unwrap();
+        let e = tree.add_root(4);
+        assert_eq!(tree.sorted_keys(), [a, c, d, e]);
+
+        let parts = tree.sorted_keys()
+            .into_iter()
+            .map(|key| (key, tree.parent_key_of(key), *tree.get(key).unwrap()))
+            .collect::<Vec<_>>();
+        let rebuilt = Tree::from_parts(parts).unwrap();
+        assert_eq!(rebuilt.sorted_keys(), [a, c, d, e]);
+    }
+
+    #[test]
+    fn tree_insertion_index() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let b = tree.add_root(2);
+        let c = tree.add_child(3, root).unwrap();
+        assert_eq!(tree.insertion_index(root), Some(0));
+        assert_eq!(tree.insertion_index(c), Some(3));
+        assert_eq!(tree.newest_child(root), Some(c));
+
+        tree.set_child(root, b).unwrap();
+        assert_eq!(tree.newest_child(root), Some(c));
+        assert_eq!(tree.insertion_index(b), Some(2));
+        tree.remove_recursive(c).unwrap();
+        assert_eq!(tree.insertion_index(c), None);
+        assert_eq!(tree.newest_child(root), Some(b));
+        assert_eq!(tree.newest_child(a), None);
+
+        tree.clear();
+        let d = tree.add_root(4);
+        assert_eq!(tree.insertion_index(d), Some(4));
+    }
+
+    #[test]
+    fn tree_fold_accumulate() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(2);
+        let a = tree.add_child(3, root).unwrap();
+        let b = tree.add_child(4, a).unwrap();
+        tree.add_child(5, root).unwrap();
+
+        let product = tree.fold_subtree(root, |_, &val| val, |acc, child| acc * child);
+        assert_eq!(product, Some(120));
+        let heights = tree.fold_subtree(root, |_, _| 0, |acc: usize, child| acc.max(child + 1));
+        assert_eq!(heights, Some(2));
+        assert_eq!(tree.fold_subtree(TreeKey::default(), |_, _| (), |_, _| ()), None);
+
+        let sums = tree.accumulate(root, 0, |sum, _, &val| sum + val).unwrap();
+        assert_eq!(sums.len(), 4);
+        assert_eq!((sums[root], sums[a], sums[b]), (2, 5, 9));
+
+        let visited = tree.accumulate(a, vec![], |path, key, _| {
+            let mut path = path.clone();
+            path.push(key);
+            path
+        }).unwrap();
+        assert_eq!(visited[b], [a, b]);
+        assert!(!visited.contains_key(root));
+    }
+
+    #[test]
+    fn tree_propagate() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(1);
+        let a = tree.add_child(2, root).unwrap();
+        let b = tree.add_child(3, a).unwrap();
+        let c = tree.add_child(4, root).unwrap();
+
+        let mut world = SecondaryMap::new();
+        assert_eq!(tree.propagate(root, &0, &mut world, |parent, &local| parent + local), Some(4));
+        assert_eq!((world[root], world[a], world[b], world[c]), (1, 3, 6, 5));
+
+        // Nothing changed, so nothing is recomputed
+        assert_eq!(tree.propagate(root, &0, &mut world, |parent, &local| parent + local), Some(0));
+
+        *tree.get_mut(a).unwrap() = 10;
+        tree.mark_dirty(a).unwrap();
+        let d = tree.add_child(5, c).unwrap();
+        tree.mark_dirty(d).unwrap();
+        assert_eq!(tree.propagate(root, &0, &mut world, |parent, &local| parent + local), Some(3));
+        assert_eq!((world[a], world[b], world[c], world[d]), (11, 14, 5, 10));
+        assert!(!tree.has_dirty_descendants(root));
+
+        assert_eq!(tree.propagate(TreeKey::default(), &0, &mut world, |_, _| 0), None);
+    }
+
+    #[test]
+    fn tree_evaluate() {
+        // Classify a number by comparing it against each node's threshold
+        let mut tree = Tree::new();
+        let root = tree.add_root(10);
+        let low = tree.add_child(5, root).unwrap();
+        let tiny = tree.add_child(0, low).unwrap();
+        tree.add_child(0, low).unwrap();
+        let high = tree.add_child(0, root).unwrap();
+
+        let chooser = |&threshold: &i32, &val: &i32| ChildSelect::Child(usize::from(val >= threshold));
+        assert_eq!(tree.evaluate(root, &3, chooser), Some(vec![root, low, tiny]));
+        assert_eq!(tree.evaluate(root, &20, chooser), Some(vec![root, high]));
+        assert_eq!(tree.evaluate(low, &3, |_, _| ChildSelect::Stop), Some(vec![low]));
+        assert_eq!(tree.evaluate(root, &3, |_, _| ChildSelect::Child(5)), Some(vec![root]));
+        assert_eq!(tree.evaluate(TreeKey::default(), &3, chooser), None);
+    }
+
+    #[test]
+    fn tree_into_values() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(String::from("root"));
+        let a = tree.add_child(String::from("a"), root).unwrap();
+        tree.add_child(String::from("b"), a).unwrap();
+        tree.add_root(String::from("other"));
+        tree.remove_recursive(a);
+
+        let mut values = tree.into_values().collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, ["other", "root"]);
+    }
+
+    #[test]
+    fn tree_is_balanced() {
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        tree.add_child(2, root).unwrap();
+        assert!(tree.is_balanced(0));
+
+        let a1 = tree.add_child(3, a).unwrap();
+        assert!(!tree.is_balanced(0));
+        assert!(tree.is_balanced(1));
+        tree.add_child(4, a1).unwrap();
+        assert!(!tree.is_balanced(1));
+        assert!(tree.is_balanced(2));
+        assert!(Tree::<()>::new().is_balanced(0));
+    }
+
+    #[test]
+    fn tree_rebalance() {
+        let mut tree = Tree::with_subtree_len();
+        let root = tree.add_root(0);
+        let children = (1..=10)
+            .map(|i| tree.add_child(i, root).unwrap())
+            .collect::<Vec<_>>();
+        let before = tree.post_order_keys().collect::<Vec<_>>();
+
+        let groups = tree.rebalance(root, Rebalance::Packed(3), |tree, keys| {
+            keys.iter().map(|&key| *tree.get(key).unwrap()).sum::<i32>() * 100
+        });
+        // Ten children make four groups of three, which make two groups of two
+        assert_eq!(groups, Some(6));
+        assert_eq!(
+            tree.render_ascii(|out, val| write!(out, "{}", val)),
+            "0\n|-- 450000\n|   |-- 600\n|   |   |-- 1\n|   |   |-- 2\n|   |   `-- 3\n|   |-- 1500\n\
+             |   |   |-- 4\n|   |   |-- 5\n|   |   `-- 6\n|   `-- 2400\n|       |-- 7\n|       |-- 8\n\
+             |       `-- 9\n`-- 100000\n    `-- 1000\n        `-- 10\n",
+        );
+        let leaves = tree.post_order_keys()
+            .filter(|key| children.contains(key))
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, before[..10]);
+        assert_eq!(tree.subtree_len(root), Some(17));
+        assert_eq!(tree.validate(), Ok(()));
+
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        for i in 1..=10 {
+            tree.add_child(i, root).unwrap();
+        }
+        assert_eq!(tree.rebalance(root, Rebalance::Even(4), |_, keys| -(keys.len() as i32)), Some(3));
+        let sizes = tree.child_keys(root).unwrap()
+            .iter()
+            .map(|&group| tree.child_keys(group).unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [4, 3, 3]);
+        assert!(tree.is_balanced(0));
+
+        assert_eq!(tree.rebalance(root, Rebalance::Even(1), |_, _| 0), None);
+        assert_eq!(tree.rebalance(TreeKey::default(), Rebalance::Even(4), |_, _| 0), None);
+        assert_eq!(tree.rebalance(root, Rebalance::Even(4), |_, _| 0), Some(0));
+    }
+
+    #[test]
+    fn tree_split_at() {
+        fn assert_send<S: Send>(_: &S) {}
+
+        let mut tree = Tree::new();
+        let root = tree.add_root(0);
+        let a = tree.add_child(1, root).unwrap();
+        let a1 = tree.add_child(2, a).unwrap();
+        let b = tree.add_child(3, root).unwrap();
+        let other = tree.add_root(4);
+        let hash = tree.subtree_hash(root).unwrap();
+
+        assert!(tree.split_at(&[a, a1]).is_none());
+        assert!(tree.split_at(&[a1, a]).is_none());
+        assert!(tree.split_at(&[b, b]).is_none());
+
+        let mut views = tree.split_at(&[a, b, other]).unwrap();
+        assert_send(&views[0]);
+        assert_eq!(views.iter().map(SubtreeMut::len).collect::<Vec<_>>(), [2, 1, 1]);
+        assert_eq!(views[0].child_keys(a), Some(&[a1][..]));
+        assert!(!views[0].contains(b));
+        assert!(views[1].get_mut(a).is_none());
+        for view in &mut views {
+            for (_, val) in view.iter_mut() {
+                *val *= 10;
+            }
+        }
+        *views[2].get_mut(other).unwrap() += 1;
+        assert_eq!(views[2].get(views[2].root()), Some(&41));
+
+        assert_eq!(*tree.get(a1).unwrap(), 20);
+        assert_eq!(*tree.get(b).unwrap(), 30);
+        assert_ne!(tree.subtree_hash(root), Some(hash));
+    }
+
+    #[test]
+    fn tree_edges_roundtrip() {
+        let tree = Tree::from_edges([("a", "b"), ("c", "d"), ("a", "c"), ("e", "f")]).unwrap();
+        let roots = tree.root_keys().map(|key| *tree.get(key).unwrap()).collect::<Vec<_>>();
+        assert_eq!(roots, ["a", "e"]);
+
+        let edges = tree.to_edge_list()
+            .into_iter()
+            .map(|(parent, child)| (*tree.get(parent).unwrap(), *tree.get(child).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, [("a", "b"), ("a", "c"), ("c", "d"), ("e", "f")]);
+        assert_eq!(Tree::from_edges(edges).unwrap().len(), 6);
+
+        assert!(matches!(Tree::from_edges([(1, 2), (3, 2)]), Err(Error::MultipleParents)));
+        assert!(matches!(Tree::from_edges([(1, 2), (2, 3), (3, 1)]), Err(Error::Cycle)));
+        assert!(matches!(Tree::from_edges([(1, 1)]), Err(Error::Cycle)));
+        assert!(Tree::<i32>::from_edges([]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tree_from_parent_list() {
+        let (tree, keys) = Tree::from_parent_list([
+            ("root", None),
+            ("b", Some(3)),
+            ("a", Some(0)),
+            ("c", Some(0)),
+            ("other", None),
+        ]).unwrap();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(tree.root_keys().collect::<Vec<_>>(), [keys[0], keys[4]]);
+        assert_eq!(tree.child_keys(keys[0]), Some(&[keys[2], keys[3]][..]));
+        assert_eq!(tree.parent_key_of(keys[1]), Some(keys[3]));
+        assert_eq!(*tree.get(keys[1]).unwrap(), "b");
+        assert!(tree.validate().is_ok());
+
+        assert!(matches!(Tree::from_parent_list([(0, Some(1))]), Err(Error::Missing)));
+        assert!(matches!(Tree::from_parent_list([(0, Some(1)), (1, Some(0))]), Err(Error::Cycle)));
+        assert!(matches!(Tree::from_parent_list([(0, Some(0))]), Err(Error::Cycle)));
+    }
+
+    #[test]
+    fn tree_to_parent_list() {
+        let rows = vec![("root", None), ("a", Some(0)), ("a1", Some(1)), ("b", Some(0)), ("other", None)];
+        let (tree, _) = Tree::from_parent_list(rows.clone()).unwrap();
+        assert_eq!(tree.to_parent_list(), rows);
+        assert_eq!(tree.into_parent_list(), rows);
+
+        let (tree, keys) = Tree::from_parent_list([("b", Some(2)), ("a", Some(2)), ("root", None)]).unwrap();
+        let rows = tree.to_parent_list();
+        assert_eq!(rows, [("root", None), ("b", Some(0)), ("a", Some(0))]);
+        let (copy, _) = Tree::from_parent_list(rows.clone()).unwrap();
+        assert_eq!(copy.to_parent_list(), tree.to_parent_list());
+        assert_eq!(rows.into_iter().collect::<Tree<_>>().to_parent_list(), tree.to_parent_list());
+        assert_eq!(tree.child_keys(keys[2]), Some(&[keys[0], keys[1]][..]));
+    }
+
+    #[test]
+    fn tree_levels() {
+        assert_eq!(Tree::<()>::new().levels().count(), 0);
+
+        let tree = [(0, None), (1, Some(0)), (2, Some(1)), (3, Some(0)), (4, None), (5, Some(4))]
+            .into_iter()
+            .collect::<Tree<_>>();
+        let levels = tree.levels()
+            .map(|level| level.into_iter().map(|key| *tree.get(key).unwrap()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(levels, [vec![0, 4], vec![1, 3, 5], vec![2]]);
+    }
 }
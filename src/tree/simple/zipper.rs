@@ -0,0 +1,166 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+use slotmap::SecondaryMap;
+use crate::tree::simple::{Tree, TreeKey};
+
+struct Edit<T> {
+    key: TreeKey,
+    val: Rc<T>,
+    prev: Option<Rc<Edit<T>>>,
+}
+
+/// An immutable cursor into a [`Tree`], supporting purely functional navigation and edits.
+///
+/// Every operation produces a new zipper, leaving the original untouched, so zippers can be cheaply
+/// cloned and kept around to backtrack to. Edits don't change the underlying tree, instead they're
+/// recorded in the zipper and can be retrieved with [`into_edits`](Zipper::into_edits).
+pub struct Zipper<'a, T, E = ()> {
+    tree: &'a Tree<T, E>,
+    focus: TreeKey,
+    edits: Option<Rc<Edit<T>>>,
+}
+
+impl<'a, T, E> Zipper<'a, T, E> {
+    pub(crate) fn new(tree: &'a Tree<T, E>, focus: TreeKey) -> Option<Zipper<'a, T, E>> {
+        tree.raw_nodes().get(focus)?;
+        Some(Zipper { tree, focus, edits: None })
+    }
+
+    fn with_focus(&self, focus: TreeKey) -> Zipper<'a, T, E> {
+        Zipper {
+            tree: self.tree,
+            focus,
+            edits: self.edits.clone(),
+        }
+    }
+
+    fn siblings(&self) -> &'a [TreeKey] {
+        match self.tree.parent_key_of(self.focus) {
+            Some(parent) => self.tree.raw_nodes()[parent].children(),
+            None => self.tree.raw_roots(),
+        }
+    }
+
+    /// Get the key of the focused node
+    #[must_use]
+    pub fn key(&self) -> TreeKey {
+        self.focus
+    }
+
+    /// Get the value of the focused node, including any edits made through this zipper
+    #[must_use]
+    pub fn value(&self) -> &T {
+        let mut edit = self.edits.as_deref();
+        while let Some(cur) = edit {
+            if cur.key == self.focus {
+                return &cur.val;
+            }
+            edit = cur.prev.as_deref();
+        }
+        self.tree.raw_nodes()[self.focus].val()
+    }
+
+    /// Move to the parent of the focused node
+    #[must_use]
+    pub fn up(&self) -> Option<Zipper<'a, T, E>> {
+        let parent = self.tree.parent_key_of(self.focus)?;
+        Some(self.with_focus(parent))
+    }
+
+    /// Move to the child of the focused node at the provided index
+    #[must_use]
+    pub fn down(&self, idx: usize) -> Option<Zipper<'a, T, E>> {
+        let child = *self.tree.raw_nodes()[self.focus].children().get(idx)?;
+        Some(self.with_focus(child))
+    }
+
+    /// Move to the previous sibling of the focused node
+    #[must_use]
+    pub fn left(&self) -> Option<Zipper<'a, T, E>> {
+        let siblings = self.siblings();
+        let pos = siblings.iter().position(|&k| k == self.focus)?;
+        Some(self.with_focus(*siblings.get(pos.checked_sub(1)?)?))
+    }
+
+    /// Move to the next sibling of the focused node
+    #[must_use]
+    pub fn right(&self) -> Option<Zipper<'a, T, E>> {
+        let siblings = self.siblings();
+        let pos = siblings.iter().position(|&k| k == self.focus)?;
+        Some(self.with_focus(*siblings.get(pos + 1)?))
+    }
+
+    /// Move to the root of the tree containing the focused node
+    #[must_use]
+    pub fn root(&self) -> Zipper<'a, T, E> {
+        let mut focus = self.focus;
+        while let Some(parent) = self.tree.parent_key_of(focus) {
+            focus = parent;
+        }
+        self.with_focus(focus)
+    }
+
+    /// Produce a new zipper where the focused node has the provided value
+    #[must_use]
+    pub fn set(&self, val: T) -> Zipper<'a, T, E> {
+        Zipper {
+            tree: self.tree,
+            focus: self.focus,
+            edits: Some(Rc::new(Edit {
+                key: self.focus,
+                val: Rc::new(val),
+                prev: self.edits.clone(),
+            })),
+        }
+    }
+
+    /// Produce a new zipper where the value of the focused node is updated by the provided function
+    #[must_use]
+    pub fn map(&self, f: impl FnOnce(&T) -> T) -> Zipper<'a, T, E> {
+        self.set(f(self.value()))
+    }
+}
+
+impl<T: Clone, E> Zipper<'_, T, E> {
+    /// Consume this zipper, returning the latest value set for each edited node. Values shared with
+    /// other zippers are cloned.
+    pub fn into_edits(self) -> Vec<(TreeKey, T)> {
+        let mut seen = SecondaryMap::new();
+        let mut out = Vec::new();
+        let mut edit = self.edits;
+        while let Some(cur) = edit {
+            let cur = Rc::unwrap_or_clone(cur);
+            if seen.insert(cur.key, ()).is_none() {
+                out.push((cur.key, Rc::unwrap_or_clone(cur.val)));
+            }
+            edit = cur.prev;
+        }
+        out
+    }
+}
+
+impl<T> Clone for Edit<T> {
+    fn clone(&self) -> Self {
+        Edit {
+            key: self.key,
+            val: self.val.clone(),
+            prev: self.prev.clone(),
+        }
+    }
+}
+
+impl<T, E> Clone for Zipper<'_, T, E> {
+    fn clone(&self) -> Self {
+        self.with_focus(self.focus)
+    }
+}
+
+impl<T: fmt::Debug, E> fmt::Debug for Zipper<'_, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Zipper")
+            .field("focus", &self.focus)
+            .field("value", self.value())
+            .finish()
+    }
+}
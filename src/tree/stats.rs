@@ -0,0 +1,51 @@
+//! Shape statistics shared by the tree types
+
+use alloc::vec::Vec;
+
+/// Statistics describing the shape of a tree, gathered in a single traversal
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// Number of root nodes
+    pub roots: usize,
+    /// Depth of the deepest node, where roots have a depth of zero. Zero for an empty tree.
+    pub max_depth: usize,
+    /// Number of nodes at each depth, starting with the roots
+    pub nodes_per_depth: Vec<usize>,
+    /// Average number of children of the nodes which have any. Zero if no node has children.
+    pub avg_branching: f64,
+}
+
+impl TreeStats {
+    /// Gather statistics for the trees under `roots`, where `children` lists the children of a
+    /// node
+    pub(crate) fn collect<K: Copy, C: AsRef<[K]>>(roots: &[K], mut children: impl FnMut(K) -> C) -> TreeStats {
+        let mut nodes_per_depth = Vec::new();
+        let mut parents = 0;
+        let mut edges = 0;
+        let mut stack = roots.iter()
+            .map(|&key| (key, 0))
+            .collect::<Vec<_>>();
+
+        while let Some((key, depth)) = stack.pop() {
+            if nodes_per_depth.len() <= depth {
+                nodes_per_depth.resize(depth + 1, 0);
+            }
+            nodes_per_depth[depth] += 1;
+
+            let children = children(key);
+            let children = children.as_ref();
+            if !children.is_empty() {
+                parents += 1;
+                edges += children.len();
+            }
+            stack.extend(children.iter().map(|&child| (child, depth + 1)));
+        }
+
+        TreeStats {
+            roots: roots.len(),
+            max_depth: nodes_per_depth.len().saturating_sub(1),
+            nodes_per_depth,
+            avg_branching: if parents == 0 { 0.0 } else { edges as f64 / parents as f64 },
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Structural consistency checks shared by the tree types
+
+use core::fmt;
+use alloc::vec::Vec;
+use slotmap::{Key, SecondaryMap};
+
+/// A broken internal invariant found by validating a tree
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvariantViolation<K> {
+    /// A relationship refers to a node which doesn't exist
+    Dangling(K),
+    /// A node is listed as a child, but its parent link points elsewhere
+    ParentMismatch {
+        /// The node listing the child
+        parent: K,
+        /// The child whose parent link disagrees
+        child: K,
+    },
+    /// A node is listed as a root, but has a parent
+    RootHasParent(K),
+    /// A node is neither a root nor the child of any node
+    Detached(K),
+    /// A node is listed more than once among the roots and children
+    Duplicated(K),
+    /// A node can't be reached from any root, as its ancestors form a cycle
+    Cycle(K),
+}
+
+impl<K: fmt::Debug> fmt::Display for InvariantViolation<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::Dangling(key) => write!(f, "Tree relationship refers to missing node {:?}", key),
+            InvariantViolation::ParentMismatch { parent, child } => {
+                write!(f, "Tree node {:?} is a child of {:?}, but has a different parent", child, parent)
+            }
+            InvariantViolation::RootHasParent(key) => write!(f, "Tree root {:?} has a parent", key),
+            InvariantViolation::Detached(key) => write!(f, "Tree node {:?} is neither a root nor a child", key),
+            InvariantViolation::Duplicated(key) => write!(f, "Tree node {:?} is listed more than once", key),
+            InvariantViolation::Cycle(key) => write!(f, "Tree node {:?} is part of a cycle", key),
+        }
+    }
+}
+
+/// Check the relationships between `keys`, the key of every node in a tree. `contains` checks
+/// whether a key refers to a node, and `parent` and `children` give the links stored for it.
+/// Violations already found by the caller are passed in as `out`.
+pub(crate) fn validate<'a, K: Key + 'a>(
+    mut out: Vec<InvariantViolation<K>>,
+    keys: &[K],
+    roots: &[K],
+    contains: impl Fn(K) -> bool,
+    parent: impl Fn(K) -> Option<K>,
+    children: impl Fn(K) -> &'a [K],
+) -> Result<(), Vec<InvariantViolation<K>>> {
+    let mut listed = SecondaryMap::<K, usize>::new();
+    let mut list = |key: K, out: &mut Vec<_>| {
+        if !contains(key) {
+            out.push(InvariantViolation::Dangling(key));
+            return false;
+        }
+        let count = listed.entry(key).map(|entry| entry.or_insert(0));
+        if let Some(count) = count {
+            *count += 1;
+        }
+        true
+    };
+
+    for &root in roots {
+        if list(root, &mut out) && parent(root).is_some() {
+            out.push(InvariantViolation::RootHasParent(root));
+        }
+    }
+    for &key in keys {
+        for &child in children(key) {
+            if list(child, &mut out) && parent(child) != Some(key) {
+                out.push(InvariantViolation::ParentMismatch { parent: key, child });
+            }
+        }
+        if let Some(parent) = parent(key) {
+            if !contains(parent) {
+                out.push(InvariantViolation::Dangling(parent));
+            }
+        }
+    }
+
+    let mut reached = SecondaryMap::<K, ()>::new();
+    let mut stack = roots.iter()
+        .copied()
+        .filter(|&root| contains(root))
+        .collect::<Vec<_>>();
+    while let Some(key) = stack.pop() {
+        if reached.insert(key, ()).is_none() {
+            stack.extend(children(key).iter().copied().filter(|&child| contains(child)));
+        }
+    }
+
+    for &key in keys {
+        match listed.get(key).copied().unwrap_or(0) {
+            0 => out.push(InvariantViolation::Detached(key)),
+            1 if reached.contains_key(key) => (),
+            1 => out.push(InvariantViolation::Cycle(key)),
+            _ => out.push(InvariantViolation::Duplicated(key)),
+        }
+    }
+
+    if out.is_empty() {
+        Ok(())
+    } else {
+        Err(out)
+    }
+}
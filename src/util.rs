@@ -1,5 +1,8 @@
+use core::hash::Hasher;
 use core::num::{NonZeroU64, NonZeroUsize, NonZeroIsize};
-use std::mem::MaybeUninit;
+use core::mem::{self, MaybeUninit};
+use alloc::vec::Vec;
+use slotmap::{Key, SecondaryMap, SlotMap};
 
 pub trait MaybeUninitArray<T, const N: usize>: Sized {
     const UNINIT: [Self; N];
@@ -24,6 +27,7 @@ impl<T> MaybeUninitSlice<T> for MaybeUninit<T> {
     }
 }
 
+#[allow(unused)]
 pub trait NonZeroExt: Sized {
     type Inner;
 
@@ -66,3 +70,177 @@ impl NonZeroExt for NonZeroIsize {
         NonZeroIsize::new(self.get().checked_sub(other)?)
     }
 }
+
+/// Split a key into its slot index and version
+fn key_parts<K: Key>(key: K) -> (u32, u32) {
+    let ffi = key.data().as_ffi();
+    (ffi as u32, (ffi >> 32) as u32)
+}
+
+/// Default limit on the slot operations [`slotmap_from_keys`] may perform beyond one per item
+pub const MAX_RESTORE_WORK: u64 = 1 << 24;
+
+/// Build a `SlotMap` with every value stored at exactly the key provided for it. Returns `None` if
+/// any key is malformed, refers to a vacant slot, or shares a slot with another key.
+///
+/// Slots not named by any key are left vacant, and will be reused by later inserts. Building
+/// takes time proportional to the largest slot index plus the versions of the keys, so keys
+/// taken from a map with heavy churn are more expensive to restore. Also returns `None` if that
+/// would take more than `max_work` slot operations beyond one per item, so untrusted keys can't
+/// stall the caller.
+pub fn slotmap_from_keys<K: Key, V>(mut items: Vec<(K, V)>, max_work: u64) -> Option<SlotMap<K, V>> {
+    items.sort_unstable_by_key(|(key, _)| key_parts(*key).0);
+
+    let mut next_idx = 1;
+    // Each key needs one insert per version it skips, and each unnamed slot below it one insert
+    let mut work = 0u64;
+    for (key, _) in &items {
+        let (idx, version) = key_parts(*key);
+        if idx < next_idx || version % 2 == 0 {
+            return None;
+        }
+        work += u64::from(version / 2) + u64::from(idx - next_idx);
+        if work > max_work {
+            return None;
+        }
+        next_idx = idx.checked_add(1)?;
+    }
+
+    let mut map = SlotMap::with_capacity_and_key(next_idx as usize);
+    let mut gaps = Vec::new();
+    let mut next_idx = 1;
+
+    for (key, mut val) in items {
+        let (idx, _) = key_parts(key);
+        // Occupy and detach skipped slots, so they stay off the free-list until we're done
+        while next_idx < idx {
+            let gap = map.insert(val);
+            val = map.detach(gap)?;
+            gaps.push(gap);
+            next_idx += 1;
+        }
+        // The free-list is empty, or only holds the slot we just vacated, so this always
+        // revisits the same slot with a newer version until it matches
+        let mut new_key = map.insert(val);
+        while new_key != key {
+            let val = map.remove(new_key)?;
+            new_key = map.insert(val);
+        }
+        next_idx += 1;
+    }
+
+    // Move the gaps onto the free-list, borrowing any value to temporarily fill each one
+    if let Some(donor) = map.keys().next() {
+        for gap in gaps {
+            let val = map.detach(donor)?;
+            map.reattach(gap, val);
+            let val = map.remove(gap)?;
+            map.reattach(donor, val);
+        }
+    }
+
+    Some(map)
+}
+
+/// Check whether following parent links from any node ever revisits a node
+pub fn has_cycle<K: Key>(parents: &SecondaryMap<K, K>) -> bool {
+    const VISITING: u8 = 1;
+    const DONE: u8 = 2;
+
+    let mut state = SecondaryMap::<K, u8>::new();
+    let mut path = Vec::new();
+
+    for start in parents.keys() {
+        let mut cur = Some(start);
+        while let Some(key) = cur {
+            match state.get(key) {
+                Some(&DONE) => break,
+                Some(_) => return true,
+                None => {
+                    state.insert(key, VISITING);
+                    path.push(key);
+                    cur = parents.get(key).copied();
+                }
+            }
+        }
+        for key in path.drain(..) {
+            state.insert(key, DONE);
+        }
+    }
+
+    false
+}
+
+/// Approximate heap usage of a slotmap, where every slot stores a value alongside its version
+pub fn slotmap_heap_size<K: Key, V>(map: &SlotMap<K, V>) -> usize {
+    map.capacity() * mem::size_of::<(V, u32)>()
+}
+
+/// Approximate heap usage of a secondary map, where every slot stores an optional value alongside
+/// its version
+pub fn secondary_heap_size<K: Key, V>(map: &SecondaryMap<K, V>) -> usize {
+    map.capacity() * mem::size_of::<(Option<V>, u32)>()
+}
+
+/// A 64-bit FNV-1a hasher, giving hashes which are stable across runs and platforms of the same
+/// endianness
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Implement comparison, hashing and display for a smart reference type by forwarding to the
+/// value it dereferences to
+macro_rules! forward_value_traits {
+    (impl<T $(: ?$sized:ident)? $(, $extra:ident $(: $bound:path)?)*> for $ty:ty) => {
+        impl<T: $(?$sized +)? PartialEq $(, $extra $(: $bound)?)*> PartialEq for $ty {
+            fn eq(&self, other: &Self) -> bool {
+                **self == **other
+            }
+        }
+
+        impl<T: $(?$sized +)? Eq $(, $extra $(: $bound)?)*> Eq for $ty {}
+
+        impl<T: $(?$sized +)? PartialOrd $(, $extra $(: $bound)?)*> PartialOrd for $ty {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                (**self).partial_cmp(&**other)
+            }
+        }
+
+        impl<T: $(?$sized +)? Ord $(, $extra $(: $bound)?)*> Ord for $ty {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                (**self).cmp(&**other)
+            }
+        }
+
+        impl<T: $(?$sized +)? core::hash::Hash $(, $extra $(: $bound)?)*> core::hash::Hash for $ty {
+            fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                (**self).hash(state)
+            }
+        }
+
+        impl<T: $(?$sized +)? core::fmt::Display $(, $extra $(: $bound)?)*> core::fmt::Display for $ty {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                (**self).fmt(f)
+            }
+        }
+    };
+}
+
+pub(crate) use forward_value_traits;
@@ -0,0 +1,118 @@
+//! JavaScript bindings to a [`simple::Tree`](crate::tree::simple::Tree) of JS values, through
+//! `wasm-bindgen`.
+//!
+//! The tree is exported as the class `Tree`. Nodes are identified by keys, which cross into JS as
+//! `bigint`s, and missing nodes are reported as `undefined`.
+
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+use crate::tree::simple::{Tree, TreeKey};
+
+/// A tree of JS values, exported to JS as `Tree`
+#[wasm_bindgen(js_name = Tree)]
+#[derive(Default)]
+pub struct JsTree {
+    tree: Tree<JsValue>,
+}
+
+#[wasm_bindgen(js_class = Tree)]
+impl JsTree {
+    /// Create a new, empty tree
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> JsTree {
+        JsTree::default()
+    }
+
+    /// Get the number of nodes in this tree
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Check whether a node is in this tree
+    pub fn contains(&self, key: u64) -> bool {
+        self.tree.get(TreeKey::from_ffi(key)).is_some()
+    }
+
+    /// Add a new root after the existing roots, returning its key
+    #[wasm_bindgen(js_name = addRoot)]
+    pub fn add_root(&mut self, val: JsValue) -> u64 {
+        self.tree.add_root(val).to_ffi()
+    }
+
+    /// Add a new child after the existing children of a node, returning its key
+    #[wasm_bindgen(js_name = addChild)]
+    pub fn add_child(&mut self, parent: u64, val: JsValue) -> Option<u64> {
+        self.tree.add_child(val, TreeKey::from_ffi(parent)).map(TreeKey::to_ffi)
+    }
+
+    /// Get the value of a node
+    pub fn get(&self, key: u64) -> Option<JsValue> {
+        self.tree.get(TreeKey::from_ffi(key)).map(|node| (*node).clone())
+    }
+
+    /// Replace the value of a node, returning the old value
+    pub fn set(&mut self, key: u64, val: JsValue) -> Option<JsValue> {
+        let mut node = self.tree.get_mut(TreeKey::from_ffi(key))?;
+        Some(core::mem::replace(&mut *node, val))
+    }
+
+    /// Remove a node and all its descendants, returning whether the node existed
+    pub fn remove(&mut self, key: u64) -> bool {
+        self.tree.remove_recursive(TreeKey::from_ffi(key)).is_some()
+    }
+
+    /// Get the key of a node's parent
+    pub fn parent(&self, key: u64) -> Option<u64> {
+        self.tree.parent_key_of(TreeKey::from_ffi(key)).map(TreeKey::to_ffi)
+    }
+
+    /// Get the keys of the roots of this tree, in order
+    pub fn roots(&self) -> Vec<u64> {
+        self.tree.raw_roots().iter().map(|key| key.to_ffi()).collect()
+    }
+
+    /// Get the keys of a node's children, in order
+    pub fn children(&self, key: u64) -> Option<Vec<u64>> {
+        let children = self.tree.child_keys(TreeKey::from_ffi(key))?;
+        Some(children.iter().map(|key| key.to_ffi()).collect())
+    }
+
+    /// Remove every node
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_tree() {
+        // Only the reserved JS values can be used outside wasm
+        let mut tree = JsTree::new();
+        let root = tree.add_root(JsValue::NULL);
+        let a = tree.add_child(root, JsValue::UNDEFINED).unwrap();
+        let b = tree.add_child(root, JsValue::NULL).unwrap();
+        let a1 = tree.add_child(a, JsValue::NULL).unwrap();
+        let other = tree.add_root(JsValue::NULL);
+
+        assert_eq!(tree.length(), 5);
+        assert_eq!(tree.roots(), [root, other]);
+        assert_eq!(tree.children(root).unwrap(), [a, b]);
+        assert_eq!(tree.parent(a1), Some(a));
+        assert_eq!(tree.parent(root), None);
+        assert!(tree.set(b, JsValue::UNDEFINED).is_some());
+
+        assert!(tree.remove(a));
+        assert!(!tree.remove(a));
+        assert!(!tree.contains(a1));
+        assert!(tree.get(a1).is_none());
+        assert_eq!(tree.add_child(a, JsValue::NULL), None);
+        assert_eq!(tree.children(a), None);
+        assert_eq!(tree.length(), 3);
+        tree.clear();
+        assert!(tree.roots().is_empty());
+    }
+}